@@ -5,6 +5,7 @@ use crate::roomservice::{
     room::{Hooks, RoomBuilder},
     BuildPlan as RoomservicePlan, GlobalPolicy, RoomserviceBuilder,
 };
+use crate::templates::engine::{TemplateContext, TemplateEngine};
 use async_trait::async_trait;
 use checksums::{hash_file, Algorithm::BLAKE2S};
 use ignore::{overrides::OverrideBuilder, WalkBuilder};
@@ -1032,8 +1033,6 @@ fn render_build_command(
     env: &Environment,
     service: &Service,
 ) -> Result<String, String> {
-    let mut rendered = command.to_string();
-
     let resolved_registry = env
         .registry
         .resolve()
@@ -1042,6 +1041,7 @@ fn render_build_command(
         .tagged_ref(&service.name, &service.version)
         .map_err(|e| format!("Failed to build image ref: {e}"))?;
 
+    let mut context = TemplateContext::new();
     for (key, value) in [
         ("image_ref", image_ref.as_str()),
         ("registry", resolved_registry.host.as_str()),
@@ -1051,10 +1051,12 @@ fn render_build_command(
         ("service_name", service.name.as_str()),
         ("version", service.version.as_str()),
     ] {
-        rendered = replace_template_var(&rendered, key, value);
+        context.insert(key, value);
     }
 
-    Ok(rendered)
+    TemplateEngine::new()
+        .render(command, &context)
+        .map_err(|e| format!("Failed to render build command: {e}"))
 }
 
 fn default_build_command(env: &Environment, build_cfg: &ServiceBuildConfig) -> String {
@@ -1080,12 +1082,6 @@ fn default_push_command(_env: &Environment) -> String {
     "docker push {{ image_ref }}".to_string()
 }
 
-fn replace_template_var(input: &str, key: &str, value: &str) -> String {
-    input
-        .replace(&format!("{{{{ {} }}}}", key), value)
-        .replace(&format!("{{{{{}}}}}", key), value)
-}
-
 fn command_spec_to_shell(command: CommandSpec) -> String {
     command.into_vec().join(" && ")
 }
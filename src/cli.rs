@@ -1,6 +1,6 @@
-use std::io;
+use std::{io, path::PathBuf, time::Duration};
 
-use crate::environment::BuildEngine;
+use crate::{deployment::DEFAULT_MAX_PARALLEL, environment::BuildEngine};
 use clap::{clap_derive::Args, Command, Parser, Subcommand, ValueEnum};
 use clap_complete::{generate, Generator, Shell};
 
@@ -62,6 +62,44 @@ pub enum Commands {
     /// Manage workflow profiles
     #[command(subcommand)]
     Workflow(WorkflowCommands),
+    /// Manage encrypted environment secrets
+    #[command(subcommand)]
+    Secrets(SecretsCommands),
+    /// Print the JSON Schema of an environment config or workflow file
+    Schema(SchemaArgs),
+    /// List, inspect, compare, clone and delete environments
+    #[command(subcommand)]
+    Env(EnvCommands),
+    /// Restore the workloads of an environment to a recorded revision
+    Rollback(RollbackArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum EnvCommands {
+    /// List every environment with its schema version, service count and parent
+    List,
+    /// Print the fully merged config of an environment
+    Show(EnvShowArgs),
+    /// Print the fully merged config of an environment, annotated with where each value came from
+    Resolve(EnvResolveArgs),
+    /// Compare the services, versions, variables and registry of two environments
+    Diff(EnvDiffArgs),
+    /// Create an environment that extends another one
+    Clone(EnvCloneArgs),
+    /// Delete an environment and its generated manifests
+    Rm(EnvRmArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SecretsCommands {
+    /// Encrypt and store a secret value
+    Set(SecretsSetArgs),
+    /// Decrypt and print a secret value
+    Get(SecretsGetArgs),
+    /// Remove a secret value
+    Rm(SecretsRmArgs),
+    /// Re-encrypt every secret of an environment with a new key
+    RotateKey(SecretsRotateKeyArgs),
 }
 
 #[derive(Debug, Subcommand)]
@@ -373,12 +411,50 @@ pub enum EnvType {
     Production,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum ManifestFormat {
+    /// A multi-document YAML stream.
+    Yaml,
+    /// A single JSON `List` holding every manifest.
+    Json,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum DeploymentStrategy {
     Restart,
     Rolling,
 }
 
+// Parses a duration given in seconds, optionally with an `s`, `m` or `h` suffix.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration '{}'; use e.g. 90, 90s, 5m or 1h", value))?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        _ => return Err(format!("invalid duration unit '{}'; use s, m or h", unit)),
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Parses `--max-parallel`, which needs to allow at least one service at a time.
+pub fn parse_max_parallel(value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!(
+            "invalid value '{}'; expected a number of 1 or more",
+            value
+        )),
+        Ok(max_parallel) => Ok(max_parallel),
+    }
+}
+
 pub fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
     generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
 }
@@ -417,6 +493,64 @@ pub struct DeployArgs {
 
     #[arg(long = "apply", help = "Apply the deployment without planning first")]
     pub apply: bool,
+
+    #[arg(
+        long,
+        default_value = "5m",
+        value_parser = parse_duration,
+        help = "How long to wait for workloads to roll out, such as 90s or 5m; 0 skips waiting"
+    )]
+    pub timeout: Duration,
+
+    #[arg(
+        long = "no-rollback",
+        help = "Leave workloads as they are when they fail to roll out, instead of restoring the previous revision"
+    )]
+    pub no_rollback: bool,
+
+    #[arg(
+        long = "prune",
+        help = "Delete objects earlier deploys applied that are no longer generated"
+    )]
+    pub prune: bool,
+
+    #[arg(
+        long = "max-parallel",
+        default_value_t = DEFAULT_MAX_PARALLEL,
+        value_parser = parse_max_parallel,
+        help = "How many services of a stage to apply at the same time"
+    )]
+    pub max_parallel: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    #[arg(short = 'c', long = "context", help = "Kubernetes context to use")]
+    pub context: String,
+
+    #[arg(short = 'n', long = "name", help = "Name of the environment")]
+    pub name: String,
+
+    #[arg(
+        long = "to",
+        help = "Revision to restore; defaults to the one recorded by the latest deploy"
+    )]
+    pub to: Option<u32>,
+
+    #[arg(
+        long = "list",
+        conflicts_with = "to",
+        help = "List the recorded revisions instead of rolling back"
+    )]
+    pub list: bool,
+
+    #[arg(
+        long,
+        default_value = "5m",
+        value_parser = parse_duration,
+        help = "How long to wait for the restored workloads to roll out; 0 skips waiting"
+    )]
+    pub timeout: Duration,
 }
 
 #[derive(Debug, Args)]
@@ -435,6 +569,40 @@ pub struct GenerateArgs {
 
     #[arg(long, short)]
     pub ignore: Option<String>,
+
+    #[arg(
+        long,
+        help = "Keep templates with unresolved {{ placeholders }} instead of failing"
+    )]
+    pub allow_unresolved: bool,
+
+    #[arg(
+        long,
+        help = "Provide the deployment_date variable (the current time), making output differ on every run"
+    )]
+    pub timestamp: bool,
+
+    #[arg(
+        long,
+        help = "Compare the rendered manifests with k8s/generated without writing; exit non-zero with a diff if they differ"
+    )]
+    pub check: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "check",
+        help = "Print the rendered manifests to stdout instead of writing k8s/generated"
+    )]
+    pub stdout: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "yaml",
+        requires = "stdout",
+        help = "Format of the manifests printed with --stdout"
+    )]
+    pub format: ManifestFormat,
 }
 
 #[derive(Debug, Args)]
@@ -565,6 +733,46 @@ pub struct GoArgs {
     /// Skip plan step
     #[arg(long = "apply", help = "Apply the deployment without planning first")]
     pub apply: bool,
+
+    #[arg(
+        long,
+        default_value = "5m",
+        value_parser = parse_duration,
+        help = "How long to wait for workloads to roll out, such as 90s or 5m; 0 skips waiting"
+    )]
+    pub timeout: Duration,
+
+    #[arg(
+        long = "no-rollback",
+        help = "Leave workloads as they are when they fail to roll out, instead of restoring the previous revision"
+    )]
+    pub no_rollback: bool,
+
+    #[arg(
+        long = "prune",
+        help = "Delete objects earlier deploys applied that are no longer generated"
+    )]
+    pub prune: bool,
+
+    #[arg(
+        long = "max-parallel",
+        default_value_t = DEFAULT_MAX_PARALLEL,
+        value_parser = parse_max_parallel,
+        help = "How many services of a stage to apply at the same time"
+    )]
+    pub max_parallel: usize,
+
+    #[arg(
+        long,
+        help = "Keep templates with unresolved {{ placeholders }} instead of failing"
+    )]
+    pub allow_unresolved: bool,
+
+    #[arg(
+        long,
+        help = "Provide the deployment_date variable (the current time), making output differ on every run"
+    )]
+    pub timestamp: bool,
 }
 
 #[derive(Debug, Args)]
@@ -684,6 +892,29 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_deploy_args_max_parallel() {
+        let args = [
+            "sailr",
+            "deploy",
+            "--context",
+            "test-context",
+            "--name",
+            "test-env",
+        ];
+        match Cli::try_parse_from(args).unwrap().commands {
+            Commands::Deploy(args) => assert_eq!(args.max_parallel, DEFAULT_MAX_PARALLEL),
+            _ => panic!("Expected Deploy command"),
+        }
+
+        let cli = Cli::try_parse_from(args.iter().chain(&["--max-parallel", "8"])).unwrap();
+        match cli.commands {
+            Commands::Deploy(args) => assert_eq!(args.max_parallel, 8),
+            _ => panic!("Expected Deploy command"),
+        }
+        assert!(Cli::try_parse_from(args.iter().chain(&["--max-parallel", "0"])).is_err());
+    }
+
     #[test]
     fn test_migrate_args_parse() {
         let cli = Cli::try_parse_from(["sailr", "migrate", "--name", "edge"]).unwrap();
@@ -725,6 +956,221 @@ mod tests {
             _ => panic!("Expected Build command"),
         }
     }
+
+    #[test]
+    fn test_generate_args_parse_allow_unresolved() {
+        let cli = Cli::try_parse_from(["sailr", "generate", "--name", "edge"]).unwrap();
+        match cli.commands {
+            Commands::Generate(args) => assert!(!args.allow_unresolved),
+            _ => panic!("Expected Generate command"),
+        }
+
+        let cli =
+            Cli::try_parse_from(["sailr", "generate", "--name", "edge", "--allow-unresolved"])
+                .unwrap();
+        match cli.commands {
+            Commands::Generate(args) => assert!(args.allow_unresolved),
+            _ => panic!("Expected Generate command"),
+        }
+    }
+
+    #[test]
+    fn test_deploy_timeout_parses_durations() {
+        let cli = Cli::try_parse_from([
+            "sailr",
+            "deploy",
+            "-c",
+            "kind",
+            "-n",
+            "dev",
+            "--apply",
+            "--timeout",
+            "90s",
+        ])
+        .unwrap();
+        match cli.commands {
+            Commands::Deploy(args) => assert_eq!(args.timeout, Duration::from_secs(90)),
+            _ => panic!("Expected Deploy command"),
+        }
+
+        let cli = Cli::try_parse_from(["sailr", "go", "-c", "kind", "-n", "dev"]).unwrap();
+        match cli.commands {
+            Commands::Go(args) => assert_eq!(args.timeout, Duration::from_secs(300)),
+            _ => panic!("Expected Go command"),
+        }
+
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_env_resolve_args_parse() {
+        let cli = Cli::try_parse_from(["sailr", "env", "resolve", "staging"]).unwrap();
+        match cli.commands {
+            Commands::Env(EnvCommands::Resolve(args)) => assert_eq!(args.name, "staging"),
+            _ => panic!("Expected Env Resolve command"),
+        }
+    }
+
+    #[test]
+    fn test_env_subcommands_parse() {
+        let cli = Cli::try_parse_from(["sailr", "env", "diff", "staging", "prod"]).unwrap();
+        match cli.commands {
+            Commands::Env(EnvCommands::Diff(args)) => {
+                assert_eq!(args.from, "staging");
+                assert_eq!(args.to, "prod");
+            }
+            _ => panic!("Expected Env Diff command"),
+        }
+
+        let cli = Cli::try_parse_from(["sailr", "env", "clone", "staging", "qa"]).unwrap();
+        match cli.commands {
+            Commands::Env(EnvCommands::Clone(args)) => {
+                assert_eq!(args.source, "staging");
+                assert_eq!(args.target, "qa");
+            }
+            _ => panic!("Expected Env Clone command"),
+        }
+
+        let cli = Cli::try_parse_from(["sailr", "env", "rm", "qa", "--yes"]).unwrap();
+        match cli.commands {
+            Commands::Env(EnvCommands::Rm(args)) => {
+                assert_eq!(args.name, "qa");
+                assert!(args.yes);
+            }
+            _ => panic!("Expected Env Rm command"),
+        }
+
+        assert!(matches!(
+            Cli::try_parse_from(["sailr", "env", "list"])
+                .unwrap()
+                .commands,
+            Commands::Env(EnvCommands::List)
+        ));
+    }
+
+    #[test]
+    fn test_lint_args_parse_format() {
+        let cli =
+            Cli::try_parse_from(["sailr", "lint", "--name", "dev", "--format", "sarif"]).unwrap();
+        match cli.commands {
+            Commands::Lint(args) => assert_eq!(args.format, LintFormat::Sarif),
+            _ => panic!("Expected Lint command"),
+        }
+
+        let cli = Cli::try_parse_from(["sailr", "lint", "--name", "dev"]).unwrap();
+        match cli.commands {
+            Commands::Lint(args) => assert_eq!(args.format, LintFormat::Text),
+            _ => panic!("Expected Lint command"),
+        }
+    }
+
+    #[test]
+    fn test_generate_args_parse_stdout() {
+        let cli = Cli::try_parse_from([
+            "sailr", "generate", "--name", "edge", "--stdout", "--format", "json",
+        ])
+        .unwrap();
+        match cli.commands {
+            Commands::Generate(args) => {
+                assert!(args.stdout);
+                assert_eq!(args.format, ManifestFormat::Json);
+            }
+            _ => panic!("Expected Generate command"),
+        }
+
+        let cli = Cli::try_parse_from(["sailr", "generate", "--name", "edge", "--stdout"]).unwrap();
+        match cli.commands {
+            Commands::Generate(args) => assert_eq!(args.format, ManifestFormat::Yaml),
+            _ => panic!("Expected Generate command"),
+        }
+
+        assert!(
+            Cli::try_parse_from(["sailr", "generate", "--name", "edge", "--format", "json"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_from([
+            "sailr", "generate", "--name", "edge", "--stdout", "--check"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_generate_args_parse_check() {
+        let cli = Cli::try_parse_from(["sailr", "generate", "--name", "edge", "--check"]).unwrap();
+        match cli.commands {
+            Commands::Generate(args) => {
+                assert!(args.check);
+                assert!(!args.timestamp);
+            }
+            _ => panic!("Expected Generate command"),
+        }
+    }
+
+    #[test]
+    fn test_secrets_commands_parse() {
+        let cli = Cli::try_parse_from([
+            "sailr",
+            "secrets",
+            "set",
+            "--name",
+            "dev",
+            "api-secrets/TOKEN",
+            "abc",
+        ])
+        .unwrap();
+        match cli.commands {
+            Commands::Secrets(SecretsCommands::Set(args)) => {
+                assert_eq!(args.name, "dev");
+                assert_eq!(args.reference, "api-secrets/TOKEN");
+                assert_eq!(args.value.as_deref(), Some("abc"));
+                assert!(args.key_file.is_none());
+            }
+            _ => panic!("Expected Secrets set command"),
+        }
+
+        let cli = Cli::try_parse_from([
+            "sailr",
+            "secrets",
+            "rotate-key",
+            "--name",
+            "dev",
+            "--new-key-file",
+            "dev.key",
+        ])
+        .unwrap();
+        match cli.commands {
+            Commands::Secrets(SecretsCommands::RotateKey(args)) => {
+                assert_eq!(args.new_key_file, PathBuf::from("dev.key"));
+            }
+            _ => panic!("Expected Secrets rotate-key command"),
+        }
+    }
+
+    #[test]
+    fn test_schema_args_parse() {
+        let cli = Cli::try_parse_from(["sailr", "schema", "workflow"]).unwrap();
+        match cli.commands {
+            Commands::Schema(args) => {
+                assert_eq!(args.kind, SchemaKind::Workflow);
+                assert!(args.output.is_none());
+            }
+            _ => panic!("Expected Schema command"),
+        }
+
+        let cli = Cli::try_parse_from(["sailr", "schema", "env", "--output", "env.json"]).unwrap();
+        match cli.commands {
+            Commands::Schema(args) => {
+                assert_eq!(args.kind, SchemaKind::Env);
+                assert_eq!(args.output, Some(PathBuf::from("env.json")));
+            }
+            _ => panic!("Expected Schema command"),
+        }
+
+        assert!(Cli::try_parse_from(["sailr", "schema", "helm"]).is_err());
+    }
 }
 
 #[derive(Debug, Args, Clone)]
@@ -743,8 +1189,125 @@ pub struct BumpArgs {
     pub version: String,
 }
 
+#[derive(Debug, Args, Clone)]
+pub struct EnvResolveArgs {
+    /// Name of the environment
+    pub name: String,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct EnvShowArgs {
+    /// Name of the environment
+    pub name: String,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct EnvDiffArgs {
+    /// Environment to compare from
+    pub from: String,
+    /// Environment to compare to
+    pub to: String,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct EnvCloneArgs {
+    /// Environment to extend
+    pub source: String,
+    /// Name of the new environment
+    pub target: String,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct EnvRmArgs {
+    /// Name of the environment
+    pub name: String,
+    /// Delete without asking for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
 #[derive(Debug, Args, Clone)]
 pub struct LintArgs {
     #[arg(short, long)]
     pub name: String,
+    /// How to print the findings
+    #[arg(long, value_enum, default_value_t = LintFormat::Text)]
+    pub format: LintFormat,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum LintFormat {
+    /// One block per finding, for people.
+    Text,
+    /// A JSON object with the counts and every finding.
+    Json,
+    /// A SARIF 2.1.0 log, for code scanning tools.
+    Sarif,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct SecretsSetArgs {
+    /// Name of the environment
+    #[arg(short, long)]
+    pub name: String,
+    /// Secret reference as [<namespace>/]<secret>/<key>, where <secret> is the name of a Secret
+    /// manifest in <namespace>, by default the environment's namespace
+    pub reference: String,
+    /// Value to store; read from stdin (or prompted for) when omitted
+    pub value: Option<String>,
+    /// Key file to encrypt with, instead of SAILR_SECRETS_KEY or SAILR_SECRETS_KEY_FILE
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct SecretsGetArgs {
+    /// Name of the environment
+    #[arg(short, long)]
+    pub name: String,
+    /// Secret reference as [<namespace>/]<secret>/<key>
+    pub reference: String,
+    /// Key file to decrypt with, instead of SAILR_SECRETS_KEY or SAILR_SECRETS_KEY_FILE
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct SecretsRmArgs {
+    /// Name of the environment
+    #[arg(short, long)]
+    pub name: String,
+    /// Secret reference as [<namespace>/]<secret>/<key>
+    pub reference: String,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct SecretsRotateKeyArgs {
+    /// Name of the environment
+    #[arg(short, long)]
+    pub name: String,
+    /// Key file holding the current key, instead of SAILR_SECRETS_KEY or SAILR_SECRETS_KEY_FILE
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
+    /// Key file to re-encrypt with; a new key is generated into it if it does not exist
+    #[arg(long)]
+    pub new_key_file: PathBuf,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum SchemaKind {
+    /// k8s/environments/<name>/config.toml
+    Env,
+    /// sailr.workflow.toml
+    Workflow,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct SchemaArgs {
+    /// Which file to print the schema for
+    #[arg(value_enum)]
+    pub kind: SchemaKind,
+    /// Write the schema to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
 }
@@ -1,13 +1,17 @@
 use std::path::Path;
 
-use crate::filesystem;
+use crate::{filesystem, templates::config_map::BuiltConfigMap};
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct Config {
     pub name: String,
     pub config_filenames: Vec<String>,
     pub content: String,
     pub root_dir: String,
+    /// `metadata.name` of the ConfigMap, including any hash suffix.
+    pub resource_name: String,
+    /// The name workloads refer to the ConfigMap by in their templates.
+    pub base_name: String,
     file_manger: filesystem::FileSystemManager,
 }
 
@@ -19,18 +23,34 @@ impl Config {
         dir: &String,
     ) -> Config {
         let config_path = Path::new(&dir).join(name);
+        let base_name = name.rsplit('/').next().unwrap_or(name).to_string();
 
         Config {
             name: name.to_string(),
             config_filenames: config_filenames.to_vec(),
             content: content.to_string(),
             root_dir: dir.to_string(),
+            resource_name: base_name.clone(),
+            base_name,
             file_manger: filesystem::FileSystemManager::new(
                 config_path.to_str().unwrap().to_string(),
             ),
         }
     }
 
+    // Wraps a ConfigMap built from the `config/` directory of template directory `name`.
+    pub fn from_built(name: &String, config_map: &BuiltConfigMap, dir: &String) -> Config {
+        let mut config = Config::new(name, &config_map.keys, &config_map.content, dir);
+        config.resource_name = config_map.name.clone();
+        config.base_name = config_map.base_name.clone();
+        config
+    }
+
+    // Whether the service's templates must be pointed at a hash-suffixed name.
+    pub fn is_renamed(&self) -> bool {
+        self.resource_name != self.base_name
+    }
+
     pub fn create_config_map(&self) -> Result<(), String> {
         Ok(())
     }
@@ -0,0 +1,532 @@
+//! Revision history of an environment's workloads, for rolling back.
+//!
+//! Before deploy applies anything, it saves the live spec of every Deployment,
+//! StatefulSet and DaemonSet it is about to apply as a revision: a ConfigMap named
+//! `sailr-<env>-revision-<N>` in the environment's namespace. A failed rollout
+//! re-applies that revision, and `sailr rollback` can re-apply any revision kept.
+
+use std::fmt::Debug;
+
+use k8s_openapi::{
+    api::{
+        apps::v1::{DaemonSet, Deployment, StatefulSet},
+        core::v1::{ConfigMap, Namespace},
+    },
+    NamespaceResourceScope,
+};
+use kube::{
+    api::{DeleteParams, ListParams, Patch, PatchParams},
+    Api, Client, Resource,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    deployment::k8sm8::{multidoc_deserialize, AppliedResource},
+    errors::DeployError,
+    templates::labels::{ENVIRONMENT_LABEL, MANAGED_BY, MANAGED_BY_LABEL},
+    LOGGER,
+};
+
+/// Label holding the revision number of a history ConfigMap.
+pub const REVISION_LABEL: &str = "sailr.dev/revision";
+
+/// Annotation holding when a revision was recorded.
+pub const RECORDED_AT_ANNOTATION: &str = "sailr.dev/recorded-at";
+
+/// How many revisions are kept per environment.
+pub const HISTORY_LIMIT: usize = 10;
+
+/// Kinds whose live spec is recorded and restored.
+pub const ROLLBACK_KINDS: &[&str] = &["Deployment", "StatefulSet", "DaemonSet"];
+
+// Annotations the cluster maintains, which must not be applied back.
+const SERVER_ANNOTATIONS: &[&str] = &[
+    "deployment.kubernetes.io/revision",
+    "deprecated.daemonset.template.generation",
+    "kubectl.kubernetes.io/last-applied-configuration",
+];
+
+/// The live spec of one workload when a revision was recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkloadSnapshot {
+    pub workload: AppliedResource,
+    /// The object as it can be applied again, or `None` when it did not exist yet.
+    pub manifest: Option<Value>,
+}
+
+/// The workloads of an environment as they were before a deploy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revision {
+    pub number: u32,
+    pub recorded_at: String,
+    pub workloads: Vec<WorkloadSnapshot>,
+}
+
+impl Revision {
+    pub fn config_map_name(env_name: &str, number: u32) -> String {
+        format!("sailr-{}-revision-{}", env_name, number)
+    }
+
+    // The ConfigMap the revision is stored in. Each workload is one JSON entry, keyed
+    // by kind, namespace and name; workloads that did not exist are stored as `null`.
+    pub fn to_config_map(&self, env_name: &str, namespace: &str) -> Value {
+        let data = self
+            .workloads
+            .iter()
+            .map(|snapshot| {
+                (
+                    snapshot_key(&snapshot.workload),
+                    Value::String(snapshot.manifest.clone().unwrap_or(Value::Null).to_string()),
+                )
+            })
+            .collect::<Map<_, _>>();
+        json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {
+                "name": Self::config_map_name(env_name, self.number),
+                "namespace": namespace,
+                "labels": {
+                    MANAGED_BY_LABEL: MANAGED_BY,
+                    ENVIRONMENT_LABEL: env_name,
+                    REVISION_LABEL: self.number.to_string(),
+                },
+                "annotations": { RECORDED_AT_ANNOTATION: self.recorded_at },
+            },
+            "data": data,
+        })
+    }
+
+    pub fn from_config_map(config_map: &ConfigMap) -> Result<Revision, String> {
+        let name = config_map.metadata.name.as_deref().unwrap_or_default();
+        let number = config_map
+            .metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(REVISION_LABEL))
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| format!("ConfigMap {} has no valid {} label", name, REVISION_LABEL))?;
+        let recorded_at = config_map
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(RECORDED_AT_ANNOTATION))
+            .cloned()
+            .unwrap_or_default();
+
+        let mut workloads = Vec::new();
+        for (key, content) in config_map.data.iter().flatten() {
+            let workload = parse_snapshot_key(key)
+                .ok_or_else(|| format!("ConfigMap {} has an invalid entry '{}'", name, key))?;
+            let manifest = serde_json::from_str::<Value>(content)
+                .map_err(|e| format!("ConfigMap {} entry '{}': {}", name, key, e))?;
+            workloads.push(WorkloadSnapshot {
+                workload,
+                manifest: (!manifest.is_null()).then_some(manifest),
+            });
+        }
+        Ok(Revision {
+            number,
+            recorded_at,
+            workloads,
+        })
+    }
+
+    /// One line per workload, for listings: its images, or that it did not exist.
+    pub fn describe(&self) -> Vec<String> {
+        self.workloads
+            .iter()
+            .map(|snapshot| {
+                let workload = format!(
+                    "{}/{} in {}",
+                    snapshot.workload.kind.to_lowercase(),
+                    snapshot.workload.name,
+                    snapshot.workload.namespace
+                );
+                match &snapshot.manifest {
+                    Some(manifest) => format!("{} ({})", workload, images(manifest).join(", ")),
+                    None => format!("{} (did not exist)", workload),
+                }
+            })
+            .collect()
+    }
+}
+
+// `deployment.shop.api`: kinds, namespaces and names never contain '.' in the first
+// two, so the last part may.
+fn snapshot_key(workload: &AppliedResource) -> String {
+    format!(
+        "{}.{}.{}",
+        workload.kind.to_lowercase(),
+        workload.namespace,
+        workload.name
+    )
+}
+
+fn parse_snapshot_key(key: &str) -> Option<AppliedResource> {
+    let mut parts = key.splitn(3, '.');
+    let kind = parts.next()?;
+    let kind = ROLLBACK_KINDS
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(kind))?;
+    Some(AppliedResource {
+        kind: kind.to_string(),
+        namespace: parts.next()?.to_string(),
+        name: parts.next()?.to_string(),
+    })
+}
+
+// The container images of a workload manifest.
+fn images(manifest: &Value) -> Vec<String> {
+    manifest["spec"]["template"]["spec"]["containers"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|container| container["image"].as_str().map(str::to_string))
+        .collect()
+}
+
+// Reduces a live object to what can be applied again: no status and none of the
+// metadata the cluster maintains.
+pub fn applicable_manifest(mut object: Value) -> Value {
+    if let Some(object) = object.as_object_mut() {
+        object.remove("status");
+        if let Some(metadata) = object.get_mut("metadata").and_then(Value::as_object_mut) {
+            metadata.retain(|key, _| {
+                ["name", "namespace", "labels", "annotations"].contains(&key.as_str())
+            });
+            if let Some(annotations) = metadata
+                .get_mut("annotations")
+                .and_then(Value::as_object_mut)
+            {
+                annotations.retain(|key, _| !SERVER_ANNOTATIONS.contains(&key.as_str()));
+                if annotations.is_empty() {
+                    metadata.remove("annotations");
+                }
+            }
+        }
+    }
+    object
+}
+
+// The workloads among the documents of `yaml` whose spec can be recorded.
+pub async fn rollback_workloads(yaml: &str) -> Result<Vec<AppliedResource>, DeployError> {
+    let mut workloads = Vec::new();
+    for document in multidoc_deserialize(yaml).await? {
+        let Some(kind) = document["kind"].as_str() else {
+            continue;
+        };
+        if !ROLLBACK_KINDS.contains(&kind) {
+            continue;
+        }
+        let Some(name) = document["metadata"]["name"].as_str() else {
+            continue;
+        };
+        workloads.push(AppliedResource {
+            kind: kind.to_string(),
+            namespace: document["metadata"]["namespace"]
+                .as_str()
+                .unwrap_or("default")
+                .to_string(),
+            name: name.to_string(),
+        });
+    }
+    Ok(workloads)
+}
+
+fn history_error(e: impl std::fmt::Display) -> DeployError {
+    DeployError::History(e.to_string())
+}
+
+async fn get_json<K>(
+    client: Client,
+    namespace: &str,
+    name: &str,
+) -> Result<Option<Value>, DeployError>
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + Serialize
+        + Debug,
+{
+    let api: Api<K> = Api::namespaced(client, namespace);
+    let Some(object) = api.get_opt(name).await.map_err(history_error)? else {
+        return Ok(None);
+    };
+    let mut value = serde_json::to_value(object).map_err(history_error)?;
+    // Typed objects leave out their type, which apply needs.
+    if let Some(object) = value.as_object_mut() {
+        object.insert("apiVersion".to_string(), json!(K::api_version(&())));
+        object.insert("kind".to_string(), json!(K::kind(&())));
+    }
+    Ok(Some(applicable_manifest(value)))
+}
+
+async fn apply_json<K>(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    manifest: &Value,
+) -> Result<(), DeployError>
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + Debug,
+{
+    let api: Api<K> = Api::namespaced(client, namespace);
+    api.patch(
+        name,
+        &PatchParams::apply("sailr").force(),
+        &Patch::Apply(manifest),
+    )
+    .await
+    .map_err(|e| {
+        DeployError::ManifestApplicationFailed(format!("Failed to restore {}: {}", name, e))
+    })?;
+    Ok(())
+}
+
+// The live, applicable spec of `workload`, or `None` when it does not exist.
+async fn live_manifest(
+    client: Client,
+    workload: &AppliedResource,
+) -> Result<Option<Value>, DeployError> {
+    let (namespace, name) = (workload.namespace.as_str(), workload.name.as_str());
+    match workload.kind.as_str() {
+        "Deployment" => get_json::<Deployment>(client, namespace, name).await,
+        "StatefulSet" => get_json::<StatefulSet>(client, namespace, name).await,
+        "DaemonSet" => get_json::<DaemonSet>(client, namespace, name).await,
+        kind => Err(DeployError::History(format!("cannot record a {}", kind))),
+    }
+}
+
+// Creates `namespace` if it does not exist yet: the environment's own namespace, where
+// sailr keeps its records, may not hold any service.
+pub(crate) async fn ensure_namespace(client: Client, namespace: &str) -> Result<(), DeployError> {
+    let namespaces: Api<Namespace> = Api::all(client);
+    let manifest = json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": { "name": namespace, "labels": { MANAGED_BY_LABEL: MANAGED_BY } },
+    });
+    namespaces
+        .patch(
+            namespace,
+            &PatchParams::apply("sailr"),
+            &Patch::Apply(&manifest),
+        )
+        .await
+        .map_err(|e| {
+            DeployError::ManifestApplicationFailed(format!(
+                "Failed to create namespace {}: {}",
+                namespace, e
+            ))
+        })?;
+    Ok(())
+}
+
+/// Every revision kept for `env_name` in `namespace`, oldest first.
+pub async fn list_revisions(
+    client: Client,
+    env_name: &str,
+    namespace: &str,
+) -> Result<Vec<Revision>, DeployError> {
+    let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let selector = format!("{}={},{}", ENVIRONMENT_LABEL, env_name, REVISION_LABEL);
+    let list = api
+        .list(&ListParams::default().labels(&selector))
+        .await
+        .map_err(history_error)?;
+    let mut revisions = list
+        .items
+        .iter()
+        .map(Revision::from_config_map)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(DeployError::History)?;
+    revisions.sort_by_key(|revision| revision.number);
+    Ok(revisions)
+}
+
+/// Records the live spec of `workloads` as the next revision of `env_name`, kept in
+/// `namespace`, and drops the revisions beyond `HISTORY_LIMIT`.
+pub async fn record_revision(
+    client: Client,
+    env_name: &str,
+    namespace: &str,
+    workloads: &[AppliedResource],
+) -> Result<Revision, DeployError> {
+    let mut workloads = workloads.to_vec();
+    workloads.sort();
+    workloads.dedup();
+    let mut snapshots = Vec::new();
+    for workload in workloads {
+        let manifest = live_manifest(client.clone(), &workload).await?;
+        snapshots.push(WorkloadSnapshot { workload, manifest });
+    }
+
+    let existing = list_revisions(client.clone(), env_name, namespace).await?;
+    let revision = Revision {
+        number: existing.last().map_or(1, |revision| revision.number + 1),
+        recorded_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        workloads: snapshots,
+    };
+
+    ensure_namespace(client.clone(), namespace).await?;
+    let config_maps: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let name = Revision::config_map_name(env_name, revision.number);
+    config_maps
+        .patch(
+            &name,
+            &PatchParams::apply("sailr").force(),
+            &Patch::Apply(revision.to_config_map(env_name, namespace)),
+        )
+        .await
+        .map_err(history_error)?;
+
+    let stale = (existing.len() + 1).saturating_sub(HISTORY_LIMIT);
+    for old in existing.iter().take(stale) {
+        let name = Revision::config_map_name(env_name, old.number);
+        if let Err(e) = config_maps.delete(&name, &DeleteParams::default()).await {
+            LOGGER.warn(&format!("Failed to delete old revision {}: {}", name, e));
+        }
+    }
+    Ok(revision)
+}
+
+/// Applies the recorded spec of every workload in `revision`. Workloads that did not
+/// exist when it was recorded are left in place. Returns the restored workloads.
+pub async fn restore_revision(
+    client: Client,
+    revision: &Revision,
+) -> Result<Vec<AppliedResource>, DeployError> {
+    let mut restored = Vec::new();
+    for snapshot in &revision.workloads {
+        let workload = &snapshot.workload;
+        let Some(manifest) = &snapshot.manifest else {
+            LOGGER.warn(&format!(
+                "{} did not exist in revision {}; leaving it in place",
+                workload, revision.number
+            ));
+            continue;
+        };
+        let (namespace, name) = (workload.namespace.as_str(), workload.name.as_str());
+        match workload.kind.as_str() {
+            "Deployment" => {
+                apply_json::<Deployment>(client.clone(), namespace, name, manifest).await?
+            }
+            "StatefulSet" => {
+                apply_json::<StatefulSet>(client.clone(), namespace, name, manifest).await?
+            }
+            "DaemonSet" => {
+                apply_json::<DaemonSet>(client.clone(), namespace, name, manifest).await?
+            }
+            _ => continue,
+        }
+        LOGGER.status("Restored", &workload.to_string(), "yellow");
+        restored.push(workload.clone());
+    }
+    Ok(restored)
+}
+
+/// Renders `revisions` as `sailr rollback --list` prints them, newest first.
+pub fn revisions_to_text(revisions: &[Revision]) -> String {
+    let mut text = String::new();
+    for revision in revisions.iter().rev() {
+        text.push_str(&format!(
+            "{:<4} {}\n",
+            revision.number, revision.recorded_at
+        ));
+        for line in revision.describe() {
+            text.push_str(&format!("       {}\n", line));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revisions_round_trip_through_config_maps() {
+        let api = AppliedResource {
+            kind: "Deployment".to_string(),
+            namespace: "shop".to_string(),
+            name: "api.v2".to_string(),
+        };
+        let worker = AppliedResource {
+            kind: "DaemonSet".to_string(),
+            namespace: "shop".to_string(),
+            name: "agent".to_string(),
+        };
+        let revision = Revision {
+            number: 3,
+            recorded_at: "2026-10-17T06:28:24Z".to_string(),
+            workloads: vec![
+                WorkloadSnapshot {
+                    workload: worker.clone(),
+                    manifest: None,
+                },
+                WorkloadSnapshot {
+                    workload: api.clone(),
+                    manifest: Some(json!({
+                        "apiVersion": "apps/v1",
+                        "kind": "Deployment",
+                        "metadata": { "name": "api.v2", "namespace": "shop" },
+                        "spec": { "template": { "spec": { "containers": [
+                            { "name": "api", "image": "ghcr.io/acme/api:1.2.0" },
+                        ] } } },
+                    })),
+                },
+            ],
+        };
+
+        let config_map = revision.to_config_map("prod", "prod");
+        assert_eq!(config_map["metadata"]["name"], "sailr-prod-revision-3");
+        assert_eq!(config_map["metadata"]["labels"][REVISION_LABEL], "3");
+        assert_eq!(config_map["data"]["daemonset.shop.agent"], "null");
+
+        let parsed: ConfigMap = serde_json::from_value(config_map).unwrap();
+        assert_eq!(Revision::from_config_map(&parsed).unwrap(), revision);
+        assert_eq!(
+            revision.describe(),
+            vec![
+                "daemonset/agent in shop (did not exist)",
+                "deployment/api.v2 in shop (ghcr.io/acme/api:1.2.0)",
+            ]
+        );
+    }
+
+    #[test]
+    fn live_objects_lose_what_the_cluster_maintains() {
+        let live = json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {
+                "name": "api",
+                "namespace": "shop",
+                "uid": "1234",
+                "resourceVersion": "42",
+                "generation": 7,
+                "managedFields": [],
+                "labels": { "app": "api" },
+                "annotations": { "deployment.kubernetes.io/revision": "7" },
+            },
+            "spec": { "replicas": 2 },
+            "status": { "replicas": 2 },
+        });
+
+        assert_eq!(
+            applicable_manifest(live),
+            json!({
+                "apiVersion": "apps/v1",
+                "kind": "Deployment",
+                "metadata": { "name": "api", "namespace": "shop", "labels": { "app": "api" } },
+                "spec": { "replicas": 2 },
+            })
+        );
+    }
+}
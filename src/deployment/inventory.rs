@@ -0,0 +1,430 @@
+//! The inventory of objects sailr applied for an environment, for pruning.
+//!
+//! Every deploy records the objects its generated manifests define in the ConfigMap
+//! `sailr-<env>-inventory`, in the environment's namespace. Objects in the inventory
+//! that are no longer generated are stale: `deploy --prune` deletes them, as long as
+//! they still carry the environment's ownership labels.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    future::Future,
+};
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{DeleteParams, Patch, PatchParams},
+    core::GroupVersionKind,
+    Api, Client, Discovery,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    deployment::{history, k8sm8::dynamic_api, k8sm8::multidoc_deserialize},
+    errors::DeployError,
+    templates::labels::{ENVIRONMENT_LABEL, MANAGED_BY, MANAGED_BY_LABEL},
+    LOGGER,
+};
+
+/// Key of the inventory ConfigMap holding the objects, as a JSON array.
+pub const INVENTORY_KEY: &str = "objects.json";
+
+// Kinds that are never pruned. Deleting a namespace deletes everything in it,
+// including what other environments or tools put there.
+const UNPRUNABLE_KINDS: &[&str] = &["Namespace", "CustomResourceDefinition"];
+
+/// One object of a generated manifest.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryEntry {
+    pub api_version: String,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+impl InventoryEntry {
+    // Identifies the object regardless of the version it was applied with, so moving a
+    // manifest to a newer apiVersion does not make the object stale.
+    fn identity(&self) -> (&str, &str, &str, &str) {
+        let group = self
+            .api_version
+            .rsplit_once('/')
+            .map_or("", |(group, _)| group);
+        (
+            group,
+            &self.kind,
+            self.namespace.as_deref().unwrap_or("default"),
+            &self.name,
+        )
+    }
+}
+
+impl fmt::Display for InventoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind.to_lowercase(), self.name)?;
+        if let Some(namespace) = &self.namespace {
+            write!(f, " in {}", namespace)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn config_map_name(env_name: &str) -> String {
+    format!("sailr-{}-inventory", env_name)
+}
+
+/// The objects the documents of `yaml` define. Documents without a kind or name are
+/// skipped; applying them fails on its own.
+pub async fn entries_in_yaml(yaml: &str) -> Result<Vec<InventoryEntry>, DeployError> {
+    let mut entries = Vec::new();
+    for document in multidoc_deserialize(yaml).await? {
+        let (Some(api_version), Some(kind), Some(name)) = (
+            document["apiVersion"].as_str(),
+            document["kind"].as_str(),
+            document["metadata"]["name"].as_str(),
+        ) else {
+            continue;
+        };
+        entries.push(InventoryEntry {
+            api_version: api_version.to_string(),
+            kind: kind.to_string(),
+            namespace: document["metadata"]["namespace"]
+                .as_str()
+                .map(str::to_string),
+            name: name.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// The entries of `previous` that `current` no longer defines and that may be pruned.
+pub fn stale_entries(
+    previous: &[InventoryEntry],
+    current: &[InventoryEntry],
+) -> Vec<InventoryEntry> {
+    let current = current
+        .iter()
+        .map(InventoryEntry::identity)
+        .collect::<BTreeSet<_>>();
+    previous
+        .iter()
+        .filter(|entry| !UNPRUNABLE_KINDS.contains(&entry.kind.as_str()))
+        .filter(|entry| !current.contains(&entry.identity()))
+        .cloned()
+        .collect()
+}
+
+/// The entries of `previous` that `current` no longer defines and whose object still
+/// exists, with the labels in `live`, and carries `env_name`'s ownership labels.
+pub fn prunable_entries(
+    previous: &[InventoryEntry],
+    current: &[InventoryEntry],
+    env_name: &str,
+    live: &BTreeMap<InventoryEntry, BTreeMap<String, String>>,
+) -> Vec<InventoryEntry> {
+    stale_entries(previous, current)
+        .into_iter()
+        .filter(|entry| {
+            live.get(entry).is_some_and(|labels| {
+                labels.get(MANAGED_BY_LABEL).map(String::as_str) == Some(MANAGED_BY)
+                    && labels.get(ENVIRONMENT_LABEL).map(String::as_str) == Some(env_name)
+            })
+        })
+        .collect()
+}
+
+/// `entries` with each object listed once, keeping its first entry, sorted.
+pub fn merge(entries: impl IntoIterator<Item = InventoryEntry>) -> Vec<InventoryEntry> {
+    let mut merged: Vec<InventoryEntry> = Vec::new();
+    for entry in entries {
+        if !merged
+            .iter()
+            .any(|merged| merged.identity() == entry.identity())
+        {
+            merged.push(entry);
+        }
+    }
+    merged.sort();
+    merged
+}
+
+fn inventory_error(e: impl fmt::Display) -> DeployError {
+    DeployError::Inventory(e.to_string())
+}
+
+/// The inventory recorded for `env_name`, empty when there is none yet.
+pub async fn read_inventory(
+    client: Client,
+    env_name: &str,
+    namespace: &str,
+) -> Result<Vec<InventoryEntry>, DeployError> {
+    let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let Some(config_map) = api
+        .get_opt(&config_map_name(env_name))
+        .await
+        .map_err(inventory_error)?
+    else {
+        return Ok(Vec::new());
+    };
+    match config_map
+        .data
+        .as_ref()
+        .and_then(|data| data.get(INVENTORY_KEY))
+    {
+        Some(objects) => serde_json::from_str(objects).map_err(inventory_error),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Replaces the inventory of `env_name` with `entries`.
+pub async fn write_inventory(
+    client: Client,
+    env_name: &str,
+    namespace: &str,
+    entries: &[InventoryEntry],
+) -> Result<(), DeployError> {
+    history::ensure_namespace(client.clone(), namespace).await?;
+    let objects = serde_json::to_string(entries).map_err(inventory_error)?;
+    let name = config_map_name(env_name);
+    let config_map = json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": {
+            "name": name,
+            "namespace": namespace,
+            "labels": {
+                MANAGED_BY_LABEL: MANAGED_BY,
+                ENVIRONMENT_LABEL: env_name,
+            },
+        },
+        "data": { INVENTORY_KEY: objects },
+    });
+    let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    api.patch(
+        &name,
+        &PatchParams::apply("sailr").force(),
+        &Patch::Apply(config_map),
+    )
+    .await
+    .map_err(inventory_error)?;
+    Ok(())
+}
+
+// The API of `entry`'s kind and namespace, or `None` when the cluster no longer
+// serves it.
+fn api_for(
+    client: Client,
+    discovery: &Discovery,
+    entry: &InventoryEntry,
+) -> Option<Api<kube::api::DynamicObject>> {
+    let (group, version) = entry
+        .api_version
+        .rsplit_once('/')
+        .unwrap_or(("", entry.api_version.as_str()));
+    let gvk = GroupVersionKind::gvk(group, version, &entry.kind);
+    let (resource, capabilities) = discovery.resolve_gvk(&gvk)?;
+    let namespace = entry.namespace.as_deref().unwrap_or("default");
+    Some(dynamic_api(
+        resource,
+        capabilities,
+        client,
+        Some(namespace),
+        false,
+    ))
+}
+
+/// The stale objects of `env_name` that still exist and still carry the environment's
+/// ownership labels, compared with the `current` generated objects.
+pub async fn prunable_objects(
+    client: Client,
+    discovery: &Discovery,
+    env_name: &str,
+    namespace: &str,
+    current: &[InventoryEntry],
+) -> Result<Vec<InventoryEntry>, DeployError> {
+    let previous = read_inventory(client.clone(), env_name, namespace).await?;
+    let mut live = BTreeMap::new();
+    for entry in stale_entries(&previous, current) {
+        let Some(api) = api_for(client.clone(), discovery, &entry) else {
+            continue;
+        };
+        let Some(object) = api.get_opt(&entry.name).await.map_err(inventory_error)? else {
+            continue;
+        };
+        live.insert(entry, object.metadata.labels.unwrap_or_default());
+    }
+    Ok(prunable_entries(&previous, current, env_name, &live))
+}
+
+/// Deletes each of `stale` with `delete`. Returns how many were deleted and the
+/// inventory to record: `current` along with every object that could not be deleted,
+/// so a later prune tries it again.
+pub async fn delete_stale<F, Fut>(
+    current: &[InventoryEntry],
+    stale: Vec<InventoryEntry>,
+    mut delete: F,
+) -> (usize, Vec<InventoryEntry>)
+where
+    F: FnMut(InventoryEntry) -> Fut,
+    Fut: Future<Output = Result<(), DeployError>>,
+{
+    let mut deleted = 0;
+    let mut kept = Vec::new();
+    for entry in stale {
+        match delete(entry.clone()).await {
+            Ok(()) => {
+                LOGGER.status("Pruned", &entry.to_string(), "red");
+                deleted += 1;
+            }
+            Err(e) => {
+                LOGGER.warn(&e.to_string());
+                kept.push(entry);
+            }
+        }
+    }
+    (deleted, merge(current.iter().cloned().chain(kept)))
+}
+
+/// Deletes `entry`, letting the cluster remove what it owns, such as the pods of a
+/// Deployment, in the background.
+pub async fn delete_object(
+    client: Client,
+    discovery: &Discovery,
+    entry: &InventoryEntry,
+) -> Result<(), DeployError> {
+    let Some(api) = api_for(client, discovery, entry) else {
+        return Ok(());
+    };
+    api.delete(&entry.name, &DeleteParams::background())
+        .await
+        .map_err(|e| inventory_error(format!("failed to delete {}: {}", entry, e)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(api_version: &str, kind: &str, name: &str) -> InventoryEntry {
+        InventoryEntry {
+            api_version: api_version.to_string(),
+            kind: kind.to_string(),
+            namespace: Some("shop".to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn entries_are_read_from_generated_documents() {
+        let yaml = r#"
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: api
+  namespace: shop
+---
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: shop
+"#;
+        let entries = entries_in_yaml(yaml).await.unwrap();
+        assert_eq!(entries[0], entry("apps/v1", "Deployment", "api"));
+        assert_eq!(entries[1].namespace, None);
+        assert_eq!(entries[1].to_string(), "namespace/shop");
+    }
+
+    #[test]
+    fn objects_no_longer_generated_are_stale() {
+        let previous = vec![
+            entry("apps/v1", "Deployment", "api"),
+            entry("v1", "ConfigMap", "api-config"),
+            entry("autoscaling/v2beta2", "HorizontalPodAutoscaler", "api"),
+            entry("v1", "Namespace", "shop"),
+        ];
+        let current = vec![
+            entry("apps/v1", "Deployment", "api"),
+            entry("autoscaling/v2", "HorizontalPodAutoscaler", "api"),
+        ];
+
+        assert_eq!(
+            stale_entries(&previous, &current),
+            vec![entry("v1", "ConfigMap", "api-config")]
+        );
+        let merged = merge(current.into_iter().chain(previous));
+        assert_eq!(merged.len(), 4);
+        assert!(merged.contains(&entry("autoscaling/v2", "HorizontalPodAutoscaler", "api")));
+    }
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn only_objects_owned_by_the_environment_are_prunable() {
+        let previous = vec![
+            entry("apps/v1", "Deployment", "api"),
+            entry("v1", "ConfigMap", "owned"),
+            entry("v1", "ConfigMap", "unmanaged"),
+            entry("v1", "ConfigMap", "production"),
+            entry("v1", "ConfigMap", "gone"),
+        ];
+        let current = vec![entry("apps/v1", "Deployment", "api")];
+        let owned = [
+            (MANAGED_BY_LABEL, MANAGED_BY),
+            (ENVIRONMENT_LABEL, "staging"),
+        ];
+        let live = BTreeMap::from([
+            (entry("apps/v1", "Deployment", "api"), labels(&owned)),
+            (entry("v1", "ConfigMap", "owned"), labels(&owned)),
+            (
+                entry("v1", "ConfigMap", "unmanaged"),
+                labels(&[(ENVIRONMENT_LABEL, "staging")]),
+            ),
+            (
+                entry("v1", "ConfigMap", "production"),
+                labels(&[
+                    (MANAGED_BY_LABEL, MANAGED_BY),
+                    (ENVIRONMENT_LABEL, "production"),
+                ]),
+            ),
+        ]);
+
+        assert_eq!(
+            prunable_entries(&previous, &current, "staging", &live),
+            vec![entry("v1", "ConfigMap", "owned")]
+        );
+    }
+
+    #[tokio::test]
+    async fn objects_that_fail_to_delete_stay_in_the_inventory() {
+        let current = vec![entry("apps/v1", "Deployment", "api")];
+        let stale = vec![
+            entry("v1", "ConfigMap", "deleted"),
+            entry("v1", "Secret", "protected"),
+        ];
+
+        let (deleted, inventory) = delete_stale(&current, stale, |entry| async move {
+            match entry.kind.as_str() {
+                "Secret" => Err(inventory_error("forbidden")),
+                _ => Ok(()),
+            }
+        })
+        .await;
+
+        assert_eq!(deleted, 1);
+        assert_eq!(
+            inventory,
+            vec![
+                entry("apps/v1", "Deployment", "api"),
+                entry("v1", "Secret", "protected"),
+            ]
+        );
+    }
+}
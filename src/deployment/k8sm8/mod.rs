@@ -18,12 +18,14 @@ use std::time::Duration;
 
 use anyhow::Result;
 
-use diffy::{create_patch, PatchFormatter};
+use diffy::{DiffOptions, PatchFormatter};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
 use k8s_openapi::serde_json;
 use k8s_openapi::{self};
 use kube::api::{ListParams, Patch, PatchParams};
 use kube::core::{DynamicObject, GroupVersionKind};
 use kube::discovery::{ApiCapabilities, ApiResource, Scope};
+use kube::runtime::wait::{await_condition, conditions};
 use kube::{Api, Client, Discovery};
 
 use serde::Deserialize;
@@ -127,30 +129,68 @@ pub async fn get_cluster_resources(
     resources
 }
 
+/// An object `apply_yaml` applied.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AppliedResource {
+    pub kind: String,
+    pub namespace: String,
+    pub name: String,
+}
+
+impl std::fmt::Display for AppliedResource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} in {}",
+            self.kind.to_lowercase(),
+            self.name,
+            self.namespace
+        )
+    }
+}
+
 pub async fn apply(
     path: Option<PathBuf>,
     client: Client,
     discovery: &Discovery,
-) -> Result<(String, String), KubeError> {
-    let ssapply = PatchParams::apply("sailr").force();
+) -> Result<Vec<AppliedResource>, KubeError> {
     let pth = path.clone().expect("path is required");
-    let mut name: String = "".to_string();
-    let mut namespace: String = "".to_string();
     let yaml = std::fs::read_to_string(&pth)
         .map_err(|e| KubeError::UnexpectedError(format!("Failed reading from file: {}", e)))?;
-    for doc in multidoc_deserialize(&yaml).await.map_err(|e| {
+    apply_yaml(&yaml, client, discovery).await
+}
+
+/// Server-side applies every document in `yaml`. Returns the objects that were applied.
+pub async fn apply_yaml(
+    yaml: &str,
+    client: Client,
+    discovery: &Discovery,
+) -> Result<Vec<AppliedResource>, KubeError> {
+    let documents = multidoc_deserialize(yaml).await.map_err(|e| {
         KubeError::UnexpectedError(format!("Multidoc Deserialization failed : {}", e))
-    })? {
-        let obj: DynamicObject = serde_yaml::from_value(doc).map_err(|e| {
+    })?;
+    apply_documents(documents, client, discovery).await
+}
+
+/// Server-side applies `documents` in the order given. Returns the objects that were
+/// applied.
+pub async fn apply_documents(
+    documents: Vec<serde_yaml::Value>,
+    client: Client,
+    discovery: &Discovery,
+) -> Result<Vec<AppliedResource>, KubeError> {
+    let ssapply = PatchParams::apply("sailr").force();
+    let mut applied = Vec::new();
+    for doc in documents {
+        let mut obj: DynamicObject = serde_yaml::from_value(doc).map_err(|e| {
             KubeError::UnexpectedError(format!("Yaml Deserialization failed: {}", e))
         })?;
-        namespace = obj
+        let namespace = obj
             .metadata
             .namespace
             .as_deref()
             .unwrap_or("default")
             .to_string();
-        name = obj.metadata.name.clone().unwrap_or_default();
         let gvk = if let Some(tm) = &obj.types {
             GroupVersionKind::try_from(tm).map_err(|e| {
                 KubeError::ManifestApplicationFailed(format!(
@@ -169,6 +209,10 @@ pub async fn apply(
         let name = &obj.metadata.name;
         let res = discovery.resolve_gvk(&gvk);
         if let Some((ar, caps)) = res {
+            // Generation adds a namespace to kinds it does not know to be cluster-scoped.
+            if caps.scope == Scope::Cluster {
+                obj.metadata.namespace = None;
+            }
             let api = dynamic_api(ar, caps, client.clone(), Some(&namespace), false);
             let data: serde_json::Value = serde_json::to_value(&obj).map_err(|e| {
                 KubeError::UnexpectedError(format!("Json Serialization failed: {}", e))
@@ -184,12 +228,64 @@ pub async fn apply(
                 gvk.kind,
                 name.clone().unwrap_or_default()
             ));
+            applied.push(AppliedResource {
+                kind: gvk.kind.clone(),
+                namespace,
+                name: name.clone().unwrap_or_default(),
+            });
         } else {
-            LOGGER.error(&format!("Cannot apply document for unknown {:?}", gvk));
+            return Err(KubeError::UnknownKind {
+                api_version: gvk.api_version(),
+                kind: gvk.kind,
+                name: name.clone().unwrap_or_default(),
+            });
         }
     }
 
-    Ok((namespace, name))
+    Ok(applied)
+}
+
+/// Kind of the objects that define custom resources.
+pub const CRD_KIND: &str = "CustomResourceDefinition";
+
+// How long a CustomResourceDefinition may take to be established once applied.
+const CRD_ESTABLISHED_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Waits for the CustomResourceDefinitions among `applied` to be established and runs
+/// discovery again, so the kinds they define can be applied. `None` when `applied`
+/// holds no CustomResourceDefinition.
+pub async fn rediscover_after_crds(
+    client: Client,
+    applied: &[AppliedResource],
+) -> Result<Option<Discovery>, KubeError> {
+    let crds = applied
+        .iter()
+        .filter(|resource| resource.kind == CRD_KIND)
+        .collect::<Vec<_>>();
+    if crds.is_empty() {
+        return Ok(None);
+    }
+
+    let api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    for crd in crds {
+        let established = await_condition(api.clone(), &crd.name, conditions::is_crd_established());
+        match tokio::time::timeout(CRD_ESTABLISHED_TIMEOUT, established).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(KubeError::KubernetesApiError(e.to_string())),
+            Err(_) => {
+                return Err(KubeError::CrdNotEstablished {
+                    name: crd.name.clone(),
+                    seconds: CRD_ESTABLISHED_TIMEOUT.as_secs(),
+                })
+            }
+        }
+    }
+
+    Discovery::new(client)
+        .run()
+        .await
+        .map(Some)
+        .map_err(|e| KubeError::DiscoveryInitializationFailed(e.to_string()))
 }
 
 /// Compares two JSON representations of Kubernetes resources and returns a diff string if they differ.
@@ -199,21 +295,34 @@ pub fn diff_resources(current: &Value, new: &Value) -> Option<String> {
     let current_str = serde_json::to_string_pretty(current).ok()?;
     let new_str = serde_json::to_string_pretty(new).ok()?;
 
+    diff_text("original", "modified", &current_str, &new_str)
+}
+
+/// Produces a unified diff between two texts, labelled with the given file names.
+/// Returns `None` if they are identical.
+pub fn diff_text(
+    original_name: &str,
+    modified_name: &str,
+    current: &str,
+    new: &str,
+) -> Option<String> {
     // If both representations are identical, no diff is needed
-    if current_str == new_str {
+    if current == new {
         return None;
     }
 
     // Create a diff patch between the current and new states
-    let patch = create_patch(&current_str, &new_str);
-    let diff = PatchFormatter::new();
-
-    let res = diff.fmt_patch(&patch).to_string();
+    let mut options = DiffOptions::new();
+    options
+        .set_original_filename(original_name.to_string())
+        .set_modified_filename(modified_name.to_string());
+    let patch = options.create_patch(current, new);
+    let diff = PatchFormatter::new().fmt_patch(&patch).to_string();
 
-    Some(res)
+    Some(diff)
 }
 
-fn dynamic_api(
+pub(crate) fn dynamic_api(
     ar: ApiResource,
     caps: ApiCapabilities,
     client: Client,
@@ -1,28 +1,371 @@
+pub mod history;
+pub mod inventory;
 pub mod k8sm8;
+pub mod order;
+pub mod rollout;
 use crate::deployment::k8sm8::deployments::delete_deployment;
-use crate::deployment::k8sm8::multidoc_deserialize;
+use crate::deployment::k8sm8::{multidoc_deserialize, AppliedResource};
 use crate::environment::{CommandSpec, Environment, Service};
 use crate::{cli::DeploymentStrategy, deployment::k8sm8::daemonsets::delete_daemonset};
 use anyhow::Result;
+use futures::StreamExt;
 use kube::core::DynamicObject;
+use std::collections::BTreeMap;
 use std::fs;
+use std::future::Future;
 use std::path::Path;
-use std::process::Command;
+use std::time::Duration;
 use walkdir::WalkDir;
 
-use crate::{errors::DeployError, LOGGER};
+use crate::secrets::{DecryptedSecrets, SecretKey, SecretStore};
+use crate::templates::engine::{TemplateContext, TemplateEngine};
+use crate::templates::namespaces::{environment_namespace, NAMESPACES_FILE};
+use crate::{
+    errors::{DeployError, TemplateError},
+    LOGGER,
+};
 
-/// Applies all valid Kubernetes YAML manifests found recursively in a given path.
-///
-/// This function is non-recursive and uses `walkdir` for efficient traversal.
+/// How many services `deploy` applies at the same time unless told otherwise.
+pub const DEFAULT_MAX_PARALLEL: usize = 4;
+
+/// Options controlling how `deploy` applies an environment.
+#[derive(Debug, Clone)]
+pub struct DeployOptions {
+    pub strategy: DeploymentStrategy,
+    /// How long to wait for the applied Deployments, StatefulSets, DaemonSets and Jobs
+    /// to roll out. `None` returns as soon as everything is applied.
+    pub rollout_timeout: Option<Duration>,
+    /// Re-apply the workloads as they were before the deploy when they fail to roll
+    /// out. Has no effect without a `rollout_timeout`.
+    pub rollback_on_failure: bool,
+    /// Delete the objects earlier deploys applied that are no longer generated, once
+    /// everything else has rolled out.
+    pub prune: bool,
+    /// How many services of a stage are applied at the same time.
+    pub max_parallel: usize,
+}
+
+impl Default for DeployOptions {
+    fn default() -> Self {
+        DeployOptions {
+            strategy: DeploymentStrategy::Rolling,
+            rollout_timeout: Some(rollout::DEFAULT_ROLLOUT_TIMEOUT),
+            rollback_on_failure: true,
+            prune: false,
+            max_parallel: DEFAULT_MAX_PARALLEL,
+        }
+    }
+}
+
+// Reads the documents of every manifest under `path`, in file name order, with the
+// encrypted secret values injected. Secret values only ever exist in memory; the files
+// on disk are left as they are.
+async fn read_documents(
+    path: &Path,
+    secrets: &mut DecryptedSecrets,
+) -> Result<Vec<serde_yaml::Value>, DeployError> {
+    let mut documents = Vec::new();
+    let walker = WalkDir::new(path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok());
+
+    for entry in walker {
+        let file_path = entry.path();
+        if file_path.is_file()
+            && (file_path
+                .extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "yml"))
+        {
+            LOGGER.debug(&format!("Reading manifest: {:?}", file_path));
+            let yaml = fs::read_to_string(file_path).map_err(|e| {
+                DeployError::ManifestApplicationFailed(format!(
+                    "Failed reading {}: {}",
+                    file_path.display(),
+                    e
+                ))
+            })?;
+            let yaml = secrets
+                .inject(&yaml)
+                .map_err(|e| DeployError::ManifestApplicationFailed(e.to_string()))?;
+            documents.extend(multidoc_deserialize(&yaml).await?);
+        }
+    }
+
+    Ok(documents)
+}
+
+/// Applies all valid Kubernetes YAML manifests found recursively in a given path,
+/// ordered by kind.
 async fn apply_manifests_from_path(
     path: &Path,
     client: kube::Client,
     discovery: &kube::discovery::Discovery,
-) -> Result<Vec<(String, String)>, DeployError> {
-    let mut applied_manifests = vec![];
-    let walker = WalkDir::new(path).into_iter().filter_map(|e| e.ok());
+    secrets: &mut DecryptedSecrets,
+) -> Result<Vec<AppliedResource>, DeployError> {
+    let mut documents = read_documents(path, secrets).await?;
+    order::sort_by_kind(&mut documents);
+    Ok(k8sm8::apply_documents(documents, client, discovery).await?)
+}
+
+// The documents of one service that are applied in a stage. `service` is `None` for
+// layouts that are not grouped by service.
+struct StageGroup<'a> {
+    service: Option<&'a Service>,
+    documents: Vec<serde_yaml::Value>,
+}
+
+// A stage is keyed by the level of its services and the wave of its documents.
+type Stages<'a> = BTreeMap<(usize, i32), Vec<StageGroup<'a>>>;
+
+// Groups the generated documents of every service of `env` into stages.
+async fn plan_stages<'a>(
+    env: &'a Environment,
+    path: &Path,
+    secrets: &mut DecryptedSecrets,
+) -> Result<Stages<'a>, DeployError> {
+    let levels = order::service_levels(&env.services)?;
+    let mut stages: Stages = BTreeMap::new();
+
+    for service in &env.services {
+        let service_path = path.join(service.get_path());
+        if !service_path.exists() {
+            LOGGER.warn(&format!(
+                "Generated manifests not found for service '{}': {:?}",
+                service.name, service_path
+            ));
+            continue;
+        }
 
+        // A service without documents still gets a stage, for its hooks.
+        let mut waves: BTreeMap<i32, Vec<serde_yaml::Value>> = BTreeMap::from([(0, Vec::new())]);
+        for document in read_documents(&service_path, secrets).await? {
+            waves
+                .entry(order::document_wave(&document)?)
+                .or_default()
+                .push(document);
+        }
+        if waves.len() > 1 && waves[&0].is_empty() {
+            waves.remove(&0);
+        }
+        for (wave, documents) in waves {
+            stages
+                .entry((levels[&service.name], wave))
+                .or_default()
+                .push(StageGroup {
+                    service: Some(service),
+                    documents,
+                });
+        }
+    }
+
+    // Fallback for legacy/generated layouts where manifests are not grouped by service directory.
+    if stages
+        .values()
+        .flatten()
+        .all(|group| group.documents.is_empty())
+    {
+        stages.clear();
+        for document in read_documents(path, secrets).await? {
+            let wave = order::document_wave(&document)?;
+            let groups = stages.entry((0, wave)).or_default();
+            match groups.first_mut() {
+                Some(group) => group.documents.push(document),
+                None => groups.push(StageGroup {
+                    service: None,
+                    documents: vec![document],
+                }),
+            }
+        }
+    }
+
+    Ok(stages)
+}
+
+// Applies the documents of one service in a stage, running its pre_deploy hooks if
+// this is its first stage and its post_deploy hooks if this is its last. Documents
+// after the service's CustomResourceDefinitions are applied once those are established,
+// with discovery run again so their custom resources are known.
+async fn apply_group(
+    group: StageGroup<'_>,
+    first: bool,
+    last: bool,
+    env: &Environment,
+    client: kube::Client,
+    discovery: &kube::discovery::Discovery,
+) -> Result<Vec<AppliedResource>, DeployError> {
+    let StageGroup {
+        service,
+        mut documents,
+    } = group;
+    let hooks = service.and_then(|service| service.hooks.as_ref().map(|hooks| (service, hooks)));
+
+    if let Some((service, hooks)) = hooks {
+        if let (true, Some(pre_deploy)) = (first, &hooks.pre_deploy) {
+            run_service_hooks("pre_deploy", pre_deploy, env, service).await?;
+        }
+    }
+
+    order::sort_by_kind(&mut documents);
+    let crds_end = documents
+        .iter()
+        .rposition(|document| document["kind"].as_str() == Some(k8sm8::CRD_KIND))
+        .map_or(0, |index| index + 1);
+    let rest = documents.split_off(crds_end);
+    let mut applied = k8sm8::apply_documents(documents, client.clone(), discovery).await?;
+    let rediscovered = k8sm8::rediscover_after_crds(client.clone(), &applied).await?;
+    applied.extend(
+        k8sm8::apply_documents(rest, client, rediscovered.as_ref().unwrap_or(discovery)).await?,
+    );
+
+    if let Some((service, hooks)) = hooks {
+        if let (true, Some(post_deploy)) = (last, &hooks.post_deploy) {
+            run_service_hooks("post_deploy", post_deploy, env, service).await?;
+        }
+    }
+    Ok(applied)
+}
+
+// Applies `stages` in order. The services of a stage are independent of each other, so
+// up to `max_parallel` of them are applied at the same time. A failing service does not
+// stop the others already in flight; once they are done, every failure is reported in
+// the order the services are defined in. With a timeout, each stage is only applied
+// once the workloads of the previous one have rolled out, and the workloads of the last
+// stage are waited for too. Discovery is run again after a stage that applied
+// CustomResourceDefinitions, so later stages can use the kinds they define.
+async fn apply_stages(
+    stages: Stages<'_>,
+    env: &Environment,
+    client: kube::Client,
+    discovery: &mut kube::discovery::Discovery,
+    options: &DeployOptions,
+) -> Result<Vec<AppliedResource>, DeployError> {
+    let mut first_stage = BTreeMap::new();
+    let mut last_stage = BTreeMap::new();
+    for (key, groups) in &stages {
+        for service in groups.iter().filter_map(|group| group.service) {
+            first_stage.entry(service.name.as_str()).or_insert(*key);
+            last_stage.insert(service.name.as_str(), *key);
+        }
+    }
+
+    let count = stages.len();
+    let mut applied = Vec::new();
+    for (index, (key, groups)) in stages.into_iter().enumerate() {
+        let stage_discovery = &*discovery;
+        if count > 1 {
+            let mut label = format!("{} of {}", index + 1, count);
+            let services = groups
+                .iter()
+                .filter_map(|group| group.service.map(|service| service.name.as_str()))
+                .collect::<Vec<_>>();
+            if !services.is_empty() {
+                label.push_str(&format!(": {}", services.join(", ")));
+            }
+            if key.1 != 0 {
+                label.push_str(&format!(" (wave {})", key.1));
+            }
+            LOGGER.status("Stage", &label, "cyan");
+        }
+
+        let groups = groups
+            .into_iter()
+            .map(|group| {
+                let name = group
+                    .service
+                    .map_or_else(|| env.name.clone(), |service| service.name.clone());
+                let first = group
+                    .service
+                    .is_some_and(|service| first_stage.get(service.name.as_str()) == Some(&key));
+                let last = group
+                    .service
+                    .is_some_and(|service| last_stage.get(service.name.as_str()) == Some(&key));
+                (name, (group, first, last))
+            })
+            .collect();
+        let stage_applied = apply_stage(
+            groups,
+            options.max_parallel,
+            |name, (group, first, last)| {
+                let client = client.clone();
+                async move {
+                    LOGGER.task_starting_as("Applying", &name);
+                    let started = std::time::Instant::now();
+                    let result =
+                        apply_group(group, first, last, env, client, stage_discovery).await;
+                    match &result {
+                        Ok(_) => LOGGER.task_completed(&name, started.elapsed()),
+                        Err(e) => LOGGER.task_failed(&name, &e.to_string()),
+                    }
+                    result
+                }
+            },
+        )
+        .await?;
+
+        if let Some(timeout) = options.rollout_timeout {
+            rollout::wait_for_rollouts(client.clone(), &stage_applied, timeout).await?;
+        }
+        if let Some(rediscovered) =
+            k8sm8::rediscover_after_crds(client.clone(), &stage_applied).await?
+        {
+            *discovery = rediscovered;
+        }
+        applied.extend(stage_applied);
+    }
+
+    Ok(applied)
+}
+
+// Applies each of the named `groups` with `apply`, up to `max_parallel` at the same
+// time, and returns everything they applied once all of them are done. A failing group
+// does not stop the others already in flight; every failure is reported, in the order
+// of `groups`.
+async fn apply_stage<G, F, Fut>(
+    groups: Vec<(String, G)>,
+    max_parallel: usize,
+    mut apply: F,
+) -> Result<Vec<AppliedResource>, DeployError>
+where
+    F: FnMut(String, G) -> Fut,
+    Fut: Future<Output = Result<Vec<AppliedResource>, DeployError>>,
+{
+    // Collected first so the stream does not carry the closure's lifetimes, which
+    // would keep the deploy future from being Send.
+    let tasks = groups
+        .into_iter()
+        .map(|(name, group)| {
+            let result = apply(name.clone(), group);
+            async move { (name, result.await) }
+        })
+        .collect::<Vec<_>>();
+    let results = futures::stream::iter(tasks)
+        .buffered(max_parallel.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut applied = Vec::new();
+    let mut failures = Vec::new();
+    for (name, result) in results {
+        match result {
+            Ok(resources) => applied.extend(resources),
+            Err(e) => failures.push(format!("  {}: {}", name, e)),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(DeployError::ServicesFailed {
+            count: failures.len(),
+            details: failures.join("\n"),
+        });
+    }
+    Ok(applied)
+}
+
+// The workloads the generated manifests under `path` define, whose live spec is
+// recorded before they are applied.
+async fn workloads_in_path(path: &Path) -> Result<Vec<AppliedResource>, DeployError> {
+    let mut workloads = Vec::new();
+    let walker = WalkDir::new(path).into_iter().filter_map(|e| e.ok());
     for entry in walker {
         let file_path = entry.path();
         if file_path.is_file()
@@ -30,48 +373,139 @@ async fn apply_manifests_from_path(
                 .extension()
                 .is_some_and(|ext| ext == "yaml" || ext == "yml"))
         {
-            LOGGER.debug(&format!("Applying manifest: {:?}", file_path));
-            let res =
-                k8sm8::apply(Some(file_path.to_path_buf()), client.clone(), discovery).await?;
-            applied_manifests.push(res);
+            if let Ok(yaml) = fs::read_to_string(file_path) {
+                workloads.extend(history::rollback_workloads(&yaml).await?);
+            }
+        }
+    }
+    Ok(workloads)
+}
+
+/// The objects deploy applies for `env`: those in its namespaces file and in the
+/// generated directory of each service, or in the whole generated directory for
+/// layouts that are not grouped by service.
+pub async fn generated_objects(
+    env: &Environment,
+) -> Result<Vec<inventory::InventoryEntry>, DeployError> {
+    let path = Path::new("./k8s/generated").join(&env.name);
+    let mut paths = env
+        .services
+        .iter()
+        .map(|service| path.join(service.get_path()))
+        .filter(|service_path| service_path.exists())
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        paths.push(path.clone());
+    } else {
+        paths.push(path.join(NAMESPACES_FILE));
+    }
+
+    let mut objects = Vec::new();
+    for entry in paths
+        .iter()
+        .flat_map(|path| WalkDir::new(path).into_iter().filter_map(|e| e.ok()))
+    {
+        let file_path = entry.path();
+        if file_path.is_file()
+            && (file_path
+                .extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "yml"))
+        {
+            if let Ok(yaml) = fs::read_to_string(file_path) {
+                objects.extend(inventory::entries_in_yaml(&yaml).await?);
+            }
         }
     }
+    Ok(inventory::merge(objects))
+}
 
-    Ok(applied_manifests)
+// Deletes the objects of `env_name` that are no longer generated and records `current`
+// as its inventory, along with any object that could not be deleted.
+async fn prune(
+    client: kube::Client,
+    discovery: &kube::discovery::Discovery,
+    env_name: &str,
+    current: &[inventory::InventoryEntry],
+) -> Result<usize, DeployError> {
+    let stale = inventory::prunable_objects(
+        client.clone(),
+        discovery,
+        env_name,
+        &environment_namespace(env_name),
+        current,
+    )
+    .await?;
+    let (pruned, objects) = inventory::delete_stale(current, stale, |entry| {
+        let client = client.clone();
+        async move { inventory::delete_object(client, discovery, &entry).await }
+    })
+    .await;
+    inventory::write_inventory(client, env_name, &environment_namespace(env_name), &objects)
+        .await?;
+    Ok(pruned)
 }
 
-fn replace_template_var(input: &str, key: &str, value: &str) -> String {
-    input
-        .replace(&format!("{{{{ {} }}}}", key), value)
-        .replace(&format!("{{{{{}}}}}", key), value)
+/// Decrypts the environment's `secrets.enc.toml`, if it has any values. The key is
+/// taken from `SAILR_SECRETS_KEY` or `SAILR_SECRETS_KEY_FILE`.
+fn load_secrets(env_name: &str) -> Result<DecryptedSecrets, DeployError> {
+    let store = SecretStore::load(env_name)
+        .map_err(|e| DeployError::EnvironmentDeploymentFailed(e.to_string()))?;
+    if store.is_empty() {
+        return Ok(DecryptedSecrets::default());
+    }
+    let secrets = SecretKey::resolve(env_name, None)
+        .and_then(|key| store.decrypt_all(&key))
+        .map_err(|e| DeployError::EnvironmentDeploymentFailed(e.to_string()))?;
+    LOGGER.info(&format!(
+        "Decrypted {} secret value(s) from {}",
+        secrets.len(),
+        store.path().display()
+    ));
+    Ok(secrets)
 }
 
-fn render_service_hook(hook: &str, env: &Environment, service: &Service) -> String {
-    let namespace = service.namespace_or(&env.name);
-    let rendered = replace_template_var(hook, "name", &service.name);
-    let rendered =
-        replace_template_var(&rendered, "platform", env.platform.as_deref().unwrap_or(""));
-    let rendered = replace_template_var(&rendered, "version", &service.version);
-    replace_template_var(&rendered, "namespace", namespace)
+/// Renders a service hook command with the service's `name`, `platform`, `version` and
+/// `namespace` available as template variables.
+fn render_service_hook(
+    hook: &str,
+    env: &Environment,
+    service: &Service,
+) -> Result<String, TemplateError> {
+    let mut context = TemplateContext::new();
+    context.insert("name", service.name.clone());
+    context.insert("platform", env.platform.clone().unwrap_or_default());
+    context.insert("version", service.version.clone());
+    context.insert(
+        "namespace",
+        service.namespace_or(&env.default_namespace()).to_string(),
+    );
+    TemplateEngine::new().render(hook, &context)
 }
 
-fn run_service_hooks(
+// Runs the hooks without blocking the services applied alongside.
+async fn run_service_hooks(
     stage: &str,
     hook_spec: &CommandSpec,
     env: &Environment,
     service: &Service,
 ) -> Result<(), DeployError> {
     for hook in hook_spec.as_vec() {
-        let rendered_hook = render_service_hook(&hook, env, service);
+        let rendered_hook = render_service_hook(&hook, env, service).map_err(|e| {
+            DeployError::ManifestApplicationFailed(format!(
+                "Failed to render {} hook for service '{}': {}",
+                stage, service.name, e
+            ))
+        })?;
         LOGGER.info(&format!(
             "Running {} hook for service '{}': {}",
             stage, service.name, rendered_hook
         ));
 
-        let output = Command::new("sh")
+        let output = tokio::process::Command::new("sh")
             .arg("-c")
             .arg(&rendered_hook)
             .output()
+            .await
             .map_err(|e| {
                 DeployError::ManifestApplicationFailed(format!(
                     "Failed to execute {} hook for service '{}': {}",
@@ -136,12 +570,15 @@ async fn delete_workload_if_matches(
     Ok(())
 }
 
-/// Main entry point for deploying resources to a Kubernetes cluster.
+/// Main entry point for deploying resources to a Kubernetes cluster. Unless
+/// `options.rollout_timeout` is `None`, this only succeeds once every applied workload
+/// has rolled out.
 pub async fn deploy(
     ctx: String,
     env_name: &str,
-    strategy: DeploymentStrategy,
+    options: &DeployOptions,
 ) -> Result<(), DeployError> {
+    let strategy = options.strategy;
     LOGGER.header(
         "Deploy",
         &format!("{} → {} ({:?})", env_name, ctx, strategy),
@@ -154,8 +591,9 @@ pub async fn deploy(
         ))
     })?;
 
+    let mut secrets = load_secrets(env_name)?;
     let client = k8sm8::create_client(ctx).await?;
-    let discovery = kube::Discovery::new(client.clone())
+    let mut discovery = kube::Discovery::new(client.clone())
         .run()
         .await
         .map_err(|e| {
@@ -167,6 +605,34 @@ pub async fn deploy(
 
     let path = Path::new("./k8s/generated").join(env_name);
 
+    // Without a revision to go back to, a failed rollout is only reported.
+    let workloads = workloads_in_path(&path).await?;
+    let revision = if workloads.is_empty() {
+        None
+    } else {
+        match history::record_revision(
+            client.clone(),
+            &env.name,
+            &env.default_namespace(),
+            &workloads,
+        )
+        .await
+        {
+            Ok(revision) => {
+                LOGGER.info(&format!(
+                    "Recorded revision {} of {} workload(s)",
+                    revision.number,
+                    revision.workloads.len()
+                ));
+                Some(revision)
+            }
+            Err(e) => {
+                LOGGER.warn(&format!("{}; rollback will not be possible", e));
+                None
+            }
+        }
+    };
+
     if strategy == DeploymentStrategy::Restart {
         LOGGER.info(&format!(
             "Restart strategy selected. Deleting existing Deployments or Daemonsets in environment: {}",
@@ -204,49 +670,228 @@ pub async fn deploy(
         }
     }
 
-    let mut applied_total = 0usize;
+    // Namespaces go first, so the resources of every service have somewhere to land.
+    let namespaces_path = path.join(NAMESPACES_FILE);
+    let mut namespaces_applied = Vec::new();
+    if namespaces_path.is_file() {
+        namespaces_applied = apply_manifests_from_path(
+            namespaces_path.as_path(),
+            client.clone(),
+            &discovery,
+            &mut secrets,
+        )
+        .await?;
+    }
 
-    for service in &env.services {
-        let service_path = path.join(service.get_path());
-        if !service_path.exists() {
-            LOGGER.warn(&format!(
-                "Generated manifests not found for service '{}': {:?}",
-                service.name, service_path
-            ));
-            continue;
-        }
+    let stages = plan_stages(&env, &path, &mut secrets).await?;
+    for secret in secrets.not_injected() {
+        LOGGER.warn(&format!(
+            "Encrypted values for Secret '{}' were not deployed: no generated manifest defines it",
+            secret
+        ));
+    }
 
-        if let Some(hooks) = &service.hooks {
-            if let Some(pre_deploy) = &hooks.pre_deploy {
-                run_service_hooks("pre_deploy", pre_deploy, &env, service)?;
-            }
+    // Everything about to be applied is recorded, so a later `--prune` can find what is
+    // removed from the generated manifests in between.
+    let objects = generated_objects(&env).await?;
+    let recorded = match inventory::read_inventory(
+        client.clone(),
+        &env.name,
+        &env.default_namespace(),
+    )
+    .await
+    {
+        Ok(previous) => {
+            let objects = inventory::merge(objects.iter().cloned().chain(previous));
+            inventory::write_inventory(
+                client.clone(),
+                &env.name,
+                &env.default_namespace(),
+                &objects,
+            )
+            .await
         }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &recorded {
+        LOGGER.warn(&e.to_string());
+    }
 
-        let applied =
-            apply_manifests_from_path(service_path.as_path(), client.clone(), &discovery).await?;
-        applied_total += applied.len();
-
-        if let Some(hooks) = &service.hooks {
-            if let Some(post_deploy) = &hooks.post_deploy {
-                run_service_hooks("post_deploy", post_deploy, &env, service)?;
-            }
+    let result = apply_stages(stages, &env, client.clone(), &mut discovery, options).await;
+    let mut applied = match result {
+        Ok(applied) => applied,
+        Err(cause @ DeployError::RolloutFailed { .. }) if options.rollback_on_failure => {
+            let (Some(revision), Some(timeout)) = (revision, options.rollout_timeout) else {
+                return Err(cause);
+            };
+            LOGGER.warn(&format!("Rolling back to revision {}", revision.number));
+            return match restore(client.clone(), &revision, timeout).await {
+                Ok(()) => Err(DeployError::RolledBack {
+                    revision: revision.number,
+                    cause: Box::new(cause),
+                }),
+                Err(error) => Err(DeployError::RollbackFailed {
+                    revision: revision.number,
+                    cause: Box::new(cause),
+                    error: Box::new(error),
+                }),
+            };
         }
+        Err(e) => return Err(e),
+    };
+    applied.extend(namespaces_applied);
+
+    let mut summary = format!("deployed successfully! Applied {} resources", applied.len());
+    if options.prune {
+        recorded?;
+        let pruned = prune(client.clone(), &discovery, &env.name, &objects).await?;
+        summary.push_str(&format!(", pruned {}", pruned));
     }
 
-    // Fallback for legacy/generated layouts where manifests are not grouped by service directory.
-    if applied_total == 0 {
-        let applied = apply_manifests_from_path(path.as_path(), client.clone(), &discovery).await?;
-        applied_total += applied.len();
+    LOGGER.status("Finished", &format!("{}.", summary), "green");
+
+    Ok(())
+}
+
+// Re-applies `revision` and waits for the restored workloads to roll out again.
+async fn restore(
+    client: kube::Client,
+    revision: &history::Revision,
+    timeout: Duration,
+) -> Result<(), DeployError> {
+    let restored = history::restore_revision(client.clone(), revision).await?;
+    rollout::wait_for_rollouts(client, &restored, timeout).await
+}
+
+/// Re-applies a recorded revision of `env_name`: revision `to`, or the one recorded by
+/// the latest deploy. The current state is recorded first, so a rollback can itself be
+/// rolled back.
+pub async fn rollback(
+    ctx: String,
+    env_name: &str,
+    to: Option<u32>,
+    rollout_timeout: Option<Duration>,
+) -> Result<(), DeployError> {
+    LOGGER.header("Rollback", &format!("{} → {}", env_name, ctx));
+
+    let client = k8sm8::create_client(ctx).await?;
+    let revisions =
+        history::list_revisions(client.clone(), env_name, &environment_namespace(env_name)).await?;
+    let revision = match to {
+        Some(number) => revisions
+            .into_iter()
+            .find(|revision| revision.number == number)
+            .ok_or_else(|| DeployError::RevisionNotFound {
+                env_name: env_name.to_string(),
+                revision: number,
+            })?,
+        None => revisions
+            .into_iter()
+            .last()
+            .ok_or_else(|| DeployError::NoRevisions(env_name.to_string()))?,
+    };
+
+    let workloads = revision
+        .workloads
+        .iter()
+        .map(|snapshot| snapshot.workload.clone())
+        .collect::<Vec<_>>();
+    let current = history::record_revision(
+        client.clone(),
+        env_name,
+        &environment_namespace(env_name),
+        &workloads,
+    )
+    .await?;
+    LOGGER.info(&format!(
+        "Recorded the current state as revision {}",
+        current.number
+    ));
+
+    let restored = history::restore_revision(client.clone(), &revision).await?;
+    if let Some(timeout) = rollout_timeout {
+        rollout::wait_for_rollouts(client, &restored, timeout).await?;
     }
 
     LOGGER.status(
         "Finished",
         &format!(
-            "deployed successfully! Applied {} manifests.",
-            applied_total
+            "rolled back to revision {} ({} workload(s) restored)",
+            revision.number,
+            restored.len()
         ),
         "green",
     );
-
     Ok(())
 }
+
+/// The revisions recorded for `env_name`, oldest first.
+pub async fn revisions(ctx: String, env_name: &str) -> Result<Vec<history::Revision>, DeployError> {
+    let client = k8sm8::create_client(ctx).await?;
+    history::list_revisions(client, env_name, &environment_namespace(env_name)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    use super::*;
+
+    fn deployment(name: &str) -> AppliedResource {
+        AppliedResource {
+            kind: "Deployment".to_string(),
+            namespace: "shop".to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn stages_run_at_most_max_parallel_groups_and_report_failures_in_order() {
+        let running = &AtomicUsize::new(0);
+        let peak = &AtomicUsize::new(0);
+        let finished = &AtomicUsize::new(0);
+        let groups = ["db", "api", "web", "worker", "cron"]
+            .map(|name| (name.to_string(), name))
+            .to_vec();
+
+        let result = apply_stage(groups, 2, |_, name| async move {
+            peak.fetch_max(running.fetch_add(1, SeqCst) + 1, SeqCst);
+            // `cron` fails well before `api` does.
+            let millis = if name == "cron" { 1 } else { 20 };
+            tokio::time::sleep(Duration::from_millis(millis)).await;
+            running.fetch_sub(1, SeqCst);
+            finished.fetch_add(1, SeqCst);
+            match name {
+                "api" | "cron" => Err(DeployError::EnvironmentDeploymentFailed(format!(
+                    "{} broke",
+                    name
+                ))),
+                _ => Ok(vec![deployment(name)]),
+            }
+        })
+        .await;
+
+        assert_eq!(peak.load(SeqCst), 2);
+        // Every group is done before the stage returns and its rollouts are checked.
+        assert_eq!(finished.load(SeqCst), 5);
+        match result {
+            Err(DeployError::ServicesFailed { count, details }) => {
+                assert_eq!(count, 2);
+                assert_eq!(
+                    details,
+                    "  api: Failed to deploy environment: api broke\n  cron: Failed to deploy environment: cron broke"
+                );
+            }
+            other => panic!("expected ServicesFailed, got {:?}", other),
+        }
+
+        let applied = apply_stage(
+            vec![("db".to_string(), "db"), ("api".to_string(), "api")],
+            4,
+            |_, name| async move { Ok(vec![deployment(name)]) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(applied, vec![deployment("db"), deployment("api")]);
+    }
+}
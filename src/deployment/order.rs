@@ -0,0 +1,212 @@
+//! The order deploy applies documents in.
+//!
+//! Deploy proceeds in stages. A service's stage follows the stages of every service it
+//! lists in `deploy_after`, and within a service the `sailr.dev/wave` annotation places
+//! documents in later (or earlier) waves, `0` by default. Each stage is applied only
+//! once the workloads of the previous one are healthy. Within a stage, documents are
+//! applied by kind, so that what a workload refers to exists before it does.
+
+use std::collections::BTreeMap;
+
+use crate::{environment::Service, errors::DeployError};
+
+/// Annotation placing a document in a wave of its service.
+pub const WAVE_ANNOTATION: &str = "sailr.dev/wave";
+
+// Kinds in the order they are applied. Kinds not listed are applied last, as they are
+// mostly custom resources that need their definitions and the workloads in place.
+const KIND_ORDER: &[&[&str]] = &[
+    &["Namespace"],
+    &["CustomResourceDefinition"],
+    &[
+        "ServiceAccount",
+        "Role",
+        "ClusterRole",
+        "RoleBinding",
+        "ClusterRoleBinding",
+    ],
+    &["ConfigMap", "Secret"],
+    &["StorageClass", "PersistentVolume", "PersistentVolumeClaim"],
+    &["Service"],
+    &[
+        "Deployment",
+        "StatefulSet",
+        "DaemonSet",
+        "ReplicaSet",
+        "Pod",
+        "Job",
+        "CronJob",
+    ],
+    &["Ingress"],
+];
+
+/// The position of `kind` in the apply order.
+pub fn kind_rank(kind: &str) -> usize {
+    KIND_ORDER
+        .iter()
+        .position(|kinds| kinds.contains(&kind))
+        .unwrap_or(KIND_ORDER.len())
+}
+
+/// Sorts `documents` into apply order, keeping the file order among documents of the
+/// same rank.
+pub fn sort_by_kind(documents: &mut [serde_yaml::Value]) {
+    documents.sort_by_key(|document| kind_rank(document["kind"].as_str().unwrap_or_default()));
+}
+
+/// The wave of `document`: its `sailr.dev/wave` annotation, or `0`.
+pub fn document_wave(document: &serde_yaml::Value) -> Result<i32, DeployError> {
+    let Some(wave) = document["metadata"]["annotations"].get(WAVE_ANNOTATION) else {
+        return Ok(0);
+    };
+    let value = match wave {
+        serde_yaml::Value::String(value) => value.clone(),
+        serde_yaml::Value::Number(value) => value.to_string(),
+        _ => String::new(),
+    };
+    value.trim().parse().map_err(|_| DeployError::InvalidWave {
+        name: format!(
+            "{}/{}",
+            document["kind"].as_str().unwrap_or_default().to_lowercase(),
+            document["metadata"]["name"].as_str().unwrap_or_default()
+        ),
+        value,
+    })
+}
+
+/// The level of every service: `0` for services that do not deploy after any other,
+/// otherwise one more than the highest level among those they deploy after.
+pub fn service_levels(services: &[Service]) -> Result<BTreeMap<String, usize>, DeployError> {
+    let by_name = services
+        .iter()
+        .map(|service| (service.name.as_str(), service))
+        .collect::<BTreeMap<_, _>>();
+    let mut levels = BTreeMap::new();
+    for service in services {
+        level_of(service, &by_name, &mut levels, &mut Vec::new())?;
+    }
+    Ok(levels)
+}
+
+fn level_of<'a>(
+    service: &'a Service,
+    by_name: &BTreeMap<&str, &'a Service>,
+    levels: &mut BTreeMap<String, usize>,
+    path: &mut Vec<&'a str>,
+) -> Result<usize, DeployError> {
+    if let Some(level) = levels.get(&service.name) {
+        return Ok(*level);
+    }
+    if let Some(start) = path.iter().position(|name| *name == service.name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(&service.name);
+        return Err(DeployError::DeployOrderCycle(cycle.join(" -> ")));
+    }
+
+    path.push(&service.name);
+    let mut level = 0;
+    for dependency in service.deploy_after.iter().flatten() {
+        let Some(dependency) = by_name.get(dependency.as_str()) else {
+            return Err(DeployError::UnknownDeployAfter {
+                service: service.name.clone(),
+                dependency: dependency.clone(),
+            });
+        };
+        level = level.max(level_of(dependency, by_name, levels, path)? + 1);
+    }
+    path.pop();
+
+    levels.insert(service.name.clone(), level);
+    Ok(level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, deploy_after: &[&str]) -> Service {
+        let mut service = Service::new(name, None, "1.0.0");
+        service.deploy_after = (!deploy_after.is_empty())
+            .then(|| deploy_after.iter().map(|s| s.to_string()).collect());
+        service
+    }
+
+    #[test]
+    fn documents_are_sorted_by_kind() {
+        let mut documents: Vec<serde_yaml::Value> = [
+            "Ingress",
+            "Deployment",
+            "Widget",
+            "Service",
+            "Secret",
+            "Namespace",
+        ]
+        .iter()
+        .map(|kind| serde_yaml::from_str(&format!("kind: {}", kind)).unwrap())
+        .collect();
+        sort_by_kind(&mut documents);
+
+        let kinds = documents
+            .iter()
+            .map(|document| document["kind"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                "Namespace",
+                "Secret",
+                "Service",
+                "Deployment",
+                "Ingress",
+                "Widget"
+            ]
+        );
+    }
+
+    #[test]
+    fn waves_come_from_the_annotation() {
+        let migrate: serde_yaml::Value = serde_yaml::from_str(
+            "kind: Job\nmetadata:\n  name: migrate\n  annotations:\n    sailr.dev/wave: \"-1\"\n",
+        )
+        .unwrap();
+        let api: serde_yaml::Value =
+            serde_yaml::from_str("kind: Deployment\nmetadata:\n  name: api\n").unwrap();
+        let invalid: serde_yaml::Value = serde_yaml::from_str(
+            "kind: Job\nmetadata:\n  name: seed\n  annotations:\n    sailr.dev/wave: later\n",
+        )
+        .unwrap();
+
+        assert_eq!(document_wave(&migrate).unwrap(), -1);
+        assert_eq!(document_wave(&api).unwrap(), 0);
+        assert_eq!(
+            document_wave(&invalid).unwrap_err().to_string(),
+            "Invalid sailr.dev/wave annotation 'later' on job/seed: expected an integer"
+        );
+    }
+
+    #[test]
+    fn services_deploy_after_their_dependencies() {
+        let levels = service_levels(&[
+            service("api", &["postgres", "migrations"]),
+            service("migrations", &["postgres"]),
+            service("postgres", &[]),
+            service("web", &[]),
+        ])
+        .unwrap();
+        assert_eq!(levels["postgres"], 0);
+        assert_eq!(levels["web"], 0);
+        assert_eq!(levels["migrations"], 1);
+        assert_eq!(levels["api"], 2);
+
+        let cycle = service_levels(&[service("a", &["b"]), service("b", &["a"])]).unwrap_err();
+        assert_eq!(
+            cycle.to_string(),
+            "deploy_after entries form a cycle: a -> b -> a"
+        );
+        let unknown = service_levels(&[service("api", &["redis"])]).unwrap_err();
+        assert_eq!(
+            unknown.to_string(),
+            "Service 'api' deploys after 'redis', which is not a service of the environment"
+        );
+    }
+}
@@ -0,0 +1,738 @@
+//! Waiting for applied workloads to finish rolling out.
+//!
+//! Every Deployment, StatefulSet, DaemonSet and Job that deploy applied is watched
+//! until it has rolled out, using the same checks as `kubectl rollout status`. When a
+//! workload fails or the timeout expires, the warning events and container states of
+//! its pods are collected so the failure can be explained.
+
+use std::{fmt::Debug, time::Duration};
+
+use futures::{future::join_all, StreamExt};
+use k8s_openapi::{
+    api::{
+        apps::v1::{DaemonSet, Deployment, StatefulSet},
+        batch::v1::Job,
+        core::v1::{Event, Pod},
+    },
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
+    NamespaceResourceScope,
+};
+use kube::{
+    api::ListParams,
+    runtime::{watcher, WatchStreamExt},
+    Api, Client, Resource,
+};
+use serde::de::DeserializeOwned;
+use tokio::time::Instant;
+
+use crate::{deployment::k8sm8::AppliedResource, errors::DeployError, LOGGER};
+
+/// How long deploy waits for workloads to roll out unless told otherwise.
+pub const DEFAULT_ROLLOUT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Kinds whose rollout deploy waits for.
+pub const WORKLOAD_KINDS: &[&str] = &["Deployment", "StatefulSet", "DaemonSet", "Job"];
+
+// Waiting reasons every pod passes through on its way up.
+const STARTING_REASONS: &[&str] = &["ContainerCreating", "PodInitializing"];
+
+// How many warning events are shown per failed workload.
+const MAX_EVENTS: usize = 10;
+
+/// Where a workload's rollout stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RolloutState {
+    Ready,
+    /// Still rolling out, with what it is waiting for.
+    Progressing(String),
+    /// The rollout will not complete without a change, such as a Deployment past its
+    /// progress deadline or a failed Job.
+    Failed(String),
+}
+
+trait Workload:
+    Resource<DynamicType = (), Scope = NamespaceResourceScope>
+    + Clone
+    + DeserializeOwned
+    + Debug
+    + Send
+    + Sync
+    + 'static
+{
+    fn rollout_state(&self) -> RolloutState;
+
+    // The label selector of the workload's pods.
+    fn pod_selector(&self) -> Option<String>;
+}
+
+impl Workload for Deployment {
+    fn rollout_state(&self) -> RolloutState {
+        deployment_state(self)
+    }
+
+    fn pod_selector(&self) -> Option<String> {
+        self.spec
+            .as_ref()
+            .and_then(|spec| selector_string(&spec.selector))
+    }
+}
+
+impl Workload for StatefulSet {
+    fn rollout_state(&self) -> RolloutState {
+        stateful_set_state(self)
+    }
+
+    fn pod_selector(&self) -> Option<String> {
+        self.spec
+            .as_ref()
+            .and_then(|spec| selector_string(&spec.selector))
+    }
+}
+
+impl Workload for DaemonSet {
+    fn rollout_state(&self) -> RolloutState {
+        daemon_set_state(self)
+    }
+
+    fn pod_selector(&self) -> Option<String> {
+        self.spec
+            .as_ref()
+            .and_then(|spec| selector_string(&spec.selector))
+    }
+}
+
+impl Workload for Job {
+    fn rollout_state(&self) -> RolloutState {
+        job_state(self)
+    }
+
+    fn pod_selector(&self) -> Option<String> {
+        self.spec
+            .as_ref()
+            .and_then(|spec| spec.selector.as_ref())
+            .and_then(selector_string)
+            .or_else(|| {
+                self.metadata
+                    .name
+                    .as_ref()
+                    .map(|name| format!("job-name={}", name))
+            })
+    }
+}
+
+fn selector_string(selector: &LabelSelector) -> Option<String> {
+    let labels = selector.match_labels.as_ref()?;
+    if labels.is_empty() {
+        return None;
+    }
+    Some(
+        labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+// Whether the controller has seen the latest spec of an object.
+fn observed(generation: Option<i64>, observed_generation: Option<i64>) -> bool {
+    observed_generation.is_some_and(|observed| observed >= generation.unwrap_or(0))
+}
+
+pub fn deployment_state(deployment: &Deployment) -> RolloutState {
+    let Some(status) = &deployment.status else {
+        return RolloutState::Progressing("waiting for status".to_string());
+    };
+    if !observed(deployment.metadata.generation, status.observed_generation) {
+        return RolloutState::Progressing("waiting for the new spec to be observed".to_string());
+    }
+    if let Some(condition) = status.conditions.iter().flatten().find(|condition| {
+        condition.type_ == "Progressing"
+            && condition.reason.as_deref() == Some("ProgressDeadlineExceeded")
+    }) {
+        return RolloutState::Failed(format!(
+            "exceeded its progress deadline: {}",
+            condition.message.as_deref().unwrap_or_default()
+        ));
+    }
+    let desired = deployment
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.replicas)
+        .unwrap_or(1);
+    let updated = status.updated_replicas.unwrap_or(0);
+    let replicas = status.replicas.unwrap_or(0);
+    let available = status.available_replicas.unwrap_or(0);
+    if updated < desired {
+        RolloutState::Progressing(format!("{} of {} replicas updated", updated, desired))
+    } else if replicas > updated {
+        RolloutState::Progressing(format!(
+            "{} old replicas pending termination",
+            replicas - updated
+        ))
+    } else if available < updated {
+        RolloutState::Progressing(format!(
+            "{} of {} updated replicas available",
+            available, updated
+        ))
+    } else {
+        RolloutState::Ready
+    }
+}
+
+pub fn stateful_set_state(stateful_set: &StatefulSet) -> RolloutState {
+    let spec = stateful_set.spec.as_ref();
+    let strategy = spec
+        .and_then(|spec| spec.update_strategy.as_ref())
+        .and_then(|strategy| strategy.type_.as_deref())
+        .unwrap_or("RollingUpdate");
+    // OnDelete StatefulSets only change when their pods are deleted.
+    if strategy != "RollingUpdate" {
+        return RolloutState::Ready;
+    }
+    let Some(status) = &stateful_set.status else {
+        return RolloutState::Progressing("waiting for status".to_string());
+    };
+    if !observed(stateful_set.metadata.generation, status.observed_generation) {
+        return RolloutState::Progressing("waiting for the new spec to be observed".to_string());
+    }
+    let desired = spec.and_then(|spec| spec.replicas).unwrap_or(1);
+    let ready = status.ready_replicas.unwrap_or(0);
+    if ready < desired {
+        return RolloutState::Progressing(format!("{} of {} pods ready", ready, desired));
+    }
+    let partition = spec
+        .and_then(|spec| spec.update_strategy.as_ref())
+        .and_then(|strategy| strategy.rolling_update.as_ref())
+        .and_then(|rolling_update| rolling_update.partition)
+        .unwrap_or(0);
+    let updated = status.updated_replicas.unwrap_or(0);
+    if partition > 0 {
+        let expected = (desired - partition).max(0);
+        return if updated < expected {
+            RolloutState::Progressing(format!(
+                "{} of {} pods above the partition updated",
+                updated, expected
+            ))
+        } else {
+            RolloutState::Ready
+        };
+    }
+    if status.update_revision.is_some() && status.update_revision != status.current_revision {
+        return RolloutState::Progressing(format!("{} of {} pods updated", updated, desired));
+    }
+    RolloutState::Ready
+}
+
+pub fn daemon_set_state(daemon_set: &DaemonSet) -> RolloutState {
+    let strategy = daemon_set
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.update_strategy.as_ref())
+        .and_then(|strategy| strategy.type_.as_deref())
+        .unwrap_or("RollingUpdate");
+    if strategy != "RollingUpdate" {
+        return RolloutState::Ready;
+    }
+    let Some(status) = &daemon_set.status else {
+        return RolloutState::Progressing("waiting for status".to_string());
+    };
+    if !observed(daemon_set.metadata.generation, status.observed_generation) {
+        return RolloutState::Progressing("waiting for the new spec to be observed".to_string());
+    }
+    let desired = status.desired_number_scheduled;
+    let updated = status.updated_number_scheduled.unwrap_or(0);
+    let available = status.number_available.unwrap_or(0);
+    if updated < desired {
+        RolloutState::Progressing(format!("{} of {} pods updated", updated, desired))
+    } else if available < desired {
+        RolloutState::Progressing(format!(
+            "{} of {} updated pods available",
+            available, desired
+        ))
+    } else {
+        RolloutState::Ready
+    }
+}
+
+pub fn job_state(job: &Job) -> RolloutState {
+    let Some(status) = &job.status else {
+        return RolloutState::Progressing("waiting for status".to_string());
+    };
+    for condition in status.conditions.iter().flatten() {
+        if condition.status != "True" {
+            continue;
+        }
+        match condition.type_.as_str() {
+            "Complete" => return RolloutState::Ready,
+            "Failed" => {
+                return RolloutState::Failed(format!(
+                    "job failed: {}: {}",
+                    condition.reason.as_deref().unwrap_or("Failed"),
+                    condition.message.as_deref().unwrap_or_default()
+                ))
+            }
+            _ => {}
+        }
+    }
+    RolloutState::Progressing(format!(
+        "{} active, {} succeeded, {} failed",
+        status.active.unwrap_or(0),
+        status.succeeded.unwrap_or(0),
+        status.failed.unwrap_or(0)
+    ))
+}
+
+// Why a workload did not roll out, with the selector of its pods.
+struct Stalled {
+    reason: String,
+    pod_selector: Option<String>,
+}
+
+// Watches `resource` until it is ready, has failed or `deadline` passes.
+async fn wait_for<K: Workload>(
+    client: Client,
+    resource: &AppliedResource,
+    deadline: Instant,
+) -> Result<(), Stalled> {
+    let api: Api<K> = Api::namespaced(client, &resource.namespace);
+    let config = watcher::Config::default().fields(&format!("metadata.name={}", resource.name));
+    let mut objects = watcher(api, config)
+        .default_backoff()
+        .applied_objects()
+        .boxed();
+
+    let mut waiting_for = "waiting for status".to_string();
+    let mut pod_selector = None;
+    loop {
+        let object = match tokio::time::timeout_at(deadline, objects.next()).await {
+            Err(_) => {
+                return Err(Stalled {
+                    reason: format!("timed out, {}", waiting_for),
+                    pod_selector,
+                })
+            }
+            Ok(None) => {
+                return Err(Stalled {
+                    reason: "the watch ended before the rollout finished".to_string(),
+                    pod_selector,
+                })
+            }
+            // The watcher retries with a backoff, so errors are only worth a mention.
+            Ok(Some(Err(e))) => {
+                LOGGER.debug(&format!("Watching {} failed: {}", resource, e));
+                continue;
+            }
+            Ok(Some(Ok(object))) => object,
+        };
+        pod_selector = object.pod_selector();
+        match object.rollout_state() {
+            RolloutState::Ready => return Ok(()),
+            RolloutState::Progressing(message) => {
+                if message != waiting_for {
+                    LOGGER.debug(&format!("{}: {}", resource, message));
+                    waiting_for = message;
+                }
+            }
+            RolloutState::Failed(reason) => {
+                return Err(Stalled {
+                    reason,
+                    pod_selector,
+                })
+            }
+        }
+    }
+}
+
+async fn wait_for_resource(
+    client: Client,
+    resource: &AppliedResource,
+    deadline: Instant,
+) -> Result<(), Stalled> {
+    let result = match resource.kind.as_str() {
+        "Deployment" => wait_for::<Deployment>(client, resource, deadline).await,
+        "StatefulSet" => wait_for::<StatefulSet>(client, resource, deadline).await,
+        "DaemonSet" => wait_for::<DaemonSet>(client, resource, deadline).await,
+        "Job" => wait_for::<Job>(client, resource, deadline).await,
+        _ => Ok(()),
+    };
+    if result.is_ok() {
+        LOGGER.status("Ready", &resource.to_string(), "green");
+    }
+    result
+}
+
+/// Waits up to `timeout` for every workload among `resources` to roll out. Fails with
+/// the reason, the pod problems and the warning events of each workload that did not.
+pub async fn wait_for_rollouts(
+    client: Client,
+    resources: &[AppliedResource],
+    timeout: Duration,
+) -> Result<(), DeployError> {
+    let workloads = resources
+        .iter()
+        .filter(|resource| WORKLOAD_KINDS.contains(&resource.kind.as_str()))
+        .collect::<Vec<_>>();
+    if workloads.is_empty() {
+        return Ok(());
+    }
+    LOGGER.status(
+        "Waiting",
+        &format!(
+            "for {} workload(s) to roll out (timeout {:?})",
+            workloads.len(),
+            timeout
+        ),
+        "cyan",
+    );
+
+    let deadline = Instant::now() + timeout;
+    let results = join_all(
+        workloads
+            .iter()
+            .map(|resource| wait_for_resource(client.clone(), resource, deadline)),
+    )
+    .await;
+
+    let mut failures = Vec::new();
+    for (resource, result) in workloads.iter().zip(results) {
+        let Err(stalled) = result else {
+            continue;
+        };
+        LOGGER.status(
+            "Failed",
+            &format!("{}: {}", resource, stalled.reason),
+            "red",
+        );
+        let mut failure = format!("{}: {}", resource, stalled.reason);
+        for line in diagnose(&client, resource, stalled.pod_selector.as_deref()).await {
+            failure.push_str(&format!("\n    {}", line));
+        }
+        failures.push(failure);
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    Err(DeployError::RolloutFailed {
+        count: failures.len(),
+        details: failures
+            .iter()
+            .map(|failure| format!("  {}", failure))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })
+}
+
+// The problems of the workload's pods followed by the latest warning events of the
+// workload, its ReplicaSets and its pods. Lookup failures are reported in place.
+async fn diagnose(
+    client: &Client,
+    resource: &AppliedResource,
+    pod_selector: Option<&str>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pod_names = Vec::new();
+    if let Some(selector) = pod_selector {
+        let pods: Api<Pod> = Api::namespaced(client.clone(), &resource.namespace);
+        match pods.list(&ListParams::default().labels(selector)).await {
+            Ok(list) => {
+                for pod in &list.items {
+                    pod_names.extend(pod.metadata.name.clone());
+                    lines.extend(pod_problems(pod));
+                }
+            }
+            Err(e) => lines.push(format!("could not list pods: {}", e)),
+        }
+    }
+
+    let events: Api<Event> = Api::namespaced(client.clone(), &resource.namespace);
+    match events
+        .list(&ListParams::default().fields("type=Warning"))
+        .await
+    {
+        Ok(list) => {
+            let owned_prefix = format!("{}-", resource.name);
+            let mut related = list
+                .items
+                .into_iter()
+                .filter(|event| {
+                    event.involved_object.name.as_deref().is_some_and(|name| {
+                        name == resource.name
+                            || name.starts_with(&owned_prefix)
+                            || pod_names.iter().any(|pod| pod == name)
+                    })
+                })
+                .collect::<Vec<_>>();
+            related.sort_by_key(event_time);
+            let skip = related.len().saturating_sub(MAX_EVENTS);
+            lines.extend(related.iter().skip(skip).map(event_line));
+        }
+        Err(e) => lines.push(format!("could not list events: {}", e)),
+    }
+    lines
+}
+
+fn event_time(event: &Event) -> Option<chrono::DateTime<chrono::Utc>> {
+    event
+        .last_timestamp
+        .as_ref()
+        .map(|time| time.0)
+        .or_else(|| event.event_time.as_ref().map(|time| time.0))
+        .or_else(|| {
+            event
+                .metadata
+                .creation_timestamp
+                .as_ref()
+                .map(|time| time.0)
+        })
+}
+
+pub fn event_line(event: &Event) -> String {
+    let object = &event.involved_object;
+    let repeated = match event.count {
+        Some(count) if count > 1 => format!(" (x{})", count),
+        _ => String::new(),
+    };
+    format!(
+        "event {}/{}: {}: {}{}",
+        object.kind.as_deref().unwrap_or_default().to_lowercase(),
+        object.name.as_deref().unwrap_or_default(),
+        event.reason.as_deref().unwrap_or_default(),
+        event.message.as_deref().unwrap_or_default().trim(),
+        repeated
+    )
+}
+
+// What is wrong with `pod`: unschedulable, containers stuck waiting, or containers
+// that exited with an error now or on their last run.
+pub fn pod_problems(pod: &Pod) -> Vec<String> {
+    let name = pod.metadata.name.as_deref().unwrap_or_default();
+    let mut problems = Vec::new();
+    let Some(status) = &pod.status else {
+        return problems;
+    };
+
+    for condition in status.conditions.iter().flatten() {
+        if condition.type_ == "PodScheduled" && condition.status == "False" {
+            problems.push(format!(
+                "pod/{} is not scheduled: {}",
+                name,
+                condition
+                    .message
+                    .as_deref()
+                    .or(condition.reason.as_deref())
+                    .unwrap_or_default()
+            ));
+        }
+    }
+
+    let containers = status
+        .init_container_statuses
+        .iter()
+        .flatten()
+        .chain(status.container_statuses.iter().flatten());
+    for container in containers {
+        let state = container.state.as_ref();
+        if let Some(waiting) = state.and_then(|state| state.waiting.as_ref()) {
+            let reason = waiting.reason.as_deref().unwrap_or("Waiting");
+            if !STARTING_REASONS.contains(&reason) {
+                problems.push(with_message(
+                    format!(
+                        "pod/{} container {} is waiting: {}",
+                        name, container.name, reason
+                    ),
+                    waiting.message.as_deref(),
+                ));
+            }
+        }
+        if let Some(terminated) = state.and_then(|state| state.terminated.as_ref()) {
+            if terminated.exit_code != 0 {
+                problems.push(with_message(
+                    format!(
+                        "pod/{} container {} terminated: {} (exit code {})",
+                        name,
+                        container.name,
+                        terminated.reason.as_deref().unwrap_or("Error"),
+                        terminated.exit_code
+                    ),
+                    terminated.message.as_deref(),
+                ));
+            }
+        }
+        if let Some(terminated) = container
+            .last_state
+            .as_ref()
+            .and_then(|state| state.terminated.as_ref())
+        {
+            problems.push(with_message(
+                format!(
+                    "pod/{} container {} last terminated: {} (exit code {}), {} restart(s)",
+                    name,
+                    container.name,
+                    terminated.reason.as_deref().unwrap_or("Error"),
+                    terminated.exit_code,
+                    container.restart_count
+                ),
+                terminated.message.as_deref(),
+            ));
+        }
+    }
+    problems
+}
+
+fn with_message(line: String, message: Option<&str>) -> String {
+    match message.map(str::trim).filter(|message| !message.is_empty()) {
+        Some(message) => format!("{}: {}", line, message),
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn from_json<K: DeserializeOwned>(value: serde_json::Value) -> K {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn deployments_are_ready_once_every_updated_replica_is_available() {
+        let deployment = |status: serde_json::Value| -> Deployment {
+            from_json(json!({
+                "metadata": { "name": "api", "generation": 2 },
+                "spec": {
+                    "replicas": 2,
+                    "selector": { "matchLabels": { "app": "api" } },
+                    "template": {},
+                },
+                "status": status,
+            }))
+        };
+
+        assert_eq!(
+            deployment_state(&deployment(json!({ "observedGeneration": 1 }))),
+            RolloutState::Progressing("waiting for the new spec to be observed".to_string())
+        );
+        assert_eq!(
+            deployment_state(&deployment(json!({
+                "observedGeneration": 2, "replicas": 3, "updatedReplicas": 2, "availableReplicas": 2,
+            }))),
+            RolloutState::Progressing("1 old replicas pending termination".to_string())
+        );
+        assert_eq!(
+            deployment_state(&deployment(json!({
+                "observedGeneration": 2, "replicas": 2, "updatedReplicas": 2, "availableReplicas": 2,
+            }))),
+            RolloutState::Ready
+        );
+        assert!(matches!(
+            deployment_state(&deployment(json!({
+                "observedGeneration": 2,
+                "conditions": [{
+                    "type": "Progressing",
+                    "status": "False",
+                    "reason": "ProgressDeadlineExceeded",
+                    "message": "ReplicaSet \"api-5d8f\" has timed out progressing.",
+                }],
+            }))),
+            RolloutState::Failed(_)
+        ));
+        assert_eq!(
+            deployment(json!({})).pod_selector(),
+            Some("app=api".to_string())
+        );
+    }
+
+    #[test]
+    fn other_workloads_follow_kubectl_rollout_status() {
+        let stateful_set: StatefulSet = from_json(json!({
+            "metadata": { "name": "db", "generation": 1 },
+            "spec": { "replicas": 2, "selector": {}, "serviceName": "db", "template": {} },
+            "status": {
+                "observedGeneration": 1, "replicas": 2, "readyReplicas": 2,
+                "currentRevision": "db-1", "updateRevision": "db-2", "updatedReplicas": 1,
+            },
+        }));
+        assert_eq!(
+            stateful_set_state(&stateful_set),
+            RolloutState::Progressing("1 of 2 pods updated".to_string())
+        );
+
+        let daemon_set: DaemonSet = from_json(json!({
+            "metadata": { "name": "agent", "generation": 1 },
+            "spec": { "selector": {}, "template": {} },
+            "status": {
+                "observedGeneration": 1, "desiredNumberScheduled": 3, "currentNumberScheduled": 3,
+                "numberMisscheduled": 0, "numberReady": 3, "updatedNumberScheduled": 3,
+                "numberAvailable": 3,
+            },
+        }));
+        assert_eq!(daemon_set_state(&daemon_set), RolloutState::Ready);
+
+        let job: Job = from_json(json!({
+            "metadata": { "name": "migrate" },
+            "status": { "failed": 6, "conditions": [{
+                "type": "Failed", "status": "True", "reason": "BackoffLimitExceeded",
+                "message": "Job has reached the specified backoff limit",
+            }] },
+        }));
+        assert_eq!(
+            job_state(&job),
+            RolloutState::Failed(
+                "job failed: BackoffLimitExceeded: Job has reached the specified backoff limit"
+                    .to_string()
+            )
+        );
+        assert_eq!(job.pod_selector(), Some("job-name=migrate".to_string()));
+    }
+
+    #[test]
+    fn pod_problems_explain_crash_loops() {
+        let pod: Pod = from_json(json!({
+            "metadata": { "name": "api-5d8f-x2x" },
+            "status": {
+                "containerStatuses": [{
+                    "name": "api",
+                    "image": "api:1.0.0",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": 4,
+                    "state": { "waiting": {
+                        "reason": "CrashLoopBackOff",
+                        "message": "back-off 1m20s restarting failed container",
+                    } },
+                    "lastState": { "terminated": { "reason": "Error", "exitCode": 1 } },
+                }, {
+                    "name": "sidecar",
+                    "image": "proxy",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": 0,
+                    "state": { "waiting": { "reason": "ContainerCreating" } },
+                }],
+            },
+        }));
+
+        assert_eq!(
+            pod_problems(&pod),
+            vec![
+                "pod/api-5d8f-x2x container api is waiting: CrashLoopBackOff: back-off 1m20s restarting failed container",
+                "pod/api-5d8f-x2x container api last terminated: Error (exit code 1), 4 restart(s)",
+            ]
+        );
+
+        let event: Event = from_json(json!({
+            "metadata": { "name": "api-5d8f-x2x.1" },
+            "involvedObject": { "kind": "Pod", "name": "api-5d8f-x2x" },
+            "reason": "BackOff",
+            "message": "Back-off restarting failed container api",
+            "count": 12,
+        }));
+        assert_eq!(
+            event_line(&event),
+            "event pod/api-5d8f-x2x: BackOff: Back-off restarting failed container api (x12)"
+        );
+    }
+}
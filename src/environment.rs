@@ -1,19 +1,27 @@
-use std::{error::Error, path::Path};
-
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Deserializer};
 use toml::{map::Map, Value};
 
+use crate::errors::VariableError;
 use crate::filesystem;
+use crate::lint::Severity;
 use crate::roomservice::config::Config;
-use crate::utils::get_current_timestamp;
+use crate::templates::engine::TemplateContext;
 use crate::LOGGER;
 
 const SCHEMA_V02: &str = "0.2.0";
 const SCHEMA_V03: &str = "0.3.0";
 const SCHEMA_V04: &str = "0.4.0";
-const SCHEMA_V05: &str = "0.5.0";
+pub const SCHEMA_V05: &str = "0.5.0";
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 #[serde(untagged)]
 pub enum RegistryConfig {
     Simple(String),
@@ -252,7 +260,7 @@ impl ResolvedRegistry {
     }
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 pub struct Environment {
     pub schema_version: String,
     pub name: String,
@@ -268,6 +276,16 @@ pub struct Environment {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub build: Option<BuildPolicy>,
     pub environment_variables: Option<Vec<EnvironmentVariable>>,
+    /// Typed values shared by every service, from `[values]` and `values/*.toml`.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    #[schemars(with = "serde_json::Map<String, serde_json::Value>")]
+    pub values: Map<String, Value>,
+    /// Extra labels added to every generated resource, from `[labels]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Severities of `sailr lint` rules, keyed by rule ID, from `[lint]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub lint: BTreeMap<String, Severity>,
 }
 
 impl Environment {
@@ -287,6 +305,9 @@ impl Environment {
             platform: None,
             build: None,
             environment_variables: Some(Vec::new()),
+            values: Map::new(),
+            labels: BTreeMap::new(),
+            lint: BTreeMap::new(),
         }
     }
 
@@ -477,13 +498,65 @@ impl Environment {
     // Loads the environment configuration from the `./k8s/environments/<name>/config.toml` file, overriding default values set in the constructor.
     // An error is returned if the file is missing, cannot be read, or contains an incompatible schema version.
     pub fn load_from_file(name: &str) -> Result<Self, Box<dyn Error>> {
-        let (raw, inherited) = Self::resolve_raw_environment(name, &mut Vec::new(), &|env_name| {
-            Self::read_environment_contents(env_name)
-        })?;
+        let (raw, inherited) = Self::resolve_raw_environment(
+            name,
+            &mut Vec::new(),
+            &|env_name| Self::read_environment_contents(env_name),
+            &|env_name| Self::read_values_files(env_name),
+            &mut Origins::new(),
+        )?;
 
         Self::environment_from_raw(raw, name, inherited)
     }
 
+    // The fully merged config of environment `name` as TOML, with a comment after every
+    // value naming the file it came from. The environment is loaded first, so the
+    // result is known to be valid. Sensitive literal values are masked.
+    pub fn resolve_annotated(name: &str) -> Result<String, Box<dyn Error>> {
+        let (raw, origins) = Self::resolve_masked(name)?;
+        annotate_origins(&raw, &origins)
+    }
+
+    // The fully merged config of environment `name` as TOML, like `resolve_annotated`
+    // but without the comments.
+    pub fn resolve_contents(name: &str) -> Result<String, Box<dyn Error>> {
+        let (raw, _) = Self::resolve_masked(name)?;
+        Ok(toml::to_string(&raw)?)
+    }
+
+    fn resolve_masked(name: &str) -> Result<(Value, Origins), Box<dyn Error>> {
+        Self::load_from_file(name)?;
+        let mut origins = Origins::new();
+        let (mut raw, _) = Self::resolve_raw_environment(
+            name,
+            &mut Vec::new(),
+            &|env_name| Self::read_environment_contents(env_name),
+            &|env_name| Self::read_values_files(env_name),
+            &mut origins,
+        )?;
+        mask_sensitive_values(&mut raw);
+        Ok((raw, origins))
+    }
+
+    // Reads every `./k8s/environments/<name>/values/*.toml` file, sorted by file name.
+    // A missing `values` directory is not an error.
+    fn read_values_files(name: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let values_dir = Path::new("./k8s/environments").join(name).join("values");
+        if !values_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&values_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                files.push((path.display().to_string(), std::fs::read_to_string(&path)?));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
     fn read_environment_contents(name: &str) -> Result<String, Box<dyn Error>> {
         let filemanager = filesystem::FileSystemManager::new(
             Path::new("./k8s/environments")
@@ -507,6 +580,8 @@ impl Environment {
         name: &str,
         stack: &mut Vec<String>,
         read_config: &EnvironmentReader<'_>,
+        read_values: &ValuesReader<'_>,
+        origins: &mut Origins,
     ) -> Result<(Value, bool), Box<dyn Error>> {
         if let Some(cycle_start) = stack.iter().position(|entry| entry == name) {
             let mut cycle = stack[cycle_start..].to_vec();
@@ -519,8 +594,14 @@ impl Environment {
 
         stack.push(name.to_string());
         let contents = read_config(name)?;
-        let raw = toml::from_str::<Value>(&contents)?;
+        let mut raw = toml::from_str::<Value>(&contents)?;
+        let own = raw.clone();
+        let values_files = read_values(name)?;
+        merge_values_files(&mut raw, values_files.clone())?;
         let Some(base_name) = raw.get("extends").and_then(Value::as_str) else {
+            strip_directives(&mut raw);
+            record_origins(&own, "", &config_path(name), origins);
+            record_values_origins(&values_files, origins);
             stack.pop();
             return Ok((raw, false));
         };
@@ -528,13 +609,18 @@ impl Environment {
         let child_defines_name = raw
             .as_table()
             .is_some_and(|table| table.contains_key("name"));
-        let (mut resolved, _) = Self::resolve_raw_environment(base_name, stack, read_config)?;
+        let (mut resolved, _) =
+            Self::resolve_raw_environment(base_name, stack, read_config, read_values, origins)?;
         merge_environment_value(&mut resolved, raw)?;
+        strip_directives(&mut resolved);
+        record_origins(&own, "", &config_path(name), origins);
+        record_values_origins(&values_files, origins);
 
         if let Some(table) = resolved.as_table_mut() {
             table.remove("extends");
             if !child_defines_name {
                 table.insert("name".to_string(), Value::String(name.to_string()));
+                origins.insert("name".to_string(), config_path(name));
             }
         }
 
@@ -716,13 +802,25 @@ impl Environment {
         );
 
         let contents = filemanager.read_file(&"config.toml".to_string(), None)?;
-        let migrated = Self::migrate_contents_to_v05(&contents)?;
+        let mut migrated = Self::migrate_contents_to_v05(&contents)?;
+        crate::schema::write_environment_schema(Path::new(crate::schema::SCHEMA_DIR))?;
+        if !migrated.starts_with("#:schema") {
+            migrated = format!("{}\n\n{}", crate::schema::ENVIRONMENT_SCHEMA_HINT, migrated);
+        }
         filemanager.create_file(&"config.toml".to_string(), &migrated)?;
         Ok(migrated)
     }
 
+    // Writes the environment to `./k8s/environments/<name>/config.toml`, with a
+    // `#:schema` hint pointing editors at `./k8s/schemas/environment.schema.json`,
+    // which is written alongside.
     pub fn save_to_file(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let contents = toml::to_string(&self)?;
+        crate::schema::write_environment_schema(Path::new(crate::schema::SCHEMA_DIR))?;
+        let contents = format!(
+            "{}\n\n{}",
+            crate::schema::ENVIRONMENT_SCHEMA_HINT,
+            toml::to_string(&self)?
+        );
 
         let filemanager = filesystem::FileSystemManager::new(
             Path::new("./k8s/environments")
@@ -736,17 +834,35 @@ impl Environment {
         Ok(())
     }
 
-    pub fn get_variables(
-        &self,
-        service: &Service,
-    ) -> Result<Vec<(String, String)>, crate::workflow::error::RegistryConfigError> {
+    /// The namespace of services that don't set their own.
+    pub fn default_namespace(&self) -> String {
+        crate::templates::namespaces::environment_namespace(&self.name)
+    }
+
+    pub fn get_variables(&self, service: &Service) -> Result<Vec<(String, String)>, VariableError> {
+        let mut variables = self.service_variables(service)?;
+        for (name, value) in self.resolve_environment_variables()? {
+            let rendered_value = match value {
+                Some(Value::String(s)) => s,
+                Some(v) => v.to_string(),
+                None => String::new(),
+            };
+
+            variables.push((name, rendered_value));
+        }
+
+        Ok(variables)
+    }
+
+    // The variables every service gets from the environment and its own definition,
+    // without the `[[environment_variables]]`.
+    fn service_variables(&self, service: &Service) -> Result<Vec<(String, String)>, VariableError> {
         let mut variables = vec![
             ("name".to_string(), self.name.clone()),
             ("log_level".to_string(), self.log_level.clone()),
             ("replicas".to_string(), self.default_replicas.to_string()),
             ("registry".to_string(), self.registry.prefix()?),
             ("domain".to_string(), self.domain.clone()),
-            ("deployment_date".to_string(), get_current_timestamp()),
             (
                 "default_replicas".to_string(),
                 self.default_replicas.to_string(),
@@ -759,7 +875,7 @@ impl Environment {
             ("service_name".to_string(), service.name.clone()),
             (
                 "service_namespace".to_string(),
-                service.namespace_or(&self.name).to_string(),
+                service.namespace_or(&self.default_namespace()).to_string(),
             ),
         ];
 
@@ -771,19 +887,60 @@ impl Environment {
 
         variables.push(("service_version".to_string(), service.get_version()));
 
-        if let Some(env_vars) = &self.environment_variables {
-            env_vars.iter().for_each(|e| {
-                let rendered_value = match e.value.clone() {
-                    Some(Value::String(s)) => s,
-                    Some(v) => v.to_string(),
-                    None => String::new(),
-                };
-
-                variables.push((e.name.clone(), rendered_value));
-            })
+        Ok(variables)
+    }
+
+    // The `[[environment_variables]]`, with values read from their sources.
+    pub fn resolve_environment_variables(
+        &self,
+    ) -> Result<Vec<(String, Option<Value>)>, VariableError> {
+        self.resolve_environment_variables_from(&VariableSources::process())
+    }
+
+    // `resolve_environment_variables`, reading the environment through `sources`.
+    pub fn resolve_environment_variables_from(
+        &self,
+        sources: &VariableSources,
+    ) -> Result<Vec<(String, Option<Value>)>, VariableError> {
+        self.environment_variables
+            .iter()
+            .flatten()
+            .map(|variable| Ok((variable.name.clone(), variable.resolve_from(sources)?)))
+            .collect()
+    }
+
+    // Builds the template context for a service. It holds everything `get_variables`
+    // returns, except environment variables keep their TOML types, plus `values`: the
+    // environment's values with the service's `[service.values]` merged on top.
+    pub fn get_template_context(
+        &self,
+        service: &Service,
+    ) -> Result<TemplateContext, VariableError> {
+        self.template_context(service, self.resolve_environment_variables()?)
+    }
+
+    // The template context of `service`, given the resolved environment variables.
+    fn template_context(
+        &self,
+        service: &Service,
+        variables: Vec<(String, Option<Value>)>,
+    ) -> Result<TemplateContext, VariableError> {
+        let mut context = TemplateContext::from_pairs(&self.service_variables(service)?);
+
+        for (name, value) in variables {
+            match value {
+                Some(value) => context.insert(name, toml_to_json(&value)),
+                None => context.insert(name, String::new()),
+            }
         }
 
-        Ok(variables)
+        let mut values = Value::Table(self.values.clone());
+        if let Some(service_values) = &service.values {
+            merge_value(&mut values, Value::Table(service_values.clone()));
+        }
+        context.insert("values", toml_to_json(&values));
+
+        Ok(context)
     }
 
     fn upgrade_builds_to_v05(&mut self) {
@@ -809,6 +966,53 @@ impl Environment {
 }
 
 type EnvironmentReader<'a> = dyn Fn(&str) -> Result<String, Box<dyn Error>> + 'a;
+type ValuesReader<'a> = dyn Fn(&str) -> Result<Vec<(String, String)>, Box<dyn Error>> + 'a;
+
+// Merges `values/*.toml` files (in the order given) over the `[values]` table of a
+// single environment's raw config, before inheritance is resolved.
+fn merge_values_files(raw: &mut Value, files: Vec<(String, String)>) -> Result<(), Box<dyn Error>> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let Some(table) = raw.as_table_mut() else {
+        return Ok(());
+    };
+    let values = table
+        .entry("values".to_string())
+        .or_insert_with(|| Value::Table(Map::new()));
+
+    for (path, contents) in files {
+        let file_values = toml::from_str::<Value>(&contents).map_err(|e| {
+            Box::new(std::io::Error::other(format!(
+                "Failed to parse values file {}: {}",
+                path, e
+            ))) as Box<dyn Error>
+        })?;
+        merge_value(values, file_values);
+    }
+
+    Ok(())
+}
+
+fn toml_to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Integer(i) => serde_json::Value::from(*i),
+        Value::Float(f) => serde_json::Number::from_f64(*f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+        Value::Datetime(datetime) => serde_json::Value::String(datetime.to_string()),
+        Value::Array(items) => serde_json::Value::Array(items.iter().map(toml_to_json).collect()),
+        Value::Table(table) => serde_json::Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.clone(), toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
 
 fn merge_environment_value(base: &mut Value, child: Value) -> Result<(), Box<dyn Error>> {
     let (Some(base_table), Some(child_table)) = (base.as_table_mut(), child.as_table()) else {
@@ -852,9 +1056,12 @@ fn merge_value(base: &mut Value, child: Value) {
 }
 
 fn merge_named_array(base: &mut Map<String, Value>, key: String, child_value: Value) {
-    let Value::Array(child_items) = child_value else {
-        base.insert(key, child_value);
-        return;
+    let (replace, child_items) = match list_directive(child_value) {
+        Ok(list) => list,
+        Err(child_value) => {
+            base.insert(key, child_value);
+            return;
+        }
     };
 
     let base_items = base
@@ -865,7 +1072,7 @@ fn merge_named_array(base: &mut Map<String, Value>, key: String, child_value: Va
         })
         .unwrap_or_default();
 
-    let mut merged_items = base_items;
+    let mut merged_items = if replace { Vec::new() } else { base_items };
 
     for child_item in child_items {
         let Some(child_name) = named_array_item_name(&child_item) else {
@@ -873,7 +1080,9 @@ fn merge_named_array(base: &mut Map<String, Value>, key: String, child_value: Va
             continue;
         };
 
-        if let Some(base_item) = merged_items
+        if is_removed(&child_item) {
+            merged_items.retain(|item| named_array_item_name(item) != Some(child_name));
+        } else if let Some(base_item) = merged_items
             .iter_mut()
             .find(|item| named_array_item_name(item) == Some(child_name))
         {
@@ -886,6 +1095,215 @@ fn merge_named_array(base: &mut Map<String, Value>, key: String, child_value: Va
     base.insert(key, Value::Array(merged_items));
 }
 
+// Top-level arrays whose entries are merged by `name`.
+const NAMED_ARRAYS: &[&str] = &["service", "environment_variables"];
+
+// Splits a named array into whether it replaces the inherited one and its entries. It
+// is either a plain array, which merges, or `{ replace = true, items = [...] }`.
+// Anything else is handed back.
+fn list_directive(value: Value) -> Result<(bool, Vec<Value>), Value> {
+    match value {
+        Value::Array(items) => Ok((false, items)),
+        Value::Table(table)
+            if !table.is_empty() && table.keys().all(|key| key == "replace" || key == "items") =>
+        {
+            let replace = table
+                .get("replace")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let items = match table.get("items") {
+                Some(Value::Array(items)) => items.clone(),
+                _ => Vec::new(),
+            };
+            Ok((replace, items))
+        }
+        other => Err(other),
+    }
+}
+
+// Whether a named array entry is `remove = true`, which drops the inherited entry.
+fn is_removed(item: &Value) -> bool {
+    item.get("remove").and_then(Value::as_bool).unwrap_or(false)
+}
+
+// Turns the named arrays of a resolved config back into plain arrays, without the
+// entries that only remove something.
+fn strip_directives(raw: &mut Value) {
+    let Some(table) = raw.as_table_mut() else {
+        return;
+    };
+    for key in NAMED_ARRAYS {
+        let Some(value) = table.remove(*key) else {
+            continue;
+        };
+        match list_directive(value) {
+            Ok((_, items)) => {
+                let items = items
+                    .into_iter()
+                    .filter(|item| !is_removed(item))
+                    .map(|mut item| {
+                        if let Some(item) = item.as_table_mut() {
+                            item.remove("remove");
+                        }
+                        item
+                    })
+                    .collect();
+                table.insert(key.to_string(), Value::Array(items));
+            }
+            Err(value) => {
+                table.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// The file each field of a resolved config was set by, keyed by its path:
+/// `domain`, `values.resources.cpu` or `service[api].version`.
+pub type Origins = BTreeMap<String, String>;
+
+fn config_path(name: &str) -> String {
+    format!("k8s/environments/{}/config.toml", name)
+}
+
+// Records `file` as the origin of every field `value` sets below `path`, after the
+// removals and replacements it makes.
+fn record_origins(value: &Value, path: &str, file: &str, origins: &mut Origins) {
+    match value {
+        Value::Table(table) if !table.is_empty() => {
+            for (key, child) in table {
+                let child_path = join_path(path, key);
+                if path.is_empty() && key == "extends" {
+                    continue;
+                }
+                if path.is_empty() && NAMED_ARRAYS.contains(&key.as_str()) {
+                    record_named_array_origins(child, &child_path, file, origins);
+                } else {
+                    record_origins(child, &child_path, file, origins);
+                }
+            }
+        }
+        _ => {
+            forget_origins(origins, path);
+            origins.insert(path.to_string(), file.to_string());
+        }
+    }
+}
+
+fn record_named_array_origins(value: &Value, path: &str, file: &str, origins: &mut Origins) {
+    let Ok((replace, items)) = list_directive(value.clone()) else {
+        return record_origins(value, path, file, origins);
+    };
+    if replace {
+        let prefix = format!("{}[", path);
+        origins.retain(|key, _| !key.starts_with(&prefix));
+    }
+    for item in &items {
+        let Some(name) = named_array_item_name(item) else {
+            continue;
+        };
+        let item_path = format!("{}[{}]", path, name);
+        if is_removed(item) {
+            forget_origins(origins, &item_path);
+        } else {
+            record_origins(item, &item_path, file, origins);
+        }
+    }
+}
+
+fn record_values_origins(files: &[(String, String)], origins: &mut Origins) {
+    for (path, contents) in files {
+        if let Ok(values) = toml::from_str::<Value>(contents) {
+            record_origins(&values, "values", path.trim_start_matches("./"), origins);
+        }
+    }
+}
+
+// Forgets the origins of `path` and of everything below it.
+fn forget_origins(origins: &mut Origins, path: &str) {
+    origins.retain(|key, _| {
+        key != path
+            && !key
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('['))
+    });
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+// The origin of `path`, or of the first field below it for values that are printed
+// inline, such as `value = { env = "TOKEN" }`.
+fn origin_of<'a>(origins: &'a Origins, path: &str) -> Option<&'a str> {
+    origins
+        .get(path)
+        .or_else(|| {
+            origins
+                .range(path.to_string()..)
+                .take_while(|(key, _)| key.starts_with(path))
+                .find(|(key, _)| {
+                    key[path.len()..].starts_with('.') || key[path.len()..].starts_with('[')
+                })
+                .map(|(_, origin)| origin)
+        })
+        .map(String::as_str)
+}
+
+// Replaces literal values of sensitive environment variables with the redaction mask.
+fn mask_sensitive_values(raw: &mut Value) {
+    let Some(Value::Array(variables)) = raw.get_mut("environment_variables") else {
+        return;
+    };
+    for variable in variables {
+        let Ok(parsed) = variable.clone().try_into::<EnvironmentVariable>() else {
+            continue;
+        };
+        if parsed.is_sensitive() && matches!(parsed.source(), Ok(None)) && parsed.value.is_some() {
+            if let Some(table) = variable.as_table_mut() {
+                table.insert(
+                    "value".to_string(),
+                    Value::String(crate::ui::REDACTED.to_string()),
+                );
+            }
+        }
+    }
+}
+
+// Renders `raw` as TOML with a `# <file>` comment after every value.
+fn annotate_origins(raw: &Value, origins: &Origins) -> Result<String, Box<dyn Error>> {
+    let mut doc = toml::to_string_pretty(raw)?.parse::<toml_edit::DocumentMut>()?;
+    annotate_table(doc.as_table_mut(), "", origins);
+    Ok(doc.to_string())
+}
+
+fn annotate_table(table: &mut toml_edit::Table, path: &str, origins: &Origins) {
+    for (key, item) in table.iter_mut() {
+        let item_path = join_path(path, key.get());
+        match item {
+            toml_edit::Item::Value(value) => {
+                if let Some(origin) = origin_of(origins, &item_path) {
+                    value.decor_mut().set_suffix(format!(" # {}", origin));
+                }
+            }
+            toml_edit::Item::Table(table) => annotate_table(table, &item_path, origins),
+            toml_edit::Item::ArrayOfTables(tables) => {
+                for table in tables.iter_mut() {
+                    let entry_path = match table.get("name").and_then(toml_edit::Item::as_str) {
+                        Some(name) => format!("{}[{}]", item_path, name),
+                        None => item_path.clone(),
+                    };
+                    annotate_table(table, &entry_path, origins);
+                }
+            }
+            toml_edit::Item::None => {}
+        }
+    }
+}
+
 fn named_array_item_name(item: &Value) -> Option<&str> {
     item.get("name").and_then(Value::as_str)
 }
@@ -933,10 +1351,57 @@ fn append_service_to_document(doc: &mut toml_edit::DocumentMut, service: &Servic
         .push(table);
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 pub struct EnvironmentVariable {
     pub name: String,
+    /// A TOML value, or where to read it from when it is used: `{ env = "NAME" }`,
+    /// `{ file = "path" }` or `{ command = "..." }`.
+    #[schemars(with = "Option<serde_json::Value>")]
     pub value: Option<Value>,
+    /// Whether the value is kept out of logs and reports. Defaults to true for values
+    /// read from the environment, a file or a command, which are then masked when they
+    /// are at least `MIN_MASKED_LENGTH` characters long.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensitive: Option<bool>,
+}
+
+/// Where a variable's value is read from when it is resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VariableSource {
+    /// A variable of the sailr process's environment.
+    Env(String),
+    /// A file, relative to the project root.
+    File(String),
+    /// The output of a `sh -c` command.
+    Command(String),
+}
+
+const VARIABLE_SOURCES: &[&str] = &["env", "file", "command"];
+
+/// Values read from a source without `sensitive = true` shorter than this are not
+/// masked, so that values such as `true` or `prod` don't blank out unrelated output.
+pub const MIN_MASKED_LENGTH: usize = 8;
+
+// Command outputs by command, so each command runs once per sailr invocation. The map
+// is only locked to find a command's cell; the command runs under the cell's own lock.
+static COMMAND_OUTPUTS: Lazy<Mutex<HashMap<String, Arc<OnceCell<String>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// What resolving a variable reads `{ env = "..." }` values from, and the UI its
+/// sensitive values are masked in.
+pub struct VariableSources<'a> {
+    pub env: &'a dyn Fn(&str) -> Option<String>,
+    pub ui: &'a crate::ui::SailrUI,
+}
+
+impl VariableSources<'_> {
+    /// The sailr process's environment and the logger.
+    pub fn process() -> Self {
+        VariableSources {
+            env: &|name| std::env::var(name).ok(),
+            ui: &LOGGER,
+        }
+    }
 }
 
 impl EnvironmentVariable {
@@ -944,12 +1409,139 @@ impl EnvironmentVariable {
         Self {
             name: name.to_string(),
             value,
+            sensitive: None,
         }
     }
 
     pub fn set_value(&mut self, value: Value) {
         self.value = Some(value);
     }
+
+    // The source of the value, when it is a table with a single `env`, `file` or
+    // `command` key. Any other table is an ordinary value.
+    pub fn source(&self) -> Result<Option<VariableSource>, VariableError> {
+        let Some(Value::Table(table)) = &self.value else {
+            return Ok(None);
+        };
+        if table.is_empty()
+            || !table
+                .keys()
+                .all(|key| VARIABLE_SOURCES.contains(&key.as_str()))
+        {
+            return Ok(None);
+        }
+        let invalid = |message: String| VariableError::InvalidSource {
+            variable: self.name.clone(),
+            message,
+        };
+        if table.len() > 1 {
+            return Err(invalid(format!(
+                "set only one of {}",
+                table.keys().cloned().collect::<Vec<_>>().join(", ")
+            )));
+        }
+        let (kind, target) = table.iter().next().expect("table has one entry");
+        let Some(target) = target.as_str() else {
+            return Err(invalid(format!("`{}` must be a string", kind)));
+        };
+        Ok(Some(match kind.as_str() {
+            "env" => VariableSource::Env(target.to_string()),
+            "file" => VariableSource::File(target.to_string()),
+            _ => VariableSource::Command(target.to_string()),
+        }))
+    }
+
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+            .unwrap_or_else(|| matches!(self.source(), Ok(Some(_))))
+    }
+
+    // Whether a resolved `value` is masked: always when the variable is marked
+    // sensitive, and only when it is long enough to not be mistaken for ordinary text
+    // when it is sensitive because it has a source.
+    fn masks(&self, value: &str) -> bool {
+        self.sensitive
+            .unwrap_or_else(|| self.is_sensitive() && value.chars().count() >= MIN_MASKED_LENGTH)
+    }
+
+    // The value, read from its source if it has one. Sensitive values are registered
+    // with the logger so they never show up in its output.
+    pub fn resolve(&self) -> Result<Option<Value>, VariableError> {
+        self.resolve_from(&VariableSources::process())
+    }
+
+    // `resolve`, reading the environment and masking values through `sources`.
+    pub fn resolve_from(&self, sources: &VariableSources) -> Result<Option<Value>, VariableError> {
+        let value = match self.source()? {
+            None => self.value.clone(),
+            Some(VariableSource::Env(name)) => match (sources.env)(&name) {
+                Some(value) => Some(Value::String(value)),
+                None => {
+                    return Err(VariableError::MissingEnv {
+                        variable: self.name.clone(),
+                        name,
+                    })
+                }
+            },
+            Some(VariableSource::File(path)) => match std::fs::read_to_string(&path) {
+                Ok(content) => Some(Value::String(
+                    content.trim_end_matches(['\n', '\r']).to_string(),
+                )),
+                Err(e) => {
+                    return Err(VariableError::MissingFile {
+                        variable: self.name.clone(),
+                        path,
+                        message: e.to_string(),
+                    })
+                }
+            },
+            Some(VariableSource::Command(command)) => {
+                Some(Value::String(self.run_command(&command)?))
+            }
+        };
+
+        let text = match &value {
+            Some(Value::String(value)) => Some(value.clone()),
+            Some(value) => Some(value.to_string()),
+            None => None,
+        };
+        if let Some(text) = text.filter(|text| self.masks(text)) {
+            sources.ui.redact(&text);
+        }
+        Ok(value)
+    }
+
+    fn run_command(&self, command: &str) -> Result<String, VariableError> {
+        let output = COMMAND_OUTPUTS
+            .lock()
+            .unwrap()
+            .entry(command.to_string())
+            .or_default()
+            .clone();
+        output
+            .get_or_try_init(|| self.spawn_command(command))
+            .cloned()
+    }
+
+    fn spawn_command(&self, command: &str) -> Result<String, VariableError> {
+        let failed = |message: String| VariableError::CommandFailed {
+            variable: self.name.clone(),
+            command: command.to_string(),
+            message,
+        };
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .map_err(|e| failed(e.to_string()))?;
+        if !output.status.success() {
+            return Err(failed(output.status.to_string()));
+        }
+        let stdout = String::from_utf8(output.stdout)
+            .map_err(|_| failed("output is not valid UTF-8".to_string()))?;
+        Ok(stdout.trim_end_matches(['\n', '\r']).to_string())
+    }
 }
 
 fn default_service_version() -> String {
@@ -1010,7 +1602,7 @@ where
     }
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, schemars::JsonSchema)]
 #[serde(untagged)]
 pub enum CommandSpec {
     Single(String),
@@ -1045,7 +1637,9 @@ fn command_spec_to_shell(command: CommandSpec) -> String {
     command.into_vec().join(" && ")
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Default)]
+#[derive(
+    Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, Default, schemars::JsonSchema,
+)]
 pub struct BuildPolicy {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub engine: Option<BuildEngine>,
@@ -1060,7 +1654,15 @@ pub struct BuildPolicy {
 }
 
 #[derive(
-    Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize, clap::ValueEnum,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+    clap::ValueEnum,
+    schemars::JsonSchema,
 )]
 #[serde(rename_all = "kebab-case")]
 pub enum BuildEngine {
@@ -1068,7 +1670,7 @@ pub enum BuildEngine {
     Runkernel,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, schemars::JsonSchema)]
 pub struct Service {
     pub name: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -1080,6 +1682,7 @@ pub struct Service {
         deserialize_with = "deserialize_build_config",
         skip_serializing_if = "Option::is_none"
     )]
+    #[schemars(with = "Option<ServiceBuildSchema>")]
     pub build: Option<ServiceBuildConfig>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub hooks: Option<ServiceHooks>,
@@ -1090,9 +1693,53 @@ pub struct Service {
         skip_serializing_if = "Option::is_none"
     )]
     pub template_path: Option<String>,
+    /// Typed template values, available as `{{ values.* }}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Map<String, serde_json::Value>>")]
+    pub values: Option<Map<String, Value>>,
+    /// Helm chart rendered natively alongside (or instead of) the service templates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub helm: Option<ServiceHelmConfig>,
+    /// How the ConfigMap built from the service's `config/` directory is generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_map: Option<ServiceConfigMapConfig>,
+    /// Services whose workloads must be healthy before this service is deployed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deploy_after: Option<Vec<String>>,
+}
+
+// The schema of `[service.build]`, which also accepts the build path on its own.
+#[derive(schemars::JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code, clippy::large_enum_variant)]
+enum ServiceBuildSchema {
+    Path(String),
+    Config(ServiceBuildConfig),
+}
+
+#[derive(
+    Debug, Clone, Default, serde::Deserialize, serde::Serialize, PartialEq, schemars::JsonSchema,
+)]
+pub struct ServiceConfigMapConfig {
+    /// Append a hash of the content to the ConfigMap name and point the service's
+    /// workloads at it, so that a config change rolls the pods.
+    #[serde(default)]
+    pub hash_suffix: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, schemars::JsonSchema)]
+pub struct ServiceHelmConfig {
+    /// Path to a chart directory or a packaged `.tgz` chart, relative to the project root.
+    pub chart: String,
+    /// Values file layered over the chart's own `values.yaml`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<String>,
+    /// Release name exposed to the chart as `.Release.Name`. Defaults to the service name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, schemars::JsonSchema)]
 pub struct ServiceBuildConfig {
     pub path: String,
     #[serde(
@@ -1100,6 +1747,7 @@ pub struct ServiceBuildConfig {
         deserialize_with = "deserialize_optional_string_vec",
         skip_serializing_if = "Option::is_none"
     )]
+    #[schemars(with = "Option<CommandSpec>")]
     pub include: Option<Vec<String>>,
     #[serde(default, alias = "depends_on", skip_serializing_if = "Option::is_none")]
     pub relies_on: Option<Vec<String>>,
@@ -1135,7 +1783,7 @@ pub struct ServiceBuildConfig {
     pub push_command: Option<String>,
 }
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, PartialEq, schemars::JsonSchema)]
 pub struct ServiceHooks {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub pre_deploy: Option<CommandSpec>,
@@ -1152,6 +1800,10 @@ impl Service {
             build: None,
             hooks: None,
             template_path: None,
+            values: None,
+            helm: None,
+            config_map: None,
+            deploy_after: None,
         }
     }
 
@@ -1269,8 +1921,18 @@ mod tests {
         name: &str,
         sources: BTreeMap<&str, &str>,
     ) -> Result<Environment, Box<dyn Error>> {
-        let (raw, inherited) =
-            Environment::resolve_raw_environment(name, &mut Vec::new(), &|env_name| {
+        load_environment_with_values(name, sources, BTreeMap::new())
+    }
+
+    fn load_environment_with_values(
+        name: &str,
+        sources: BTreeMap<&str, &str>,
+        values_files: BTreeMap<&str, Vec<(&str, &str)>>,
+    ) -> Result<Environment, Box<dyn Error>> {
+        let (raw, inherited) = Environment::resolve_raw_environment(
+            name,
+            &mut Vec::new(),
+            &|env_name| {
                 sources
                     .get(env_name)
                     .map(|content| content.to_string())
@@ -1280,7 +1942,20 @@ mod tests {
                             env_name
                         ))) as Box<dyn Error>
                     })
-            })?;
+            },
+            &|env_name| {
+                Ok(values_files
+                    .get(env_name)
+                    .map(|files| {
+                        files
+                            .iter()
+                            .map(|(path, content)| (path.to_string(), content.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default())
+            },
+            &mut Origins::new(),
+        )?;
 
         Environment::environment_from_raw(raw, name, inherited)
     }
@@ -1588,6 +2263,171 @@ domain = "prod.example.com"
         assert_eq!(env.registry.prefix().unwrap(), "docker.io/staging");
     }
 
+    #[test]
+    fn test_service_values_are_typed_in_template_context() {
+        let config = r#"
+schema_version = "0.5.0"
+name = "dev"
+log_level = "INFO"
+domain = "dev.example.com"
+default_replicas = 1
+registry = "docker.io/acme"
+
+[[service]]
+name = "api"
+version = "1.0.0"
+
+[service.values]
+hosts = ["a.example.com", "b.example.com"]
+
+[service.values.resources.limits]
+cpu = "500m"
+memory = 512
+
+[[environment_variables]]
+name = "ports"
+value = [80, 443]
+"#;
+
+        let env = load_environment_from_sources("dev", BTreeMap::from([("dev", config)])).unwrap();
+        let service = env.get_service("api").unwrap();
+        let context = env.get_template_context(service).unwrap();
+
+        assert_eq!(
+            context.get("values"),
+            Some(&json!({
+                "hosts": ["a.example.com", "b.example.com"],
+                "resources": {"limits": {"cpu": "500m", "memory": 512}}
+            }))
+        );
+        assert_eq!(context.get("ports"), Some(&json!([80, 443])));
+        assert_eq!(context.get("service_name"), Some(&json!("api")));
+
+        let rendered = crate::templates::engine::TemplateEngine::new()
+            .strict(true)
+            .render(
+                "cpu: {{ values.resources.limits.cpu }}\n{{#each values.hosts}}\n- {{ this }}\n{{/each}}\n",
+                &context,
+            )
+            .unwrap();
+        assert_eq!(rendered, "cpu: 500m\n- a.example.com\n- b.example.com\n");
+    }
+
+    #[test]
+    fn test_service_helm_block_is_parsed() {
+        let config = r#"
+schema_version = "0.5.0"
+name = "dev"
+log_level = "INFO"
+domain = "dev.example.com"
+default_replicas = 1
+registry = "docker.io/acme"
+
+[[service]]
+name = "cache"
+
+[service.helm]
+chart = "charts/redis-18.1.0.tgz"
+values = "cache-values.yaml"
+"#;
+
+        let env = load_environment_from_sources("dev", BTreeMap::from([("dev", config)])).unwrap();
+        let service = env.get_service("cache").unwrap();
+
+        assert_eq!(
+            service.helm,
+            Some(ServiceHelmConfig {
+                chart: "charts/redis-18.1.0.tgz".to_string(),
+                values: Some("cache-values.yaml".to_string()),
+                release: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_values_files_merge_per_environment() {
+        let base = r#"
+schema_version = "0.5.0"
+name = "base"
+log_level = "INFO"
+domain = "base.example.com"
+default_replicas = 1
+registry = "docker.io/base"
+
+[values]
+tier = "inline"
+
+[[service]]
+name = "api"
+version = "1.0.0"
+
+[service.values.resources]
+cpu = "250m"
+"#;
+        let prod = r#"
+schema_version = "0.5.0"
+extends = "base"
+"#;
+
+        let env = load_environment_with_values(
+            "prod",
+            BTreeMap::from([("base", base), ("prod", prod)]),
+            BTreeMap::from([
+                (
+                    "base",
+                    vec![
+                        (
+                            "values/00-common.toml",
+                            "replicas = 1\n[resources]\ncpu = \"100m\"\nmemory = \"128Mi\"\n",
+                        ),
+                        ("values/10-base.toml", "tier = \"base\"\n"),
+                    ],
+                ),
+                ("prod", vec![("values/prod.toml", "replicas = 3\n")]),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            env.values.get("tier"),
+            Some(&Value::String("base".to_string()))
+        );
+        assert_eq!(env.values.get("replicas"), Some(&Value::Integer(3)));
+
+        let service = env.get_service("api").unwrap();
+        let context = env.get_template_context(service).unwrap();
+        assert_eq!(
+            context.get("values"),
+            Some(&json!({
+                "replicas": 3,
+                "tier": "base",
+                "resources": {"cpu": "250m", "memory": "128Mi"}
+            }))
+        );
+    }
+
+    #[test]
+    fn test_values_files_report_parse_errors() {
+        let config = r#"
+schema_version = "0.5.0"
+name = "dev"
+log_level = "INFO"
+domain = "dev.example.com"
+default_replicas = 1
+"#;
+
+        let err = load_environment_with_values(
+            "dev",
+            BTreeMap::from([("dev", config)]),
+            BTreeMap::from([("dev", vec![("values/broken.toml", "not = = toml")])]),
+        )
+        .unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("Failed to parse values file values/broken.toml"));
+    }
+
     #[test]
     fn test_environment_extends_reports_missing_base() {
         let child = r#"
@@ -1901,4 +2741,257 @@ name = "postgres"
             Some("aux/postgres")
         );
     }
+
+    #[test]
+    fn test_environment_variables_resolve_from_their_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret_file = dir.path().join("db.txt");
+        std::fs::write(&secret_file, "s3cr3t-from-file\n").unwrap();
+        let config = format!(
+            r#"
+schema_version = "0.5.0"
+name = "dev"
+log_level = "INFO"
+domain = "dev.example.com"
+default_replicas = 1
+registry = "docker.io/acme"
+
+[[service]]
+name = "api"
+
+[[environment_variables]]
+name = "DB_USER"
+value = {{ env = "SAILR_TEST_DB_USER" }}
+sensitive = false
+
+[[environment_variables]]
+name = "DB_PASSWORD"
+value = {{ file = "{}" }}
+
+[[environment_variables]]
+name = "DB_TOKEN"
+value = {{ command = "printf 'token-from-command\n\n'" }}
+
+[[environment_variables]]
+name = "DB_OPTIONS"
+value = {{ env = "prod", pool = 5 }}
+
+[[environment_variables]]
+name = "DB_PORT"
+value = {{ env = "SAILR_TEST_DB_PORT" }}
+"#,
+            secret_file.display()
+        );
+
+        let env = load_environment_from_sources("dev", BTreeMap::from([("dev", config.as_str())]))
+            .unwrap();
+        let service = env.get_service("api").unwrap();
+        let lookup = |name: &str| match name {
+            "SAILR_TEST_DB_USER" => Some("app-user".to_string()),
+            "SAILR_TEST_DB_PORT" => Some("5432".to_string()),
+            _ => None,
+        };
+        let ui = crate::ui::SailrUI::new(true, false);
+        let sources = VariableSources {
+            env: &lookup,
+            ui: &ui,
+        };
+        let variables = env.resolve_environment_variables_from(&sources).unwrap();
+        let context = env.template_context(service, variables).unwrap();
+
+        assert_eq!(context.get("DB_USER"), Some(&json!("app-user")));
+        assert_eq!(context.get("DB_PASSWORD"), Some(&json!("s3cr3t-from-file")));
+        assert_eq!(context.get("DB_TOKEN"), Some(&json!("token-from-command")));
+        assert_eq!(
+            context.get("DB_OPTIONS"),
+            Some(&json!({"env": "prod", "pool": 5}))
+        );
+        assert_eq!(context.get("DB_PORT"), Some(&json!("5432")));
+
+        let variables = env.environment_variables.as_ref().unwrap();
+        assert!(!variables[0].is_sensitive());
+        assert!(variables[1].is_sensitive());
+        assert!(!variables[3].is_sensitive());
+        assert!(variables[4].is_sensitive());
+        assert_eq!(
+            ui.redacted("user app-user, password s3cr3t-from-file, port 5432"),
+            "user app-user, password ******, port 5432"
+        );
+    }
+
+    #[test]
+    fn test_environment_variable_sources_report_what_is_missing() {
+        let variable = |name: &str, value: &str| {
+            let table: Value = toml::from_str(&format!("value = {}", value)).unwrap();
+            EnvironmentVariable::new(name, Some(table["value"].clone()))
+        };
+
+        assert_eq!(
+            variable("DB_PASSWORD", r#"{ env = "SAILR_TEST_UNSET_VARIABLE" }"#)
+                .resolve()
+                .unwrap_err()
+                .to_string(),
+            "Variable 'DB_PASSWORD' reads the environment variable 'SAILR_TEST_UNSET_VARIABLE', which is not set"
+        );
+        assert!(matches!(
+            variable("TOKEN", r#"{ command = "exit 3" }"#).resolve(),
+            Err(VariableError::CommandFailed { command, .. }) if command == "exit 3"
+        ));
+        assert!(matches!(
+            variable("TOKEN", r#"{ env = "A", file = "b" }"#).resolve(),
+            Err(VariableError::InvalidSource { .. })
+        ));
+    }
+
+    #[test]
+    fn test_environment_extends_removes_and_replaces_inherited_entries() {
+        let base = r#"
+schema_version = "0.5.0"
+name = "base"
+log_level = "INFO"
+domain = "base.example.com"
+default_replicas = 1
+registry = "docker.io/base"
+
+[[service]]
+name = "api"
+version = "1.0.0"
+
+[[service]]
+name = "worker"
+version = "1.0.0"
+
+[[environment_variables]]
+name = "API_URL"
+value = "https://base.example.com"
+
+[[environment_variables]]
+name = "DEBUG"
+value = "true"
+"#;
+        let staging = r#"
+schema_version = "0.5.0"
+extends = "base"
+
+[[service]]
+name = "worker"
+remove = true
+
+[environment_variables]
+replace = true
+
+[[environment_variables.items]]
+name = "LOG_FORMAT"
+value = "json"
+"#;
+        let production = r#"
+schema_version = "0.5.0"
+extends = "staging"
+
+[environment_variables]
+replace = true
+"#;
+        let sources = BTreeMap::from([
+            ("base", base),
+            ("staging", staging),
+            ("production", production),
+        ]);
+
+        let env = load_environment_from_sources("staging", sources.clone()).unwrap();
+        assert_eq!(
+            env.services
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["api"]
+        );
+        let variables = env.environment_variables.as_ref().unwrap();
+        assert_eq!(variables.len(), 1);
+        assert_eq!(variables[0].name, "LOG_FORMAT");
+
+        let env = load_environment_from_sources("production", sources).unwrap();
+        assert_eq!(env.services.len(), 1);
+        assert!(env.environment_variables.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resolved_environment_records_where_values_came_from() {
+        let base = r#"
+schema_version = "0.5.0"
+name = "base"
+log_level = "INFO"
+domain = "base.example.com"
+default_replicas = 1
+registry = "docker.io/base"
+
+[[service]]
+name = "api"
+version = "1.0.0"
+
+[[service]]
+name = "worker"
+version = "1.0.0"
+
+[[environment_variables]]
+name = "API_KEY"
+value = "hunter22"
+sensitive = true
+
+[values]
+replicas = 1
+"#;
+        let child = r#"
+schema_version = "0.5.0"
+extends = "base"
+domain = "child.example.com"
+
+[[service]]
+name = "api"
+version = "2.0.0"
+
+[[service]]
+name = "worker"
+remove = true
+"#;
+        let sources = BTreeMap::from([("base", base), ("child", child)]);
+        let mut origins = Origins::new();
+        let (mut raw, _) = Environment::resolve_raw_environment(
+            "child",
+            &mut Vec::new(),
+            &|env_name| Ok(sources[env_name].to_string()),
+            &|env_name| {
+                Ok(match env_name {
+                    "child" => vec![(
+                        "./k8s/environments/child/values/scale.toml".to_string(),
+                        "replicas = 3".to_string(),
+                    )],
+                    _ => Vec::new(),
+                })
+            },
+            &mut origins,
+        )
+        .unwrap();
+
+        assert_eq!(origins["domain"], "k8s/environments/child/config.toml");
+        assert_eq!(origins["log_level"], "k8s/environments/base/config.toml");
+        assert_eq!(origins["name"], "k8s/environments/child/config.toml");
+        assert_eq!(
+            origins["service[api].version"],
+            "k8s/environments/child/config.toml"
+        );
+        assert!(!origins.contains_key("service[worker].version"));
+        assert_eq!(
+            origins["values.replicas"],
+            "k8s/environments/child/values/scale.toml"
+        );
+
+        mask_sensitive_values(&mut raw);
+        let annotated = annotate_origins(&raw, &origins).unwrap();
+        assert!(annotated
+            .contains("domain = \"child.example.com\" # k8s/environments/child/config.toml\n"));
+        assert!(annotated.contains("version = \"2.0.0\" # k8s/environments/child/config.toml\n"));
+        assert!(annotated.contains("value = \"******\" # k8s/environments/base/config.toml\n"));
+        assert!(!annotated.contains("worker"));
+        assert!(!annotated.contains("hunter22"));
+    }
 }
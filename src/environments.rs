@@ -0,0 +1,554 @@
+//! Working with the environments of a project as a whole: listing them, comparing two
+//! of them, and cloning or deleting one.
+//!
+//! Every environment lives in `k8s/environments/<name>/config.toml`. A clone is a
+//! child that `extends` its source, so it follows later changes to the source and
+//! only has to hold what it overrides.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use toml::Value;
+
+use crate::{
+    environment::{Environment, EnvironmentVariable, SCHEMA_V05},
+    errors::EnvironmentError,
+    schema::{write_environment_schema, ENVIRONMENT_SCHEMA_HINT, SCHEMA_DIR},
+    templates::namespaces::is_valid_namespace,
+    ui::REDACTED,
+    utils::ENV_DIR,
+};
+
+/// Directory `sailr generate` writes each environment's manifests to.
+pub const GENERATED_DIR: &str = "./k8s/generated";
+
+/// One row of `sailr env list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvironmentSummary {
+    pub name: String,
+    /// The environment this one `extends`, if any.
+    pub extends: Option<String>,
+    /// Schema version and service count of the resolved environment, or why it
+    /// could not be resolved.
+    pub resolved: Result<(String, usize), String>,
+}
+
+// The names of every environment with a config.toml, sorted.
+pub fn environment_names() -> Result<Vec<String>, EnvironmentError> {
+    let dir = Path::new(ENV_DIR);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.join("config.toml").is_file() {
+            if let Some(name) = path.file_name() {
+                names.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// Summarizes every environment. Environments that fail to resolve are listed with
+// the reason rather than failing the whole listing.
+pub fn list_environments() -> Result<Vec<EnvironmentSummary>, EnvironmentError> {
+    let mut summaries = Vec::new();
+    for name in environment_names()? {
+        let resolved = Environment::load_from_file(&name)
+            .map(|env| (env.schema_version, env.services.len()))
+            .map_err(|e| e.to_string());
+        summaries.push(EnvironmentSummary {
+            extends: parent_of(&name)?,
+            name,
+            resolved,
+        });
+    }
+    Ok(summaries)
+}
+
+// Renders `summaries` as the table `sailr env list` prints.
+pub fn summaries_to_text(summaries: &[EnvironmentSummary]) -> String {
+    let width = summaries
+        .iter()
+        .map(|summary| summary.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("NAME".len());
+    let mut text = format!(
+        "{:<width$}  {:<8}  {:<8}  EXTENDS\n",
+        "NAME",
+        "SCHEMA",
+        "SERVICES",
+        width = width
+    );
+    for summary in summaries {
+        let extends = summary.extends.as_deref().unwrap_or("-");
+        match &summary.resolved {
+            Ok((schema_version, services)) => text.push_str(&format!(
+                "{:<width$}  {:<8}  {:<8}  {}\n",
+                summary.name,
+                schema_version,
+                services,
+                extends,
+                width = width
+            )),
+            Err(message) => text.push_str(&format!(
+                "{:<width$}  {:<8}  {:<8}  {}  (invalid: {})\n",
+                summary.name,
+                "-",
+                "-",
+                extends,
+                message,
+                width = width
+            )),
+        }
+    }
+    text
+}
+
+// The `extends` of environment `name` as written in its own config.toml.
+fn parent_of(name: &str) -> Result<Option<String>, EnvironmentError> {
+    let contents = fs::read_to_string(config_file(name))?;
+    let raw = toml::from_str::<Value>(&contents).map_err(|e| EnvironmentError::Load {
+        name: name.to_string(),
+        message: e.to_string(),
+    })?;
+    Ok(raw
+        .get("extends")
+        .and_then(Value::as_str)
+        .map(str::to_string))
+}
+
+// The environments that extend `name` directly.
+fn dependents_of(name: &str) -> Result<Vec<String>, EnvironmentError> {
+    let mut dependents = Vec::new();
+    for other in environment_names()? {
+        // An unreadable config cannot be resolved anyway, so it does not hold `name`.
+        if other != name && parent_of(&other).ok().flatten().as_deref() == Some(name) {
+            dependents.push(other);
+        }
+    }
+    Ok(dependents)
+}
+
+fn config_file(name: &str) -> PathBuf {
+    Path::new(ENV_DIR).join(name).join("config.toml")
+}
+
+fn exists(name: &str) -> bool {
+    config_file(name).is_file()
+}
+
+/// How one entry differs between two environments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Changed { from: String, to: String },
+}
+
+/// One difference between two environments, such as a service's version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// `settings`, `registry`, `services` or `variables`.
+    pub section: &'static str,
+    /// What differs: a setting path, a service name, `api.version` or a variable name.
+    pub subject: String,
+    pub change: Change,
+}
+
+/// The result of `sailr env diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvironmentDiff {
+    pub from: String,
+    pub to: String,
+    pub differences: Vec<Difference>,
+}
+
+impl EnvironmentDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    pub fn to_text(&self) -> String {
+        if self.is_empty() {
+            return format!("Environments '{}' and '{}' match\n", self.from, self.to);
+        }
+        let mut text = format!("--- {}\n+++ {}\n", self.from, self.to);
+        let mut section = "";
+        for difference in &self.differences {
+            if difference.section != section {
+                section = difference.section;
+                text.push_str(&format!("\n{}:\n", section));
+            }
+            let line = match &difference.change {
+                Change::Added(value) => format!("  + {}: {}", difference.subject, value),
+                Change::Removed(value) => format!("  - {}: {}", difference.subject, value),
+                Change::Changed { from, to } => {
+                    format!("  ~ {}: {} -> {}", difference.subject, from, to)
+                }
+            };
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// Compares two resolved environments section by section: top-level settings, the
+// registry, services by name and environment variables by name. Sensitive literal
+// values are shown masked, and value sources are compared without being read.
+pub fn diff_environments(from: &Environment, to: &Environment) -> EnvironmentDiff {
+    let mut differences = Vec::new();
+
+    let settings = |env: &Environment| {
+        let mut settings = BTreeMap::new();
+        if let Ok(Value::Table(mut table)) = Value::try_from(env) {
+            for key in ["name", "service", "environment_variables", "registry"] {
+                table.remove(key);
+            }
+            flatten(&Value::Table(table), "", &mut settings);
+        }
+        settings
+    };
+    diff_maps(
+        "settings",
+        "",
+        &settings(from),
+        &settings(to),
+        &mut differences,
+    );
+
+    let registry = |env: &Environment| env.registry.prefix().unwrap_or_else(|e| e.to_string());
+    let (from_registry, to_registry) = (registry(from), registry(to));
+    if from_registry != to_registry {
+        differences.push(Difference {
+            section: "registry",
+            subject: "prefix".to_string(),
+            change: Change::Changed {
+                from: from_registry,
+                to: to_registry,
+            },
+        });
+    }
+
+    let services = |env: &Environment| {
+        env.services
+            .iter()
+            .map(|service| {
+                let mut fields = BTreeMap::new();
+                if let Ok(Value::Table(mut table)) = Value::try_from(service) {
+                    table.remove("name");
+                    flatten(&Value::Table(table), "", &mut fields);
+                }
+                (service.name.clone(), fields)
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+    let (from_services, to_services) = (services(from), services(to));
+    for name in keys(&from_services, &to_services) {
+        match (from_services.get(name), to_services.get(name)) {
+            (Some(from), Some(to)) => {
+                diff_maps(
+                    "services",
+                    &format!("{}.", name),
+                    from,
+                    to,
+                    &mut differences,
+                );
+            }
+            (Some(_), None) => differences.push(Difference {
+                section: "services",
+                subject: name.clone(),
+                change: Change::Removed(service_version(from, name)),
+            }),
+            (None, Some(_)) => differences.push(Difference {
+                section: "services",
+                subject: name.clone(),
+                change: Change::Added(service_version(to, name)),
+            }),
+            (None, None) => {}
+        }
+    }
+
+    // Variables are compared by value but shown masked, so a changed secret is still
+    // reported.
+    let (from_variables, to_variables) = (variables_by_name(from), variables_by_name(to));
+    for name in keys(&from_variables, &to_variables) {
+        let change = match (from_variables.get(name), to_variables.get(name)) {
+            (Some(from), Some(to))
+                if from.value == to.value && from.is_sensitive() == to.is_sensitive() =>
+            {
+                continue
+            }
+            (Some(from), Some(to)) => Change::Changed {
+                from: display_variable(from),
+                to: display_variable(to),
+            },
+            (Some(from), None) => Change::Removed(display_variable(from)),
+            (None, Some(to)) => Change::Added(display_variable(to)),
+            (None, None) => continue,
+        };
+        differences.push(Difference {
+            section: "variables",
+            subject: name.clone(),
+            change,
+        });
+    }
+
+    EnvironmentDiff {
+        from: from.name.clone(),
+        to: to.name.clone(),
+        differences,
+    }
+}
+
+// Adds a difference for every key of `from` and `to` whose value differs.
+fn diff_maps(
+    section: &'static str,
+    prefix: &str,
+    from: &BTreeMap<String, String>,
+    to: &BTreeMap<String, String>,
+    differences: &mut Vec<Difference>,
+) {
+    for key in keys(from, to) {
+        let change = match (from.get(key), to.get(key)) {
+            (Some(from), Some(to)) if from == to => continue,
+            (Some(from), Some(to)) => Change::Changed {
+                from: from.clone(),
+                to: to.clone(),
+            },
+            (Some(from), None) => Change::Removed(from.clone()),
+            (None, Some(to)) => Change::Added(to.clone()),
+            (None, None) => continue,
+        };
+        differences.push(Difference {
+            section,
+            subject: format!("{}{}", prefix, key),
+            change,
+        });
+    }
+}
+
+fn keys<'a, V>(from: &'a BTreeMap<String, V>, to: &'a BTreeMap<String, V>) -> BTreeSet<&'a String> {
+    from.keys().chain(to.keys()).collect()
+}
+
+fn variables_by_name(env: &Environment) -> BTreeMap<String, &EnvironmentVariable> {
+    env.environment_variables
+        .iter()
+        .flatten()
+        .map(|variable| (variable.name.clone(), variable))
+        .collect()
+}
+
+fn service_version(env: &Environment, name: &str) -> String {
+    env.get_service(name)
+        .map(|service| service.version.clone())
+        .unwrap_or_default()
+}
+
+// The value of `variable` as shown in a diff: its source as written, or its literal
+// value, masked when sensitive.
+fn display_variable(variable: &EnvironmentVariable) -> String {
+    match (&variable.value, variable.source()) {
+        (Some(_), Ok(None)) if variable.is_sensitive() => REDACTED.to_string(),
+        (Some(value), _) => display_value(value),
+        (None, _) => "(unset)".to_string(),
+    }
+}
+
+// Strings are shown bare, anything else as TOML.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Flattens the tables of `value` into dotted paths. Arrays are kept whole.
+fn flatten(value: &Value, path: &str, out: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                flatten(value, &path, out);
+            }
+        }
+        value => {
+            out.insert(path.to_string(), display_value(value));
+        }
+    }
+}
+
+// The config.toml of an environment that extends `source` and overrides nothing.
+pub fn clone_contents(source: &str) -> String {
+    format!(
+        "{}\n\nschema_version = \"{}\"\nextends = \"{}\"\n",
+        ENVIRONMENT_SCHEMA_HINT,
+        SCHEMA_V05,
+        source.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+// Creates environment `target` as a child of `source`. The source has to resolve to
+// schema 0.5.0, which inheritance requires.
+pub fn clone_environment(source: &str, target: &str) -> Result<PathBuf, EnvironmentError> {
+    if !is_valid_namespace(target) {
+        return Err(EnvironmentError::InvalidName(target.to_string()));
+    }
+    if !exists(source) {
+        return Err(EnvironmentError::NotFound(source.to_string()));
+    }
+    if Path::new(ENV_DIR).join(target).exists() {
+        return Err(EnvironmentError::AlreadyExists(target.to_string()));
+    }
+    let env = Environment::load_from_file(source).map_err(|e| EnvironmentError::Load {
+        name: source.to_string(),
+        message: e.to_string(),
+    })?;
+    if env.schema_version != SCHEMA_V05 {
+        return Err(EnvironmentError::Load {
+            name: source.to_string(),
+            message: format!(
+                "only schema {} environments can be extended; run `sailr migrate {}` first",
+                SCHEMA_V05, source
+            ),
+        });
+    }
+
+    write_environment_schema(Path::new(SCHEMA_DIR))?;
+    let path = config_file(target);
+    fs::create_dir_all(Path::new(ENV_DIR).join(target))?;
+    fs::write(&path, clone_contents(source))?;
+    Ok(path)
+}
+
+// The directories `sailr env rm <name>` deletes: the environment's own and its
+// generated manifests, when there are any.
+pub fn environment_paths(name: &str) -> Result<Vec<PathBuf>, EnvironmentError> {
+    if !exists(name) {
+        return Err(EnvironmentError::NotFound(name.to_string()));
+    }
+    let dependents = dependents_of(name)?;
+    if !dependents.is_empty() {
+        return Err(EnvironmentError::HasDependents {
+            name: name.to_string(),
+            dependents: dependents
+                .iter()
+                .map(|dependent| format!("'{}'", dependent))
+                .collect::<Vec<_>>()
+                .join(", "),
+        });
+    }
+    Ok([ENV_DIR, GENERATED_DIR]
+        .iter()
+        .map(|dir| Path::new(dir).join(name))
+        .filter(|path| path.is_dir())
+        .collect())
+}
+
+// Deletes environment `name`, which must not be extended by another environment.
+pub fn delete_environment(name: &str) -> Result<Vec<PathBuf>, EnvironmentError> {
+    let paths = environment_paths(name)?;
+    for path in &paths {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::{RegistryConfig, Service};
+
+    #[test]
+    fn diff_reports_services_versions_variables_and_registry() {
+        let mut dev = Environment::new("dev");
+        dev.services = vec![
+            Service::new("api", None, "1.0.0"),
+            Service::new("debug", None, "0.1.0"),
+        ];
+        let mut password =
+            EnvironmentVariable::new("DB_PASSWORD", Some(Value::String("dev-secret".into())));
+        password.sensitive = Some(true);
+        dev.environment_variables = Some(vec![
+            EnvironmentVariable::new("LOG_FORMAT", Some(Value::String("text".into()))),
+            password,
+        ]);
+
+        let mut prod = Environment::new("prod");
+        prod.domain = "prod.example.com".to_string();
+        prod.registry = RegistryConfig::Simple("ghcr.io/acme".to_string());
+        prod.services = vec![
+            Service::new("api", Some("shop"), "1.1.0"),
+            Service::new("worker", None, "2.0.0"),
+        ];
+        let mut password =
+            EnvironmentVariable::new("DB_PASSWORD", Some(Value::String("prod-secret".into())));
+        password.sensitive = Some(true);
+        prod.environment_variables = Some(vec![
+            EnvironmentVariable::new("LOG_FORMAT", Some(Value::String("json".into()))),
+            password,
+        ]);
+
+        let diff = diff_environments(&dev, &prod);
+        let subjects = diff
+            .differences
+            .iter()
+            .map(|difference| (difference.section, difference.subject.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subjects,
+            vec![
+                ("settings", "domain"),
+                ("registry", "prefix"),
+                ("services", "api.namespace"),
+                ("services", "api.version"),
+                ("services", "debug"),
+                ("services", "worker"),
+                ("variables", "DB_PASSWORD"),
+                ("variables", "LOG_FORMAT"),
+            ]
+        );
+        assert_eq!(
+            diff.differences[3].change,
+            Change::Changed {
+                from: "1.0.0".to_string(),
+                to: "1.1.0".to_string()
+            }
+        );
+        assert_eq!(
+            diff.differences[4].change,
+            Change::Removed("0.1.0".to_string())
+        );
+
+        let text = diff.to_text();
+        assert!(text.contains("  + worker: 2.0.0\n"));
+        assert!(text.contains("  ~ LOG_FORMAT: text -> json\n"));
+        assert!(text.contains(&format!("  ~ DB_PASSWORD: {} -> {}\n", REDACTED, REDACTED)));
+        assert!(!text.contains("secret"));
+        assert!(diff_environments(&dev, &dev).is_empty());
+    }
+
+    #[test]
+    fn clones_extend_their_source() {
+        let contents = clone_contents("staging");
+        assert!(contents.starts_with(ENVIRONMENT_SCHEMA_HINT));
+
+        let raw = toml::from_str::<Value>(&contents).unwrap();
+        assert_eq!(raw["schema_version"].as_str(), Some(SCHEMA_V05));
+        assert_eq!(raw["extends"].as_str(), Some("staging"));
+        assert_eq!(raw.as_table().unwrap().len(), 2);
+    }
+}
@@ -81,6 +81,16 @@ pub enum KubeError {
     #[error("Failed to delete Kubernetes resource: {0}")]
     ResourceDeletionFailed(String),
 
+    #[error("The cluster does not serve {kind} ({api_version}), so {name} cannot be applied; is its CustomResourceDefinition installed?")]
+    UnknownKind {
+        api_version: String,
+        kind: String,
+        name: String,
+    },
+
+    #[error("CustomResourceDefinition {name} was not established within {seconds}s")]
+    CrdNotEstablished { name: String, seconds: u64 },
+
     // Generic catch-all error variant for any unhandled error.
     #[error("An unexpected error occurred: {0}")]
     UnexpectedError(String),
@@ -180,4 +190,324 @@ pub enum DeployError {
 
     #[error("Kubernetes API error: {0}")]
     KubernetesApiError(#[from] KubeError),
+
+    #[error("{count} workload(s) did not roll out:\n{details}")]
+    RolloutFailed { count: usize, details: String },
+
+    #[error("{count} service(s) failed to deploy:\n{details}")]
+    ServicesFailed { count: usize, details: String },
+
+    #[error("{cause}\nRolled back to revision {revision}")]
+    RolledBack {
+        revision: u32,
+        cause: Box<DeployError>,
+    },
+
+    #[error("{cause}\nRolling back to revision {revision} also failed: {error}")]
+    RollbackFailed {
+        revision: u32,
+        cause: Box<DeployError>,
+        error: Box<DeployError>,
+    },
+
+    #[error("Failed to read or record revision history: {0}")]
+    History(String),
+
+    #[error("Failed to read, record or prune the object inventory: {0}")]
+    Inventory(String),
+
+    #[error("Service '{service}' deploys after '{dependency}', which is not a service of the environment")]
+    UnknownDeployAfter { service: String, dependency: String },
+
+    #[error("deploy_after entries form a cycle: {0}")]
+    DeployOrderCycle(String),
+
+    #[error("Invalid sailr.dev/wave annotation '{value}' on {name}: expected an integer")]
+    InvalidWave { name: String, value: String },
+
+    #[error("Environment '{env_name}' has no revision {revision}")]
+    RevisionNotFound { env_name: String, revision: u32 },
+
+    #[error("Environment '{0}' has no recorded revisions to roll back to")]
+    NoRevisions(String),
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("Unknown variable '{name}' at line {line}, column {column}")]
+    UnknownVariable {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("Unknown filter '{name}' at line {line}, column {column}")]
+    UnknownFilter {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("Invalid argument for filter '{filter}' at line {line}, column {column}: {message}")]
+    InvalidFilterArgument {
+        filter: String,
+        message: String,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("Template syntax error at line {line}, column {column}: {message}")]
+    Syntax {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("Unknown partial '{name}' at line {line}, column {column}")]
+    UnknownPartial {
+        name: String,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("Partial include cycle {} at line {line}, column {column}", chain.join(" > "))]
+    PartialCycle {
+        chain: Vec<String>,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("In partial '{partial}' included at line {line}, column {column}: {source}")]
+    InPartial {
+        partial: String,
+        line: usize,
+        column: usize,
+        source: Box<TemplateError>,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum OverlayError {
+    #[error("Failed to read overlay {path}: {message}")]
+    Read { path: String, message: String },
+
+    #[error("Invalid overlay {path}: {message}")]
+    Invalid { path: String, message: String },
+
+    #[error("Failed to apply overlay {path} to {resource}: {message}")]
+    Apply {
+        path: String,
+        resource: String,
+        message: String,
+    },
+
+    #[error("Overlay {path} did not match any resource of service '{service}'")]
+    Unmatched { path: String, service: String },
+}
+
+#[derive(Error, Debug)]
+pub enum HelmError {
+    #[error("Failed to read chart {path}: {message}")]
+    Read { path: String, message: String },
+
+    #[error("Invalid chart {path}: {message}")]
+    InvalidChart { path: String, message: String },
+
+    #[error("Template parse error in {template}:{line}: {message}")]
+    Parse {
+        template: String,
+        line: usize,
+        message: String,
+    },
+
+    #[error("Template render error in {template}:{line}: {message}")]
+    Render {
+        template: String,
+        line: usize,
+        message: String,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigMapError {
+    #[error("Failed to read config file {path}: {message}")]
+    Read { path: String, message: String },
+
+    #[error("Config file {path} maps to invalid ConfigMap key '{key}'")]
+    InvalidKey { key: String, path: String },
+
+    #[error("Config files {first} and {second} both map to ConfigMap key '{key}'")]
+    DuplicateKey {
+        key: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("ConfigMap {name} is {size} bytes, over the {limit} byte limit")]
+    TooLarge {
+        name: String,
+        size: usize,
+        limit: usize,
+    },
+
+    #[error("Failed to render ConfigMap {name}: {message}")]
+    Render { name: String, message: String },
+}
+
+#[derive(Error, Debug)]
+pub enum LabelError {
+    #[error("Invalid label key '{key}': {reason}")]
+    InvalidKey { key: String, reason: String },
+
+    #[error("Invalid value '{value}' for label '{key}': at most 63 letters, digits, '-', '_' or '.', starting and ending with a letter or digit")]
+    InvalidValue { key: String, value: String },
+
+    #[error("Label '{key}' is set by sailr and cannot be configured under [labels]")]
+    Reserved { key: String },
+
+    #[error("Failed to add labels: {0}")]
+    Render(String),
+}
+
+#[derive(Error, Debug)]
+pub enum NamespaceError {
+    #[error("Service '{service}' uses invalid namespace '{name}': namespaces are at most 63 lower case letters, digits or '-', starting and ending with a letter or digit")]
+    InvalidName { name: String, service: String },
+
+    #[error("Failed to set namespaces: {0}")]
+    Render(String),
+}
+
+#[derive(Error, Debug)]
+pub enum VariableError {
+    #[error("Registry config error: {0}")]
+    Registry(#[from] crate::workflow::error::RegistryConfigError),
+
+    #[error("Variable '{variable}' reads the environment variable '{name}', which is not set")]
+    MissingEnv { variable: String, name: String },
+
+    #[error("Variable '{variable}' reads '{path}', which could not be read: {message}")]
+    MissingFile {
+        variable: String,
+        path: String,
+        message: String,
+    },
+
+    #[error("Variable '{variable}' runs `{command}`, which failed: {message}")]
+    CommandFailed {
+        variable: String,
+        command: String,
+        message: String,
+    },
+
+    #[error("Variable '{variable}' has an invalid value source: {message}")]
+    InvalidSource { variable: String, message: String },
+}
+
+#[derive(Error, Debug)]
+pub enum EnvironmentError {
+    #[error("Environment '{0}' does not exist")]
+    NotFound(String),
+
+    #[error("Environment '{0}' already exists")]
+    AlreadyExists(String),
+
+    #[error("Invalid environment name '{0}': names are at most 63 lower case letters, digits or '-', starting and ending with a letter or digit")]
+    InvalidName(String),
+
+    #[error("Environment '{name}' is extended by {dependents}; delete them or change their `extends` first")]
+    HasDependents { name: String, dependents: String },
+
+    #[error("Failed to load environment '{name}': {message}")]
+    Load { name: String, message: String },
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum LintError {
+    #[error("Unknown lint rule '{rule}' in [lint]; known rules are: {known}")]
+    UnknownRule { rule: String, known: String },
+
+    #[error("Failed to read templates: {0}")]
+    Templates(String),
+}
+
+#[derive(Error, Debug)]
+pub enum SecretsError {
+    #[error("No secrets key for environment '{env}': set SAILR_SECRETS_KEY, SAILR_SECRETS_KEY_FILE or pass --key-file")]
+    MissingKey { env: String },
+
+    #[error("Invalid secrets key: {0}")]
+    InvalidKey(String),
+
+    #[error("{path} is encrypted with key {expected}, but the configured key is {actual}")]
+    KeyMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Unsupported cipher '{0}'; only aes-256-gcm is supported")]
+    UnsupportedCipher(String),
+
+    #[error("Invalid secret reference '{0}'; expected <secret>/<key>")]
+    InvalidReference(String),
+
+    #[error("Failed to decrypt {secret}/{key}; the file or key may be corrupt")]
+    Decrypt { secret: String, key: String },
+
+    #[error("Failed to encrypt {secret}/{key}")]
+    Encrypt { secret: String, key: String },
+
+    #[error("Failed to read {path}: {message}")]
+    Read { path: String, message: String },
+
+    #[error("Failed to write {path}: {message}")]
+    Write { path: String, message: String },
+
+    #[error("Failed to inject secrets into {resource}: {message}")]
+    Inject { resource: String, message: String },
+}
+
+impl TemplateError {
+    /// The 1-based line of the template the error points at.
+    pub fn line(&self) -> usize {
+        match self {
+            TemplateError::UnknownVariable { line, .. }
+            | TemplateError::UnknownFilter { line, .. }
+            | TemplateError::InvalidFilterArgument { line, .. }
+            | TemplateError::Syntax { line, .. }
+            | TemplateError::UnknownPartial { line, .. }
+            | TemplateError::PartialCycle { line, .. }
+            | TemplateError::InPartial { line, .. } => *line,
+        }
+    }
+
+    /// The partials the error happened in, outermost first, with the line each one was
+    /// included from.
+    pub fn partial_chain(&self) -> Vec<(&str, usize)> {
+        let mut chain = Vec::new();
+        let mut error = self;
+        while let TemplateError::InPartial {
+            partial,
+            line,
+            source,
+            ..
+        } = error
+        {
+            chain.push((partial.as_str(), *line));
+            error = source;
+        }
+        chain
+    }
+
+    /// The error where it actually happened, inside any partials.
+    pub fn innermost(&self) -> &TemplateError {
+        match self {
+            TemplateError::InPartial { source, .. } => source.innermost(),
+            error => error,
+        }
+    }
 }
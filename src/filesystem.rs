@@ -21,7 +21,7 @@ pub fn rm_dir(dir_name: &str) -> Result<(), SailrError> {
     Ok(())
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize, Default)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize, Default)]
 pub struct FileSystemManager {
     pub path: String,
 }
@@ -106,6 +106,8 @@ impl FileSystemManager {
             let file_name = path.file_name().unwrap().to_str().unwrap().to_string();
             files.push(file_name);
         }
+        // Directory order is platform dependent; sort so generated output is stable.
+        files.sort();
         Ok(files)
     }
 
@@ -1,16 +1,83 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+};
 
 use anyhow::Result;
+use walkdir::WalkDir;
 
 use crate::{
-    config::Config, errors::GenerateError, filesystem::FileSystemManager, templates::Template,
+    cli::ManifestFormat,
+    config::Config,
+    deployment::k8sm8::diff_text,
+    errors::GenerateError,
+    filesystem::FileSystemManager,
+    overlays::parse_documents,
+    templates::{namespaces::NAMESPACES_FILE, Template},
     LOGGER,
 };
 
+/// Options controlling how `crate::generate` renders an environment.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateOptions {
+    /// Keep templates that still contain unresolved `{{ placeholders }}` instead of
+    /// failing the run. Useful for templates that deliberately emit braces.
+    pub allow_unresolved: bool,
+    /// Provide the `deployment_date` variable (the current time). Off by default so
+    /// that generating unchanged inputs twice produces identical output.
+    pub timestamp: bool,
+    /// Compare the rendered output with `k8s/generated` instead of writing it, failing
+    /// with a unified diff when they differ.
+    pub check: bool,
+    /// Recorded in the `sailr.dev/source-revision` annotation of every resource.
+    pub source_revision: Option<String>,
+    /// Print the rendered manifests to stdout in this format instead of writing
+    /// `k8s/generated`.
+    pub stdout: Option<ManifestFormat>,
+}
+
+/// Files touched by a `Generator::generate` run, relative to `k8s/generated`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GenerateSummary {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl GenerateSummary {
+    pub fn is_unchanged(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    pub fn display(&self) {
+        for path in &self.added {
+            LOGGER.status("Added", path, "green");
+        }
+        for path in &self.changed {
+            LOGGER.status("Changed", path, "yellow");
+        }
+        for path in &self.removed {
+            LOGGER.status("Removed", path, "red");
+        }
+        LOGGER.info(&format!(
+            "{} added, {} changed, {} removed, {} unchanged",
+            self.added.len(),
+            self.changed.len(),
+            self.removed.len(),
+            self.unchanged
+        ));
+    }
+}
+
 pub struct Generator {
     filemanager: FileSystemManager,
+    root: PathBuf,
     templates: Vec<Template>,
     config_maps: Vec<Config>,
+    namespaces: Option<String>,
+    scope: Option<BTreeSet<String>>,
 }
 
 impl Default for Generator {
@@ -21,13 +88,27 @@ impl Default for Generator {
 
 impl Generator {
     pub fn new() -> Generator {
+        Generator::with_root("./k8s/generated")
+    }
+
+    // Creates a generator that writes under `root` instead of `./k8s/generated`.
+    pub fn with_root(root: &str) -> Generator {
         Generator {
-            filemanager: FileSystemManager::new("./k8s/generated".to_string()),
+            filemanager: FileSystemManager::new(root.to_string()),
+            root: PathBuf::from(root),
             templates: Vec::new(),
             config_maps: Vec::new(),
+            namespaces: None,
+            scope: None,
         }
     }
 
+    // Limits removal of stale files to these service directories. Used when only some
+    // services are generated, so the output of the others is left alone.
+    pub fn limit_to(&mut self, service_paths: impl IntoIterator<Item = String>) {
+        self.scope = Some(service_paths.into_iter().collect());
+    }
+
     pub fn add_template(&mut self, original_template: &Template, new_content: String) {
         self.templates.push(Template::new(
             original_template.name.clone(),
@@ -41,49 +122,373 @@ impl Generator {
     }
 
     pub fn add_config_map(&mut self, config_map: &Config) {
-        self.config_maps.push(Config::new(
-            &config_map.name,
-            &config_map.config_filenames,
-            &config_map.content,
-            &config_map.root_dir,
-        ));
+        self.config_maps.push(config_map.clone());
     }
 
-    pub fn generate(&mut self, name: &String) -> Result<(), GenerateError> {
-        self.filemanager.delete_dir(name).unwrap();
+    // Sets the Namespace manifests written to `<env>/namespaces.yaml`. They cover the
+    // whole environment, so the file is always in scope.
+    pub fn set_namespaces(&mut self, content: String) {
+        self.namespaces = Some(content).filter(|content| !content.is_empty());
+    }
 
+    // Renders the environment into memory, keyed by path relative to the generated
+    // root (`<env>/<service>/<file>`). Nothing is written.
+    pub fn render(&self, name: &str) -> BTreeMap<String, String> {
+        let mut files = BTreeMap::new();
+        if let Some(namespaces) = &self.namespaces {
+            files.insert(format!("{}/{}", name, NAMESPACES_FILE), namespaces.clone());
+        }
         for config_map in &self.config_maps {
-            let path = Path::new(name)
-                .join(&config_map.name)
-                .join("configMap.yaml")
-                .to_str()
-                .unwrap()
-                .to_string();
-            match self
-                .filemanager
-                .create_file(&path, &config_map.content.to_string())
-            {
-                Ok(_) => LOGGER.info(&path),
-                Err(_) => return Err(GenerateError::K8sResourceGenerationFailed(path)),
+            files.insert(
+                format!("{}/{}/configMap.yaml", name, config_map.name),
+                config_map.content.clone(),
+            );
+        }
+        for template in &self.templates {
+            files.insert(
+                format!("{}/{}/{}", name, template.name, template.file_name),
+                template.content.clone(),
+            );
+        }
+        files
+    }
+
+    // Every rendered document, the Namespaces first and then file by file in path order,
+    // as it would be written to `k8s/generated`.
+    pub fn documents(&self, name: &str) -> Result<Vec<serde_json::Value>, GenerateError> {
+        let mut rendered = self.render(name);
+        let namespaces = rendered.remove(&format!("{}/{}", name, NAMESPACES_FILE));
+        let mut documents = Vec::new();
+        for (path, content) in namespaces
+            .map(|content| (NAMESPACES_FILE.to_string(), content))
+            .into_iter()
+            .chain(rendered)
+        {
+            documents.extend(parse_documents(&content).map_err(|e| {
+                GenerateError::K8sResourceGenerationFailed(format!("{}: {}", path, e))
+            })?);
+        }
+        Ok(documents)
+    }
+
+    // Renders every document in `format`: a multi-document YAML stream, or a JSON `List`.
+    pub fn to_stream(&self, name: &str, format: ManifestFormat) -> Result<String, GenerateError> {
+        let documents = self.documents(name)?;
+        let failed = |e: String| GenerateError::K8sResourceGenerationFailed(e);
+        match format {
+            ManifestFormat::Yaml => documents
+                .iter()
+                .map(|document| serde_yaml::to_string(document).map_err(|e| failed(e.to_string())))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|documents| documents.join("---\n")),
+            ManifestFormat::Json => {
+                let list = serde_json::json!({
+                    "apiVersion": "v1",
+                    "kind": "List",
+                    "items": documents,
+                });
+                serde_json::to_string_pretty(&list)
+                    .map(|json| json + "\n")
+                    .map_err(|e| failed(e.to_string()))
+            }
+        }
+    }
+
+    // Reads the files currently generated for `name` that this run is responsible for.
+    pub fn read_existing(&self, name: &str) -> Result<BTreeMap<String, Vec<u8>>, GenerateError> {
+        let mut files = BTreeMap::new();
+        let env_dir = self.root.join(name);
+        if !env_dir.is_dir() {
+            return Ok(files);
+        }
+
+        for entry in WalkDir::new(&env_dir).sort_by_file_name() {
+            let entry =
+                entry.map_err(|e| GenerateError::K8sResourceGenerationFailed(e.to_string()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(&self.root) else {
+                continue;
             };
+            let key = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !self.in_scope(name, &key) {
+                continue;
+            }
+            let content = fs::read(entry.path()).map_err(|e| {
+                GenerateError::K8sResourceGenerationFailed(format!("{}: {}", key, e))
+            })?;
+            files.insert(key, content);
         }
+        Ok(files)
+    }
 
-        for template in &self.templates {
-            let path = Path::new(name)
-                .join(&template.name)
-                .join(&template.file_name)
-                .to_str()
-                .unwrap()
-                .to_string();
-
-            match self
-                .filemanager
-                .create_file(&path, &template.content.to_string())
-            {
-                Ok(_) => LOGGER.info(&path),
-                Err(_) => return Err(GenerateError::K8sResourceGenerationFailed(path)),
+    fn in_scope(&self, name: &str, key: &str) -> bool {
+        match &self.scope {
+            None => true,
+            Some(_) if key == format!("{}/{}", name, NAMESPACES_FILE) => true,
+            Some(paths) => paths
+                .iter()
+                .any(|path| key.starts_with(&format!("{}/{}/", name, path))),
+        }
+    }
+
+    // Writes the rendered environment, touching only files whose content changed and
+    // removing files that are no longer generated.
+    pub fn generate(&mut self, name: &str) -> Result<GenerateSummary, GenerateError> {
+        let rendered = self.render(name);
+        let mut existing = self.read_existing(name)?;
+        let mut summary = GenerateSummary::default();
+
+        for (path, content) in &rendered {
+            match existing.remove(path) {
+                Some(current) if current == content.as_bytes() => {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                Some(_) => summary.changed.push(path.clone()),
+                None => summary.added.push(path.clone()),
+            }
+            self.filemanager
+                .create_file(path, content)
+                .map_err(|_| GenerateError::K8sResourceGenerationFailed(path.clone()))?;
+        }
+
+        for path in existing.into_keys() {
+            self.filemanager
+                .delete_file(&path)
+                .map_err(|_| GenerateError::K8sResourceGenerationFailed(path.clone()))?;
+            self.remove_empty_parents(name, &path);
+            summary.removed.push(path);
+        }
+
+        Ok(summary)
+    }
+
+    // Compares the rendered environment with what is on disk without writing anything.
+    // Returns what `generate` would do and a unified diff of every differing file.
+    pub fn check(&self, name: &str) -> Result<(GenerateSummary, String), GenerateError> {
+        let rendered = self.render(name);
+        let mut existing = self.read_existing(name)?;
+        let mut summary = GenerateSummary::default();
+        let mut diff = String::new();
+        let root = self.root.to_string_lossy();
+        let root = root.trim_start_matches("./");
+
+        for (path, content) in &rendered {
+            let label = format!("{}/{}", root, path);
+            let current = existing
+                .remove(path)
+                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+            let original = match &current {
+                Some(current) if current == content => {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                Some(_) => {
+                    summary.changed.push(path.clone());
+                    format!("a/{}", label)
+                }
+                None => {
+                    summary.added.push(path.clone());
+                    "/dev/null".to_string()
+                }
             };
+            let current = current.unwrap_or_default();
+            let modified = format!("b/{}", label);
+            diff.push_str(&diff_text(&original, &modified, &current, content).unwrap_or_default());
+        }
+
+        for (path, bytes) in existing {
+            let original = format!("a/{}/{}", root, path);
+            let current = String::from_utf8_lossy(&bytes);
+            diff.push_str(&diff_text(&original, "/dev/null", &current, "").unwrap_or_default());
+            summary.removed.push(path);
+        }
+
+        Ok((summary, diff))
+    }
+
+    // Removes directories left empty by deleting `path`, stopping at the environment dir.
+    fn remove_empty_parents(&self, name: &str, path: &str) {
+        let env_dir = self.root.join(name);
+        let mut dir = self.root.join(path);
+        while dir.pop() && dir.starts_with(&env_dir) && dir != env_dir {
+            if fs::remove_dir(&dir).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn generator(root: &Path, files: &[(&str, &str, &str)]) -> Generator {
+        let mut generator = Generator::with_root(root.to_str().unwrap());
+        for (service, file, content) in files {
+            let template = Template::new(service.to_string(), file.to_string(), String::new());
+            generator.add_template(&template, content.to_string());
         }
-        Ok(())
+        generator
+    }
+
+    #[test]
+    fn generate_only_writes_changed_files_and_removes_stale_ones() {
+        let dir = tempdir().unwrap();
+        let first = generator(
+            dir.path(),
+            &[
+                ("api", "deployment.yaml", "replicas: 1\n"),
+                ("api", "service.yaml", "port: 80\n"),
+                ("worker", "deployment.yaml", "replicas: 1\n"),
+            ],
+        )
+        .generate("dev")
+        .unwrap();
+        assert_eq!(first.added.len(), 3);
+
+        let unchanged = dir.path().join("dev/api/service.yaml");
+        let before = fs::metadata(&unchanged).unwrap().modified().unwrap();
+
+        let second = generator(
+            dir.path(),
+            &[
+                ("api", "deployment.yaml", "replicas: 2\n"),
+                ("api", "service.yaml", "port: 80\n"),
+            ],
+        )
+        .generate("dev")
+        .unwrap();
+
+        assert_eq!(
+            second,
+            GenerateSummary {
+                added: vec![],
+                changed: vec!["dev/api/deployment.yaml".to_string()],
+                removed: vec!["dev/worker/deployment.yaml".to_string()],
+                unchanged: 1,
+            }
+        );
+        assert_eq!(
+            fs::metadata(&unchanged).unwrap().modified().unwrap(),
+            before
+        );
+        assert!(!dir.path().join("dev/worker").exists());
+    }
+
+    #[test]
+    fn check_reports_differences_without_writing() {
+        let dir = tempdir().unwrap();
+        generator(
+            dir.path(),
+            &[
+                ("api", "deployment.yaml", "replicas: 1\nimage: api\n"),
+                ("worker", "deployment.yaml", "replicas: 1\n"),
+            ],
+        )
+        .generate("dev")
+        .unwrap();
+
+        let edited = generator(
+            dir.path(),
+            &[
+                ("api", "deployment.yaml", "replicas: 2\nimage: api\n"),
+                ("api", "service.yaml", "port: 80\n"),
+            ],
+        );
+        let (summary, diff) = edited.check("dev").unwrap();
+
+        assert_eq!(summary.changed, vec!["dev/api/deployment.yaml".to_string()]);
+        assert_eq!(summary.added, vec!["dev/api/service.yaml".to_string()]);
+        assert_eq!(
+            summary.removed,
+            vec!["dev/worker/deployment.yaml".to_string()]
+        );
+        assert!(diff.contains("--- a/"), "{}", diff);
+        assert!(diff.contains("dev/api/deployment.yaml\n"));
+        assert!(diff.contains("-replicas: 1\n+replicas: 2\n"));
+        assert!(diff.contains("+++ /dev/null"));
+        assert!(!dir.path().join("dev/api/service.yaml").exists());
+        assert!(dir.path().join("dev/worker/deployment.yaml").exists());
+
+        let (clean, diff) = generator(
+            dir.path(),
+            &[
+                ("api", "deployment.yaml", "replicas: 1\nimage: api\n"),
+                ("worker", "deployment.yaml", "replicas: 1\n"),
+            ],
+        )
+        .check("dev")
+        .unwrap();
+        assert!(clean.is_unchanged());
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn generate_leaves_services_outside_the_scope_alone() {
+        let dir = tempdir().unwrap();
+        generator(
+            dir.path(),
+            &[
+                ("api", "deployment.yaml", "a\n"),
+                ("worker", "deployment.yaml", "b\n"),
+            ],
+        )
+        .generate("dev")
+        .unwrap();
+
+        let mut only_api = generator(dir.path(), &[("api", "deployment.yaml", "c\n")]);
+        only_api.limit_to(["api".to_string()]);
+        let summary = only_api.generate("dev").unwrap();
+
+        assert_eq!(summary.changed, vec!["dev/api/deployment.yaml".to_string()]);
+        assert!(summary.removed.is_empty());
+        assert!(dir.path().join("dev/worker/deployment.yaml").exists());
+    }
+
+    #[test]
+    fn streams_namespaces_first_as_yaml_or_a_json_list() {
+        let dir = tempdir().unwrap();
+        let mut generator = generator(
+            dir.path(),
+            &[
+                ("api", "service.yaml", "apiVersion: v1\nkind: Service\nmetadata:\n  name: api\n"),
+                (
+                    "api",
+                    "deployment.yaml",
+                    "---\napiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\n---\napiVersion: v1\nkind: ServiceAccount\nmetadata:\n  name: api\n",
+                ),
+            ],
+        );
+        generator.set_namespaces(
+            "apiVersion: v1\nkind: Namespace\nmetadata:\n  name: dev\n".to_string(),
+        );
+
+        let yaml = generator.to_stream("dev", ManifestFormat::Yaml).unwrap();
+        let kinds: Vec<_> = parse_documents(&yaml)
+            .unwrap()
+            .into_iter()
+            .map(|document| document["kind"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec!["Namespace", "Deployment", "ServiceAccount", "Service"]
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&generator.to_stream("dev", ManifestFormat::Json).unwrap())
+                .unwrap();
+        assert_eq!(json["kind"], "List");
+        assert_eq!(json["items"].as_array().unwrap().len(), 4);
+        assert_eq!(json["items"][0]["metadata"]["name"], "dev");
+        assert!(!dir.path().join("dev").exists());
     }
 }
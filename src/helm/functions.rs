@@ -0,0 +1,951 @@
+//! Go template builtins and the Sprig functions commonly used by Helm charts.
+//!
+//! Functions that need the template set (`include`, `tpl`) live in the executor;
+//! everything here is a pure function over `serde_json::Value`s. Functions that read
+//! the clock or randomness (`now`, `randAlphaNum`, `uuidv4`) are deliberately absent so
+//! rendering stays reproducible.
+
+use std::cmp::Ordering;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Go's notion of truthiness: false, 0, nil and empty strings, lists and maps are false.
+pub fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|f| f != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Formats a value the way Go's `%v` does, printing nil as an empty string like Helm.
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(display).collect::<Vec<_>>().join(" ")
+        ),
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            format!(
+                "map[{}]",
+                entries
+                    .iter()
+                    .map(|(key, value)| format!("{}:{}", key, display(value)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+        }
+    }
+}
+
+/// The Go kind name of a value, as reported by `kindOf`.
+pub fn kind_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "invalid",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "float64",
+        Value::Number(_) => "int64",
+        Value::String(_) => "string",
+        Value::Array(_) => "slice",
+        Value::Object(_) => "map",
+    }
+}
+
+/// Calls a method on a value, e.g. `.Capabilities.APIVersions.Has` or `.Files.Get`.
+pub fn method(base: &Value, name: &str, args: &[Value]) -> Result<Value, String> {
+    match (name, base, args) {
+        ("Has", Value::Array(items), [needle]) => Ok(Value::Bool(items.contains(needle))),
+        ("Get", Value::Object(map), [Value::String(key)]) => {
+            Ok(map.get(key).cloned().unwrap_or_else(|| Value::from("")))
+        }
+        _ => Err(format!(
+            "can't call method {} on {} with {} argument(s)",
+            name,
+            kind_of(base),
+            args.len()
+        )),
+    }
+}
+
+/// Calls the function `name`. Errors are reported without the function name, which
+/// the executor adds along with the template position.
+pub fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+    match name {
+        // Go builtins.
+        "and" => {
+            let last = args.last().ok_or("and requires arguments")?;
+            Ok(args.iter().find(|v| !truthy(v)).unwrap_or(last).clone())
+        }
+        "or" => {
+            let last = args.last().ok_or("or requires arguments")?;
+            Ok(args.iter().find(|v| truthy(v)).unwrap_or(last).clone())
+        }
+        "not" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::Bool(!truthy(value)))
+        }
+        "eq" => {
+            let (first, rest) = args.split_first().ok_or("eq requires arguments")?;
+            Ok(Value::Bool(rest.iter().any(|other| equal(first, other))))
+        }
+        "ne" => {
+            let [a, b] = exact::<2>(name, args)?;
+            Ok(Value::Bool(!equal(a, b)))
+        }
+        "lt" | "le" | "gt" | "ge" => {
+            let [a, b] = exact::<2>(name, args)?;
+            let ordering = compare(a, b)?;
+            Ok(Value::Bool(match name {
+                "lt" => ordering == Ordering::Less,
+                "le" => ordering != Ordering::Greater,
+                "gt" => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            }))
+        }
+        "len" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::from(match value {
+                Value::String(s) => s.len(),
+                Value::Array(items) => items.len(),
+                Value::Object(map) => map.len(),
+                Value::Null => 0,
+                other => return Err(format!("len of type {}", kind_of(other))),
+            }))
+        }
+        "index" => {
+            let (first, keys) = args.split_first().ok_or("index requires arguments")?;
+            let mut current = first.clone();
+            for key in keys {
+                current = match (current, key) {
+                    (Value::Object(mut map), key) => {
+                        map.remove(&display(key)).unwrap_or(Value::Null)
+                    }
+                    (Value::Array(items), Value::Number(n)) => {
+                        let index = n.as_u64().ok_or("index out of range")? as usize;
+                        items
+                            .get(index)
+                            .cloned()
+                            .ok_or_else(|| format!("index out of range: {}", index))?
+                    }
+                    (Value::Null, _) => Value::Null,
+                    (other, _) => {
+                        return Err(format!("can't index item of type {}", kind_of(&other)))
+                    }
+                };
+            }
+            Ok(current)
+        }
+        "print" => Ok(Value::String(sprint(args))),
+        "println" => Ok(Value::String(format!(
+            "{}\n",
+            args.iter().map(display).collect::<Vec<_>>().join(" ")
+        ))),
+        "printf" => {
+            let (format, rest) = args.split_first().ok_or("printf requires a format")?;
+            Ok(Value::String(sprintf(&display(format), rest)))
+        }
+
+        // Defaults and flow control.
+        "default" => match args {
+            [default] => Ok(default.clone()),
+            [default, given] => Ok(if truthy(given) { given } else { default }.clone()),
+            _ => Err(arity(name, 2, args)),
+        },
+        "empty" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::Bool(!truthy(value)))
+        }
+        "coalesce" => Ok(args
+            .iter()
+            .find(|v| truthy(v))
+            .cloned()
+            .unwrap_or(Value::Null)),
+        "ternary" => {
+            let [yes, no, condition] = exact::<3>(name, args)?;
+            Ok(if truthy(condition) { yes } else { no }.clone())
+        }
+        "required" => {
+            let [message, value] = exact::<2>(name, args)?;
+            match value {
+                Value::Null => Err(display(message)),
+                Value::String(s) if s.is_empty() => Err(display(message)),
+                _ => Ok(value.clone()),
+            }
+        }
+        "fail" => {
+            let [message] = exact::<1>(name, args)?;
+            Err(display(message))
+        }
+        "lookup" => Ok(Value::Object(Map::new())),
+
+        // Strings.
+        "quote" | "squote" => {
+            let quoted: Vec<String> = args
+                .iter()
+                .filter(|v| !v.is_null())
+                .map(|v| {
+                    if name == "quote" {
+                        go_quote(&display(v))
+                    } else {
+                        format!("'{}'", display(v))
+                    }
+                })
+                .collect();
+            Ok(Value::String(quoted.join(" ")))
+        }
+        "upper" => map_string(name, args, |s| s.to_uppercase()),
+        "lower" => map_string(name, args, |s| s.to_lowercase()),
+        "title" => map_string(name, args, title),
+        "trim" => map_string(name, args, |s| s.trim().to_string()),
+        "nospace" => map_string(name, args, |s| s.split_whitespace().collect()),
+        "toString" => map_string(name, args, str::to_string),
+        "b64enc" => map_string(name, args, |s| STANDARD.encode(s)),
+        "b64dec" => {
+            let [value] = exact::<1>(name, args)?;
+            let bytes = STANDARD.decode(display(value)).map_err(|e| e.to_string())?;
+            Ok(Value::String(String::from_utf8_lossy(&bytes).into_owned()))
+        }
+        "sha256sum" => map_string(name, args, |s| {
+            format!("{:x}", Sha256::digest(s.as_bytes()))
+        }),
+        "trimAll" => {
+            let [cutset, value] = exact::<2>(name, args)?;
+            let cutset: Vec<char> = display(cutset).chars().collect();
+            Ok(Value::String(
+                display(value).trim_matches(cutset.as_slice()).to_string(),
+            ))
+        }
+        "trimPrefix" => {
+            let [prefix, value] = exact::<2>(name, args)?;
+            let value = display(value);
+            let prefix = display(prefix);
+            Ok(Value::String(
+                value.strip_prefix(&prefix).unwrap_or(&value).to_string(),
+            ))
+        }
+        "trimSuffix" => {
+            let [suffix, value] = exact::<2>(name, args)?;
+            let value = display(value);
+            let suffix = display(suffix);
+            Ok(Value::String(
+                value.strip_suffix(&suffix).unwrap_or(&value).to_string(),
+            ))
+        }
+        "trunc" => {
+            let [length, value] = exact::<2>(name, args)?;
+            let chars: Vec<char> = display(value).chars().collect();
+            let length = to_i64(length);
+            let truncated: String = if length >= 0 {
+                chars.iter().take(length as usize).collect()
+            } else {
+                let keep = (-length) as usize;
+                chars[chars.len().saturating_sub(keep)..].iter().collect()
+            };
+            Ok(Value::String(truncated))
+        }
+        "substr" => {
+            let [start, end, value] = exact::<3>(name, args)?;
+            let chars: Vec<char> = display(value).chars().collect();
+            let start = (to_i64(start).max(0) as usize).min(chars.len());
+            let end = match to_i64(end) {
+                end if end < 0 => chars.len(),
+                end => (end as usize).min(chars.len()),
+            };
+            Ok(Value::String(chars[start..end.max(start)].iter().collect()))
+        }
+        "replace" => {
+            let [old, new, value] = exact::<3>(name, args)?;
+            Ok(Value::String(
+                display(value).replace(&display(old), &display(new)),
+            ))
+        }
+        "contains" => {
+            let [needle, value] = exact::<2>(name, args)?;
+            Ok(Value::Bool(display(value).contains(&display(needle))))
+        }
+        "hasPrefix" => {
+            let [prefix, value] = exact::<2>(name, args)?;
+            Ok(Value::Bool(display(value).starts_with(&display(prefix))))
+        }
+        "hasSuffix" => {
+            let [suffix, value] = exact::<2>(name, args)?;
+            Ok(Value::Bool(display(value).ends_with(&display(suffix))))
+        }
+        "indent" | "nindent" => {
+            let [width, value] = exact::<2>(name, args)?;
+            let pad = " ".repeat(to_i64(width).max(0) as usize);
+            let indented = format!(
+                "{}{}",
+                pad,
+                display(value).replace('\n', &format!("\n{}", pad))
+            );
+            Ok(Value::String(if name == "nindent" {
+                format!("\n{}", indented)
+            } else {
+                indented
+            }))
+        }
+        "repeat" => {
+            let [count, value] = exact::<2>(name, args)?;
+            Ok(Value::String(
+                display(value).repeat(to_i64(count).max(0) as usize),
+            ))
+        }
+        "cat" => Ok(Value::String(
+            args.iter()
+                .filter(|v| !v.is_null())
+                .map(display)
+                .collect::<Vec<_>>()
+                .join(" "),
+        )),
+        "join" => {
+            let [separator, list] = exact::<2>(name, args)?;
+            let items: Vec<String> = as_list(list).iter().map(display).collect();
+            Ok(Value::String(items.join(&display(separator))))
+        }
+        "splitList" => {
+            let [separator, value] = exact::<2>(name, args)?;
+            Ok(Value::Array(
+                display(value)
+                    .split(display(separator).as_str())
+                    .map(Value::from)
+                    .collect(),
+            ))
+        }
+        "split" => {
+            let [separator, value] = exact::<2>(name, args)?;
+            let parts = display(value)
+                .split(display(separator).as_str())
+                .enumerate()
+                .map(|(i, part)| (format!("_{}", i), Value::from(part)))
+                .collect();
+            Ok(Value::Object(parts))
+        }
+
+        // Serialisation.
+        "toYaml" => {
+            let [value] = exact::<1>(name, args)?;
+            let yaml = serde_yaml::to_string(&sorted(value)).map_err(|e| e.to_string())?;
+            Ok(Value::String(yaml.trim_end_matches('\n').to_string()))
+        }
+        "toJson" | "toPrettyJson" => {
+            let [value] = exact::<1>(name, args)?;
+            let value = sorted(value);
+            let json = if name == "toJson" {
+                serde_json::to_string(&value)
+            } else {
+                serde_json::to_string_pretty(&value)
+            };
+            json.map(Value::String).map_err(|e| e.to_string())
+        }
+        "fromYaml" => {
+            let [value] = exact::<1>(name, args)?;
+            serde_yaml::from_str::<Value>(&display(value)).map_err(|e| e.to_string())
+        }
+        "fromJson" => {
+            let [value] = exact::<1>(name, args)?;
+            serde_json::from_str::<Value>(&display(value)).map_err(|e| e.to_string())
+        }
+
+        // Numbers.
+        "int" | "int64" | "atoi" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::from(to_i64(value)))
+        }
+        "float64" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::from(to_f64(value)))
+        }
+        "add" => Ok(Value::from(args.iter().map(to_i64).sum::<i64>())),
+        "mul" => Ok(Value::from(args.iter().map(to_i64).product::<i64>())),
+        "add1" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::from(to_i64(value) + 1))
+        }
+        "sub" => {
+            let [a, b] = exact::<2>(name, args)?;
+            Ok(Value::from(to_i64(a) - to_i64(b)))
+        }
+        "div" | "mod" => {
+            let [a, b] = exact::<2>(name, args)?;
+            let divisor = to_i64(b);
+            if divisor == 0 {
+                return Err("integer divide by zero".to_string());
+            }
+            Ok(Value::from(if name == "div" {
+                to_i64(a) / divisor
+            } else {
+                to_i64(a) % divisor
+            }))
+        }
+        "max" | "min" => {
+            let numbers = args.iter().map(to_i64);
+            let result = if name == "max" {
+                numbers.max()
+            } else {
+                numbers.min()
+            };
+            result.map(Value::from).ok_or_else(|| arity(name, 1, args))
+        }
+        "until" => {
+            let [count] = exact::<1>(name, args)?;
+            Ok(Value::Array((0..to_i64(count)).map(Value::from).collect()))
+        }
+
+        // Lists.
+        "list" => Ok(Value::Array(args.to_vec())),
+        "first" | "last" => {
+            let [list] = exact::<1>(name, args)?;
+            let items = as_list(list);
+            let item = if name == "first" {
+                items.first()
+            } else {
+                items.last()
+            };
+            Ok(item.cloned().unwrap_or(Value::Null))
+        }
+        "rest" | "initial" => {
+            let [list] = exact::<1>(name, args)?;
+            let items = as_list(list);
+            let slice = match (name, items.len()) {
+                (_, 0) => &items[..],
+                ("rest", _) => &items[1..],
+                _ => &items[..items.len() - 1],
+            };
+            Ok(Value::Array(slice.to_vec()))
+        }
+        "append" | "prepend" => {
+            let [list, value] = exact::<2>(name, args)?;
+            let mut items = as_list(list);
+            if name == "append" {
+                items.push(value.clone());
+            } else {
+                items.insert(0, value.clone());
+            }
+            Ok(Value::Array(items))
+        }
+        "concat" => Ok(Value::Array(args.iter().flat_map(as_list).collect())),
+        "has" => {
+            let [needle, list] = exact::<2>(name, args)?;
+            Ok(Value::Bool(
+                as_list(list).iter().any(|item| equal(item, needle)),
+            ))
+        }
+        "uniq" => {
+            let [list] = exact::<1>(name, args)?;
+            let mut unique: Vec<Value> = Vec::new();
+            for item in as_list(list) {
+                if !unique.iter().any(|seen| equal(seen, &item)) {
+                    unique.push(item);
+                }
+            }
+            Ok(Value::Array(unique))
+        }
+        "without" => {
+            let (list, removed) = args.split_first().ok_or_else(|| arity(name, 2, args))?;
+            Ok(Value::Array(
+                as_list(list)
+                    .into_iter()
+                    .filter(|item| !removed.iter().any(|r| equal(r, item)))
+                    .collect(),
+            ))
+        }
+        "compact" => {
+            let [list] = exact::<1>(name, args)?;
+            Ok(Value::Array(
+                as_list(list).into_iter().filter(truthy).collect(),
+            ))
+        }
+        "sortAlpha" => {
+            let [list] = exact::<1>(name, args)?;
+            let mut items: Vec<String> = as_list(list).iter().map(display).collect();
+            items.sort();
+            Ok(Value::Array(items.into_iter().map(Value::from).collect()))
+        }
+        "toStrings" => {
+            let [list] = exact::<1>(name, args)?;
+            Ok(Value::Array(
+                as_list(list)
+                    .iter()
+                    .map(|v| Value::from(display(v)))
+                    .collect(),
+            ))
+        }
+
+        // Dictionaries.
+        "dict" => {
+            let mut map = Map::new();
+            for pair in args.chunks(2) {
+                let value = pair.get(1).cloned().unwrap_or_else(|| Value::from(""));
+                map.insert(display(&pair[0]), value);
+            }
+            Ok(Value::Object(map))
+        }
+        "get" => {
+            let [dict, key] = exact::<2>(name, args)?;
+            Ok(as_map(dict)
+                .get(&display(key))
+                .cloned()
+                .unwrap_or_else(|| Value::from("")))
+        }
+        "set" => {
+            let [dict, key, value] = exact::<3>(name, args)?;
+            let mut map = as_map(dict);
+            map.insert(display(key), value.clone());
+            Ok(Value::Object(map))
+        }
+        "unset" => {
+            let [dict, key] = exact::<2>(name, args)?;
+            let mut map = as_map(dict);
+            map.remove(&display(key));
+            Ok(Value::Object(map))
+        }
+        "hasKey" => {
+            let [dict, key] = exact::<2>(name, args)?;
+            Ok(Value::Bool(as_map(dict).contains_key(&display(key))))
+        }
+        "keys" => {
+            let mut keys: Vec<String> = args
+                .iter()
+                .flat_map(|d| as_map(d).into_iter().map(|(k, _)| k))
+                .collect();
+            keys.sort();
+            Ok(Value::Array(keys.into_iter().map(Value::from).collect()))
+        }
+        "pluck" => {
+            let (key, dicts) = args.split_first().ok_or_else(|| arity(name, 2, args))?;
+            let key = display(key);
+            Ok(Value::Array(
+                dicts
+                    .iter()
+                    .filter_map(|d| as_map(d).get(&key).cloned())
+                    .collect(),
+            ))
+        }
+        "dig" => {
+            if args.len() < 3 {
+                return Err(arity(name, 3, args));
+            }
+            let (dict, rest) = args.split_last().ok_or_else(|| arity(name, 3, args))?;
+            let (default, keys) = rest.split_last().ok_or_else(|| arity(name, 3, args))?;
+            let mut current = dict.clone();
+            for key in keys {
+                match current.get(display(key)) {
+                    Some(next) => current = next.clone(),
+                    None => return Ok(default.clone()),
+                }
+            }
+            Ok(current)
+        }
+        "merge" | "mergeOverwrite" => {
+            let (destination, sources) = args.split_first().ok_or_else(|| arity(name, 2, args))?;
+            let mut merged = destination.clone();
+            for source in sources {
+                merge_into(&mut merged, source, name == "mergeOverwrite");
+            }
+            Ok(merged)
+        }
+        "deepCopy" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(value.clone())
+        }
+
+        // Types and versions.
+        "kindOf" => {
+            let [value] = exact::<1>(name, args)?;
+            Ok(Value::from(kind_of(value)))
+        }
+        "kindIs" => {
+            let [kind, value] = exact::<2>(name, args)?;
+            let kind = display(kind);
+            let actual = kind_of(value);
+            Ok(Value::Bool(
+                kind == actual || (kind == "int" && actual == "int64"),
+            ))
+        }
+        "semverCompare" => {
+            let [constraint, version] = exact::<2>(name, args)?;
+            semver_matches(&display(constraint), &display(version)).map(Value::Bool)
+        }
+
+        _ => Err(format!("function \"{}\" not defined", name)),
+    }
+}
+
+fn arity(name: &str, want: usize, args: &[Value]) -> String {
+    format!(
+        "wrong number of args for {}: want {} got {}",
+        name,
+        want,
+        args.len()
+    )
+}
+
+fn exact<'a, const N: usize>(name: &str, args: &'a [Value]) -> Result<&'a [Value; N], String> {
+    args.try_into().map_err(|_| arity(name, N, args))
+}
+
+fn map_string(name: &str, args: &[Value], f: impl Fn(&str) -> String) -> Result<Value, String> {
+    let [value] = exact::<1>(name, args)?;
+    Ok(Value::String(f(&display(value))))
+}
+
+fn as_list(value: &Value) -> Vec<Value> {
+    match value {
+        Value::Array(items) => items.clone(),
+        Value::Null => Vec::new(),
+        other => vec![other.clone()],
+    }
+}
+
+fn as_map(value: &Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map.clone(),
+        _ => Map::new(),
+    }
+}
+
+fn to_i64(value: &Value) -> i64 {
+    match value {
+        Value::Number(n) => n
+            .as_i64()
+            .or_else(|| n.as_f64().map(|f| f as i64))
+            .unwrap_or_default(),
+        Value::String(s) => s
+            .trim()
+            .parse::<i64>()
+            .or_else(|_| s.trim().parse::<f64>().map(|f| f as i64))
+            .unwrap_or_default(),
+        Value::Bool(b) => i64::from(*b),
+        _ => 0,
+    }
+}
+
+fn to_f64(value: &Value) -> f64 {
+    match value {
+        Value::Number(n) => n.as_f64().unwrap_or_default(),
+        Value::String(s) => s.trim().parse().unwrap_or_default(),
+        Value::Bool(b) => f64::from(u8::from(*b)),
+        _ => 0.0,
+    }
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Result<Ordering, String> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x
+            .as_f64()
+            .zip(y.as_f64())
+            .and_then(|(x, y)| x.partial_cmp(&y))
+            .ok_or_else(|| "incomparable numbers".to_string()),
+        (Value::String(x), Value::String(y)) => Ok(x.cmp(y)),
+        _ => Err(format!(
+            "incompatible types for comparison: {} and {}",
+            kind_of(a),
+            kind_of(b)
+        )),
+    }
+}
+
+// Go's `fmt.Sprint`: spaces are added between operands when neither is a string.
+fn sprint(args: &[Value]) -> String {
+    let mut out = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 && !args[i - 1].is_string() && !arg.is_string() {
+            out.push(' ');
+        }
+        out.push_str(&display(arg));
+    }
+    out
+}
+
+// A subset of Go's `fmt.Sprintf`: flags `-` and `0`, width, precision and the verbs
+// `%s %v %d %q %t %f %x %%`.
+fn sprintf(format: &str, args: &[Value]) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    let mut next_arg = args.iter();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut left_align = false;
+        let mut zero_pad = false;
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => left_align = true,
+                '0' => zero_pad = true,
+                '+' | ' ' | '#' => {}
+                _ => break,
+            }
+            chars.next();
+        }
+        let mut width = String::new();
+        while let Some(&digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            width.push(digit);
+            chars.next();
+        }
+        let mut precision = None;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut digits = String::new();
+            while let Some(&digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(digit);
+                chars.next();
+            }
+            precision = Some(digits.parse::<usize>().unwrap_or(0));
+        }
+        let Some(verb) = chars.next() else {
+            out.push_str("%!(NOVERB)");
+            break;
+        };
+        if verb == '%' {
+            out.push('%');
+            continue;
+        }
+        let Some(arg) = next_arg.next() else {
+            out.push_str(&format!("%!{}(MISSING)", verb));
+            continue;
+        };
+
+        let formatted = match verb {
+            's' | 'v' => display(arg),
+            'd' => to_i64(arg).to_string(),
+            'q' => go_quote(&display(arg)),
+            't' => truthy(arg).to_string(),
+            'f' => format!("{:.*}", precision.unwrap_or(6), to_f64(arg)),
+            'x' => match arg {
+                Value::Number(_) => format!("{:x}", to_i64(arg)),
+                other => display(other)
+                    .bytes()
+                    .map(|b| format!("{:02x}", b))
+                    .collect(),
+            },
+            other => format!("%!{}({})", other, display(arg)),
+        };
+        let formatted = match (verb, precision) {
+            ('s' | 'v', Some(limit)) => formatted.chars().take(limit).collect(),
+            _ => formatted,
+        };
+
+        let width = width.parse::<usize>().unwrap_or(0);
+        let padding = width.saturating_sub(formatted.chars().count());
+        if left_align {
+            out.push_str(&formatted);
+            out.push_str(&" ".repeat(padding));
+        } else if zero_pad {
+            out.push_str(&"0".repeat(padding));
+            out.push_str(&formatted);
+        } else {
+            out.push_str(&" ".repeat(padding));
+            out.push_str(&formatted);
+        }
+    }
+
+    out
+}
+
+fn go_quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| format!("\"{}\"", value))
+}
+
+fn title(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut at_word_start = true;
+    for c in value.chars() {
+        if at_word_start {
+            out.extend(c.to_uppercase());
+        } else {
+            out.push(c);
+        }
+        at_word_start = c.is_whitespace();
+    }
+    out
+}
+
+// Go's encoders sort map keys; doing the same keeps `toYaml`/`toJson` output stable.
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), sorted(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(sorted).collect()),
+        other => other.clone(),
+    }
+}
+
+fn merge_into(destination: &mut Value, source: &Value, overwrite: bool) {
+    let (Value::Object(destination), Value::Object(source)) = (destination, source) else {
+        return;
+    };
+    for (key, value) in source {
+        match destination.get_mut(key) {
+            Some(existing) if existing.is_object() && value.is_object() => {
+                merge_into(existing, value, overwrite)
+            }
+            Some(existing) if overwrite || existing.is_null() => *existing = value.clone(),
+            Some(_) => {}
+            None => {
+                destination.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn parse_version(version: &str) -> Option<[u64; 3]> {
+    let version = version.trim().trim_start_matches(['v', '=']);
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = [0u64; 3];
+    for (i, part) in core.split('.').enumerate() {
+        if i >= 3 {
+            return None;
+        }
+        if matches!(part, "x" | "X" | "*") {
+            break;
+        }
+        parts[i] = part.parse().ok()?;
+    }
+    Some(parts)
+}
+
+// Evaluates a Masterminds-style constraint such as `>=1.19-0 <1.30` or `^1.2 || ~2.0`.
+// Pre-release tags are ignored, which matches how charts use `-0` to admit them.
+fn semver_matches(constraint: &str, version: &str) -> Result<bool, String> {
+    let version =
+        parse_version(version).ok_or_else(|| format!("invalid semantic version: {}", version))?;
+
+    for alternative in constraint.split("||") {
+        // Operators may be separated from their version by a space (`>= 1.19`).
+        let mut clauses = Vec::new();
+        let mut operator = String::new();
+        for token in alternative
+            .split([',', ' '])
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+        {
+            if token.chars().all(|c| "<>=!~^".contains(c)) {
+                operator.push_str(token);
+            } else {
+                clauses.push(format!("{}{}", std::mem::take(&mut operator), token));
+            }
+        }
+
+        let mut satisfied = true;
+        for clause in &clauses {
+            let split = clause
+                .find(|c: char| c.is_ascii_digit() || c == 'v')
+                .unwrap_or(clause.len());
+            let (op, target) = clause.split_at(split);
+            let target =
+                parse_version(target).ok_or_else(|| format!("invalid constraint: {}", clause))?;
+            let upper = |index: usize| {
+                let mut bound = [0u64; 3];
+                bound[..index].copy_from_slice(&target[..index]);
+                bound[index - 1] += 1;
+                bound
+            };
+            let matches = match op.trim() {
+                ">=" => version >= target,
+                ">" => version > target,
+                "<=" => version <= target,
+                "<" => version < target,
+                "!=" => version != target,
+                "" | "=" => version == target,
+                "~" => version >= target && version < upper(2),
+                "^" if target[0] > 0 => version >= target && version < upper(1),
+                "^" => version >= target && version < upper(2),
+                other => return Err(format!("unsupported constraint operator: {}", other)),
+            };
+            satisfied &= matches;
+        }
+        if satisfied {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn formats_and_converts_like_sprig() {
+        assert_eq!(
+            call(
+                "printf",
+                &[
+                    json!("%s-%d-%05.1f|%-4s|"),
+                    json!("a"),
+                    json!("7"),
+                    json!(2.26),
+                    json!("x")
+                ]
+            )
+            .unwrap(),
+            json!("a-7-002.3|x   |")
+        );
+        assert_eq!(
+            call("toYaml", &[json!({"b": 1, "a": [true]})]).unwrap(),
+            json!("a:\n- true\nb: 1")
+        );
+        assert_eq!(
+            call("trunc", &[json!(3), json!("abcdef")]).unwrap(),
+            json!("abc")
+        );
+        assert_eq!(
+            call("quote", &[json!("say \"hi\"")]).unwrap(),
+            json!("\"say \\\"hi\\\"\"")
+        );
+        assert_eq!(
+            call(
+                "merge",
+                &[
+                    json!({"a": 1, "n": {"x": 1}}),
+                    json!({"a": 2, "b": 3, "n": {"y": 2}})
+                ]
+            )
+            .unwrap(),
+            json!({"a": 1, "n": {"x": 1, "y": 2}, "b": 3})
+        );
+        assert!(call("required", &[json!("image is required"), json!("")])
+            .unwrap_err()
+            .contains("image is required"));
+    }
+
+    #[test]
+    fn compares_semantic_versions() {
+        assert!(semver_matches(">=1.19-0", "v1.33.0").unwrap());
+        assert!(!semver_matches("<1.19", "v1.33.0").unwrap());
+        assert!(semver_matches("^1.2.0", "1.9.1").unwrap());
+        assert!(!semver_matches("~1.2.0", "1.3.0").unwrap());
+        assert!(semver_matches(">=2.0 || >= 1.30, <1.34", "1.33.2").unwrap());
+    }
+}
@@ -0,0 +1,700 @@
+//! Native rendering of Helm charts referenced by a service's `[service.helm]` block.
+//!
+//! Charts are read from a local directory or a vendored `.tgz` archive and rendered
+//! in-process with [`template`], so `helm` does not need to be installed. The result is
+//! returned as [`Template`]s that `crate::generate` treats like hand-written service
+//! templates: overlays, schema validation and the generated output directory all apply.
+//!
+//! Values are layered as chart `values.yaml` < the service's values file < Sailr's
+//! variables, which are exposed under `.Values.sailr` (e.g. `.Values.sailr.registry`).
+//! The values file itself may use Sailr placeholders such as `{{ service_version }}`.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+use serde_json::{json, Map, Value};
+use walkdir::WalkDir;
+
+use crate::{
+    environment::{Environment, Service, ServiceHelmConfig},
+    errors::HelmError,
+    templates::Template,
+    utils::replace_variables,
+};
+
+pub mod functions;
+pub mod template;
+
+use template::{ParsedTemplate, TemplateSet};
+
+/// Kubernetes version reported to charts through `.Capabilities.KubeVersion`.
+const KUBE_VERSION: (u32, u32) = (1, 33);
+
+/// API versions reported through `.Capabilities.APIVersions`. Both the group version
+/// and the `group/version/Kind` forms are listed, as Helm does.
+const API_VERSIONS: &[(&str, &[&str])] = &[
+    (
+        "v1",
+        &[
+            "ConfigMap",
+            "Namespace",
+            "PersistentVolumeClaim",
+            "Pod",
+            "Secret",
+            "Service",
+            "ServiceAccount",
+        ],
+    ),
+    (
+        "apps/v1",
+        &["DaemonSet", "Deployment", "ReplicaSet", "StatefulSet"],
+    ),
+    ("batch/v1", &["CronJob", "Job"]),
+    ("autoscaling/v1", &["HorizontalPodAutoscaler"]),
+    ("autoscaling/v2", &["HorizontalPodAutoscaler"]),
+    ("policy/v1", &["PodDisruptionBudget"]),
+    (
+        "networking.k8s.io/v1",
+        &["Ingress", "IngressClass", "NetworkPolicy"],
+    ),
+    (
+        "rbac.authorization.k8s.io/v1",
+        &["ClusterRole", "ClusterRoleBinding", "Role", "RoleBinding"],
+    ),
+    ("storage.k8s.io/v1", &["StorageClass"]),
+    ("apiextensions.k8s.io/v1", &["CustomResourceDefinition"]),
+    (
+        "admissionregistration.k8s.io/v1",
+        &[
+            "MutatingWebhookConfiguration",
+            "ValidatingWebhookConfiguration",
+        ],
+    ),
+    ("scheduling.k8s.io/v1", &["PriorityClass"]),
+    ("coordination.k8s.io/v1", &["Lease"]),
+    ("discovery.k8s.io/v1", &["EndpointSlice"]),
+];
+
+/// A chart loaded into memory, along with its subcharts.
+#[derive(Debug, Clone)]
+pub struct Chart {
+    pub name: String,
+    metadata: Map<String, Value>,
+    values: Value,
+    templates: BTreeMap<String, String>,
+    // The manifests under `crds/`, which Helm installs as they are, without templating.
+    crds: BTreeMap<String, String>,
+    files: Map<String, Value>,
+    dependencies: Vec<Dependency>,
+    subcharts: Vec<Chart>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Dependency {
+    name: String,
+    #[serde(default)]
+    alias: Option<String>,
+    #[serde(default)]
+    condition: Option<String>,
+}
+
+/// Release information exposed to templates as `.Release`.
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub name: String,
+    pub namespace: String,
+}
+
+/// A rendered chart template, e.g. `redis/templates/deployment.yaml`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedManifest {
+    pub path: String,
+    pub content: String,
+}
+
+impl Chart {
+    /// Loads a chart from a directory or a `.tgz` archive.
+    pub fn load(path: &Path) -> Result<Chart, HelmError> {
+        let source = path.display().to_string();
+        let files = if path.is_dir() {
+            read_chart_dir(path)?
+        } else if path.is_file() {
+            let bytes = fs::read(path).map_err(|e| HelmError::Read {
+                path: source.clone(),
+                message: e.to_string(),
+            })?;
+            read_chart_archive(&source, &bytes)?
+        } else {
+            return Err(HelmError::Read {
+                path: source,
+                message: "no chart directory or archive at this path".to_string(),
+            });
+        };
+        Chart::from_files(&source, files)
+    }
+
+    /// Builds a chart from its files, keyed by their path relative to the chart root.
+    pub fn from_files(source: &str, files: BTreeMap<String, Vec<u8>>) -> Result<Chart, HelmError> {
+        let invalid = |message: String| HelmError::InvalidChart {
+            path: source.to_string(),
+            message,
+        };
+
+        let chart_yaml = files
+            .get("Chart.yaml")
+            .ok_or_else(|| invalid("missing Chart.yaml".to_string()))?;
+        let metadata: Map<String, Value> = serde_yaml::from_slice(chart_yaml)
+            .map_err(|e| invalid(format!("Chart.yaml: {}", e)))?;
+        let name = metadata
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("Chart.yaml has no name".to_string()))?
+            .to_string();
+        let dependencies: Vec<Dependency> = match metadata.get("dependencies") {
+            Some(deps) => serde_json::from_value(deps.clone())
+                .map_err(|e| invalid(format!("Chart.yaml dependencies: {}", e)))?,
+            None => Vec::new(),
+        };
+
+        let values = match files.get("values.yaml") {
+            Some(bytes) => serde_yaml::from_slice::<Option<Value>>(bytes)
+                .map_err(|e| invalid(format!("values.yaml: {}", e)))?
+                .unwrap_or_else(|| json!({})),
+            None => json!({}),
+        };
+
+        let mut templates = BTreeMap::new();
+        let mut crds = BTreeMap::new();
+        let mut chart_files = Map::new();
+        let mut subchart_files: BTreeMap<String, BTreeMap<String, Vec<u8>>> = BTreeMap::new();
+        let mut subcharts = Vec::new();
+
+        for (path, bytes) in &files {
+            if path.starts_with("templates/") {
+                let content = String::from_utf8(bytes.clone())
+                    .map_err(|_| invalid(format!("{} is not valid UTF-8", path)))?;
+                templates.insert(path.clone(), content);
+            } else if path.starts_with("crds/")
+                && [".yaml", ".yml", ".json"]
+                    .iter()
+                    .any(|ext| path.ends_with(ext))
+            {
+                let content = String::from_utf8(bytes.clone())
+                    .map_err(|_| invalid(format!("{} is not valid UTF-8", path)))?;
+                crds.insert(path.clone(), content);
+            } else if let Some(rest) = path.strip_prefix("charts/") {
+                match rest.split_once('/') {
+                    Some((dir, inner)) => {
+                        subchart_files
+                            .entry(dir.to_string())
+                            .or_default()
+                            .insert(inner.to_string(), bytes.clone());
+                    }
+                    None if rest.ends_with(".tgz") => {
+                        let archive = format!("{}/{}", source, path);
+                        let files = read_chart_archive(&archive, bytes)?;
+                        subcharts.push(Chart::from_files(&archive, files)?);
+                    }
+                    None => {}
+                }
+            } else {
+                chart_files.insert(
+                    path.clone(),
+                    Value::String(String::from_utf8_lossy(bytes).into_owned()),
+                );
+            }
+        }
+
+        for (dir, files) in subchart_files {
+            subcharts.push(Chart::from_files(
+                &format!("{}/charts/{}", source, dir),
+                files,
+            )?);
+        }
+
+        Ok(Chart {
+            name,
+            metadata,
+            values,
+            templates,
+            crds,
+            files: chart_files,
+            dependencies,
+            subcharts,
+        })
+    }
+
+    /// Renders every template of the chart and its enabled subcharts. `overrides` are
+    /// merged over the chart's own values. Templates that render to nothing but
+    /// whitespace are dropped, as Helm does. The CRDs of the chart and its enabled
+    /// subcharts come first, as they are written.
+    pub fn render(
+        &self,
+        overrides: &Value,
+        release: &Release,
+    ) -> Result<Vec<RenderedManifest>, HelmError> {
+        let mut values = self.values.clone();
+        coalesce(&mut values, overrides);
+
+        let mut set = TemplateSet::new();
+        let mut jobs = Vec::new();
+        let mut rendered = Vec::new();
+        self.collect(
+            &self.name,
+            values,
+            release,
+            &mut set,
+            &mut jobs,
+            &mut rendered,
+        )?;
+
+        for (template, data) in jobs {
+            let content = set.execute(&template, &data)?;
+            if content.trim().is_empty() {
+                continue;
+            }
+            rendered.push(RenderedManifest {
+                path: template.name().to_string(),
+                content,
+            });
+        }
+        Ok(rendered)
+    }
+
+    // Parses the templates of this chart and its enabled subcharts into `set`, queueing
+    // the renderable ones (not partials or NOTES.txt) together with their data. Their
+    // CRDs are added to `crds`.
+    fn collect(
+        &self,
+        prefix: &str,
+        values: Value,
+        release: &Release,
+        set: &mut TemplateSet,
+        jobs: &mut Vec<(ParsedTemplate, Value)>,
+        crds: &mut Vec<RenderedManifest>,
+    ) -> Result<(), HelmError> {
+        crds.extend(
+            self.crds
+                .iter()
+                .filter(|(_, content)| !content.trim().is_empty())
+                .map(|(path, content)| RenderedManifest {
+                    path: format!("{}/{}", prefix, path),
+                    content: content.clone(),
+                }),
+        );
+        for subchart in &self.subcharts {
+            let dependency = self.dependencies.iter().find(|d| d.name == subchart.name);
+            let key = dependency
+                .and_then(|d| d.alias.clone())
+                .unwrap_or_else(|| subchart.name.clone());
+            if let Some(condition) = dependency.and_then(|d| d.condition.as_deref()) {
+                if !condition_enabled(&values, condition) {
+                    continue;
+                }
+            }
+
+            let mut subchart_values = subchart.values.clone();
+            if let Some(scoped) = values.get(&key) {
+                coalesce(&mut subchart_values, scoped);
+            }
+            if let Some(global) = values.get("global") {
+                let mut globals = json!({ "global": global });
+                if let Some(own) = subchart_values.get("global") {
+                    coalesce(&mut globals, &json!({ "global": own }));
+                }
+                coalesce(&mut subchart_values, &globals);
+            }
+
+            subchart.collect(
+                &format!("{}/charts/{}", prefix, key),
+                subchart_values,
+                release,
+                set,
+                jobs,
+                crds,
+            )?;
+        }
+
+        for (path, source) in &self.templates {
+            let name = format!("{}/{}", prefix, path);
+            let template = set.parse(&name, source)?;
+
+            let file_name = path.rsplit('/').next().unwrap_or(path);
+            if file_name.starts_with('_') || file_name == "NOTES.txt" {
+                continue;
+            }
+            let data = self.template_data(prefix, &name, &values, release);
+            jobs.push((template, data));
+        }
+        Ok(())
+    }
+
+    fn template_data(&self, prefix: &str, name: &str, values: &Value, release: &Release) -> Value {
+        let chart: Map<String, Value> = self
+            .metadata
+            .iter()
+            .map(|(key, value)| (chart_field_name(key), value.clone()))
+            .collect();
+
+        let mut api_versions = Vec::new();
+        for (group_version, kinds) in API_VERSIONS {
+            api_versions.push(Value::from(*group_version));
+            api_versions.extend(
+                kinds
+                    .iter()
+                    .map(|kind| Value::from(format!("{}/{}", group_version, kind))),
+            );
+        }
+        let (major, minor) = KUBE_VERSION;
+        let kube_version = format!("v{}.{}.0", major, minor);
+
+        json!({
+            "Values": values,
+            "Release": {
+                "Name": release.name,
+                "Namespace": release.namespace,
+                "Service": "Helm",
+                "IsInstall": true,
+                "IsUpgrade": false,
+                "Revision": 1,
+            },
+            "Chart": chart,
+            "Capabilities": {
+                "KubeVersion": {
+                    "Version": kube_version,
+                    "GitVersion": kube_version,
+                    "Major": major.to_string(),
+                    "Minor": minor.to_string(),
+                },
+                "APIVersions": api_versions,
+            },
+            "Template": {
+                "Name": name,
+                "BasePath": format!("{}/templates", prefix),
+            },
+            "Files": self.files,
+        })
+    }
+}
+
+/// Renders the chart configured for `service` into templates destined for the
+/// service's generated directory.
+pub fn render_service_chart(
+    env: &Environment,
+    service: &Service,
+    helm: &ServiceHelmConfig,
+) -> Result<Vec<Template>, HelmError> {
+    let chart = Chart::load(&PathBuf::from(&helm.chart))?;
+    let variables = env.get_variables(service).map_err(|e| HelmError::Read {
+        path: helm.chart.clone(),
+        message: e.to_string(),
+    })?;
+
+    let mut overrides = match &helm.values {
+        Some(path) => read_values_file(path, &variables)?,
+        None => json!({}),
+    };
+    let sailr: Map<String, Value> = variables
+        .into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect();
+    coalesce(&mut overrides, &json!({ "sailr": sailr }));
+
+    let release = Release {
+        name: helm.release.clone().unwrap_or_else(|| service.name.clone()),
+        namespace: service.namespace_or(&env.default_namespace()).to_string(),
+    };
+
+    let templates = chart
+        .render(&overrides, &release)?
+        .into_iter()
+        .map(|manifest| {
+            Template::new(
+                service.get_path(),
+                manifest_file_name(&manifest.path),
+                manifest.content,
+            )
+        })
+        .collect();
+    Ok(templates)
+}
+
+// Reads a values file, first substituting Sailr placeholders such as `{{ registry }}`.
+fn read_values_file(path: &str, variables: &[(String, String)]) -> Result<Value, HelmError> {
+    let content = fs::read_to_string(path).map_err(|e| HelmError::Read {
+        path: path.to_string(),
+        message: e.to_string(),
+    })?;
+    let content = replace_variables(content, variables.iter().cloned().collect()).map_err(|e| {
+        HelmError::Read {
+            path: path.to_string(),
+            message: e.to_string(),
+        }
+    })?;
+    serde_yaml::from_str::<Option<Value>>(&content)
+        .map(|values| values.unwrap_or_else(|| json!({})))
+        .map_err(|e| HelmError::Read {
+            path: path.to_string(),
+            message: e.to_string(),
+        })
+}
+
+// `redis/templates/deployment.yaml` becomes `redis-deployment.yaml`, and a subchart's
+// `redis/charts/common/templates/cm.yaml` becomes `redis-common-cm.yaml`.
+fn manifest_file_name(path: &str) -> String {
+    path.split('/')
+        .filter(|part| *part != "templates" && *part != "charts")
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Chart.yaml keys are exposed capitalised (`.Chart.AppVersion`), with Helm's spelling
+// of `APIVersion`.
+fn chart_field_name(key: &str) -> String {
+    if key == "apiVersion" {
+        return "APIVersion".to_string();
+    }
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// A dependency condition is a comma-separated list of value paths; the first one that
+// resolves to a boolean decides. Dependencies without a resolvable condition are enabled.
+fn condition_enabled(values: &Value, condition: &str) -> bool {
+    for path in condition.split(',').map(str::trim) {
+        let resolved = path
+            .split('.')
+            .try_fold(values, |current, key| current.get(key));
+        if let Some(Value::Bool(enabled)) = resolved {
+            return *enabled;
+        }
+    }
+    true
+}
+
+// Merges `overrides` into `base` the way Helm coalesces values: maps merge
+// recursively, other values replace, and an explicit null removes the key.
+fn coalesce(base: &mut Value, overrides: &Value) {
+    let (Value::Object(base), Value::Object(overrides)) = (&mut *base, overrides) else {
+        *base = overrides.clone();
+        return;
+    };
+    for (key, value) in overrides {
+        match (base.get_mut(key), value) {
+            (_, Value::Null) => {
+                base.remove(key);
+            }
+            (Some(existing), Value::Object(_)) if existing.is_object() => coalesce(existing, value),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn read_chart_dir(root: &Path) -> Result<BTreeMap<String, Vec<u8>>, HelmError> {
+    let mut files = BTreeMap::new();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry.map_err(|e| HelmError::Read {
+            path: root.display().to_string(),
+            message: e.to_string(),
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let key = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let bytes = fs::read(entry.path()).map_err(|e| HelmError::Read {
+            path: entry.path().display().to_string(),
+            message: e.to_string(),
+        })?;
+        files.insert(key, bytes);
+    }
+    Ok(files)
+}
+
+// Packaged charts hold a single top-level directory named after the chart; it is
+// stripped so paths are relative to the chart root.
+fn read_chart_archive(source: &str, bytes: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, HelmError> {
+    let read_error = |e: std::io::Error| HelmError::Read {
+        path: source.to_string(),
+        message: e.to_string(),
+    };
+
+    let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+    let mut files = BTreeMap::new();
+    for entry in archive.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(read_error)?.into_owned();
+        let mut components = path.components();
+        components.next();
+        let key = components
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if key.is_empty() {
+            continue;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content).map_err(read_error)?;
+        files.insert(key, content);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(entries: &[(&str, &str)]) -> BTreeMap<String, Vec<u8>> {
+        entries
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn release() -> Release {
+        Release {
+            name: "cache".to_string(),
+            namespace: "staging".to_string(),
+        }
+    }
+
+    fn web_chart() -> BTreeMap<String, Vec<u8>> {
+        files(&[
+            ("Chart.yaml", "apiVersion: v2\nname: web\nversion: 0.1.0\nappVersion: \"2.0\"\ndependencies:\n  - name: metrics\n    condition: metrics.enabled\n"),
+            ("values.yaml", "replicas: 1\nimage:\n  repository: nginx\n  tag: \"\"\nmetrics:\n  enabled: false\n"),
+            ("templates/_helpers.tpl", "{{- define \"web.fullname\" -}}\n{{ .Release.Name }}-{{ .Chart.Name }}\n{{- end }}\n"),
+            ("templates/deployment.yaml", "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: {{ include \"web.fullname\" . }}\n  namespace: {{ .Release.Namespace }}\nspec:\n  replicas: {{ .Values.replicas }}\n  template:\n    spec:\n      containers:\n        - name: web\n          image: \"{{ .Values.image.repository }}:{{ .Values.image.tag | default .Chart.AppVersion }}\"\n"),
+            ("templates/NOTES.txt", "Thanks for installing {{ .Chart.Name }}"),
+            ("templates/optional.yaml", "{{- if .Values.extra }}\nkind: ConfigMap\n{{- end }}\n"),
+            ("charts/metrics/Chart.yaml", "name: metrics\nversion: 1.0.0\n"),
+            ("charts/metrics/values.yaml", "port: 9090\n"),
+            ("charts/metrics/templates/service.yaml", "kind: Service\nport: {{ .Values.port }}\nregion: {{ .Values.global.region }}\n"),
+            ("charts/metrics/crds/monitors.yaml", "kind: CustomResourceDefinition\nname: monitors\n"),
+        ])
+    }
+
+    #[test]
+    fn renders_chart_with_helpers_and_overrides() {
+        let chart = Chart::from_files("web", web_chart()).unwrap();
+        let rendered = chart
+            .render(
+                &json!({"replicas": 3, "image": {"tag": "1.27"}}),
+                &release(),
+            )
+            .unwrap();
+
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(rendered[0].path, "web/templates/deployment.yaml");
+        assert!(rendered[0].content.contains("name: cache-web\n"));
+        assert!(rendered[0].content.contains("namespace: staging\n"));
+        assert!(rendered[0].content.contains("replicas: 3\n"));
+        assert!(rendered[0].content.contains("image: \"nginx:1.27\""));
+    }
+
+    #[test]
+    fn renders_enabled_subcharts_with_scoped_values_and_globals() {
+        let chart = Chart::from_files("web", web_chart()).unwrap();
+        let rendered = chart
+            .render(
+                &json!({"metrics": {"enabled": true, "port": 9100}, "global": {"region": "eu"}}),
+                &release(),
+            )
+            .unwrap();
+
+        let service = rendered
+            .iter()
+            .find(|m| m.path == "web/charts/metrics/templates/service.yaml")
+            .unwrap();
+        assert_eq!(service.content, "kind: Service\nport: 9100\nregion: eu\n");
+        assert_eq!(
+            manifest_file_name(&service.path),
+            "web-metrics-service.yaml"
+        );
+    }
+
+    #[test]
+    fn emits_crds_first_without_templating() {
+        let mut chart_files = web_chart();
+        chart_files.extend(files(&[(
+            "crds/widgets.yaml",
+            "kind: CustomResourceDefinition\nname: {{ not.a.template }}\n",
+        )]));
+        let chart = Chart::from_files("web", chart_files).unwrap();
+
+        let rendered = chart.render(&json!({}), &release()).unwrap();
+        assert_eq!(rendered[0].path, "web/crds/widgets.yaml");
+        assert_eq!(
+            rendered[0].content,
+            "kind: CustomResourceDefinition\nname: {{ not.a.template }}\n"
+        );
+        assert_eq!(rendered[1].path, "web/templates/deployment.yaml");
+
+        let with_metrics = chart
+            .render(&json!({"metrics": {"enabled": true}}), &release())
+            .unwrap();
+        assert_eq!(
+            with_metrics[1].path,
+            "web/charts/metrics/crds/monitors.yaml"
+        );
+    }
+
+    #[test]
+    fn loads_packaged_charts() {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        for (path, content) in [
+            ("web/Chart.yaml", "name: web\nversion: 0.1.0\n"),
+            (
+                "web/templates/cm.yaml",
+                "kind: ConfigMap\nname: {{ .Release.Name }}\n",
+            ),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("web-0.1.0.tgz");
+        fs::write(&archive, bytes).unwrap();
+
+        let rendered = Chart::load(&archive)
+            .unwrap()
+            .render(&json!({}), &release())
+            .unwrap();
+        assert_eq!(rendered[0].content, "kind: ConfigMap\nname: cache\n");
+    }
+
+    #[test]
+    fn coalesce_merges_maps_and_removes_nulls() {
+        let mut base = json!({"a": {"b": 1, "c": 2}, "d": 3});
+        coalesce(&mut base, &json!({"a": {"c": null, "e": 4}, "d": [1]}));
+        assert_eq!(base, json!({"a": {"b": 1, "e": 4}, "d": [1]}));
+    }
+}
@@ -0,0 +1,1268 @@
+//! A native implementation of the subset of Go's `text/template` that Helm charts use.
+//!
+//! Supported: `{{ }}` actions with `{{-`/`-}}` whitespace trimming, comments,
+//! pipelines, parenthesised sub-pipelines, `$variables` (`:=` and `=`), field chains,
+//! `if`/`else if`/`else`, `with`/`else`, `range` (with `$i, $v :=`, `break`,
+//! `continue` and `else`), `define`, `template`, `block`, plus Helm's `include` and
+//! `tpl`. Everything else is provided by [`super::functions`].
+//!
+//! Values are `serde_json::Value`s. Missing fields evaluate to nil and print as an
+//! empty string, which matches Helm's rendering of `<no value>`.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use super::functions;
+use crate::errors::HelmError;
+
+/// Nesting limit for `include`/`template`, guarding against recursive defines.
+const MAX_INCLUDE_DEPTH: usize = 100;
+
+/// A file's top-level nodes plus the `define` blocks it declared.
+type ParsedSource = (Vec<Node>, Vec<(String, Vec<Node>)>);
+
+/// The `if`/`else if` (or `with`/`else with`) arms of a conditional.
+type Branches = Vec<(Pipeline, Vec<Node>)>;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Action {
+        pipeline: Pipeline,
+        line: usize,
+    },
+    If {
+        branches: Branches,
+        otherwise: Vec<Node>,
+        line: usize,
+    },
+    With {
+        branches: Branches,
+        otherwise: Vec<Node>,
+        line: usize,
+    },
+    Range {
+        pipeline: Pipeline,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+        line: usize,
+    },
+    Template {
+        name: String,
+        pipeline: Option<Pipeline>,
+        line: usize,
+    },
+    Break,
+    Continue,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Pipeline {
+    declarations: Vec<String>,
+    assign: bool,
+    commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+struct Command {
+    args: Vec<Operand>,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Dot,
+    Field(Vec<String>),
+    Variable(String, Vec<String>),
+    Function(String),
+    Literal(Value),
+    Pipeline(Box<Pipeline>, Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+struct Define {
+    origin: String,
+    nodes: Vec<Node>,
+}
+
+/// A parsed template file, ready to be executed against a [`TemplateSet`].
+#[derive(Debug, Clone)]
+pub struct ParsedTemplate {
+    name: String,
+    nodes: Vec<Node>,
+}
+
+impl ParsedTemplate {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Named templates (`define`) shared by every file of a chart and its subcharts.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateSet {
+    defines: HashMap<String, Define>,
+}
+
+impl TemplateSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses `source`, registering its `define` blocks in the set.
+    pub fn parse(&mut self, name: &str, source: &str) -> Result<ParsedTemplate, HelmError> {
+        let (nodes, defines) = parse_source(name, source)?;
+        for (define_name, nodes) in defines {
+            self.defines.insert(
+                define_name,
+                Define {
+                    origin: name.to_string(),
+                    nodes,
+                },
+            );
+        }
+        Ok(ParsedTemplate {
+            name: name.to_string(),
+            nodes,
+        })
+    }
+
+    /// Executes a parsed template with `data` as both `.` and `$`.
+    pub fn execute(&self, template: &ParsedTemplate, data: &Value) -> Result<String, HelmError> {
+        let mut exec = Executor::new(self, &template.name, data, 0);
+        let mut out = String::new();
+        exec.nodes(&template.nodes, data, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn parse_source(name: &str, source: &str) -> Result<ParsedSource, HelmError> {
+    let items = lex(source).map_err(|(line, message)| HelmError::Parse {
+        template: name.to_string(),
+        line,
+        message,
+    })?;
+    let mut parser = Parser {
+        name,
+        items,
+        pos: 0,
+        defines: Vec::new(),
+    };
+    let (nodes, end) = parser.list()?;
+    match end {
+        ListEnd::Eof => Ok((nodes, parser.defines)),
+        ListEnd::End(line) => Err(parser.error(line, "unexpected {{end}}")),
+        ListEnd::Else(line, _) => Err(parser.error(line, "unexpected {{else}}")),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Lexing: split the source into literal text and action bodies.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+enum Item {
+    Text(String),
+    Action { body: String, line: usize },
+}
+
+fn lex(source: &str) -> Result<Vec<Item>, (usize, String)> {
+    let mut items = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+
+    while pos < source.len() {
+        let Some(offset) = source[pos..].find("{{") else {
+            items.push(Item::Text(source[pos..].to_string()));
+            break;
+        };
+        let start = pos + offset;
+        let mut text = &source[pos..start];
+        let mut inner = start + 2;
+        if is_trim_marker(&source[inner..]) {
+            text = text.trim_end();
+            inner += 1;
+        }
+        if !text.is_empty() {
+            items.push(Item::Text(text.to_string()));
+        }
+
+        line += source[pos..start].matches('\n').count();
+        let (body_end, close, trim_right) =
+            find_close(source, inner).ok_or_else(|| (line, "unclosed action".to_string()))?;
+        items.push(Item::Action {
+            body: source[inner..body_end].to_string(),
+            line,
+        });
+        line += source[start..close].matches('\n').count();
+        pos = close;
+
+        if trim_right {
+            let rest = &source[pos..];
+            let trimmed = rest.trim_start();
+            line += rest[..rest.len() - trimmed.len()].matches('\n').count();
+            pos = source.len() - trimmed.len();
+        }
+    }
+
+    Ok(items)
+}
+
+fn is_trim_marker(rest: &str) -> bool {
+    let mut chars = rest.chars();
+    chars.next() == Some('-') && chars.next().is_some_and(char::is_whitespace)
+}
+
+// Finds the `}}` closing an action that starts at `inner`, skipping quoted strings and
+// comments. Returns the end of the action body, the index just past `}}` and whether
+// the action ends in a ` -}}` trim marker.
+fn find_close(source: &str, inner: usize) -> Option<(usize, usize, bool)> {
+    let bytes = source.as_bytes();
+    let mut i = inner;
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    if source[i..].starts_with("/*") {
+        i += source[i..].find("*/")? + 2;
+    }
+
+    let mut quote: Option<u8> = None;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if let Some(q) = quote {
+            if byte == b'\\' && q != b'`' {
+                i += 2;
+                continue;
+            }
+            if byte == q {
+                quote = None;
+            }
+            i += 1;
+            continue;
+        }
+        match byte {
+            b'"' | b'`' | b'\'' => quote = Some(byte),
+            b'}' if bytes.get(i + 1) == Some(&b'}') => {
+                let trim =
+                    i >= inner + 2 && bytes[i - 1] == b'-' && bytes[i - 2].is_ascii_whitespace();
+                let body_end = if trim { i - 1 } else { i };
+                return Some((body_end, i + 2, trim));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// ---------------------------------------------------------------------------
+// Tokenizing action bodies.
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Field(String),
+    Variable(String),
+    Ident(String),
+    Literal(Value),
+    LeftParen,
+    RightParen,
+    Pipe,
+    Declare,
+    Assign,
+    Comma,
+}
+
+// Each token is paired with whether whitespace preceded it, which decides whether a
+// `.field` continues the previous term or starts a new argument.
+fn tokenize(body: &str) -> Result<Vec<(Token, bool)>, String> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut spaced = true;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            spaced = true;
+            i += 1;
+            continue;
+        }
+
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::LeftParen
+            }
+            ')' => {
+                i += 1;
+                Token::RightParen
+            }
+            '|' => {
+                i += 1;
+                Token::Pipe
+            }
+            ',' => {
+                i += 1;
+                Token::Comma
+            }
+            ':' if chars.get(i + 1) == Some(&'=') => {
+                i += 2;
+                Token::Declare
+            }
+            '=' => {
+                i += 1;
+                Token::Assign
+            }
+            '"' => {
+                let (value, next) = lex_quoted(&chars, i)?;
+                i = next;
+                Token::Literal(Value::String(value))
+            }
+            '`' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&ch| ch == '`')
+                    .ok_or("unterminated raw string")?;
+                let value: String = chars[i + 1..i + 1 + end].iter().collect();
+                i += end + 2;
+                Token::Literal(Value::String(value))
+            }
+            '\'' => {
+                let ch = *chars.get(i + 1).ok_or("unterminated character constant")?;
+                if chars.get(i + 2) != Some(&'\'') {
+                    return Err("unsupported character constant".to_string());
+                }
+                i += 3;
+                Token::Literal(Value::from(ch as i64))
+            }
+            '.' if chars.get(i + 1).is_some_and(|ch| is_ident_start(*ch)) => {
+                let (ident, next) = lex_ident(&chars, i + 1);
+                i = next;
+                Token::Field(ident)
+            }
+            '.' => {
+                i += 1;
+                Token::Dot
+            }
+            '$' => {
+                let (ident, next) = lex_ident(&chars, i + 1);
+                i = next;
+                Token::Variable(format!("${}", ident))
+            }
+            c if c.is_ascii_digit()
+                || ((c == '-' || c == '+')
+                    && chars.get(i + 1).is_some_and(|ch| ch.is_ascii_digit())) =>
+            {
+                let (value, next) = lex_number(&chars, i)?;
+                i = next;
+                Token::Literal(value)
+            }
+            c if is_ident_start(c) => {
+                let (ident, next) = lex_ident(&chars, i);
+                i = next;
+                match ident.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "nil" => Token::Literal(Value::Null),
+                    _ => Token::Ident(ident),
+                }
+            }
+            other => return Err(format!("unexpected character '{}' in action", other)),
+        };
+
+        tokens.push((token, spaced));
+        spaced = false;
+    }
+
+    Ok(tokens)
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn lex_ident(chars: &[char], start: usize) -> (String, usize) {
+    let mut end = start;
+    while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+        end += 1;
+    }
+    (chars[start..end].iter().collect(), end)
+}
+
+fn lex_quoted(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let mut value = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Ok((value, i + 1)),
+            '\\' => {
+                let escaped = *chars.get(i + 1).ok_or("unterminated quoted string")?;
+                value.push(match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    other => other,
+                });
+                i += 2;
+            }
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err("unterminated quoted string".to_string())
+}
+
+fn lex_number(chars: &[char], start: usize) -> Result<(Value, usize), String> {
+    let mut end = start + 1;
+    while end < chars.len()
+        && (chars[end].is_ascii_alphanumeric()
+            || chars[end] == '.'
+            || chars[end] == '_'
+            || ((chars[end] == '-' || chars[end] == '+') && matches!(chars[end - 1], 'e' | 'E')))
+    {
+        end += 1;
+    }
+    let raw: String = chars[start..end].iter().filter(|c| **c != '_').collect();
+    let (negative, digits) = match raw.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, raw.trim_start_matches('+')),
+    };
+
+    let parsed = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok().map(Value::from)
+    } else if let Ok(int) = digits.parse::<i64>() {
+        Some(Value::from(int))
+    } else {
+        digits
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+    };
+
+    let value = parsed.ok_or_else(|| format!("bad number syntax: {}", raw))?;
+    let value = match (negative, &value) {
+        (true, Value::Number(n)) if n.is_i64() => Value::from(-n.as_i64().unwrap_or_default()),
+        (true, Value::Number(n)) => Value::from(-n.as_f64().unwrap_or_default()),
+        _ => value,
+    };
+    Ok((value, end))
+}
+
+// ---------------------------------------------------------------------------
+// Parsing.
+// ---------------------------------------------------------------------------
+
+enum ListEnd {
+    Eof,
+    End(usize),
+    Else(usize, Vec<(Token, bool)>),
+}
+
+struct Parser<'a> {
+    name: &'a str,
+    items: Vec<Item>,
+    pos: usize,
+    defines: Vec<(String, Vec<Node>)>,
+}
+
+impl Parser<'_> {
+    fn error(&self, line: usize, message: impl Into<String>) -> HelmError {
+        HelmError::Parse {
+            template: self.name.to_string(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn list(&mut self) -> Result<(Vec<Node>, ListEnd), HelmError> {
+        let mut nodes = Vec::new();
+
+        while self.pos < self.items.len() {
+            let item = std::mem::replace(&mut self.items[self.pos], Item::Text(String::new()));
+            self.pos += 1;
+
+            let (body, line) = match item {
+                Item::Text(text) => {
+                    nodes.push(Node::Text(text));
+                    continue;
+                }
+                Item::Action { body, line } => (body, line),
+            };
+
+            let trimmed = body.trim();
+            if trimmed.starts_with("/*") {
+                continue;
+            }
+
+            let tokens = tokenize(trimmed).map_err(|message| self.error(line, message))?;
+            let keyword = match tokens.first() {
+                Some((Token::Ident(word), _)) => word.as_str(),
+                _ => "",
+            };
+            let rest = tokens.get(1..).map(<[_]>::to_vec).unwrap_or_default();
+
+            match keyword {
+                "end" => return Ok((nodes, ListEnd::End(line))),
+                "else" => return Ok((nodes, ListEnd::Else(line, rest))),
+                "if" | "with" => {
+                    let (branches, otherwise) = self.conditional(keyword, rest, line)?;
+                    nodes.push(if keyword == "if" {
+                        Node::If {
+                            branches,
+                            otherwise,
+                            line,
+                        }
+                    } else {
+                        Node::With {
+                            branches,
+                            otherwise,
+                            line,
+                        }
+                    });
+                }
+                "range" => {
+                    let pipeline = self.pipeline(rest, line)?;
+                    if pipeline.declarations.len() > 2 {
+                        return Err(self.error(line, "too many declarations in range"));
+                    }
+                    let (body, end) = self.list()?;
+                    let otherwise = match end {
+                        ListEnd::End(_) => Vec::new(),
+                        ListEnd::Else(_, tokens) if tokens.is_empty() => self.block_end(line)?,
+                        ListEnd::Else(else_line, _) => {
+                            return Err(self.error(else_line, "unexpected tokens after else"))
+                        }
+                        ListEnd::Eof => return Err(self.error(line, "unclosed range")),
+                    };
+                    nodes.push(Node::Range {
+                        pipeline,
+                        body,
+                        otherwise,
+                        line,
+                    });
+                }
+                "define" | "block" => {
+                    let (name, pipeline) = self.template_args(keyword, rest, line)?;
+                    let body = self.block_end(line)?;
+                    self.defines.push((name.clone(), body));
+                    if keyword == "block" {
+                        nodes.push(Node::Template {
+                            name,
+                            pipeline,
+                            line,
+                        });
+                    }
+                }
+                "template" => {
+                    let (name, pipeline) = self.template_args(keyword, rest, line)?;
+                    nodes.push(Node::Template {
+                        name,
+                        pipeline,
+                        line,
+                    });
+                }
+                "break" => nodes.push(Node::Break),
+                "continue" => nodes.push(Node::Continue),
+                _ => nodes.push(Node::Action {
+                    pipeline: self.pipeline(tokens, line)?,
+                    line,
+                }),
+            }
+        }
+
+        Ok((nodes, ListEnd::Eof))
+    }
+
+    // Parses the body of a block up to its `{{end}}`, rejecting a stray `{{else}}`.
+    fn block_end(&mut self, line: usize) -> Result<Vec<Node>, HelmError> {
+        match self.list()? {
+            (nodes, ListEnd::End(_)) => Ok(nodes),
+            (_, ListEnd::Else(else_line, _)) => Err(self.error(else_line, "unexpected {{else}}")),
+            (_, ListEnd::Eof) => Err(self.error(line, "unexpected EOF, missing {{end}}")),
+        }
+    }
+
+    fn conditional(
+        &mut self,
+        keyword: &str,
+        tokens: Vec<(Token, bool)>,
+        line: usize,
+    ) -> Result<(Branches, Vec<Node>), HelmError> {
+        let mut branches = Vec::new();
+        let mut pipeline = self.pipeline(tokens, line)?;
+
+        loop {
+            let (body, end) = self.list()?;
+            branches.push((pipeline, body));
+            match end {
+                ListEnd::End(_) => return Ok((branches, Vec::new())),
+                ListEnd::Else(_, tokens) if tokens.is_empty() => {
+                    return Ok((branches, self.block_end(line)?));
+                }
+                ListEnd::Else(else_line, tokens) => {
+                    let chained = matches!(&tokens[0].0, Token::Ident(word) if word == keyword);
+                    if !chained {
+                        return Err(self.error(else_line, "unexpected tokens after else"));
+                    }
+                    pipeline = self.pipeline(tokens[1..].to_vec(), else_line)?;
+                }
+                ListEnd::Eof => {
+                    return Err(self.error(line, format!("unclosed {{{{{}}}}}", keyword)))
+                }
+            }
+        }
+    }
+
+    fn template_args(
+        &self,
+        keyword: &str,
+        tokens: Vec<(Token, bool)>,
+        line: usize,
+    ) -> Result<(String, Option<Pipeline>), HelmError> {
+        let mut tokens = tokens.into_iter();
+        let name = match tokens.next() {
+            Some((Token::Literal(Value::String(name)), _)) => name,
+            _ => return Err(self.error(line, format!("{} requires a quoted name", keyword))),
+        };
+        let rest: Vec<_> = tokens.collect();
+        let pipeline = if rest.is_empty() {
+            None
+        } else {
+            Some(self.pipeline(rest, line)?)
+        };
+        Ok((name, pipeline))
+    }
+
+    fn pipeline(&self, tokens: Vec<(Token, bool)>, line: usize) -> Result<Pipeline, HelmError> {
+        if tokens.is_empty() {
+            return Err(self.error(line, "missing value for command"));
+        }
+        let mut stream = TokenStream { tokens, pos: 0 };
+        let pipeline = stream
+            .pipeline(false)
+            .map_err(|message| self.error(line, message))?;
+        if let Some(token) = stream.peek() {
+            return Err(self.error(line, format!("unexpected {:?} in operand", token)));
+        }
+        Ok(pipeline)
+    }
+}
+
+struct TokenStream {
+    tokens: Vec<(Token, bool)>,
+    pos: usize,
+}
+
+impl TokenStream {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    // Collects `.field` tokens written directly after a term, e.g. `$x.a.b`.
+    fn field_chain(&mut self) -> Vec<String> {
+        let mut chain = Vec::new();
+        while let Some((Token::Field(field), false)) = self.tokens.get(self.pos) {
+            chain.push(field.clone());
+            self.pos += 1;
+        }
+        chain
+    }
+
+    fn pipeline(&mut self, nested: bool) -> Result<Pipeline, String> {
+        let mut pipeline = Pipeline::default();
+
+        match (
+            self.peek(),
+            self.peek_at(1),
+            self.peek_at(2),
+            self.peek_at(3),
+        ) {
+            (Some(Token::Variable(name)), Some(Token::Declare | Token::Assign), _, _) => {
+                pipeline.assign = self.peek_at(1) == Some(&Token::Assign);
+                pipeline.declarations.push(name.clone());
+                self.pos += 2;
+            }
+            (
+                Some(Token::Variable(first)),
+                Some(Token::Comma),
+                Some(Token::Variable(second)),
+                Some(Token::Declare | Token::Assign),
+            ) => {
+                pipeline.assign = self.peek_at(3) == Some(&Token::Assign);
+                pipeline.declarations = vec![first.clone(), second.clone()];
+                self.pos += 4;
+            }
+            _ => {}
+        }
+
+        loop {
+            let mut args = Vec::new();
+            while !matches!(self.peek(), None | Some(Token::Pipe | Token::RightParen)) {
+                args.push(self.operand()?);
+            }
+            if args.is_empty() {
+                return Err("missing value for command".to_string());
+            }
+            pipeline.commands.push(Command { args });
+
+            match self.peek() {
+                Some(Token::Pipe) => {
+                    self.pos += 1;
+                }
+                Some(Token::RightParen) if nested => break,
+                Some(Token::RightParen) => return Err("unexpected right paren".to_string()),
+                _ => break,
+            }
+        }
+
+        Ok(pipeline)
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Dot) => Ok(Operand::Dot),
+            Some(Token::Field(field)) => {
+                let mut chain = vec![field];
+                chain.extend(self.field_chain());
+                Ok(Operand::Field(chain))
+            }
+            Some(Token::Variable(name)) => Ok(Operand::Variable(name, self.field_chain())),
+            Some(Token::Ident(name)) => Ok(Operand::Function(name)),
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(Token::LeftParen) => {
+                let inner = self.pipeline(true)?;
+                if self.next() != Some(Token::RightParen) {
+                    return Err("unclosed left paren".to_string());
+                }
+                Ok(Operand::Pipeline(Box::new(inner), self.field_chain()))
+            }
+            Some(token) => Err(format!("unexpected {:?} in operand", token)),
+            None => Err("missing value for command".to_string()),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Execution.
+// ---------------------------------------------------------------------------
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+}
+
+struct Executor<'a> {
+    set: &'a TemplateSet,
+    template: String,
+    variables: Vec<(String, Value)>,
+    depth: usize,
+}
+
+impl<'a> Executor<'a> {
+    fn new(set: &'a TemplateSet, template: &str, data: &Value, depth: usize) -> Self {
+        Executor {
+            set,
+            template: template.to_string(),
+            variables: vec![("$".to_string(), data.clone())],
+            depth,
+        }
+    }
+
+    fn error(&self, line: usize, message: impl Into<String>) -> HelmError {
+        HelmError::Render {
+            template: self.template.clone(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node], dot: &Value, out: &mut String) -> Result<Flow, HelmError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Action { pipeline, line } => {
+                    let value = self.pipeline(pipeline, dot, *line)?;
+                    if pipeline.declarations.is_empty() {
+                        out.push_str(&functions::display(&value));
+                    }
+                }
+                Node::If {
+                    branches,
+                    otherwise,
+                    line,
+                } => {
+                    let flow = self.conditional(branches, otherwise, dot, *line, false, out)?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Node::With {
+                    branches,
+                    otherwise,
+                    line,
+                } => {
+                    let flow = self.conditional(branches, otherwise, dot, *line, true, out)?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Node::Range {
+                    pipeline,
+                    body,
+                    otherwise,
+                    line,
+                } => {
+                    let flow = self.range(pipeline, body, otherwise, dot, *line, out)?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Node::Template {
+                    name,
+                    pipeline,
+                    line,
+                } => {
+                    let data = match pipeline {
+                        Some(pipeline) => self.pipeline(pipeline, dot, *line)?,
+                        None => Value::Null,
+                    };
+                    out.push_str(&self.include(name, &data, *line)?);
+                }
+                Node::Break => return Ok(Flow::Break),
+                Node::Continue => return Ok(Flow::Continue),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn conditional(
+        &mut self,
+        branches: &[(Pipeline, Vec<Node>)],
+        otherwise: &[Node],
+        dot: &Value,
+        line: usize,
+        rebind_dot: bool,
+        out: &mut String,
+    ) -> Result<Flow, HelmError> {
+        let mark = self.variables.len();
+        for (pipeline, body) in branches {
+            let value = self.pipeline(pipeline, dot, line)?;
+            if functions::truthy(&value) {
+                let flow = if rebind_dot {
+                    self.nodes(body, &value, out)
+                } else {
+                    self.nodes(body, dot, out)
+                };
+                self.variables.truncate(mark);
+                return flow;
+            }
+        }
+        let flow = self.nodes(otherwise, dot, out);
+        self.variables.truncate(mark);
+        flow
+    }
+
+    fn range(
+        &mut self,
+        pipeline: &Pipeline,
+        body: &[Node],
+        otherwise: &[Node],
+        dot: &Value,
+        line: usize,
+        out: &mut String,
+    ) -> Result<Flow, HelmError> {
+        let value = self.commands(&pipeline.commands, dot, line)?;
+        let entries: Vec<(Value, Value)> = match value {
+            Value::Array(items) => items
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| (Value::from(index), item))
+                .collect(),
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.into_iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries
+                    .into_iter()
+                    .map(|(key, item)| (Value::String(key), item))
+                    .collect()
+            }
+            Value::Number(n) if n.is_i64() => (0..n.as_i64().unwrap_or_default())
+                .map(|i| (Value::from(i), Value::from(i)))
+                .collect(),
+            Value::Null => Vec::new(),
+            other => {
+                return Err(self.error(
+                    line,
+                    format!("range can't iterate over {}", functions::display(&other)),
+                ))
+            }
+        };
+
+        if entries.is_empty() {
+            let mark = self.variables.len();
+            let flow = self.nodes(otherwise, dot, out);
+            self.variables.truncate(mark);
+            return flow.map(|_| Flow::Normal);
+        }
+
+        for (key, item) in entries {
+            let mark = self.variables.len();
+            match pipeline.declarations.as_slice() {
+                [value_var] => self.variables.push((value_var.clone(), item.clone())),
+                [key_var, value_var] => {
+                    self.variables.push((key_var.clone(), key));
+                    self.variables.push((value_var.clone(), item.clone()));
+                }
+                _ => {}
+            }
+            let flow = self.nodes(body, &item, out);
+            self.variables.truncate(mark);
+            if matches!(flow?, Flow::Break) {
+                break;
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn include(&self, name: &str, data: &Value, line: usize) -> Result<String, HelmError> {
+        let define = self
+            .set
+            .defines
+            .get(name)
+            .ok_or_else(|| self.error(line, format!("no template \"{}\" defined", name)))?;
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.error(
+                line,
+                format!("template \"{}\" exceeded the maximum include depth", name),
+            ));
+        }
+        let mut exec = Executor::new(self.set, &define.origin, data, self.depth + 1);
+        let mut out = String::new();
+        exec.nodes(&define.nodes, data, &mut out)?;
+        Ok(out)
+    }
+
+    // Helm's `tpl`: renders a string as a template, with access to the chart's defines.
+    fn tpl(&self, source: &str, data: &Value, line: usize) -> Result<String, HelmError> {
+        let name = format!("{} (tpl)", self.template);
+        let (nodes, defines) = parse_source(&name, source)?;
+        let extended;
+        let set = if defines.is_empty() {
+            self.set
+        } else {
+            let mut set = self.set.clone();
+            for (define_name, nodes) in defines {
+                set.defines.insert(
+                    define_name,
+                    Define {
+                        origin: name.clone(),
+                        nodes,
+                    },
+                );
+            }
+            extended = set;
+            &extended
+        };
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(self.error(line, "tpl exceeded the maximum include depth"));
+        }
+        let mut exec = Executor::new(set, &name, data, self.depth + 1);
+        let mut out = String::new();
+        exec.nodes(&nodes, data, &mut out)?;
+        Ok(out)
+    }
+
+    fn pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        dot: &Value,
+        line: usize,
+    ) -> Result<Value, HelmError> {
+        let value = self.commands(&pipeline.commands, dot, line)?;
+
+        if let Some(name) = pipeline.declarations.first() {
+            if pipeline.assign {
+                self.assign(name, &[], value.clone(), line)?;
+            } else {
+                self.variables.push((name.clone(), value.clone()));
+            }
+        }
+        Ok(value)
+    }
+
+    fn commands(
+        &mut self,
+        commands: &[Command],
+        dot: &Value,
+        line: usize,
+    ) -> Result<Value, HelmError> {
+        let mut piped = None;
+        for command in commands {
+            piped = Some(self.command(command, dot, piped, line)?);
+        }
+        Ok(piped.unwrap_or(Value::Null))
+    }
+
+    fn command(
+        &mut self,
+        command: &Command,
+        dot: &Value,
+        piped: Option<Value>,
+        line: usize,
+    ) -> Result<Value, HelmError> {
+        let (first, rest) = match command.args.split_first() {
+            Some(split) => split,
+            None => return Err(self.error(line, "missing value for command")),
+        };
+
+        if let Operand::Function(name) = first {
+            let mut args = Vec::with_capacity(rest.len() + 1);
+            for operand in rest {
+                args.push(self.operand(operand, dot, line)?);
+            }
+            args.extend(piped);
+            return self.call(name, args, rest.first(), line);
+        }
+
+        if rest.is_empty() && piped.is_none() {
+            return self.operand(first, dot, line);
+        }
+
+        // A field chain followed by arguments is a method call, e.g.
+        // `.Capabilities.APIVersions.Has "apps/v1"` or `.Files.Get "config.ini"`.
+        let (root, chain) = match first {
+            Operand::Field(chain) => (dot.clone(), chain),
+            Operand::Variable(name, chain) => (self.variable(name, line)?, chain),
+            _ => return Err(self.error(line, "can't give argument to non-function")),
+        };
+        let Some((method, fields)) = chain.split_last() else {
+            return Err(self.error(line, "can't give argument to non-function"));
+        };
+        let base = self.walk(root, fields, line)?;
+        let mut args = Vec::with_capacity(rest.len() + 1);
+        for operand in rest {
+            args.push(self.operand(operand, dot, line)?);
+        }
+        args.extend(piped);
+        functions::method(&base, method, &args).map_err(|message| self.error(line, message))
+    }
+
+    fn call(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        target: Option<&Operand>,
+        line: usize,
+    ) -> Result<Value, HelmError> {
+        match name {
+            "include" | "tpl" => {
+                let (Some(first), data) = (args.first(), args.get(1)) else {
+                    return Err(self.error(line, format!("wrong number of args for {}", name)));
+                };
+                let data = data.cloned().unwrap_or(Value::Null);
+                let Value::String(first) = first else {
+                    return Err(self.error(line, format!("{} expects a string argument", name)));
+                };
+                let rendered = if name == "include" {
+                    self.include(first, &data, line)?
+                } else {
+                    self.tpl(first, &data, line)?
+                };
+                Ok(Value::String(rendered))
+            }
+            _ => {
+                let result = functions::call(name, &args).map_err(|message| {
+                    self.error(line, format!("error calling {}: {}", name, message))
+                })?;
+
+                // Sprig's dict helpers mutate their first argument; emulate that for
+                // variables so `$_ := set $ctx "key" "value"` behaves as in Helm.
+                if matches!(name, "set" | "unset" | "merge" | "mergeOverwrite") {
+                    if let Some(Operand::Variable(variable, chain)) = target {
+                        self.assign(variable, chain, result.clone(), line)?;
+                    }
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand, dot: &Value, line: usize) -> Result<Value, HelmError> {
+        match operand {
+            Operand::Dot => Ok(dot.clone()),
+            Operand::Field(chain) => self.walk(dot.clone(), chain, line),
+            Operand::Variable(name, chain) => {
+                let value = self.variable(name, line)?;
+                self.walk(value, chain, line)
+            }
+            Operand::Function(name) => self.call(name, Vec::new(), None, line),
+            Operand::Literal(value) => Ok(value.clone()),
+            Operand::Pipeline(pipeline, chain) => {
+                let value = self.pipeline(pipeline, dot, line)?;
+                self.walk(value, chain, line)
+            }
+        }
+    }
+
+    fn variable(&self, name: &str, line: usize) -> Result<Value, HelmError> {
+        self.variables
+            .iter()
+            .rev()
+            .find(|(var, _)| var == name)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| self.error(line, format!("undefined variable \"{}\"", name)))
+    }
+
+    fn assign(
+        &mut self,
+        name: &str,
+        chain: &[String],
+        value: Value,
+        line: usize,
+    ) -> Result<(), HelmError> {
+        let Some(slot) = self
+            .variables
+            .iter_mut()
+            .rev()
+            .find(|(var, _)| var == name)
+            .map(|(_, slot)| slot)
+        else {
+            return Err(self.error(line, format!("undefined variable \"{}\"", name)));
+        };
+
+        let mut target = slot;
+        for field in chain {
+            match target {
+                Value::Object(map) => match map.get_mut(field) {
+                    Some(next) => target = next,
+                    None => return Ok(()),
+                },
+                _ => return Ok(()),
+            }
+        }
+        *target = value;
+        Ok(())
+    }
+
+    fn walk(&self, mut value: Value, chain: &[String], line: usize) -> Result<Value, HelmError> {
+        for field in chain {
+            value = match value {
+                Value::Object(mut map) => map.remove(field).unwrap_or(Value::Null),
+                Value::Null => Value::Null,
+                other => {
+                    return Err(self.error(
+                        line,
+                        format!(
+                            "can't evaluate field {} in type {}",
+                            field,
+                            functions::kind_of(&other)
+                        ),
+                    ))
+                }
+            };
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(source: &str, data: Value) -> Result<String, HelmError> {
+        let mut set = TemplateSet::new();
+        let template = set.parse("test.yaml", source)?;
+        set.execute(&template, &data)
+    }
+
+    #[test]
+    fn renders_fields_pipelines_and_trim_markers() {
+        let data = json!({"Values": {"image": {"repository": "nginx", "tag": ""}}});
+        let source = "image: {{ .Values.image.repository }}:{{ .Values.image.tag | default \"latest\" }}\n{{- /* a comment */}}\nname: {{ \"web\" | upper | quote }}\n";
+
+        assert_eq!(
+            render(source, data).unwrap(),
+            "image: nginx:latest\nname: \"WEB\"\n"
+        );
+    }
+
+    #[test]
+    fn supports_control_flow_and_variables() {
+        let data =
+            json!({"Values": {"ports": [80, 443], "env": {"B": "2", "A": "1"}, "enabled": true}});
+        let source = "{{- if not .Values.enabled }}off{{ else if .Values.enabled }}on{{ end }}\n\
+{{- range $i, $p := .Values.ports }}\n- {{ $i }}={{ $p }}{{ end }}\n\
+{{- range $k, $v := .Values.env }}\n{{ $k }}: {{ $v | quote }}{{ end }}\n\
+{{- with .Values.missing }}never{{ else }}\nfallback{{ end }}\n\
+{{- $count := 0 }}{{ range .Values.ports }}{{ $count = add $count 1 }}{{ end }}\ncount={{ $count }}";
+
+        assert_eq!(
+            render(source, data).unwrap(),
+            "on\n- 0=80\n- 1=443\nA: \"1\"\nB: \"2\"\nfallback\ncount=2"
+        );
+    }
+
+    #[test]
+    fn includes_defines_with_nindent_and_tpl() {
+        let data = json!({"Values": {"labels": {"tier": "web"}, "name": "api", "greeting": "hi {{ .Values.name }}"}});
+        let source = "{{- define \"labels\" -}}\napp: {{ .Values.name }}\n{{ toYaml .Values.labels }}\n{{- end }}\nmetadata:\n  labels:\n    {{- include \"labels\" . | nindent 4 }}\nmsg: {{ tpl .Values.greeting . }}\n";
+
+        assert_eq!(
+            render(source, data).unwrap(),
+            "\nmetadata:\n  labels:\n    app: api\n    tier: web\nmsg: hi api\n"
+        );
+    }
+
+    #[test]
+    fn set_mutates_variables_and_method_calls_work() {
+        let data = json!({"Capabilities": {"APIVersions": ["policy/v1"]}});
+        let source = "{{- $ctx := dict \"a\" 1 }}{{- $_ := set $ctx \"b\" 2 }}{{ $ctx.b }} {{ .Capabilities.APIVersions.Has \"policy/v1\" }}";
+
+        assert_eq!(render(source, data).unwrap(), "2 true");
+    }
+
+    #[test]
+    fn reports_parse_and_render_errors_with_lines() {
+        let unclosed = render("a: 1\n{{ if .x }}\nb", json!({})).unwrap_err();
+        assert!(
+            matches!(unclosed, HelmError::Parse { line: 2, .. }),
+            "{:?}",
+            unclosed
+        );
+
+        let unknown = render("a: 1\nb: {{ nope .x }}", json!({})).unwrap_err();
+        assert!(
+            matches!(&unknown, HelmError::Render { line: 2, message, .. } if message.contains("not defined")),
+            "{:?}",
+            unknown
+        );
+
+        let recursive = render(
+            "{{ define \"a\" }}{{ include \"a\" . }}{{ end }}{{ include \"a\" . }}",
+            json!({}),
+        )
+        .unwrap_err();
+        assert!(recursive.to_string().contains("maximum include depth"));
+    }
+}
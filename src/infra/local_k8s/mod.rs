@@ -1,8 +1,13 @@
 use std::path::Path;
 
-use crate::{filesystem::FileSystemManager, load_global_vars, utils::ENV_DIR, LOGGER};
+use crate::{
+    filesystem::FileSystemManager,
+    load_global_vars,
+    utils::{replace_variables, ENV_DIR},
+    LOGGER,
+};
 
-use super::{ClusterConfig, ClusterTargetBuilder, Infra};
+use super::{ClusterConfig, ClusterTargetBuilder};
 
 pub struct LocalK8 {
     pub files: Vec<(String, String)>, // (filename, content)
@@ -41,7 +46,13 @@ impl ClusterTargetBuilder for LocalK8 {
         let mut vars = load_global_vars().unwrap();
         vars.extend(variables.clone());
         for (filename, content) in &self.files {
-            let generated_content = Infra::replace_variables(content.clone(), vars.clone());
+            let generated_content = match replace_variables(content.clone(), vars.clone()) {
+                Ok(generated_content) => generated_content,
+                Err(e) => {
+                    LOGGER.error(&format!("Failed to render {}: {}", filename, e));
+                    return;
+                }
+            };
             let path = Path::new(ENV_DIR).join(&config.cluster_name).join(filename);
             LOGGER.trace(path.to_str().unwrap());
             self.file_manager
@@ -9,7 +9,12 @@ pub enum ClusterTarget {
 
 use std::{collections::BTreeMap, path::Path};
 
-use crate::{filesystem::FileSystemManager, load_global_vars, utils::ENV_DIR, LOGGER};
+use crate::{
+    filesystem::FileSystemManager,
+    load_global_vars,
+    utils::{replace_variables, ENV_DIR},
+    LOGGER,
+};
 
 pub mod local_k8s;
 
@@ -56,14 +61,6 @@ impl Infra {
         ]
     }
 
-    fn replace_variables(content: String, variables: BTreeMap<String, String>) -> String {
-        let mut new_content = content.clone();
-        for (key, value) in variables {
-            new_content = new_content.replace(&format!("{{{{{}}}}}", key), &value);
-        }
-        new_content
-    }
-
     pub fn use_template(
         name: &String,
         template_path: &String,
@@ -100,7 +97,13 @@ impl Infra {
                 .read_file(&file_path, Some(&"".to_string()))
                 .unwrap();
 
-            let generated_content = Infra::replace_variables(content, vars.clone());
+            let generated_content = match replace_variables(content, vars.clone()) {
+                Ok(generated_content) => generated_content,
+                Err(e) => {
+                    LOGGER.error(&format!("Failed to render {}: {}", file_path, e));
+                    return;
+                }
+            };
             let path = Path::new(ENV_DIR)
                 .join(&config.cluster_name)
                 .join(filename.clone());
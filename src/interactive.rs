@@ -314,9 +314,11 @@ async fn run_external_action(
                     k8sm8::secrets::get_secret(client.clone(), &args.namespace, &secret_name)
                         .await
                         .map_err(|e| CliError::Other(e.to_string()))?;
-                secret.data.unwrap().iter().for_each(|(k, v)| {
-                    println!("{}: {}", k, String::from_utf8_lossy(v.0.as_ref()));
-                });
+                // Values are never printed; use `sailr secrets get` to read a stored value.
+                println!("Secret: {}", secret_name);
+                for (k, v) in secret.data.unwrap_or_default() {
+                    println!("  {}: <redacted, {} bytes>", k, v.0.len());
+                }
             }
         }
         Action::DescribePod => {
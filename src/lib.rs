@@ -1,11 +1,19 @@
 use std::{collections::BTreeMap, path::Path};
 
+use config::Config;
 use environment::{Environment, Service};
 use filesystem::FileSystemManager;
-use generate::Generator;
+use generate::{GenerateOptions, Generator};
 use infra::Infra;
+use overlays::OverlaySet;
 use serde::Deserialize;
-use templates::TemplateManager;
+use templates::{
+    config_map::rewrite_references,
+    engine::UnresolvedPlaceholder,
+    labels::{recorded_revision, StandardMetadata},
+    namespaces::{environment_namespaces, namespace_manifests, service_namespaces, set_namespace},
+    Template, TemplateManager,
+};
 use utils::replace_variables;
 
 use once_cell::sync::Lazy;
@@ -15,22 +23,32 @@ pub mod cli;
 pub mod config;
 pub mod deployment;
 pub mod environment;
+pub mod environments;
 pub mod errors;
 pub mod filesystem;
 pub mod generate;
+pub mod helm;
 pub mod infra;
 pub mod interactive;
+pub mod lint;
 pub mod oci;
 pub mod orchestrator;
+pub mod overlays;
 pub mod plan;
 pub mod provider;
 pub mod roomservice;
+pub mod schema;
+pub mod secrets;
 pub mod templates;
 pub mod tui;
 pub mod ui;
 pub mod utils;
 pub mod workflow;
 
+/// Variable holding the time of generation, set only with `--timestamp` so output
+/// stays reproducible by default.
+pub const DEPLOYMENT_DATE: &str = "deployment_date";
+
 pub static LOGGER: Lazy<ui::SailrUI> = Lazy::new(|| ui::SailrUI::new(false, false));
 
 #[derive(Debug, Deserialize)]
@@ -78,42 +96,264 @@ pub fn load_global_vars() -> Result<BTreeMap<String, String>, Box<dyn std::error
     Ok(vars)
 }
 
-pub fn generate(name: &str, env: &Environment, services: Vec<&Service>) -> anyhow::Result<()> {
+pub fn generate(
+    name: &str,
+    env: &Environment,
+    services: Vec<&Service>,
+    options: &GenerateOptions,
+) -> anyhow::Result<()> {
     let mut template_manager = TemplateManager::new();
     let (templates, config_maps) = template_manager
         .read_templates(Some(env))
         .map_err(|e| anyhow::anyhow!("Failed to read templates: {:?}", e))?;
 
     let mut generator = Generator::new();
-
+    let mut unresolved_count = 0;
+    let mut failed_checks = 0;
+    let rendered_paths: Vec<String> = services.iter().map(|s| s.get_path()).collect();
+    let source_revision = if options.check {
+        // Keep the revision already recorded in k8s/generated, so a new commit alone
+        // does not make the generated manifests stale.
+        let existing = generator
+            .read_existing(name)
+            .map_err(|e| anyhow::anyhow!("Failed to read generated templates: {:?}", e))?;
+        recorded_revision(
+            existing
+                .values()
+                .filter_map(|content| std::str::from_utf8(content).ok()),
+        )
+        .or_else(|| options.source_revision.clone())
+    } else {
+        options.source_revision.clone()
+    };
+
+    // A stream only holds the namespaces of the services in it; k8s/generated always
+    // has every namespace of the environment.
+    let namespaces = match options.stdout {
+        Some(_) => service_namespaces(env, services.iter().copied())?,
+        None => environment_namespaces(env)?,
+    };
+    let environment_metadata = StandardMetadata::for_environment(env, source_revision.as_deref())?;
+    generator.set_namespaces(namespace_manifests(&namespaces, &environment_metadata)?);
+
+    let default_namespace = env.default_namespace();
     for service in services {
-        let variables = &env
-            .get_variables(service)
-            .map_err(|e| anyhow::anyhow!("Registry config error: {}", e))?;
+        let namespace = service.namespace_or(&default_namespace);
+        let mut context = env.get_template_context(service).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to resolve variables of service '{}': {}",
+                service.name,
+                e
+            )
+        })?;
+        if options.timestamp {
+            context.insert(DEPLOYMENT_DATE, utils::get_current_timestamp());
+        }
+        let mut overlays = OverlaySet::load(name, &service.name)?;
+        let mut skipped_templates = false;
+        let service_configs: Vec<&Config> = config_maps
+            .iter()
+            .filter(|config| config.base_name == service.name)
+            .collect();
+        let metadata = StandardMetadata::for_service(env, service, source_revision.as_deref())?;
+
         for template in &templates {
             if template.name != service.name && template.name != service.get_path() {
                 continue;
             }
-            let content = template_manager
-                .replace_variables(template, variables)
+            let output = template_manager
+                .render_template(template, &context)
                 .map_err(|e| anyhow::anyhow!("Failed to replace variables: {:?}", e))?;
 
-            generator.add_template(template, content)
-        }
-        for config in &config_maps {
-            if config.name.split("/").last().unwrap() != service.name {
+            if !options.timestamp {
+                check_deployment_date(template, &output.unresolved)?;
+            }
+            if !output.unresolved.is_empty() {
+                unresolved_count += output.unresolved.len();
+                report_unresolved(template, &output.unresolved, options.allow_unresolved);
+                if !options.allow_unresolved {
+                    skipped_templates = true;
+                    continue;
+                }
+            }
+
+            let content = post_process(
+                &output.content,
+                namespace,
+                &mut overlays,
+                &service_configs,
+                &metadata,
+            )?;
+
+            if template_manager.check_rendered(template, &content).is_err() {
+                failed_checks += 1;
+                skipped_templates = true;
                 continue;
             }
 
-            generator.add_config_map(config);
+            generator.add_template(template, content)
         }
+        if let Some(helm) = &service.helm {
+            for template in helm::render_service_chart(env, service, helm)? {
+                let content = post_process(
+                    &template.content,
+                    namespace,
+                    &mut overlays,
+                    &service_configs,
+                    &metadata,
+                )?;
+
+                if template_manager
+                    .check_rendered(&template, &content)
+                    .is_err()
+                {
+                    failed_checks += 1;
+                    skipped_templates = true;
+                    continue;
+                }
+
+                generator.add_template(&template, content)
+            }
+        }
+        if !skipped_templates {
+            overlays.finish()?;
+        }
+        for config in service_configs {
+            let mut config = config.clone();
+            config.content = metadata.apply(&config.content)?;
+            generator.add_config_map(&config);
+        }
+    }
+
+    if unresolved_count > 0 && !options.allow_unresolved {
+        anyhow::bail!(
+            "{} unresolved template placeholder(s); define the missing variables or pass --allow-unresolved to keep them",
+            unresolved_count
+        );
+    }
+
+    if failed_checks > 0 {
+        anyhow::bail!("{} template(s) failed validation", failed_checks);
+    }
+
+    if rendered_paths.len() < env.services.len() {
+        generator.limit_to(rendered_paths);
+    }
+    if let Some(format) = options.stdout {
+        let stream = generator
+            .to_stream(name, format)
+            .map_err(|e| anyhow::anyhow!("Failed to render manifests: {:?}", e))?;
+        print!("{}", stream);
+        return Ok(());
     }
-    generator
-        .generate(&name.to_string())
+    if options.check {
+        return check_generated(name, &generator);
+    }
+    let summary = generator
+        .generate(name)
         .map_err(|e| anyhow::anyhow!("Failed to generate templates: {:?}", e))?;
+    summary.display();
     Ok(())
 }
 
+// Turns a service's rendered template or chart output into what is written to
+// k8s/generated. Namespaces are set first, so overlays can target the namespace
+// documents end up in.
+fn post_process(
+    content: &str,
+    namespace: &str,
+    overlays: &mut OverlaySet,
+    configs: &[&Config],
+    metadata: &StandardMetadata,
+) -> anyhow::Result<String> {
+    let content = set_namespace(content, namespace)?;
+    let content = overlays.apply(&content)?;
+    let content = point_at_config_maps(&content, configs)?;
+    Ok(metadata.apply(&content)?)
+}
+
+// Rewrites references to the service's ConfigMaps that were given a hash suffix.
+fn point_at_config_maps(content: &str, configs: &[&Config]) -> anyhow::Result<String> {
+    let mut content = content.to_string();
+    for config in configs.iter().filter(|config| config.is_renamed()) {
+        content = rewrite_references(&content, &config.base_name, &config.resource_name)?;
+    }
+    Ok(content)
+}
+
+// Fails with a unified diff when the rendered environment differs from k8s/generated.
+// The diff goes to stdout so it shows up in CI logs and can be redirected to a file.
+// Sensitive variable values are masked in it.
+fn check_generated(name: &str, generator: &Generator) -> anyhow::Result<()> {
+    let (summary, diff) = generator
+        .check(name)
+        .map_err(|e| anyhow::anyhow!("Failed to compare generated templates: {:?}", e))?;
+
+    if summary.is_unchanged() {
+        LOGGER.status(
+            "Up to date",
+            &format!("k8s/generated/{} matches its templates", name),
+            "green",
+        );
+        return Ok(());
+    }
+
+    print!("{}", LOGGER.redacted(&diff));
+    summary.display();
+    anyhow::bail!(
+        "k8s/generated/{} is out of date ({} added, {} changed, {} removed); run `sailr generate --name {}`",
+        name,
+        summary.added.len(),
+        summary.changed.len(),
+        summary.removed.len(),
+        name
+    )
+}
+
+// Fails when the template reads `deployment_date` without `--timestamp`. The variable
+// used to always be set, so this names the flag instead of reporting a plain typo.
+fn check_deployment_date(
+    template: &Template,
+    unresolved: &[UnresolvedPlaceholder],
+) -> anyhow::Result<()> {
+    match unresolved
+        .iter()
+        .find(|placeholder| placeholder.variable.as_deref() == Some(DEPLOYMENT_DATE))
+    {
+        Some(placeholder) => anyhow::bail!(
+            "{}:{}:{}: {} is only set when generating with --timestamp; pass --timestamp or remove it from the template",
+            template.source_path(),
+            placeholder.line,
+            placeholder.column,
+            DEPLOYMENT_DATE
+        ),
+        None => Ok(()),
+    }
+}
+
+fn report_unresolved(template: &Template, unresolved: &[UnresolvedPlaceholder], allowed: bool) {
+    for placeholder in unresolved {
+        let suggestion = placeholder
+            .suggestion
+            .as_ref()
+            .map(|name| format!(" (did you mean '{}'?)", name))
+            .unwrap_or_default();
+        let message = format!(
+            "{}:{}:{}: unresolved placeholder {}{}",
+            template.source_path(),
+            placeholder.line,
+            placeholder.column,
+            placeholder.placeholder,
+            suggestion
+        );
+        if allowed {
+            LOGGER.warn(&message);
+        } else {
+            LOGGER.error(&message);
+        }
+    }
+}
+
 pub fn create_default_env_config(
     name: String,
     config_template: Option<String>,
@@ -143,7 +383,13 @@ pub fn create_default_env_config(
             .read_file(&config.1, Some(&"".to_string()))
             .unwrap();
 
-        let generated_config = replace_variables(content.clone(), vars);
+        let generated_config = match replace_variables(content.clone(), vars) {
+            Ok(generated_config) => generated_config,
+            Err(e) => {
+                LOGGER.error(&format!("Failed to render {}: {}", config.1, e));
+                return;
+            }
+        };
 
         file_manager
             .create_file(
@@ -160,7 +406,13 @@ pub fn create_default_env_config(
             .read_file(&config_template.clone(), Some(&"".to_string()))
             .unwrap();
 
-        let generated_config = replace_variables(content.clone(), vars);
+        let generated_config = match replace_variables(content.clone(), vars) {
+            Ok(generated_config) => generated_config,
+            Err(e) => {
+                LOGGER.error(&format!("Failed to render {}: {}", config_template, e));
+                return;
+            }
+        };
 
         file_manager
             .create_file(
@@ -177,7 +429,13 @@ pub fn create_default_env_config(
             "config.toml".to_string(),
             include_str!("default_config.toml").to_string(),
         );
-        let generated_config = replace_variables(default_env_config.1, vars);
+        let generated_config = match replace_variables(default_env_config.1, vars) {
+            Ok(generated_config) => generated_config,
+            Err(e) => {
+                LOGGER.error(&format!("Failed to render default config.toml: {}", e));
+                return;
+            }
+        };
 
         file_manager
             .create_file(
@@ -0,0 +1,861 @@
+//! Rule-based checks of an environment, its templates and the manifests they render to.
+//!
+//! Every rule has an ID and a default severity. An environment can change the severity
+//! of any rule, or turn it off, in its `[lint]` table:
+//!
+//! ```toml
+//! [lint]
+//! latest-image-tag = "error"
+//! missing-probes = "off"
+//! ```
+//!
+//! A [`LintReport`] can be printed as text, JSON or SARIF 2.1.0 for code scanning tools.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    deployment::order::service_levels,
+    environment::{Environment, Service, SCHEMA_V05},
+    errors::{LintError, VariableError},
+    helm,
+    overlays::{parse_documents, OverlaySet},
+    templates::{Template, TemplateManager},
+};
+
+/// How much a rule's findings matter.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, schemars::JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The rule is not checked.
+    Off,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Off => "off",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+pub struct Rule {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+pub const SCHEMA_VERSION: &str = "schema-version";
+pub const EMPTY_VERSION: &str = "empty-version";
+pub const DUPLICATE_SERVICE: &str = "duplicate-service";
+pub const UNKNOWN_DEPENDENCY: &str = "unknown-dependency";
+pub const INVALID_DEPLOY_ORDER: &str = "invalid-deploy-order";
+pub const MISSING_TEMPLATES: &str = "missing-templates";
+pub const INVALID_REGISTRY: &str = "invalid-registry";
+pub const INVALID_TEMPLATE: &str = "invalid-template";
+pub const UNRESOLVED_VARIABLE: &str = "unresolved-variable";
+pub const UNDEFINED_VARIABLE: &str = "undefined-variable";
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+pub const MISSING_RESOURCE_LIMITS: &str = "missing-resource-limits";
+pub const MISSING_PROBES: &str = "missing-probes";
+pub const LATEST_IMAGE_TAG: &str = "latest-image-tag";
+
+/// Every rule, in the order they are checked.
+pub const RULES: &[Rule] = &[
+    Rule {
+        id: SCHEMA_VERSION,
+        severity: Severity::Warning,
+        description: "The config uses a legacy or unrecognized schema_version.",
+    },
+    Rule {
+        id: EMPTY_VERSION,
+        severity: Severity::Warning,
+        description: "A service has an empty version string.",
+    },
+    Rule {
+        id: DUPLICATE_SERVICE,
+        severity: Severity::Error,
+        description: "Two services share a name.",
+    },
+    Rule {
+        id: UNKNOWN_DEPENDENCY,
+        severity: Severity::Error,
+        description: "A relies_on entry names neither a buildable service nor an existing path.",
+    },
+    Rule {
+        id: INVALID_DEPLOY_ORDER,
+        severity: Severity::Error,
+        description: "A deploy_after entry names an unknown service, or the entries form a cycle.",
+    },
+    Rule {
+        id: MISSING_TEMPLATES,
+        severity: Severity::Error,
+        description: "A service has no template directory and no Helm chart.",
+    },
+    Rule {
+        id: INVALID_REGISTRY,
+        severity: Severity::Error,
+        description: "The registry setting is not a valid registry host and namespace.",
+    },
+    Rule {
+        id: INVALID_TEMPLATE,
+        severity: Severity::Error,
+        description: "A template or Helm chart fails to render.",
+    },
+    Rule {
+        id: UNRESOLVED_VARIABLE,
+        severity: Severity::Error,
+        description: "A variable's env, file or command source cannot be read.",
+    },
+    Rule {
+        id: UNDEFINED_VARIABLE,
+        severity: Severity::Error,
+        description: "A template uses a variable the environment does not define.",
+    },
+    Rule {
+        id: UNUSED_VARIABLE,
+        severity: Severity::Warning,
+        description: "An environment variable or [values] key is not used by any template.",
+    },
+    Rule {
+        id: MISSING_RESOURCE_LIMITS,
+        severity: Severity::Warning,
+        description: "A container has no resource limits.",
+    },
+    Rule {
+        id: MISSING_PROBES,
+        severity: Severity::Warning,
+        description: "A long-running container has no readiness or liveness probe.",
+    },
+    Rule {
+        id: LATEST_IMAGE_TAG,
+        severity: Severity::Warning,
+        description: "A container image uses the latest tag, or no tag at all.",
+    },
+];
+
+// Kinds whose pods are expected to keep running, and so to have probes.
+const LONG_RUNNING_KINDS: &[&str] = &["Deployment", "StatefulSet", "DaemonSet", "ReplicaSet"];
+
+/// One problem found by a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// The file the finding is about, relative to the project root.
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintReport {
+    pub environment: String,
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+
+    // 0 when nothing was found, 1 when there are errors and 2 when there are only
+    // warnings.
+    pub fn exit_code(&self) -> i32 {
+        if self.errors() > 0 {
+            1
+        } else if self.warnings() > 0 {
+            2
+        } else {
+            0
+        }
+    }
+
+    // One `severity[rule]: message` block per finding, with its location.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for finding in &self.findings {
+            text.push_str(&format!(
+                "{}[{}]: {}\n  --> {}",
+                finding.severity.as_str(),
+                finding.rule,
+                finding.message,
+                finding.path
+            ));
+            if let Some(line) = finding.line {
+                text.push_str(&format!(":{}", line));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "environment": self.environment,
+            "errors": self.errors(),
+            "warnings": self.warnings(),
+            "findings": self.findings,
+        })
+    }
+
+    // A SARIF 2.1.0 log with every rule and one result per finding.
+    pub fn to_sarif(&self) -> Value {
+        let rules: Vec<Value> = RULES
+            .iter()
+            .map(|rule| {
+                json!({
+                    "id": rule.id,
+                    "shortDescription": { "text": rule.description },
+                    "defaultConfiguration": { "level": sarif_level(rule.severity) },
+                })
+            })
+            .collect();
+        let results: Vec<Value> = self
+            .findings
+            .iter()
+            .map(|finding| {
+                let mut location = json!({
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": finding.path },
+                    }
+                });
+                if let Some(line) = finding.line {
+                    location["physicalLocation"]["region"] = json!({ "startLine": line });
+                }
+                json!({
+                    "ruleId": finding.rule,
+                    "ruleIndex": RULES.iter().position(|rule| rule.id == finding.rule),
+                    "level": sarif_level(finding.severity),
+                    "message": { "text": finding.message },
+                    "locations": [location],
+                })
+            })
+            .collect();
+
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "sailr",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules,
+                    }
+                },
+                "results": results,
+            }],
+        })
+    }
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Off => "none",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+// The severity of every rule for `env`, with its `[lint]` table applied.
+fn severities(env: &Environment) -> Result<BTreeMap<&'static str, Severity>, LintError> {
+    let mut severities: BTreeMap<&'static str, Severity> =
+        RULES.iter().map(|rule| (rule.id, rule.severity)).collect();
+    for (id, severity) in &env.lint {
+        let Some(rule) = RULES.iter().find(|rule| rule.id == id) else {
+            return Err(LintError::UnknownRule {
+                rule: id.clone(),
+                known: RULES
+                    .iter()
+                    .map(|rule| rule.id)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            });
+        };
+        severities.insert(rule.id, *severity);
+    }
+    Ok(severities)
+}
+
+/// Collects the findings of an environment, dropping those of rules that are off.
+pub struct Linter {
+    severities: BTreeMap<&'static str, Severity>,
+    findings: Vec<Finding>,
+}
+
+impl Linter {
+    pub fn new(env: &Environment) -> Result<Linter, LintError> {
+        Ok(Linter {
+            severities: severities(env)?,
+            findings: Vec::new(),
+        })
+    }
+
+    fn report(
+        &mut self,
+        rule: &'static str,
+        service: Option<&str>,
+        path: &str,
+        line: Option<usize>,
+        message: String,
+    ) {
+        let severity = self.severities.get(rule).copied().unwrap_or(Severity::Off);
+        if severity == Severity::Off {
+            return;
+        }
+        self.findings.push(Finding {
+            rule,
+            severity,
+            message,
+            service: service.map(str::to_string),
+            path: path.to_string(),
+            line,
+        });
+    }
+
+    // Checks the environment's config.toml. `path_exists` decides whether a relies_on
+    // entry that is not a service names a path.
+    pub fn check_config(&mut self, env: &Environment, path_exists: impl Fn(&str) -> bool) {
+        let config = config_path(&env.name);
+
+        if env.schema_version == "0.2.0" || env.schema_version == "0.3.0" {
+            self.report(
+                SCHEMA_VERSION,
+                None,
+                &config,
+                None,
+                format!(
+                    "Schema version {} is legacy; run `sailr migrate` to upgrade to {}",
+                    env.schema_version, SCHEMA_V05
+                ),
+            );
+        } else if env.schema_version != "0.4.0" && env.schema_version != SCHEMA_V05 {
+            self.report(
+                SCHEMA_VERSION,
+                None,
+                &config,
+                None,
+                format!("Schema version {} is unrecognized", env.schema_version),
+            );
+        }
+
+        if let Err(e) = env.registry.resolve() {
+            self.report(INVALID_REGISTRY, None, &config, None, e.to_string());
+        }
+
+        let buildable: BTreeSet<&str> = env
+            .services
+            .iter()
+            .filter(|service| service.build.is_some())
+            .map(|service| service.name.as_str())
+            .collect();
+        let mut seen = BTreeSet::new();
+        for service in &env.services {
+            let name = Some(service.name.as_str());
+            if !seen.insert(service.name.as_str()) {
+                self.report(
+                    DUPLICATE_SERVICE,
+                    name,
+                    &config,
+                    None,
+                    format!("Service '{}' is defined more than once", service.name),
+                );
+            }
+            if service.version.trim().is_empty() {
+                self.report(
+                    EMPTY_VERSION,
+                    name,
+                    &config,
+                    None,
+                    format!("Service '{}' has an empty version string", service.name),
+                );
+            }
+            let relies_on = service
+                .build
+                .as_ref()
+                .and_then(|build| build.relies_on.as_ref());
+            for dependency in relies_on.into_iter().flatten() {
+                if !buildable.contains(dependency.as_str()) && !path_exists(dependency) {
+                    self.report(
+                        UNKNOWN_DEPENDENCY,
+                        name,
+                        &config,
+                        None,
+                        format!(
+                            "Service '{}' relies on '{}', which is neither a buildable service nor an existing path",
+                            service.name, dependency
+                        ),
+                    );
+                }
+            }
+        }
+
+        if let Err(e) = service_levels(&env.services) {
+            self.report(INVALID_DEPLOY_ORDER, None, &config, None, e.to_string());
+        }
+    }
+
+    // Reports the `[[environment_variables]]` and `[values]` keys of `env` that none of
+    // the `referenced` variable paths reads.
+    pub fn check_unused(
+        &mut self,
+        env: &Environment,
+        referenced: &BTreeSet<String>,
+        include_environment_variables: bool,
+    ) {
+        let config = config_path(&env.name);
+        let is_read = |name: &str| {
+            referenced.iter().any(|path| {
+                path == name
+                    || path
+                        .strip_prefix(name)
+                        .is_some_and(|rest| rest.starts_with('.'))
+                    || name
+                        .strip_prefix(path.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+        };
+
+        if include_environment_variables {
+            for variable in env.environment_variables.iter().flatten() {
+                if !is_read(&variable.name) {
+                    self.report(
+                        UNUSED_VARIABLE,
+                        None,
+                        &config,
+                        None,
+                        format!(
+                            "Environment variable '{}' is not used by any template",
+                            variable.name
+                        ),
+                    );
+                }
+            }
+        }
+        for key in env.values.keys() {
+            if !is_read(&format!("values.{}", key)) {
+                self.report(
+                    UNUSED_VARIABLE,
+                    None,
+                    &config,
+                    None,
+                    format!("Value '{}' is not used by any template", key),
+                );
+            }
+        }
+    }
+
+    // Checks the rendered manifests in `content`, read from `path`, for resource limits,
+    // probes and image tags.
+    pub fn check_manifests(&mut self, service: &Service, path: &str, content: &str) {
+        let Ok(documents) = parse_documents(content) else {
+            return;
+        };
+        let name = Some(service.name.as_str());
+        for document in &documents {
+            let kind = document["kind"].as_str().unwrap_or_default();
+            let Some(pod) = pod_spec(document) else {
+                continue;
+            };
+            let resource = format!(
+                "{}/{}",
+                kind,
+                document["metadata"]["name"].as_str().unwrap_or_default()
+            );
+
+            let containers = pod["containers"].as_array().into_iter().flatten();
+            let init_containers = pod["initContainers"].as_array().into_iter().flatten();
+            for (container, init) in containers
+                .map(|container| (container, false))
+                .chain(init_containers.map(|container| (container, true)))
+            {
+                let container_name = container["name"].as_str().unwrap_or_default();
+                if container["resources"]["limits"]
+                    .as_object()
+                    .is_none_or(|limits| limits.is_empty())
+                {
+                    self.report(
+                        MISSING_RESOURCE_LIMITS,
+                        name,
+                        path,
+                        None,
+                        format!(
+                            "Container '{}' of {} has no resource limits",
+                            container_name, resource
+                        ),
+                    );
+                }
+                if !init && LONG_RUNNING_KINDS.contains(&kind) {
+                    let missing: Vec<&str> = ["readinessProbe", "livenessProbe"]
+                        .into_iter()
+                        .filter(|probe| container.get(*probe).is_none_or(Value::is_null))
+                        .collect();
+                    if !missing.is_empty() {
+                        self.report(
+                            MISSING_PROBES,
+                            name,
+                            path,
+                            None,
+                            format!(
+                                "Container '{}' of {} has no {}",
+                                container_name,
+                                resource,
+                                missing.join(" or ")
+                            ),
+                        );
+                    }
+                }
+                if let Some(image) = container["image"].as_str() {
+                    if uses_latest_tag(image) {
+                        self.report(
+                            LATEST_IMAGE_TAG,
+                            name,
+                            path,
+                            None,
+                            format!(
+                                "Container '{}' of {} uses image '{}'; pin a version instead of latest",
+                                container_name, resource, image
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn finish(self, env: &Environment) -> LintReport {
+        LintReport {
+            environment: env.name.clone(),
+            findings: self.findings,
+        }
+    }
+}
+
+// Lints `env`: its config, the templates of its services rendered with its variables
+// and, for services with a `[service.helm]` block, their charts.
+pub fn lint(env: &Environment) -> Result<LintReport, LintError> {
+    let mut linter = Linter::new(env)?;
+    linter.check_config(env, |path| Path::new(path).exists());
+
+    let mut template_manager = TemplateManager::new();
+    let (templates, _) = template_manager
+        .read_templates(Some(env))
+        .map_err(|e| LintError::Templates(e.to_string()))?;
+
+    let mut referenced = BTreeSet::new();
+    let mut uses_helm = false;
+    let mut services_seen = BTreeSet::new();
+    for service in &env.services {
+        if !services_seen.insert(service.name.as_str()) {
+            continue;
+        }
+        let name = Some(service.name.as_str());
+        let service_templates: Vec<&Template> = templates
+            .iter()
+            .filter(|template| template.name == service.name || template.name == service.get_path())
+            .collect();
+        if service_templates.is_empty() && service.helm.is_none() {
+            linter.report(
+                MISSING_TEMPLATES,
+                name,
+                &config_path(&env.name),
+                None,
+                format!(
+                    "Service '{}' has no templates in k8s/templates/{} or k8s/environments/{}/templates/{}",
+                    service.name,
+                    service.get_path(),
+                    env.name,
+                    service.get_path()
+                ),
+            );
+        }
+
+        let context = match env.get_template_context(service) {
+            Ok(context) => context,
+            // The registry is already reported by check_config.
+            Err(VariableError::Registry(_)) => continue,
+            Err(e) => {
+                linter.report(
+                    UNRESOLVED_VARIABLE,
+                    name,
+                    &config_path(&env.name),
+                    None,
+                    e.to_string(),
+                );
+                continue;
+            }
+        };
+        let mut overlays = OverlaySet::load(&env.name, &service.name)
+            .map_err(|e| LintError::Templates(e.to_string()))?;
+
+        for template in service_templates {
+            let path = template.source_path();
+            match template_manager.referenced_variables(template) {
+                Ok(variables) => referenced.extend(variables),
+                Err(e) => {
+                    linter.report(INVALID_TEMPLATE, name, &path, Some(e.line()), e.to_string());
+                    continue;
+                }
+            }
+            let Ok(output) = template_manager.render_template(template, &context) else {
+                continue;
+            };
+            for placeholder in &output.unresolved {
+                let suggestion = placeholder
+                    .suggestion
+                    .as_ref()
+                    .map(|name| format!(" (did you mean '{}'?)", name))
+                    .unwrap_or_default();
+                linter.report(
+                    UNDEFINED_VARIABLE,
+                    name,
+                    &path,
+                    Some(placeholder.line),
+                    format!(
+                        "{} is not defined by the environment{}",
+                        placeholder.placeholder, suggestion
+                    ),
+                );
+            }
+            let content = overlays.apply(&output.content).unwrap_or(output.content);
+            linter.check_manifests(service, &path, &content);
+        }
+
+        if let Some(helm) = &service.helm {
+            uses_helm = true;
+            match helm::render_service_chart(env, service, helm) {
+                Ok(templates) => {
+                    for template in templates {
+                        let content = overlays
+                            .apply(&template.content)
+                            .unwrap_or(template.content.clone());
+                        linter.check_manifests(service, &template.source_path(), &content);
+                    }
+                }
+                Err(e) => linter.report(INVALID_TEMPLATE, name, &helm.chart, None, e.to_string()),
+            }
+        }
+    }
+
+    // Helm charts see every environment variable under `.Values.sailr`, so they can't be
+    // told apart from unused ones.
+    linter.check_unused(env, &referenced, !uses_helm);
+    Ok(linter.finish(env))
+}
+
+fn config_path(env: &str) -> String {
+    format!("k8s/environments/{}/config.toml", env)
+}
+
+// The pod spec of a workload, CronJobs included, or of a bare Pod.
+fn pod_spec(document: &Value) -> Option<&Value> {
+    let spec = match document["kind"].as_str()? {
+        "Pod" => &document["spec"],
+        "CronJob" => &document["spec"]["jobTemplate"]["spec"]["template"]["spec"],
+        _ => &document["spec"]["template"]["spec"],
+    };
+    spec.get("containers").map(|_| spec)
+}
+
+// Whether `image` is tagged `latest`, or has neither a tag nor a digest, which means
+// the same.
+fn uses_latest_tag(image: &str) -> bool {
+    if image.contains('@') {
+        return false;
+    }
+    let name = image.rsplit('/').next().unwrap_or(image);
+    match name.split_once(':') {
+        Some((_, tag)) => tag == "latest",
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::{EnvironmentVariable, RegistryConfig, ServiceBuildConfig};
+
+    fn service(name: &str, relies_on: Option<Vec<&str>>) -> Service {
+        let mut service = Service::new(name, None, "1.0.0");
+        service.build = Some(ServiceBuildConfig {
+            path: format!("./{}", name),
+            include: None,
+            relies_on: relies_on.map(|deps| deps.into_iter().map(str::to_string).collect()),
+            before_synchronous: None,
+            before: None,
+            run_parallel: None,
+            run_synchronous: None,
+            after: None,
+            finally: None,
+            dockerfile: None,
+            build_command: None,
+            push_command: None,
+        });
+        service
+    }
+
+    #[test]
+    fn config_rules_report_with_their_configured_severity() {
+        let mut env = Environment::new("dev");
+        env.registry = RegistryConfig::Simple("https://registry.example.com/".to_string());
+        env.services = vec![
+            service("api", None),
+            service("web", Some(vec!["api", "shared/lib", "missing"])),
+            Service::new("api", None, " "),
+        ];
+        env.services[1].deploy_after = Some(vec!["cache".to_string()]);
+        env.lint
+            .insert(UNKNOWN_DEPENDENCY.to_string(), Severity::Warning);
+        env.lint.insert(EMPTY_VERSION.to_string(), Severity::Off);
+
+        let mut linter = Linter::new(&env).unwrap();
+        linter.check_config(&env, |path| path == "shared/lib");
+        let report = linter.finish(&env);
+
+        let found: Vec<(&str, Severity)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.rule, finding.severity))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (INVALID_REGISTRY, Severity::Error),
+                (UNKNOWN_DEPENDENCY, Severity::Warning),
+                (DUPLICATE_SERVICE, Severity::Error),
+                (INVALID_DEPLOY_ORDER, Severity::Error),
+            ]
+        );
+        assert!(report.findings[1].message.contains("'missing'"));
+        assert_eq!(report.exit_code(), 1);
+
+        env.lint.insert("no-such-rule".to_string(), Severity::Error);
+        assert!(matches!(
+            Linter::new(&env),
+            Err(LintError::UnknownRule { rule, .. }) if rule == "no-such-rule"
+        ));
+    }
+
+    #[test]
+    fn manifest_rules_check_every_container() {
+        let mut env = Environment::new("dev");
+        let content = "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\nspec:\n  template:\n    spec:\n      initContainers:\n        - name: migrate\n          image: registry.local:5000/api-migrate\n          resources:\n            limits:\n              memory: 64Mi\n      containers:\n        - name: api\n          image: registry.local:5000/api:1.2.0\n          readinessProbe:\n            httpGet:\n              port: 8080\n---\napiVersion: batch/v1\nkind: CronJob\nmetadata:\n  name: cleanup\nspec:\n  jobTemplate:\n    spec:\n      template:\n        spec:\n          containers:\n            - name: cleanup\n              image: busybox:latest\n              resources:\n                limits:\n                  cpu: 100m\n";
+        let api = Service::new("api", None, "1.2.0");
+
+        let mut linter = Linter::new(&env).unwrap();
+        linter.check_manifests(&api, "k8s/templates/api/deployment.yaml", content);
+        let report = linter.finish(&env);
+
+        let messages: Vec<(&str, &str)> = report
+            .findings
+            .iter()
+            .map(|finding| (finding.rule, finding.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    MISSING_RESOURCE_LIMITS,
+                    "Container 'api' of Deployment/api has no resource limits"
+                ),
+                (
+                    MISSING_PROBES,
+                    "Container 'api' of Deployment/api has no livenessProbe"
+                ),
+                (
+                    LATEST_IMAGE_TAG,
+                    "Container 'migrate' of Deployment/api uses image 'registry.local:5000/api-migrate'; pin a version instead of latest"
+                ),
+                (
+                    LATEST_IMAGE_TAG,
+                    "Container 'cleanup' of CronJob/cleanup uses image 'busybox:latest'; pin a version instead of latest"
+                ),
+            ]
+        );
+        assert_eq!(report.exit_code(), 2);
+
+        env.lint.insert(MISSING_PROBES.to_string(), Severity::Off);
+        env.lint
+            .insert(MISSING_RESOURCE_LIMITS.to_string(), Severity::Off);
+        env.lint.insert(LATEST_IMAGE_TAG.to_string(), Severity::Off);
+        let mut linter = Linter::new(&env).unwrap();
+        linter.check_manifests(&api, "k8s/templates/api/deployment.yaml", content);
+        assert_eq!(linter.finish(&env).exit_code(), 0);
+    }
+
+    #[test]
+    fn unused_variables_and_sarif_output() {
+        let mut env = Environment::new("dev");
+        env.environment_variables = Some(vec![
+            EnvironmentVariable::new("DATABASE_URL", None),
+            EnvironmentVariable::new("UNUSED", None),
+        ]);
+        env.values
+            .insert("replicas".to_string(), toml::Value::Integer(2));
+        env.values.insert(
+            "ingress".to_string(),
+            toml::Value::Table(Default::default()),
+        );
+        let referenced: BTreeSet<String> = ["DATABASE_URL", "values.ingress.host"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        let mut linter = Linter::new(&env).unwrap();
+        linter.check_unused(&env, &referenced, true);
+        let report = linter.finish(&env);
+
+        assert_eq!(
+            report
+                .findings
+                .iter()
+                .map(|finding| finding.message.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Environment variable 'UNUSED' is not used by any template",
+                "Value 'replicas' is not used by any template",
+            ]
+        );
+
+        let sarif = report.to_sarif();
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"].as_array().unwrap().len(),
+            RULES.len()
+        );
+        assert_eq!(run["results"][0]["ruleId"], UNUSED_VARIABLE);
+        assert_eq!(run["results"][0]["level"], "warning");
+        assert_eq!(
+            run["results"][0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "k8s/environments/dev/config.toml"
+        );
+        assert_eq!(report.to_json()["warnings"], 2);
+    }
+}
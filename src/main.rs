@@ -2,12 +2,18 @@ use std::{io, process::exit};
 
 use sailr::{
     builder::{filter_services_exact, split_matches, Builder},
-    cli::{Cli, Commands, EnvType, InfraCommands, Provider, WorkflowCommands},
+    cli::{
+        Cli, Commands, EnvCommands, EnvType, InfraCommands, LintFormat, Provider, SecretsCommands,
+        WorkflowCommands,
+    },
     create_default_env_config,
     create_default_env_infra,
+    deployment::{history, DeployOptions},
     environment::{Environment, Service},
+    environments,
     errors::CliError,
     generate,
+    generate::GenerateOptions,
     infra::{local_k8s::LocalK8, Infra},
     plan::{generate_deployment_plan, validate_plan_safety},
     templates::{
@@ -297,6 +303,7 @@ async fn main() -> Result<(), CliError> {
                     &arg.name,
                     &arg.context,
                     &arg.namespace.unwrap_or("default".to_string()),
+                    arg.prune,
                 )
                 .await
                 {
@@ -313,7 +320,14 @@ async fn main() -> Result<(), CliError> {
                 }
             } else {
                 LOGGER.info(&format!("Deploying environment '{}'", arg.name));
-                sailr::deployment::deploy(arg.context.to_string(), &arg.name, arg.strategy).await?;
+                let options = deploy_options(
+                    arg.strategy,
+                    arg.timeout,
+                    arg.no_rollback,
+                    arg.prune,
+                    arg.max_parallel,
+                );
+                sailr::deployment::deploy(arg.context.to_string(), &arg.name, &options).await?;
             }
         }
         Commands::Generate(arg) => {
@@ -335,7 +349,15 @@ async fn main() -> Result<(), CliError> {
                 &split_matches(arg.ignore),
             );
 
-            generate(&arg.name, &env, services).map_err(|e| CliError::Other(e.to_string()))?;
+            let options = GenerateOptions {
+                allow_unresolved: arg.allow_unresolved,
+                timestamp: arg.timestamp,
+                check: arg.check,
+                source_revision: source_revision(),
+                stdout: arg.stdout.then_some(arg.format),
+            };
+            generate(&arg.name, &env, services, &options)
+                .map_err(|e| CliError::Other(e.to_string()))?;
 
             LOGGER.info("Generation Complete");
         }
@@ -420,7 +442,14 @@ async fn main() -> Result<(), CliError> {
                 };
             }
 
-            generate(&arg.name, &env, services).map_err(|e| CliError::Other(e.to_string()))?;
+            let options = GenerateOptions {
+                allow_unresolved: arg.allow_unresolved,
+                timestamp: arg.timestamp,
+                source_revision: source_revision(),
+                ..GenerateOptions::default()
+            };
+            generate(&arg.name, &env, services, &options)
+                .map_err(|e| CliError::Other(e.to_string()))?;
 
             if !arg.apply {
                 LOGGER.info("🔍 Generating deployment plan for build-generate-deploy workflow...");
@@ -429,6 +458,7 @@ async fn main() -> Result<(), CliError> {
                     &arg.name,
                     &arg.context,
                     &arg.namespace.unwrap_or("default".to_string()),
+                    arg.prune,
                 )
                 .await
                 {
@@ -460,7 +490,14 @@ async fn main() -> Result<(), CliError> {
                 }
             }
 
-            sailr::deployment::deploy(arg.context.to_string(), &arg.name, arg.strategy).await?;
+            let options = deploy_options(
+                arg.strategy,
+                arg.timeout,
+                arg.no_rollback,
+                arg.prune,
+                arg.max_parallel,
+            );
+            sailr::deployment::deploy(arg.context.to_string(), &arg.name, &options).await?;
         }
         Commands::AddService(args) => {
             LOGGER.info(&format!(
@@ -690,6 +727,21 @@ async fn main() -> Result<(), CliError> {
         Commands::Bump(arg) => handle_bump(arg)?,
         Commands::Lint(arg) => handle_lint(arg)?,
         Commands::Workflow(cmd) => handle_workflow(cmd).await?,
+        Commands::Secrets(cmd) => handle_secrets(cmd)?,
+        Commands::Schema(args) => handle_schema(args)?,
+        Commands::Env(cmd) => handle_env(cmd)?,
+        Commands::Rollback(arg) => {
+            if arg.list {
+                let revisions = sailr::deployment::revisions(arg.context, &arg.name).await?;
+                if revisions.is_empty() {
+                    LOGGER.info(&format!("No revisions recorded for '{}'", arg.name));
+                }
+                print!("{}", history::revisions_to_text(&revisions));
+            } else {
+                let timeout = (!arg.timeout.is_zero()).then_some(arg.timeout);
+                sailr::deployment::rollback(arg.context, &arg.name, arg.to, timeout).await?;
+            }
+        }
         Commands::Interactive(args) => {
             // Handle interactive commands
             sailr::interactive::main_menu(args)
@@ -755,44 +807,267 @@ fn handle_bump(arg: sailr::cli::BumpArgs) -> Result<(), CliError> {
     Ok(())
 }
 
+// A rollout timeout of zero skips waiting for workloads.
+fn deploy_options(
+    strategy: sailr::cli::DeploymentStrategy,
+    timeout: std::time::Duration,
+    no_rollback: bool,
+    prune: bool,
+    max_parallel: usize,
+) -> DeployOptions {
+    DeployOptions {
+        strategy,
+        rollout_timeout: (!timeout.is_zero()).then_some(timeout),
+        rollback_on_failure: !no_rollback,
+        prune,
+        max_parallel,
+    }
+}
+
+fn handle_env(cmd: EnvCommands) -> Result<(), CliError> {
+    let error = |e: Box<dyn std::error::Error>| CliError::Other(e.to_string());
+    let env_error = |e: sailr::errors::EnvironmentError| CliError::Other(e.to_string());
+    match cmd {
+        EnvCommands::List => {
+            let summaries = environments::list_environments().map_err(env_error)?;
+            if summaries.is_empty() {
+                LOGGER.info("No environments found in k8s/environments");
+            } else {
+                print!("{}", environments::summaries_to_text(&summaries));
+            }
+        }
+        EnvCommands::Show(args) => {
+            print!(
+                "{}",
+                Environment::resolve_contents(&args.name).map_err(error)?
+            );
+        }
+        EnvCommands::Resolve(args) => {
+            let resolved = Environment::resolve_annotated(&args.name).map_err(error)?;
+            print!("{}", resolved);
+        }
+        EnvCommands::Diff(args) => {
+            let from = Environment::load_from_file(&args.from).map_err(error)?;
+            let to = Environment::load_from_file(&args.to).map_err(error)?;
+            print!("{}", environments::diff_environments(&from, &to).to_text());
+        }
+        EnvCommands::Clone(args) => {
+            let path =
+                environments::clone_environment(&args.source, &args.target).map_err(env_error)?;
+            LOGGER.status("Created", &path.display().to_string(), "green");
+            LOGGER.info(&format!(
+                "'{}' extends '{}'; add the settings it should override to {}",
+                args.target,
+                args.source,
+                path.display()
+            ));
+        }
+        EnvCommands::Rm(args) => {
+            let paths = environments::environment_paths(&args.name).map_err(env_error)?;
+            if !args.yes {
+                let listed = paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let confirm = inquire::Confirm::new(&format!(
+                    "Delete environment '{}' ({})?",
+                    args.name, listed
+                ))
+                .with_default(false)
+                .with_help_message("Pass --yes to skip this prompt")
+                .prompt()
+                .map_err(|e| CliError::Other(format!("Failed to confirm deletion: {}", e)))?;
+                if !confirm {
+                    LOGGER.info("Deletion cancelled by user.");
+                    return Ok(());
+                }
+            }
+            for path in environments::delete_environment(&args.name).map_err(env_error)? {
+                LOGGER.status("Deleted", &path.display().to_string(), "green");
+            }
+        }
+    }
+    Ok(())
+}
+
+// Prints the lint findings in the requested format and exits with 1 when there are
+// errors, or 2 when there are only warnings.
 fn handle_lint(arg: sailr::cli::LintArgs) -> Result<(), CliError> {
     let env = sailr::environment::Environment::load_from_file(&arg.name)
         .map_err(|e| CliError::Other(e.to_string()))?;
-    sailr::LOGGER.info(&format!("Linting environment '{}'...", arg.name));
-    let mut warnings = 0;
-
-    if env.schema_version == "0.2.0" || env.schema_version == "0.3.0" {
-        sailr::LOGGER.warn(&format!(
-            "Schema version {} is legacy; please migrate to 0.5.0.",
-            env.schema_version
-        ));
-        warnings += 1;
-    } else if env.schema_version != "0.4.0" && env.schema_version != "0.5.0" {
-        sailr::LOGGER.warn(&format!(
-            "Schema version {} is unrecognized.",
-            env.schema_version
-        ));
-        warnings += 1;
+    LOGGER.info(&format!("Linting environment '{}'...", arg.name));
+    let report = sailr::lint::lint(&env).map_err(|e| CliError::Other(e.to_string()))?;
+
+    match arg.format {
+        LintFormat::Text => print!("{}", report.to_text()),
+        LintFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report.to_json())
+                .map_err(|e| CliError::Other(e.to_string()))?
+        ),
+        LintFormat::Sarif => println!(
+            "{}",
+            serde_json::to_string_pretty(&report.to_sarif())
+                .map_err(|e| CliError::Other(e.to_string()))?
+        ),
     }
 
-    for service in &env.services {
-        if service.version.trim().is_empty() {
-            sailr::LOGGER.warn(&format!(
-                "Service '{}' has an empty version string.",
-                service.name
+    match report.exit_code() {
+        0 => {
+            LOGGER.info("Lint passed with no findings. Environment config is healthy.");
+            Ok(())
+        }
+        code => {
+            LOGGER.warn(&format!(
+                "Lint finished with {} error(s) and {} warning(s).",
+                report.errors(),
+                report.warnings()
             ));
-            warnings += 1;
+            exit(code)
         }
     }
+}
+
+fn handle_secrets(cmd: SecretsCommands) -> Result<(), CliError> {
+    use sailr::secrets::{parse_reference, SecretKey, SecretStore};
+    use std::io::{IsTerminal, Read};
+
+    let error = |e: sailr::errors::SecretsError| CliError::Other(e.to_string());
+
+    match cmd {
+        SecretsCommands::Set(args) => {
+            let (secret, key_name) = parse_reference(&args.reference).map_err(error)?;
+            let key = SecretKey::resolve(&args.name, args.key_file.as_deref()).map_err(error)?;
+            let value = match args.value {
+                Some(value) => value,
+                None if io::stdin().is_terminal() => {
+                    inquire::Password::new(&format!("Value for {}:", args.reference))
+                        .without_confirmation()
+                        .prompt()
+                        .map_err(|e| CliError::Other(e.to_string()))?
+                }
+                None => {
+                    let mut value = String::new();
+                    io::stdin()
+                        .read_to_string(&mut value)
+                        .map_err(|e| CliError::Other(e.to_string()))?;
+                    value.strip_suffix('\n').unwrap_or(&value).to_string()
+                }
+            };
 
-    if warnings == 0 {
-        sailr::LOGGER.info("Lint passed with no warnings. Environment config is healthy.");
-    } else {
-        sailr::LOGGER.warn(&format!("Lint finished with {} warnings.", warnings));
+            let mut store = SecretStore::load(&args.name).map_err(error)?;
+            store.set(&key, secret, key_name, &value).map_err(error)?;
+            store.save().map_err(error)?;
+            LOGGER.status(
+                "Encrypted",
+                &format!("{} in {}", args.reference, store.path().display()),
+                "green",
+            );
+        }
+        SecretsCommands::Get(args) => {
+            let (secret, key_name) = parse_reference(&args.reference).map_err(error)?;
+            let key = SecretKey::resolve(&args.name, args.key_file.as_deref()).map_err(error)?;
+            let store = SecretStore::load(&args.name).map_err(error)?;
+            match store.get(&key, secret, key_name).map_err(error)? {
+                Some(value) => println!("{}", value),
+                None => {
+                    return Err(CliError::Other(format!(
+                        "Secret {} not found in environment {}",
+                        args.reference, args.name
+                    )))
+                }
+            }
+        }
+        SecretsCommands::Rm(args) => {
+            let (secret, key_name) = parse_reference(&args.reference).map_err(error)?;
+            let mut store = SecretStore::load(&args.name).map_err(error)?;
+            if !store.remove(secret, key_name) {
+                return Err(CliError::Other(format!(
+                    "Secret {} not found in environment {}",
+                    args.reference, args.name
+                )));
+            }
+            store.save().map_err(error)?;
+            LOGGER.status("Removed", &args.reference, "green");
+        }
+        SecretsCommands::RotateKey(args) => {
+            let mut store = SecretStore::load(&args.name).map_err(error)?;
+            let new_key = if args.new_key_file.exists() {
+                SecretKey::from_file(&args.new_key_file).map_err(error)?
+            } else {
+                let key = SecretKey::generate();
+                key.write_file(&args.new_key_file).map_err(error)?;
+                LOGGER.status(
+                    "Generated",
+                    &format!(
+                        "new key in {}; keep it out of version control",
+                        args.new_key_file.display()
+                    ),
+                    "green",
+                );
+                key
+            };
+            // An empty store has nothing to decrypt, so it needs no current key.
+            let old_key = if store.is_empty() {
+                new_key.clone()
+            } else {
+                SecretKey::resolve(&args.name, args.key_file.as_deref()).map_err(error)?
+            };
+            let rotated = store.rotate(&old_key, &new_key).map_err(error)?;
+            store.save().map_err(error)?;
+            LOGGER.status(
+                "Rotated",
+                &format!(
+                    "{} secret(s) in {} to key {}",
+                    rotated,
+                    store.path().display(),
+                    new_key.id()
+                ),
+                "green",
+            );
+        }
     }
     Ok(())
 }
 
+fn handle_schema(arg: sailr::cli::SchemaArgs) -> Result<(), CliError> {
+    let schema = match arg.kind {
+        sailr::cli::SchemaKind::Env => sailr::schema::environment_schema(),
+        sailr::cli::SchemaKind::Workflow => sailr::schema::workflow_schema(),
+    };
+    let content = serde_json::to_string_pretty(&schema)
+        .map_err(|e| CliError::Other(format!("Failed to render schema: {}", e)))?;
+
+    match arg.output {
+        Some(path) => {
+            fs::write(&path, content + "\n").map_err(|e| {
+                CliError::Other(format!("Failed to write {}: {}", path.display(), e))
+            })?;
+            LOGGER.status("Written", &format!("schema to {}", path.display()), "green");
+        }
+        None => println!("{}", content),
+    }
+    Ok(())
+}
+
+// The commit the manifests are generated from, for the source revision annotation.
+// Outside a git checkout, and without a CI commit variable, there is none.
+fn source_revision() -> Option<String> {
+    use sailr::workflow::{
+        planner::{SourceRevisionResolver, SystemSourceRevisionResolver},
+        runner::RunnerContext,
+    };
+
+    match SystemSourceRevisionResolver.resolve(&RunnerContext::detect(false)) {
+        Ok(revision) => revision,
+        Err(e) => {
+            LOGGER.debug(&format!("No source revision: {}", e));
+            None
+        }
+    }
+}
+
 async fn handle_workflow(cmd: WorkflowCommands) -> Result<(), CliError> {
     use sailr::workflow::config::WorkflowConfig;
 
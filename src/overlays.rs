@@ -0,0 +1,549 @@
+//! Kustomize-style overlays for rendered manifests.
+//!
+//! Every `.yaml`, `.yml` or `.json` file under `k8s/overlays/<env>/<service>/` is applied,
+//! in file name order, to the service's rendered templates before they are written to
+//! `k8s/generated/<env>`. A file may hold several documents, each one of:
+//!
+//! - a strategic merge patch: a partial resource identified by `kind` and `metadata.name`.
+//!   Maps merge recursively, `null` removes a field, and well-known lists (`containers`,
+//!   `env`, `ports`, `volumes`, `volumeMounts`, ...) merge by their key field.
+//!   `$patch: delete` on a list item removes it and `$patch: replace` on a map replaces it.
+//! - a JSON6902 patch: a `target` (`kind`, `name`, optional `namespace`) and a `patch`
+//!   list of RFC 6902 operations.
+
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::errors::OverlayError;
+
+pub const OVERLAYS_DIR: &str = "./k8s/overlays";
+
+const PATCH_DIRECTIVE: &str = "$patch";
+
+/// Lists merged by key instead of being replaced, with the candidate key fields in order.
+const LIST_MERGE_KEYS: &[(&str, &[&str])] = &[
+    ("containers", &["name"]),
+    ("initContainers", &["name"]),
+    ("ephemeralContainers", &["name"]),
+    ("env", &["name"]),
+    ("volumes", &["name"]),
+    ("imagePullSecrets", &["name"]),
+    ("volumeMounts", &["mountPath"]),
+    ("volumeDevices", &["devicePath"]),
+    ("ports", &["containerPort", "port"]),
+    ("hostAliases", &["ip"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchTarget {
+    pub kind: String,
+    pub name: String,
+    pub namespace: Option<String>,
+}
+
+impl PatchTarget {
+    fn matches(&self, document: &Value) -> bool {
+        let kind = document.get("kind").and_then(Value::as_str);
+        let name = document.pointer("/metadata/name").and_then(Value::as_str);
+        let namespace = document
+            .pointer("/metadata/namespace")
+            .and_then(Value::as_str);
+
+        kind == Some(self.kind.as_str())
+            && name == Some(self.name.as_str())
+            && self
+                .namespace
+                .as_deref()
+                .is_none_or(|expected| namespace == Some(expected))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OverlayPatch {
+    StrategicMerge(Value),
+    Json6902(json_patch::Patch),
+}
+
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    /// The file the patch was read from, for error messages.
+    pub source: String,
+    pub target: PatchTarget,
+    pub patch: OverlayPatch,
+}
+
+/// The overlays of one service, tracking which of them matched a resource.
+#[derive(Debug, Clone)]
+pub struct OverlaySet {
+    service: String,
+    overlays: Vec<Overlay>,
+    matched: Vec<bool>,
+}
+
+impl OverlaySet {
+    /// Loads `./k8s/overlays/<env>/<service>/`. A missing directory yields an empty set.
+    pub fn load(env: &str, service: &str) -> Result<Self, OverlayError> {
+        Self::load_from(Path::new(OVERLAYS_DIR), env, service)
+    }
+
+    pub fn load_from(root: &Path, env: &str, service: &str) -> Result<Self, OverlayError> {
+        let dir = root.join(env).join(service);
+        let mut overlays = Vec::new();
+
+        if dir.is_dir() {
+            let read_error = |e: std::io::Error| OverlayError::Read {
+                path: dir.display().to_string(),
+                message: e.to_string(),
+            };
+            let mut files = std::fs::read_dir(&dir)
+                .map_err(read_error)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(read_error)?;
+            files.retain(|path| {
+                path.is_file()
+                    && path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| matches!(ext, "yaml" | "yml" | "json"))
+            });
+            files.sort();
+
+            for file in files {
+                let source = file.display().to_string();
+                let content = std::fs::read_to_string(&file).map_err(|e| OverlayError::Read {
+                    path: source.clone(),
+                    message: e.to_string(),
+                })?;
+                overlays.extend(parse_overlays(&source, &content)?);
+            }
+        }
+
+        Ok(Self::new(service, overlays))
+    }
+
+    pub fn new(service: &str, overlays: Vec<Overlay>) -> Self {
+        Self {
+            service: service.to_string(),
+            matched: vec![false; overlays.len()],
+            overlays,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.overlays.is_empty()
+    }
+
+    /// Applies every matching overlay to the documents in `content`. The content is
+    /// returned untouched when no overlay matches.
+    pub fn apply(&mut self, content: &str) -> Result<String, OverlayError> {
+        if self.overlays.is_empty() {
+            return Ok(content.to_string());
+        }
+
+        let mut documents = parse_documents(content).map_err(|message| OverlayError::Apply {
+            path: self.overlays[0].source.clone(),
+            resource: "rendered template".to_string(),
+            message,
+        })?;
+        let mut changed = false;
+
+        for (index, overlay) in self.overlays.iter().enumerate() {
+            for document in documents
+                .iter_mut()
+                .filter(|document| overlay.target.matches(document))
+            {
+                let resource = format!("{}/{}", overlay.target.kind, overlay.target.name);
+                match &overlay.patch {
+                    OverlayPatch::StrategicMerge(patch) => strategic_merge(document, patch),
+                    OverlayPatch::Json6902(patch) => json_patch::patch(document, &patch.0)
+                        .map_err(|e| OverlayError::Apply {
+                            path: overlay.source.clone(),
+                            resource,
+                            message: e.to_string(),
+                        })?,
+                }
+                self.matched[index] = true;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return Ok(content.to_string());
+        }
+
+        documents
+            .iter()
+            .map(|document| {
+                serde_yaml::to_string(document).map_err(|e| OverlayError::Apply {
+                    path: self.overlays[0].source.clone(),
+                    resource: "rendered template".to_string(),
+                    message: e.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|documents| documents.join("---\n"))
+    }
+
+    /// Fails if an overlay never matched a resource, which usually means a typo in its
+    /// kind or name.
+    pub fn finish(self) -> Result<(), OverlayError> {
+        match self.matched.iter().position(|matched| !matched) {
+            Some(index) => Err(OverlayError::Unmatched {
+                path: self.overlays[index].source.clone(),
+                service: self.service,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn parse_documents(content: &str) -> Result<Vec<Value>, String> {
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(content) {
+        let yaml = serde_yaml::Value::deserialize(document).map_err(|e| e.to_string())?;
+        let value = serde_json::to_value(yaml).map_err(|e| e.to_string())?;
+        if !value.is_null() {
+            documents.push(value);
+        }
+    }
+    Ok(documents)
+}
+
+/// Parses every patch document in an overlay file.
+pub fn parse_overlays(source: &str, content: &str) -> Result<Vec<Overlay>, OverlayError> {
+    let invalid = |message: String| OverlayError::Invalid {
+        path: source.to_string(),
+        message,
+    };
+
+    parse_documents(content)
+        .map_err(invalid)?
+        .into_iter()
+        .map(|document| {
+            if document.get("target").is_some() && document.get("patch").is_some() {
+                parse_json6902(document).map_err(invalid)
+            } else {
+                parse_strategic_merge(document).map_err(invalid)
+            }
+            .map(|(target, patch)| Overlay {
+                source: source.to_string(),
+                target,
+                patch,
+            })
+        })
+        .collect()
+}
+
+fn parse_json6902(document: Value) -> Result<(PatchTarget, OverlayPatch), String> {
+    let target = document.get("target").cloned().unwrap_or(Value::Null);
+    let field = |name: &str| target.get(name).and_then(Value::as_str).map(str::to_string);
+    let target = PatchTarget {
+        kind: field("kind").ok_or("JSON6902 target is missing 'kind'")?,
+        name: field("name").ok_or("JSON6902 target is missing 'name'")?,
+        namespace: field("namespace"),
+    };
+
+    let operations = match document.get("patch") {
+        Some(Value::String(inline)) => parse_documents(inline)?
+            .into_iter()
+            .next()
+            .unwrap_or(Value::Null),
+        Some(operations) => operations.clone(),
+        None => Value::Null,
+    };
+    let patch = serde_json::from_value::<json_patch::Patch>(operations)
+        .map_err(|e| format!("invalid JSON6902 operations: {}", e))?;
+
+    Ok((target, OverlayPatch::Json6902(patch)))
+}
+
+fn parse_strategic_merge(document: Value) -> Result<(PatchTarget, OverlayPatch), String> {
+    let kind = document.get("kind").and_then(Value::as_str);
+    let name = document.pointer("/metadata/name").and_then(Value::as_str);
+    let (Some(kind), Some(name)) = (kind, name) else {
+        return Err(
+            "a strategic merge patch needs 'kind' and 'metadata.name' to identify its target"
+                .to_string(),
+        );
+    };
+    let target = PatchTarget {
+        kind: kind.to_string(),
+        name: name.to_string(),
+        namespace: document
+            .pointer("/metadata/namespace")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    };
+
+    Ok((target, OverlayPatch::StrategicMerge(document)))
+}
+
+fn strategic_merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch_map) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if patch_map.get(PATCH_DIRECTIVE).and_then(Value::as_str) == Some("replace") {
+        *target = without_directives(patch);
+        return;
+    }
+    let Value::Object(target_map) = target else {
+        *target = without_directives(patch);
+        return;
+    };
+
+    for (key, patch_value) in patch_map {
+        if key == PATCH_DIRECTIVE {
+            continue;
+        }
+
+        match (target_map.get_mut(key), patch_value) {
+            (_, Value::Null) => {
+                target_map.remove(key);
+            }
+            (Some(Value::Array(existing)), Value::Array(items)) => {
+                merge_list(key, existing, items);
+            }
+            (Some(existing @ Value::Object(_)), Value::Object(_)) => {
+                strategic_merge(existing, patch_value);
+            }
+            _ => {
+                target_map.insert(key.clone(), without_directives(patch_value));
+            }
+        }
+    }
+}
+
+fn merge_list(field: &str, existing: &mut Vec<Value>, items: &[Value]) {
+    let merge_key = LIST_MERGE_KEYS
+        .iter()
+        .find(|(name, _)| *name == field)
+        .and_then(|(_, candidates)| {
+            candidates
+                .iter()
+                .find(|candidate| items.iter().all(|item| item.get(**candidate).is_some()))
+        });
+
+    let Some(merge_key) = merge_key else {
+        *existing = items.iter().map(without_directives).collect();
+        return;
+    };
+
+    for item in items {
+        let id = item.get(*merge_key);
+        let delete = item.get(PATCH_DIRECTIVE).and_then(Value::as_str) == Some("delete");
+        let position = existing
+            .iter()
+            .position(|candidate| candidate.get(*merge_key) == id);
+
+        match (position, delete) {
+            (Some(index), true) => {
+                existing.remove(index);
+            }
+            (Some(index), false) => strategic_merge(&mut existing[index], item),
+            (None, true) => {}
+            (None, false) => existing.push(without_directives(item)),
+        }
+    }
+}
+
+fn without_directives(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(key, _)| key.as_str() != PATCH_DIRECTIVE)
+                .map(|(key, value)| (key.clone(), without_directives(value)))
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(without_directives).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENT: &str = r#"apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: api
+spec:
+  replicas: 1
+  template:
+    spec:
+      containers:
+        - name: api
+          image: api:1.0.0
+          env:
+            - name: LOG_LEVEL
+              value: info
+            - name: DEBUG
+              value: "1"
+        - name: sidecar
+          image: proxy:2
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: api
+spec:
+  ports:
+    - port: 80
+"#;
+
+    fn overlay_set(files: &[(&str, &str)]) -> OverlaySet {
+        let overlays = files
+            .iter()
+            .flat_map(|(source, content)| parse_overlays(source, content).unwrap())
+            .collect();
+        OverlaySet::new("api", overlays)
+    }
+
+    fn documents(content: &str) -> Vec<Value> {
+        parse_documents(content).unwrap()
+    }
+
+    #[test]
+    fn strategic_merge_patches_maps_and_keyed_lists() {
+        let mut overlays = overlay_set(&[(
+            "replicas.yaml",
+            r#"apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: api
+  annotations:
+    team: core
+spec:
+  replicas: 3
+  template:
+    spec:
+      containers:
+        - name: api
+          env:
+            - name: DEBUG
+              $patch: delete
+            - name: REGION
+              value: eu
+        - name: sidecar
+          $patch: delete
+"#,
+        )]);
+
+        let patched = documents(&overlays.apply(DEPLOYMENT).unwrap());
+
+        assert_eq!(patched[0]["spec"]["replicas"], 3);
+        assert_eq!(patched[0]["metadata"]["annotations"]["team"], "core");
+        assert_eq!(
+            patched[0]["spec"]["template"]["spec"]["containers"],
+            serde_json::json!([{
+                "name": "api",
+                "image": "api:1.0.0",
+                "env": [
+                    {"name": "LOG_LEVEL", "value": "info"},
+                    {"name": "REGION", "value": "eu"}
+                ]
+            }])
+        );
+        assert_eq!(patched[1], documents(DEPLOYMENT)[1]);
+        overlays.finish().unwrap();
+    }
+
+    #[test]
+    fn targets_match_the_namespace_generation_assigns() {
+        let mut overlays = overlay_set(&[(
+            "service.yaml",
+            r#"target:
+  kind: Service
+  name: api
+  namespace: shop
+patch:
+  - op: replace
+    path: /spec/ports/0/port
+    value: 8080
+"#,
+        )]);
+
+        let content = crate::templates::namespaces::set_namespace(DEPLOYMENT, "shop").unwrap();
+        let patched = documents(&overlays.apply(&content).unwrap());
+
+        assert_eq!(patched[1]["spec"]["ports"][0]["port"], 8080);
+        overlays.finish().unwrap();
+    }
+
+    #[test]
+    fn json6902_patches_apply_to_their_target() {
+        let mut overlays = overlay_set(&[(
+            "service.yaml",
+            r#"target:
+  kind: Service
+  name: api
+patch:
+  - op: replace
+    path: /spec/ports/0/port
+    value: 8080
+  - op: add
+    path: /metadata/labels
+    value:
+      tier: web
+"#,
+        )]);
+
+        let patched = documents(&overlays.apply(DEPLOYMENT).unwrap());
+
+        assert_eq!(patched[1]["spec"]["ports"][0]["port"], 8080);
+        assert_eq!(patched[1]["metadata"]["labels"]["tier"], "web");
+        assert_eq!(patched[0], documents(DEPLOYMENT)[0]);
+    }
+
+    #[test]
+    fn unmatched_overlays_are_reported() {
+        let mut overlays = overlay_set(&[(
+            "typo.yaml",
+            "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: apii\nspec:\n  replicas: 2\n",
+        )]);
+
+        assert_eq!(overlays.apply(DEPLOYMENT).unwrap(), DEPLOYMENT);
+        let err = overlays.finish().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Overlay typo.yaml did not match any resource of service 'api'"
+        );
+    }
+
+    #[test]
+    fn rejects_patches_without_a_target() {
+        let err = parse_overlays("bad.yaml", "spec:\n  replicas: 2\n").unwrap_err();
+
+        assert!(matches!(err, OverlayError::Invalid { .. }));
+    }
+
+    #[test]
+    fn loads_overlay_files_in_name_order() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("prod").join("api");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("20-replicas.yaml"),
+            "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\nspec:\n  replicas: 5\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("10-replicas.yaml"),
+            "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\nspec:\n  replicas: 2\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("README.md"), "ignored").unwrap();
+
+        let mut overlays = OverlaySet::load_from(root.path(), "prod", "api").unwrap();
+        let patched = documents(&overlays.apply(DEPLOYMENT).unwrap());
+
+        assert_eq!(patched[0]["spec"]["replicas"], 5);
+        assert!(OverlaySet::load_from(root.path(), "dev", "api")
+            .unwrap()
+            .is_empty());
+    }
+}
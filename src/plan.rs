@@ -5,8 +5,11 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use walkdir::WalkDir;
 
-use crate::deployment::k8sm8::get_cluster_resources;
+use crate::deployment::generated_objects;
+use crate::deployment::inventory::{prunable_objects, InventoryEntry};
+use crate::deployment::k8sm8::{create_client, get_cluster_resources};
 use crate::environment::Environment;
 use crate::LOGGER;
 
@@ -153,6 +156,10 @@ impl DeploymentPlan {
                 &change.action,
                 &format!("{}/{}{}", change.resource_type, change.name, namespace_str),
             );
+
+            for detail in &change.details {
+                LOGGER.println(&format!("    {}", detail));
+            }
         }
 
         LOGGER.println("");
@@ -176,10 +183,13 @@ fn log_change(action: &ChangeAction, msg: &str) {
     LOGGER.println(&format!("  {} {}", styled_symbol, msg));
 }
 
+/// Compares the generated manifests of `env_name` with the cluster. With `prune`, the
+/// objects earlier deploys applied that are no longer generated are listed as deletions.
 pub async fn generate_deployment_plan(
     env_name: &str,
     context: &str,
     namespace: &str,
+    prune: bool,
 ) -> Result<DeploymentPlan> {
     let mut plan = DeploymentPlan::new();
 
@@ -216,9 +226,45 @@ pub async fn generate_deployment_plan(
         analyze_service_manifests(&mut plan, &service_path, &service.name, &current_resources)?;
     }
 
+    if prune {
+        for change in prune_changes(&env, context).await? {
+            plan.add_change(change);
+        }
+    }
+
     Ok(plan)
 }
 
+// The deletions `deploy --prune` would make.
+async fn prune_changes(env: &Environment, context: &str) -> Result<Vec<ResourceChange>> {
+    let client = create_client(context.to_string()).await?;
+    let discovery = kube::Discovery::new(client.clone()).run().await?;
+    let current = generated_objects(env).await?;
+    let stale = prunable_objects(
+        client,
+        &discovery,
+        &env.name,
+        &env.default_namespace(),
+        &current,
+    )
+    .await?;
+    Ok(delete_changes(stale))
+}
+
+// A deletion for each of the `stale` objects.
+fn delete_changes(stale: Vec<InventoryEntry>) -> Vec<ResourceChange> {
+    stale
+        .into_iter()
+        .map(|entry| ResourceChange {
+            action: ChangeAction::Delete,
+            resource_type: entry.kind,
+            name: entry.name,
+            namespace: entry.namespace,
+            details: vec!["no longer generated".to_string()],
+        })
+        .collect()
+}
+
 async fn get_current_cluster_resources(
     context: &str,
     namespace: &str,
@@ -405,21 +451,23 @@ fn analyze_service_manifests(
     service_name: &str,
     current_resources: &HashMap<String, Value>,
 ) -> Result<()> {
-    let manifest_files = [
-        "deployment.yaml",
-        "service.yaml",
-        "configmap.yaml",
-        "secret.yaml",
-        "ingress.yaml",
-        "hpa.yaml",
-    ];
-
-    for manifest_file in &manifest_files {
-        let manifest_path = Path::new(service_path).join(manifest_file);
-
-        if manifest_path.exists() {
-            analyze_manifest_file(plan, &manifest_path, service_name, current_resources)?;
-        }
+    // Only the service's own directory; nested services are analyzed on their own.
+    let manifest_paths: Vec<_> = WalkDir::new(service_path)
+        .max_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext == "yaml" || ext == "yml")
+        })
+        .collect();
+
+    for manifest_path in &manifest_paths {
+        analyze_manifest_file(plan, manifest_path, service_name, current_resources)?;
     }
 
     Ok(())
@@ -838,3 +886,47 @@ pub fn validate_plan_safety(plan: &DeploymentPlan) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::deployment::inventory::prunable_entries;
+    use crate::templates::labels::{ENVIRONMENT_LABEL, MANAGED_BY, MANAGED_BY_LABEL};
+
+    fn entry(kind: &str, name: &str) -> InventoryEntry {
+        InventoryEntry {
+            api_version: "v1".to_string(),
+            kind: kind.to_string(),
+            namespace: Some("staging".to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn prune_deletes_only_stale_objects() {
+        let previous = vec![
+            entry("Service", "api"),
+            entry("ConfigMap", "api-config"),
+            entry("Namespace", "staging"),
+        ];
+        let current = vec![entry("Service", "api")];
+        let owned = BTreeMap::from([
+            (MANAGED_BY_LABEL.to_string(), MANAGED_BY.to_string()),
+            (ENVIRONMENT_LABEL.to_string(), "staging".to_string()),
+        ]);
+        let live = previous
+            .iter()
+            .map(|entry| (entry.clone(), owned.clone()))
+            .collect();
+
+        let changes = delete_changes(prunable_entries(&previous, &current, "staging", &live));
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].action, ChangeAction::Delete);
+        assert_eq!(changes[0].resource_type, "ConfigMap");
+        assert_eq!(changes[0].name, "api-config");
+        assert_eq!(changes[0].namespace.as_deref(), Some("staging"));
+    }
+}
@@ -0,0 +1,277 @@
+//! JSON Schemas for `config.toml` and `sailr.workflow.toml`, so editors such as VS Code
+//! (through Taplo) can complete keys and flag mistakes.
+//!
+//! The schemas are derived from the serde types and then tightened to what schema
+//! 0.5.0 accepts: `schema_version` must be `0.5.0`, unknown keys are rejected, which
+//! rules out `[[service_whitelist]]` and `[build.rooms]`, and the serde aliases are
+//! listed as keys of their own.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use serde_json::{json, Map, Value};
+
+use crate::{environment::Environment, workflow::config::WorkflowConfig};
+
+/// Directory `sailr` writes the schemas referenced by `#:schema` hints to.
+pub const SCHEMA_DIR: &str = "./k8s/schemas";
+
+pub const ENVIRONMENT_SCHEMA_FILE: &str = "environment.schema.json";
+
+/// The `#:schema` hint written at the top of `k8s/environments/<env>/config.toml`.
+pub const ENVIRONMENT_SCHEMA_HINT: &str = "#:schema ../../schemas/environment.schema.json";
+
+const SCHEMA_VERSION: &str = "0.5.0";
+
+// Keys serde accepts under another name, per definition: (definition, alias, key).
+const ALIASES: &[(&str, &str, &str)] = &[
+    ("BuildPolicy", "beforeAll", "before_all"),
+    ("BuildPolicy", "afterAll", "after_all"),
+    ("Service", "template_path", "path"),
+    ("ServiceBuildConfig", "depends_on", "relies_on"),
+    (
+        "ServiceBuildConfig",
+        "beforeSynchronous",
+        "before_synchronous",
+    ),
+    ("ServiceBuildConfig", "runParallel", "run_parallel"),
+    ("ServiceBuildConfig", "runSynchronous", "run_synchronous"),
+];
+
+// TOML has no null, so optional keys are simply left out of `required`.
+fn generator() -> SchemaGenerator {
+    SchemaSettings::draft07()
+        .with(|settings| {
+            settings.option_nullable = false;
+            settings.option_add_null_type = false;
+        })
+        .into_generator()
+}
+
+/// The schema of an environment's `config.toml`.
+pub fn environment_schema() -> Value {
+    let mut generator = generator();
+    let workflow = generator.subschema_for::<WorkflowConfig>();
+    let root = generator.into_root_schema_for::<Environment>();
+    let mut schema = serde_json::to_value(root).unwrap_or(Value::Null);
+
+    if let Some(properties) = schema["properties"].as_object_mut() {
+        properties.insert(
+            "schema_version".to_string(),
+            json!({
+                "description": "Version of the config format. Run `sailr migrate` to upgrade older files.",
+                "type": "string",
+                "const": SCHEMA_VERSION,
+            }),
+        );
+        properties.insert(
+            "extends".to_string(),
+            json!({
+                "description": "Environment this one is layered on. Keys set here override the base environment's.",
+                "type": "string",
+            }),
+        );
+        // Workflow profiles may also be kept alongside the environment.
+        if let Ok(workflow) = serde_json::to_value(workflow) {
+            properties.insert(
+                "workflow".to_string(),
+                json!({
+                    "description": "Workflow profiles, as in sailr.workflow.toml.",
+                    "allOf": [workflow],
+                }),
+            );
+        }
+    }
+
+    // Entries of the named arrays can drop an inherited entry, and the arrays can be
+    // written as `{ replace = true, items = [...] }` to drop every inherited entry.
+    for definition in ["Service", "EnvironmentVariable"] {
+        if let Some(properties) = schema["definitions"][definition]["properties"].as_object_mut() {
+            properties.insert(
+                "remove".to_string(),
+                json!({
+                    "description": "Drop the inherited entry with this name.",
+                    "type": "boolean",
+                }),
+            );
+        }
+    }
+    if let Some(properties) = schema["properties"].as_object_mut() {
+        for key in ["service", "environment_variables"] {
+            if let Some(list) = properties.remove(key) {
+                let items = json!({
+                    "type": "array",
+                    "items": list["items"].clone(),
+                });
+                properties.insert(
+                    key.to_string(),
+                    json!({
+                        "anyOf": [
+                            list,
+                            {
+                                "type": "object",
+                                "properties": {
+                                    "replace": {
+                                        "description": "Drop every inherited entry before adding `items`.",
+                                        "type": "boolean",
+                                    },
+                                    "items": items,
+                                },
+                            },
+                        ],
+                    }),
+                );
+            }
+        }
+    }
+
+    // An environment that extends another only has to set what it overrides.
+    if let Some(required) = schema
+        .as_object_mut()
+        .and_then(|root| root.remove("required"))
+    {
+        schema["anyOf"] = json!([{ "required": ["extends"] }, { "required": required }]);
+    }
+
+    tighten(&mut schema);
+    schema
+}
+
+/// The schema of `sailr.workflow.toml`.
+pub fn workflow_schema() -> Value {
+    let root = generator().into_root_schema_for::<WorkflowConfig>();
+    let mut schema = serde_json::to_value(root).unwrap_or(Value::Null);
+    tighten(&mut schema);
+    schema
+}
+
+// Adds the aliases, rejects unknown keys in every table and drops `null` defaults.
+fn tighten(schema: &mut Value) {
+    if let Some(definitions) = schema["definitions"].as_object_mut() {
+        for (definition, alias, key) in ALIASES {
+            let Some(properties) = definitions
+                .get_mut(*definition)
+                .and_then(|definition| definition["properties"].as_object_mut())
+            else {
+                continue;
+            };
+            if let Some(property) = properties.get(*key).cloned() {
+                properties.insert(alias.to_string(), alias_of(key, property));
+            }
+        }
+    }
+    deny_unknown_keys(schema);
+}
+
+fn alias_of(key: &str, property: Value) -> Value {
+    let mut alias = Map::new();
+    alias.insert(
+        "description".to_string(),
+        json!(format!("Alias of `{}`.", key)),
+    );
+    alias.insert("allOf".to_string(), json!([property]));
+    Value::Object(alias)
+}
+
+fn deny_unknown_keys(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            if object.contains_key("properties") && !object.contains_key("additionalProperties") {
+                object.insert("additionalProperties".to_string(), json!(false));
+            }
+            if object.get("default").is_some_and(Value::is_null) {
+                object.remove("default");
+            }
+            for value in object.values_mut() {
+                deny_unknown_keys(value);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(deny_unknown_keys),
+        _ => {}
+    }
+}
+
+// Writes the environment schema to `dir`, returning its path. The file is rewritten on
+// every call so it always matches the running sailr.
+pub fn write_environment_schema(dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(ENVIRONMENT_SCHEMA_FILE);
+    let content = serde_json::to_string_pretty(&environment_schema())?;
+    fs::write(&path, content + "\n")?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Follows `$ref`s, including those wrapped in `allOf`, into the definitions.
+    fn resolve<'a>(schema: &'a Value, value: &'a Value) -> &'a Value {
+        let value = match value["allOf"].as_array() {
+            Some(all_of) if all_of.len() == 1 => &all_of[0],
+            _ => value,
+        };
+        match value["$ref"].as_str() {
+            Some(reference) => {
+                let name = reference.trim_start_matches("#/definitions/");
+                &schema["definitions"][name]
+            }
+            None => value,
+        }
+    }
+
+    #[test]
+    fn environment_schema_describes_the_050_format() {
+        let schema = environment_schema();
+
+        assert_eq!(schema["properties"]["schema_version"]["const"], "0.5.0");
+        assert_eq!(schema["additionalProperties"], false);
+        assert!(schema["properties"].get("service").is_some());
+        assert_eq!(
+            schema["properties"]["environment_variables"]["anyOf"][1]["additionalProperties"],
+            false
+        );
+        assert!(schema["properties"].get("service_whitelist").is_none());
+        assert!(schema["properties"].get("extends").is_some());
+        assert!(schema["properties"].get("workflow").is_some());
+        assert!(schema.get("required").is_none());
+        assert_eq!(schema["anyOf"][0]["required"], json!(["extends"]));
+
+        let build_policy = resolve(&schema, &schema["properties"]["build"]);
+        assert_eq!(build_policy["additionalProperties"], false);
+        assert!(build_policy["properties"].get("rooms").is_none());
+        assert!(build_policy["properties"].get("beforeAll").is_some());
+
+        let service = &schema["definitions"]["Service"];
+        assert!(service["properties"].get("template_path").is_some());
+        assert_eq!(service["properties"]["remove"]["type"], "boolean");
+        assert_eq!(service["required"], json!(["name"]));
+        let build = resolve(&schema, &service["properties"]["build"]);
+        assert_eq!(build["anyOf"][0]["type"], "string");
+
+        let engine = &schema["definitions"]["BuildEngine"];
+        assert_eq!(engine["enum"], json!(["roomservice", "runkernel"]));
+    }
+
+    #[test]
+    fn workflow_schema_lists_profile_modes() {
+        let schema = workflow_schema();
+        let profile = resolve(
+            &schema,
+            &schema["properties"]["workflow"]["additionalProperties"],
+        );
+
+        assert_eq!(profile["additionalProperties"], false);
+        assert!(profile["properties"].get("name").is_none());
+        assert_eq!(profile["required"], json!(["environment", "mode"]));
+        let mode = resolve(&schema, &profile["properties"]["mode"]);
+        assert!(mode["enum"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("rollback")));
+        assert!(!schema.to_string().contains("\"default\":null"));
+    }
+}
@@ -0,0 +1,701 @@
+//! Encrypted secrets stored next to an environment.
+//!
+//! Secrets live in `k8s/environments/<env>/secrets.enc.toml`, grouped by the name of
+//! the Kubernetes Secret they belong to, optionally qualified by its namespace as
+//! `<namespace>/<secret>`. An unqualified Secret is the one in the environment's
+//! default namespace. Every value is encrypted on its own with AES-256-GCM, using the
+//! environment name and its reference as associated data, so values cannot be swapped
+//! between entries or copied from one environment's file into another's. The file is
+//! safe to commit; the key is not.
+//!
+//! The key is a base64 encoded 32-byte value, read from `SAILR_SECRETS_KEY` or from a
+//! key file (`--key-file` or `SAILR_SECRETS_KEY_FILE`). Decrypted values only exist in
+//! memory: `sailr deploy` injects them into the generated Secret manifests right before
+//! applying them, and nothing is written to `k8s/generated`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{errors::SecretsError, overlays::parse_documents};
+
+/// Name of the encrypted secrets file inside an environment directory.
+pub const SECRETS_FILE: &str = "secrets.enc.toml";
+/// Environment variable holding the base64 encoded key.
+pub const KEY_ENV: &str = "SAILR_SECRETS_KEY";
+/// Environment variable holding the path of a key file.
+pub const KEY_FILE_ENV: &str = "SAILR_SECRETS_KEY_FILE";
+
+const CIPHER: &str = "aes-256-gcm";
+const FORMAT_VERSION: u32 = 1;
+const NONCE_LENGTH: usize = 12;
+const HEADER: &str =
+    "# Encrypted by `sailr secrets`. Change it with `sailr secrets set` and `sailr secrets rm`.\n";
+
+/// A 256-bit AES-GCM key.
+#[derive(Clone)]
+pub struct SecretKey([u8; 32]);
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SecretKey({})", self.id())
+    }
+}
+
+impl SecretKey {
+    pub fn generate() -> SecretKey {
+        SecretKey(Aes256Gcm::generate_key(OsRng).into())
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<SecretKey, SecretsError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| SecretsError::InvalidKey(e.to_string()))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            SecretsError::InvalidKey(format!("expected 32 bytes, got {}", bytes.len()))
+        })?;
+        Ok(SecretKey(bytes))
+    }
+
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.0)
+    }
+
+    pub fn from_file(path: &Path) -> Result<SecretKey, SecretsError> {
+        let encoded = fs::read_to_string(path).map_err(|e| SecretsError::Read {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        })?;
+        SecretKey::from_base64(&encoded)
+    }
+
+    // Writes the key to `path`, readable by the current user only.
+    pub fn write_file(&self, path: &Path) -> Result<(), SecretsError> {
+        let write_error = |e: std::io::Error| SecretsError::Write {
+            path: path.display().to_string(),
+            message: e.to_string(),
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(write_error)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path).map_err(write_error)?;
+        std::io::Write::write_all(&mut file, format!("{}\n", self.to_base64()).as_bytes())
+            .map_err(write_error)
+    }
+
+    // Finds the key for `env`: an explicit key file first, then `SAILR_SECRETS_KEY`,
+    // then `SAILR_SECRETS_KEY_FILE`.
+    pub fn resolve(env: &str, key_file: Option<&Path>) -> Result<SecretKey, SecretsError> {
+        if let Some(path) = key_file {
+            return SecretKey::from_file(path);
+        }
+        if let Ok(encoded) = std::env::var(KEY_ENV) {
+            return SecretKey::from_base64(&encoded);
+        }
+        if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+            return SecretKey::from_file(Path::new(&path));
+        }
+        Err(SecretsError::MissingKey {
+            env: env.to_string(),
+        })
+    }
+
+    /// A short fingerprint stored in the secrets file to detect the wrong key early.
+    pub fn id(&self) -> String {
+        let digest = Sha256::digest(self.0);
+        digest
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+struct SecretsFile {
+    version: u32,
+    cipher: String,
+    key_id: String,
+    #[serde(default)]
+    secrets: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Splits a `[<namespace>/]<secret>/<key>` reference into the Secret, namespace
+/// included, and the key.
+pub fn parse_reference(reference: &str) -> Result<(&str, &str), SecretsError> {
+    match reference.rsplit_once('/') {
+        Some((secret, key))
+            if !key.is_empty()
+                && secret.split('/').count() <= 2
+                && secret.split('/').all(|part| !part.is_empty()) =>
+        {
+            Ok((secret, key))
+        }
+        _ => Err(SecretsError::InvalidReference(reference.to_string())),
+    }
+}
+
+/// The encrypted secrets of one environment.
+#[derive(Debug, Clone)]
+pub struct SecretStore {
+    env: String,
+    path: PathBuf,
+    file: Option<SecretsFile>,
+}
+
+impl SecretStore {
+    pub fn path_for(env: &str) -> PathBuf {
+        Path::new("./k8s/environments").join(env).join(SECRETS_FILE)
+    }
+
+    pub fn load(env: &str) -> Result<SecretStore, SecretsError> {
+        SecretStore::load_from(env, &SecretStore::path_for(env))
+    }
+
+    // Loads the store of `env` at `path`. A missing file is an empty store.
+    pub fn load_from(env: &str, path: &Path) -> Result<SecretStore, SecretsError> {
+        if !path.exists() {
+            return Ok(SecretStore {
+                env: env.to_string(),
+                path: path.to_path_buf(),
+                file: None,
+            });
+        }
+        let read_error = |message: String| SecretsError::Read {
+            path: path.display().to_string(),
+            message,
+        };
+        let content = fs::read_to_string(path).map_err(|e| read_error(e.to_string()))?;
+        let file: SecretsFile = toml::from_str(&content).map_err(|e| read_error(e.to_string()))?;
+        if file.cipher != CIPHER {
+            return Err(SecretsError::UnsupportedCipher(file.cipher));
+        }
+        Ok(SecretStore {
+            env: env.to_string(),
+            path: path.to_path_buf(),
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file
+            .as_ref()
+            .is_none_or(|file| file.secrets.values().all(|keys| keys.is_empty()))
+    }
+
+    /// Every stored reference as `<secret>/<key>`, in order.
+    pub fn references(&self) -> Vec<String> {
+        self.file
+            .iter()
+            .flat_map(|file| file.secrets.iter())
+            .flat_map(|(secret, keys)| keys.keys().map(move |key| format!("{}/{}", secret, key)))
+            .collect()
+    }
+
+    pub fn set(
+        &mut self,
+        key: &SecretKey,
+        secret: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<(), SecretsError> {
+        self.check_key(key)?;
+        let encrypted = encrypt(key, &self.env, secret, name, value)?;
+        let file = self.file.get_or_insert_with(|| SecretsFile {
+            version: FORMAT_VERSION,
+            cipher: CIPHER.to_string(),
+            key_id: key.id(),
+            secrets: BTreeMap::new(),
+        });
+        file.secrets
+            .entry(secret.to_string())
+            .or_default()
+            .insert(name.to_string(), encrypted);
+        Ok(())
+    }
+
+    pub fn get(
+        &self,
+        key: &SecretKey,
+        secret: &str,
+        name: &str,
+    ) -> Result<Option<String>, SecretsError> {
+        self.check_key(key)?;
+        let Some(encrypted) = self
+            .file
+            .as_ref()
+            .and_then(|file| file.secrets.get(secret))
+            .and_then(|keys| keys.get(name))
+        else {
+            return Ok(None);
+        };
+        decrypt(key, &self.env, secret, name, encrypted).map(Some)
+    }
+
+    // Removes a value, and its Secret once it has no values left. Returns whether
+    // anything was removed. No key is needed.
+    pub fn remove(&mut self, secret: &str, name: &str) -> bool {
+        let Some(file) = self.file.as_mut() else {
+            return false;
+        };
+        let Some(keys) = file.secrets.get_mut(secret) else {
+            return false;
+        };
+        let removed = keys.remove(name).is_some();
+        if keys.is_empty() {
+            file.secrets.remove(secret);
+        }
+        removed
+    }
+
+    // Re-encrypts every value with `new_key`. Returns the number of values rotated.
+    // `old_key` is not checked when there is nothing to decrypt.
+    pub fn rotate(
+        &mut self,
+        old_key: &SecretKey,
+        new_key: &SecretKey,
+    ) -> Result<usize, SecretsError> {
+        let decrypted = if self.is_empty() {
+            DecryptedSecrets::default()
+        } else {
+            self.decrypt_all(old_key)?
+        };
+        let mut file = SecretsFile {
+            version: FORMAT_VERSION,
+            cipher: CIPHER.to_string(),
+            key_id: new_key.id(),
+            secrets: BTreeMap::new(),
+        };
+        let mut count = 0;
+        for (secret, keys) in &decrypted.secrets {
+            for (name, value) in keys {
+                file.secrets.entry(secret.clone()).or_default().insert(
+                    name.clone(),
+                    encrypt(new_key, &self.env, secret, name, value)?,
+                );
+                count += 1;
+            }
+        }
+        self.file = Some(file);
+        Ok(count)
+    }
+
+    pub fn decrypt_all(&self, key: &SecretKey) -> Result<DecryptedSecrets, SecretsError> {
+        self.check_key(key)?;
+        let mut secrets = BTreeMap::new();
+        for (secret, keys) in self.file.iter().flat_map(|file| file.secrets.iter()) {
+            let mut values = BTreeMap::new();
+            for (name, encrypted) in keys {
+                values.insert(
+                    name.clone(),
+                    decrypt(key, &self.env, secret, name, encrypted)?,
+                );
+            }
+            secrets.insert(secret.clone(), values);
+        }
+        Ok(DecryptedSecrets {
+            namespace: crate::templates::namespaces::environment_namespace(&self.env),
+            secrets,
+            injected: BTreeSet::new(),
+        })
+    }
+
+    pub fn save(&self) -> Result<(), SecretsError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let write_error = |message: String| SecretsError::Write {
+            path: self.path.display().to_string(),
+            message,
+        };
+        let content = toml::to_string(file).map_err(|e| write_error(e.to_string()))?;
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| write_error(e.to_string()))?;
+        }
+        fs::write(&self.path, format!("{}{}", HEADER, content))
+            .map_err(|e| write_error(e.to_string()))
+    }
+
+    fn check_key(&self, key: &SecretKey) -> Result<(), SecretsError> {
+        match &self.file {
+            Some(file) if file.key_id != key.id() => Err(SecretsError::KeyMismatch {
+                path: self.path.display().to_string(),
+                expected: file.key_id.clone(),
+                actual: key.id(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The associated data of a value: the environment and the value's reference.
+fn associated_data(env: &str, secret: &str, name: &str) -> String {
+    format!("{}/{}/{}", env, secret, name)
+}
+
+fn encrypt(
+    key: &SecretKey,
+    env: &str,
+    secret: &str,
+    name: &str,
+    value: &str,
+) -> Result<String, SecretsError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let aad = associated_data(env, secret, name);
+    let ciphertext = key
+        .cipher()
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: value.as_bytes(),
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| SecretsError::Encrypt {
+            secret: secret.to_string(),
+            key: name.to_string(),
+        })?;
+    let mut bytes = nonce.to_vec();
+    bytes.extend(ciphertext);
+    Ok(STANDARD.encode(bytes))
+}
+
+fn decrypt(
+    key: &SecretKey,
+    env: &str,
+    secret: &str,
+    name: &str,
+    encrypted: &str,
+) -> Result<String, SecretsError> {
+    let error = || SecretsError::Decrypt {
+        secret: secret.to_string(),
+        key: name.to_string(),
+    };
+    let bytes = STANDARD.decode(encrypted).map_err(|_| error())?;
+    if bytes.len() < NONCE_LENGTH {
+        return Err(error());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    let aad = associated_data(env, secret, name);
+    let plaintext = key
+        .cipher()
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| error())?;
+    String::from_utf8(plaintext).map_err(|_| error())
+}
+
+/// Decrypted values, ready to be injected into Secret manifests. Never written to disk.
+#[derive(Default)]
+pub struct DecryptedSecrets {
+    // The namespace of Secrets stored without one: the environment's default.
+    namespace: String,
+    secrets: BTreeMap<String, BTreeMap<String, String>>,
+    injected: BTreeSet<String>,
+}
+
+impl DecryptedSecrets {
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    /// The number of stored values.
+    pub fn len(&self) -> usize {
+        self.secrets.values().map(BTreeMap::len).sum()
+    }
+
+    /// Fills `data` of every Secret in `content` that has stored values for its
+    /// namespace and name. The content is returned untouched when it holds no such
+    /// Secret.
+    pub fn inject(&mut self, content: &str) -> Result<String, SecretsError> {
+        if self.secrets.is_empty() {
+            return Ok(content.to_string());
+        }
+        let inject_error = |message: String| SecretsError::Inject {
+            resource: "manifest".to_string(),
+            message,
+        };
+        let mut documents = parse_documents(content).map_err(inject_error)?;
+        let mut changed = false;
+
+        for document in &mut documents {
+            if document["kind"].as_str() != Some("Secret") {
+                continue;
+            }
+            let Some(name) = document["metadata"]["name"].as_str() else {
+                continue;
+            };
+            let namespace = document["metadata"]["namespace"]
+                .as_str()
+                .unwrap_or("default");
+            // Unqualified values first, so those stored for the namespace by name win.
+            let mut entries = Vec::new();
+            if namespace == self.namespace {
+                entries.push(name.to_string());
+            }
+            entries.push(format!("{}/{}", namespace, name));
+            let entries = entries
+                .into_iter()
+                .filter(|entry| self.secrets.contains_key(entry))
+                .collect::<Vec<_>>();
+            if entries.is_empty() {
+                continue;
+            }
+            let Some(object) = document.as_object_mut() else {
+                continue;
+            };
+            if !object.get("data").is_some_and(Value::is_object) {
+                object.insert("data".to_string(), Value::Object(Default::default()));
+            }
+            for entry in entries {
+                for (key, value) in &self.secrets[&entry] {
+                    object["data"][key] = Value::String(STANDARD.encode(value));
+                    if let Some(string_data) =
+                        object.get_mut("stringData").and_then(Value::as_object_mut)
+                    {
+                        string_data.remove(key);
+                    }
+                }
+                self.injected.insert(entry);
+            }
+            changed = true;
+        }
+
+        if !changed {
+            return Ok(content.to_string());
+        }
+        documents
+            .iter()
+            .map(|document| {
+                serde_yaml::to_string(document).map_err(|e| inject_error(e.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|documents| documents.join("---\n"))
+    }
+
+    /// Secrets with stored values that no manifest has asked for so far.
+    pub fn not_injected(&self) -> Vec<&str> {
+        self.secrets
+            .keys()
+            .filter(|secret| !self.injected.contains(*secret))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (tempfile::TempDir, SecretStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SecretStore::load_from("staging", &dir.path().join(SECRETS_FILE)).unwrap();
+        (dir, store)
+    }
+
+    #[test]
+    fn set_get_and_remove_round_trip_through_the_file() {
+        let (_dir, mut store) = store();
+        let key = SecretKey::generate();
+        store
+            .set(&key, "api", "DATABASE_URL", "postgres://db")
+            .unwrap();
+        store.set(&key, "api", "JWT_SECRET", "hunter2").unwrap();
+        store.save().unwrap();
+
+        let content = fs::read_to_string(store.path()).unwrap();
+        assert!(!content.contains("postgres://db"));
+        assert!(!content.contains("hunter2"));
+
+        let mut store = SecretStore::load_from("staging", store.path()).unwrap();
+        assert_eq!(
+            store.references(),
+            vec!["api/DATABASE_URL", "api/JWT_SECRET"]
+        );
+        assert_eq!(
+            store.get(&key, "api", "DATABASE_URL").unwrap().as_deref(),
+            Some("postgres://db")
+        );
+        assert_eq!(store.get(&key, "api", "MISSING").unwrap(), None);
+
+        assert!(store.remove("api", "DATABASE_URL"));
+        assert!(!store.remove("api", "DATABASE_URL"));
+        assert!(store.remove("api", "JWT_SECRET"));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn rejects_the_wrong_key_and_tampered_values() {
+        let (_dir, mut store) = store();
+        let key = SecretKey::generate();
+        store.set(&key, "api", "TOKEN", "abc").unwrap();
+
+        assert!(matches!(
+            store.get(&SecretKey::generate(), "api", "TOKEN"),
+            Err(SecretsError::KeyMismatch { .. })
+        ));
+
+        // A value moved to another entry fails authentication.
+        let file = store.file.as_mut().unwrap();
+        let value = file.secrets["api"]["TOKEN"].clone();
+        file.secrets
+            .get_mut("api")
+            .unwrap()
+            .insert("OTHER".to_string(), value);
+        assert!(matches!(
+            store.get(&key, "api", "OTHER"),
+            Err(SecretsError::Decrypt { .. })
+        ));
+
+        // So does a file copied into another environment.
+        store.save().unwrap();
+        let copied = SecretStore::load_from("production", store.path()).unwrap();
+        assert!(matches!(
+            copied.get(&key, "api", "TOKEN"),
+            Err(SecretsError::Decrypt { .. })
+        ));
+    }
+
+    #[test]
+    fn rotate_re_encrypts_every_value() {
+        let (_dir, mut store) = store();
+        let old_key = SecretKey::generate();
+        let new_key = SecretKey::generate();
+        store.set(&old_key, "api", "TOKEN", "abc").unwrap();
+        store.set(&old_key, "worker", "QUEUE", "redis://q").unwrap();
+
+        assert_eq!(store.rotate(&old_key, &new_key).unwrap(), 2);
+        assert!(store.get(&old_key, "api", "TOKEN").is_err());
+        assert_eq!(
+            store.get(&new_key, "worker", "QUEUE").unwrap().as_deref(),
+            Some("redis://q")
+        );
+    }
+
+    #[test]
+    fn keys_round_trip_through_base64_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = SecretKey::generate();
+        let path = dir.path().join("keys/dev.key");
+        key.write_file(&path).unwrap();
+
+        assert_eq!(SecretKey::from_file(&path).unwrap().id(), key.id());
+        assert!(matches!(
+            SecretKey::from_base64("c2hvcnQ="),
+            Err(SecretsError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn injects_values_into_matching_secret_manifests() {
+        let (_dir, mut store) = store();
+        let key = SecretKey::generate();
+        store.set(&key, "api-secrets", "TOKEN", "abc").unwrap();
+        store.set(&key, "unused", "A", "b").unwrap();
+        let mut secrets = store.decrypt_all(&key).unwrap();
+
+        let manifest = "apiVersion: v1\nkind: Secret\nmetadata:\n  name: api-secrets\n  namespace: staging\ntype: Opaque\nstringData:\n  TOKEN: placeholder\n  KEEP: me\n---\napiVersion: v1\nkind: Service\nmetadata:\n  name: api-secrets\n  namespace: staging\n";
+        let injected = secrets.inject(manifest).unwrap();
+        let documents = parse_documents(&injected).unwrap();
+
+        assert_eq!(documents[0]["data"]["TOKEN"], "YWJj");
+        assert_eq!(documents[0]["stringData"]["KEEP"], "me");
+        assert!(documents[0]["stringData"].get("TOKEN").is_none());
+        assert!(documents[1].get("data").is_none());
+        assert_eq!(secrets.not_injected(), vec!["unused"]);
+
+        let service = "apiVersion: v1\nkind: Service\nmetadata:\n  name: web\n";
+        assert_eq!(secrets.inject(service).unwrap(), service);
+    }
+
+    #[test]
+    fn injects_only_into_secrets_of_the_matching_namespace() {
+        let (_dir, mut store) = store();
+        let key = SecretKey::generate();
+        store.set(&key, "db", "PASSWORD", "env").unwrap();
+        store.set(&key, "shop/db", "PASSWORD", "shop").unwrap();
+        let mut secrets = store.decrypt_all(&key).unwrap();
+
+        let secret = |namespace: &str| {
+            format!(
+                "apiVersion: v1\nkind: Secret\nmetadata:\n  name: db\n  namespace: {}\n",
+                namespace
+            )
+        };
+        let data = |content: String| parse_documents(&content).unwrap()[0]["data"].clone();
+
+        assert_eq!(
+            data(secrets.inject(&secret("staging")).unwrap())["PASSWORD"],
+            "ZW52"
+        );
+        assert_eq!(
+            data(secrets.inject(&secret("shop")).unwrap())["PASSWORD"],
+            "c2hvcA=="
+        );
+        let other = secret("billing");
+        assert_eq!(secrets.inject(&other).unwrap(), other);
+        assert!(secrets.not_injected().is_empty());
+    }
+
+    #[test]
+    fn injects_into_the_namespace_the_environment_name_becomes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store =
+            SecretStore::load_from("local_dev", &dir.path().join(SECRETS_FILE)).unwrap();
+        let key = SecretKey::generate();
+        store.set(&key, "db", "PASSWORD", "env").unwrap();
+        let mut secrets = store.decrypt_all(&key).unwrap();
+
+        let secret =
+            "apiVersion: v1\nkind: Secret\nmetadata:\n  name: db\n  namespace: local-dev\n";
+        let injected = parse_documents(&secrets.inject(secret).unwrap()).unwrap();
+
+        assert_eq!(injected[0]["data"]["PASSWORD"], "ZW52");
+        assert!(secrets.not_injected().is_empty());
+    }
+
+    #[test]
+    fn parses_references() {
+        assert_eq!(parse_reference("api/TOKEN").unwrap(), ("api", "TOKEN"));
+        assert_eq!(
+            parse_reference("shop/api/TOKEN").unwrap(),
+            ("shop/api", "TOKEN")
+        );
+        for reference in ["api", "/TOKEN", "api/", "/api/TOKEN", "a/b/c/d"] {
+            assert!(parse_reference(reference).is_err(), "{}", reference);
+        }
+    }
+}
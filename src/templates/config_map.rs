@@ -0,0 +1,461 @@
+//! Builds the ConfigMap generated from a service's `config/` directory.
+//!
+//! Every file under `config/` becomes one key. Files in subdirectories are flattened
+//! into a single key by joining the path components with `__`, so
+//! `config/nginx/conf.d/default.conf` becomes `nginx__conf.d__default.conf`. Files
+//! that are valid UTF-8 go to `data`, anything else to `binaryData`.
+//!
+//! With a hash suffix the ConfigMap is named `<name>-<hash>`, where the hash covers the
+//! content. References in the service's workloads are rewritten to the suffixed name
+//! with [`rewrite_references`], so a config change rolls the pods.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::errors::ConfigMapError;
+
+/// Largest ConfigMap the API server accepts, counting keys and values.
+pub const MAX_CONFIG_MAP_SIZE: usize = 1024 * 1024;
+
+/// Separator used to flatten nested `config/` paths into a single key.
+pub const KEY_SEPARATOR: &str = "__";
+
+const HASH_LENGTH: usize = 10;
+
+/// A ConfigMap ready to be written to `k8s/generated`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuiltConfigMap {
+    /// The name the ConfigMap was requested under, before any hash suffix.
+    pub base_name: String,
+    /// The name written to `metadata.name`.
+    pub name: String,
+    /// Every key, across `data` and `binaryData`, in sorted order.
+    pub keys: Vec<String>,
+    /// The rendered YAML document.
+    pub content: String,
+}
+
+impl BuiltConfigMap {
+    /// Whether references to `base_name` have to be rewritten to `name`.
+    pub fn is_renamed(&self) -> bool {
+        self.base_name != self.name
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConfigMapBuilder {
+    name: String,
+    namespace: Option<String>,
+    hash_suffix: bool,
+    data: BTreeMap<String, String>,
+    binary_data: BTreeMap<String, Vec<u8>>,
+    sources: BTreeMap<String, String>,
+}
+
+impl ConfigMapBuilder {
+    pub fn new(name: &str) -> ConfigMapBuilder {
+        ConfigMapBuilder {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Sets `metadata.namespace` on the ConfigMap.
+    pub fn namespace(mut self, namespace: &str) -> ConfigMapBuilder {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    // Appends a hash of the content to the ConfigMap name.
+    pub fn hash_suffix(mut self, enabled: bool) -> ConfigMapBuilder {
+        self.hash_suffix = enabled;
+        self
+    }
+
+    // Adds every file below `dir`, in path order. Nested paths are flattened into keys.
+    pub fn add_dir(&mut self, dir: &Path) -> Result<(), ConfigMapError> {
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry.map_err(|e| ConfigMapError::Read {
+                path: dir.display().to_string(),
+                message: e.to_string(),
+            })?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(dir)
+                .unwrap_or(entry.path())
+                .to_path_buf();
+            let content = fs::read(entry.path()).map_err(|e| ConfigMapError::Read {
+                path: entry.path().display().to_string(),
+                message: e.to_string(),
+            })?;
+            self.add_file(&relative, content)?;
+        }
+        Ok(())
+    }
+
+    // Adds a single file under the key derived from its path relative to `config/`.
+    pub fn add_file(&mut self, relative: &Path, content: Vec<u8>) -> Result<(), ConfigMapError> {
+        let source = relative.to_string_lossy().replace('\\', "/");
+        let key = flatten_key(relative);
+        validate_key(&key, &source)?;
+
+        if let Some(first) = self.sources.insert(key.clone(), source.clone()) {
+            return Err(ConfigMapError::DuplicateKey {
+                key,
+                first,
+                second: source,
+            });
+        }
+        match String::from_utf8(content) {
+            Ok(text) => {
+                self.data.insert(key, text);
+            }
+            Err(e) => {
+                self.binary_data.insert(key, e.into_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    // Size as counted by the API server: the length of every key and value.
+    pub fn size(&self) -> usize {
+        let data: usize = self.data.iter().map(|(k, v)| k.len() + v.len()).sum();
+        let binary: usize = self
+            .binary_data
+            .iter()
+            .map(|(k, v)| k.len() + v.len())
+            .sum();
+        data + binary
+    }
+
+    pub fn build(&self) -> Result<BuiltConfigMap, ConfigMapError> {
+        let size = self.size();
+        if size > MAX_CONFIG_MAP_SIZE {
+            return Err(ConfigMapError::TooLarge {
+                name: self.name.clone(),
+                size,
+                limit: MAX_CONFIG_MAP_SIZE,
+            });
+        }
+
+        let name = if self.hash_suffix {
+            format!("{}-{}", self.name, self.content_hash())
+        } else {
+            self.name.clone()
+        };
+
+        let mut metadata = Map::new();
+        metadata.insert("name".to_string(), Value::String(name.clone()));
+        if let Some(namespace) = &self.namespace {
+            metadata.insert("namespace".to_string(), Value::String(namespace.clone()));
+        }
+        let mut config_map = Map::new();
+        config_map.insert("apiVersion".to_string(), Value::from("v1"));
+        config_map.insert("kind".to_string(), Value::from("ConfigMap"));
+        config_map.insert("metadata".to_string(), Value::Object(metadata));
+        if !self.data.is_empty() {
+            config_map.insert("data".to_string(), serde_json::json!(self.data));
+        }
+        if !self.binary_data.is_empty() {
+            let binary_data = self
+                .binary_data
+                .iter()
+                .map(|(key, value)| (key.clone(), Value::String(STANDARD.encode(value))))
+                .collect();
+            config_map.insert("binaryData".to_string(), Value::Object(binary_data));
+        }
+        let content = serde_yaml::to_string(&config_map).map_err(|e| ConfigMapError::Render {
+            name: name.clone(),
+            message: e.to_string(),
+        })?;
+
+        Ok(BuiltConfigMap {
+            base_name: self.name.clone(),
+            name,
+            keys: self.sources.keys().cloned().collect(),
+            content,
+        })
+    }
+
+    // A short, stable hash of the keys and values. The name and namespace are left out
+    // so that only a content change produces a new name.
+    fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (key, value) in &self.data {
+            hasher.update(b"data\0");
+            hasher.update(key.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(value.as_bytes());
+            hasher.update(b"\0");
+        }
+        for (key, value) in &self.binary_data {
+            hasher.update(b"binaryData\0");
+            hasher.update(key.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(value);
+            hasher.update(b"\0");
+        }
+        let digest = hasher.finalize();
+        digest
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()[..HASH_LENGTH]
+            .to_string()
+    }
+}
+
+fn flatten_key(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(KEY_SEPARATOR)
+}
+
+// ConfigMap keys must be at most 253 characters of `[-._a-zA-Z0-9]`, and not `.` or `..`.
+fn validate_key(key: &str, source: &str) -> Result<(), ConfigMapError> {
+    let valid = !key.is_empty()
+        && key.len() <= 253
+        && key != "."
+        && key != ".."
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(ConfigMapError::InvalidKey {
+            key: key.to_string(),
+            path: source.to_string(),
+        })
+    }
+}
+
+/// Points every ConfigMap reference named `from` at `to`: `configMap` volumes and
+/// projections, `envFrom[].configMapRef` and `env[].valueFrom.configMapKeyRef`. The
+/// content is returned untouched when nothing refers to `from`.
+pub fn rewrite_references(content: &str, from: &str, to: &str) -> Result<String, ConfigMapError> {
+    let mut documents = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(content) {
+        let yaml =
+            serde_yaml::Value::deserialize(document).map_err(|e| ConfigMapError::Render {
+                name: from.to_string(),
+                message: e.to_string(),
+            })?;
+        let value = serde_json::to_value(yaml).map_err(|e| ConfigMapError::Render {
+            name: from.to_string(),
+            message: e.to_string(),
+        })?;
+        if !value.is_null() {
+            documents.push(value);
+        }
+    }
+
+    let mut changed = false;
+    for document in &mut documents {
+        changed |= rewrite_value(document, from, to);
+    }
+    if !changed {
+        return Ok(content.to_string());
+    }
+
+    documents
+        .iter()
+        .map(|document| {
+            serde_yaml::to_string(document).map_err(|e| ConfigMapError::Render {
+                name: from.to_string(),
+                message: e.to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|documents| documents.join("---\n"))
+}
+
+fn rewrite_value(value: &mut Value, from: &str, to: &str) -> bool {
+    let mut changed = false;
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if matches!(
+                    key.as_str(),
+                    "configMap" | "configMapRef" | "configMapKeyRef"
+                ) {
+                    if let Some(name) = child.get_mut("name") {
+                        if name.as_str() == Some(from) {
+                            *name = Value::String(to.to_string());
+                            changed = true;
+                        }
+                    }
+                }
+                changed |= rewrite_value(child, from, to);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                changed |= rewrite_value(item, from, to);
+            }
+        }
+        _ => {}
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents(content: &str) -> Vec<serde_yaml::Value> {
+        serde_yaml::Deserializer::from_str(content)
+            .map(|document| serde_yaml::Value::deserialize(document).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn builds_data_binary_data_and_flattened_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("nginx/conf.d")).unwrap();
+        fs::write(dir.path().join("app.env"), "A=1\nB=2\n").unwrap();
+        fs::write(dir.path().join("nginx/conf.d/default.conf"), "server {}\n").unwrap();
+        fs::write(dir.path().join("logo.png"), [0x89, 0x50, 0x4e, 0x47, 0xff]).unwrap();
+
+        let mut builder = ConfigMapBuilder::new("web").namespace("staging");
+        builder.add_dir(dir.path()).unwrap();
+        let built = builder.build().unwrap();
+
+        assert_eq!(built.name, "web");
+        assert!(!built.is_renamed());
+        assert_eq!(
+            built.keys,
+            vec!["app.env", "logo.png", "nginx__conf.d__default.conf"]
+        );
+
+        let document = &documents(&built.content)[0];
+        assert_eq!(document["kind"].as_str(), Some("ConfigMap"));
+        assert_eq!(document["metadata"]["namespace"].as_str(), Some("staging"));
+        assert_eq!(document["data"]["app.env"].as_str(), Some("A=1\nB=2\n"));
+        assert_eq!(
+            document["data"]["nginx__conf.d__default.conf"].as_str(),
+            Some("server {}\n")
+        );
+        assert_eq!(
+            document["binaryData"]["logo.png"].as_str(),
+            Some("iVBOR/8=")
+        );
+    }
+
+    #[test]
+    fn rejects_config_maps_over_the_size_limit() {
+        let mut builder = ConfigMapBuilder::new("big");
+        builder
+            .add_file(Path::new("blob.txt"), vec![b'a'; MAX_CONFIG_MAP_SIZE])
+            .unwrap();
+
+        assert!(matches!(
+            builder.build(),
+            Err(ConfigMapError::TooLarge { size, .. }) if size == MAX_CONFIG_MAP_SIZE + 8
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_and_colliding_keys() {
+        let mut builder = ConfigMapBuilder::new("web");
+        assert!(matches!(
+            builder.add_file(Path::new("my config.txt"), Vec::new()),
+            Err(ConfigMapError::InvalidKey { .. })
+        ));
+
+        builder
+            .add_file(Path::new("a/b.conf"), b"one".to_vec())
+            .unwrap();
+        assert!(matches!(
+            builder.add_file(Path::new("a__b.conf"), b"two".to_vec()),
+            Err(ConfigMapError::DuplicateKey { .. })
+        ));
+    }
+
+    #[test]
+    fn hash_suffix_follows_the_content() {
+        let build = |value: &str| {
+            let mut builder = ConfigMapBuilder::new("web").hash_suffix(true);
+            builder
+                .add_file(Path::new("app.env"), value.as_bytes().to_vec())
+                .unwrap();
+            builder.build().unwrap()
+        };
+
+        let first = build("A=1");
+        assert!(first.is_renamed());
+        assert!(first.name.starts_with("web-"));
+        assert_eq!(first.name.len(), "web-".len() + HASH_LENGTH);
+        assert_eq!(first.name, build("A=1").name);
+        assert_ne!(first.name, build("A=2").name);
+    }
+
+    #[test]
+    fn rewrites_references_in_workloads() {
+        let deployment = r#"apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: web
+spec:
+  template:
+    spec:
+      containers:
+        - name: web
+          envFrom:
+            - configMapRef:
+                name: web
+          env:
+            - name: MODE
+              valueFrom:
+                configMapKeyRef:
+                  name: web
+                  key: mode
+            - name: OTHER
+              valueFrom:
+                configMapKeyRef:
+                  name: shared
+                  key: other
+      volumes:
+        - name: config
+          configMap:
+            name: web
+"#;
+        let rewritten = rewrite_references(deployment, "web", "web-0123456789").unwrap();
+        let document = &documents(&rewritten)[0];
+        let pod = &document["spec"]["template"]["spec"];
+        let container = &pod["containers"][0];
+
+        assert_eq!(document["metadata"]["name"].as_str(), Some("web"));
+        assert_eq!(
+            container["envFrom"][0]["configMapRef"]["name"].as_str(),
+            Some("web-0123456789")
+        );
+        assert_eq!(
+            container["env"][0]["valueFrom"]["configMapKeyRef"]["name"].as_str(),
+            Some("web-0123456789")
+        );
+        assert_eq!(
+            container["env"][1]["valueFrom"]["configMapKeyRef"]["name"].as_str(),
+            Some("shared")
+        );
+        assert_eq!(
+            pod["volumes"][0]["configMap"]["name"].as_str(),
+            Some("web-0123456789")
+        );
+
+        let service = "apiVersion: v1\nkind: Service\nmetadata:\n  name: web\n";
+        assert_eq!(
+            rewrite_references(service, "web", "web-1").unwrap(),
+            service
+        );
+    }
+}
@@ -0,0 +1,1629 @@
+//! The template engine shared by manifest generation, service hooks and infra templates.
+//!
+//! Supported syntax:
+//!
+//! - `{{ name }}` / `{{name}}` substitutes a variable; dotted paths (`{{ values.a.b }}`)
+//!   walk nested tables.
+//! - `{{ name | default: 2 }}` pipes the value through filters. Available filters are
+//!   `default: <value>`, `upper`, `lower`, `quote`, `b64enc` and `indent: <n>`.
+//! - `{{#if name}} ... {{else}} ... {{/if}}` and `{{#unless name}} ... {{/unless}}`.
+//! - `{{#each items}} ... {{/each}}` with `this`, `@index`, `@key`, `@first` and `@last`
+//!   available inside the body.
+//! - `{{> name key=value ...}}` includes the partial `name`, with the parameters
+//!   available as variables inside it. A partial on a line of its own is indented to
+//!   match that line. Partials can include other partials; cycles are an error.
+//!
+//! A block tag that sits alone on its line removes that whole line from the output, so
+//! conditionals don't leave blank lines in the generated YAML.
+//!
+//! In the default (lenient) mode an unknown variable or a tag that isn't valid template
+//! syntax (for example a Go template expression inside a ConfigMap) is left untouched.
+//! Strict mode turns both into errors.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use base64::Engine as _;
+use serde_json::{Map, Value};
+
+use crate::errors::TemplateError;
+
+const FILTERS: &[(&str, usize)] = &[
+    ("default", 1),
+    ("upper", 0),
+    ("lower", 0),
+    ("quote", 0),
+    ("b64enc", 0),
+    ("indent", 1),
+];
+
+/// Variables available to a template render.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateContext {
+    root: Map<String, Value>,
+}
+
+impl TemplateContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a context from flat `(name, value)` pairs, such as the ones returned by
+    /// `Environment::get_variables`.
+    pub fn from_pairs(pairs: &[(String, String)]) -> Self {
+        let mut context = Self::new();
+        for (key, value) in pairs {
+            context.insert(key.clone(), value.clone());
+        }
+        context
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<Value>) {
+        self.root.insert(key.into(), value.into());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.root.get(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.root.contains_key(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.root.keys()
+    }
+}
+
+impl From<BTreeMap<String, String>> for TemplateContext {
+    fn from(variables: BTreeMap<String, String>) -> Self {
+        let mut context = Self::new();
+        for (key, value) in variables {
+            context.insert(key, value);
+        }
+        context
+    }
+}
+
+/// A tag that was left in the output untouched because it didn't resolve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedPlaceholder {
+    /// The tag exactly as written in the template, braces included.
+    pub placeholder: String,
+    /// The variable the tag reads, when it is a variable that isn't defined.
+    pub variable: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    /// The closest known variable name, when one is close enough to be a likely typo.
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOutput {
+    pub content: String,
+    pub unresolved: Vec<UnresolvedPlaceholder>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TemplateEngine {
+    strict: bool,
+    partials: BTreeMap<String, String>,
+}
+
+impl TemplateEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// In strict mode unknown variables and malformed tags are errors instead of being
+    /// passed through untouched.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Makes `partials`, keyed by name, available to `{{> name}}` tags.
+    pub fn partials(mut self, partials: BTreeMap<String, String>) -> Self {
+        self.partials = partials;
+        self
+    }
+
+    pub fn partial(&self, name: &str) -> Option<&str> {
+        self.partials.get(name).map(String::as_str)
+    }
+
+    pub fn render(&self, source: &str, context: &TemplateContext) -> Result<String, TemplateError> {
+        self.render_with_report(source, context)
+            .map(|output| output.content)
+    }
+
+    /// Renders `source` and also returns every placeholder that was left in the output
+    /// untouched. Only meaningful in lenient mode; strict mode fails on the first one.
+    pub fn render_with_report(
+        &self,
+        source: &str,
+        context: &TemplateContext,
+    ) -> Result<RenderOutput, TemplateError> {
+        let nodes = parse(source, self.strict)?;
+
+        let mut renderer = Renderer {
+            strict: self.strict,
+            root: Value::Object(context.root.clone()),
+            scopes: Vec::new(),
+            unresolved: Vec::new(),
+            partials: &self.partials,
+            stack: Vec::new(),
+        };
+        let mut content = String::with_capacity(source.len());
+        renderer.render_nodes(&nodes, &mut content)?;
+        Ok(RenderOutput {
+            content,
+            unresolved: renderer.unresolved,
+        })
+    }
+
+    /// Every variable path `source` reads, including those read by the partials it
+    /// includes. `this` and the `@` loop variables are left out.
+    pub fn referenced_variables(&self, source: &str) -> Result<BTreeSet<String>, TemplateError> {
+        let mut variables = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        self.collect_variables(&parse(source, self.strict)?, &mut variables, &mut visited)?;
+        Ok(variables)
+    }
+
+    fn collect_variables(
+        &self,
+        nodes: &[Node],
+        variables: &mut BTreeSet<String>,
+        visited: &mut BTreeSet<String>,
+    ) -> Result<(), TemplateError> {
+        let mut add = |operand: &Operand| {
+            if let Operand::Path(path) = operand {
+                if path != "this" && !path.starts_with("this.") && !path.starts_with('@') {
+                    variables.insert(path.clone());
+                }
+            }
+        };
+        let mut partials = Vec::new();
+        let mut blocks = Vec::new();
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Output { expression, .. } => {
+                    if let Ok(expression) = expression {
+                        add(&expression.head);
+                        expression
+                            .filters
+                            .iter()
+                            .flat_map(|filter| &filter.args)
+                            .for_each(&mut add);
+                    }
+                }
+                Node::Block {
+                    target,
+                    body,
+                    otherwise,
+                    ..
+                } => {
+                    add(&Operand::Path(target.clone()));
+                    blocks.push(body);
+                    blocks.push(otherwise);
+                }
+                Node::Partial { name, params, .. } => {
+                    params.iter().for_each(|(_, operand)| add(operand));
+                    partials.push(name);
+                }
+            }
+        }
+        for body in blocks {
+            self.collect_variables(body, variables, visited)?;
+        }
+        for name in partials {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if let Some(source) = self.partials.get(name) {
+                self.collect_variables(&parse(source, self.strict)?, variables, visited)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse(source: &str, strict: bool) -> Result<Vec<Node>, TemplateError> {
+    let tokens = tokenize(source);
+    let mut parser = Parser {
+        tokens,
+        cursor: 0,
+        strict,
+    };
+    parser.parse_nodes(None)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Text(String),
+    Tag {
+        inner: String,
+        raw: String,
+        position: Position,
+        /// The leading whitespace of a tag that sits alone on its line.
+        indent: Option<String>,
+        /// The source text the tag was read from: the whole line, line ending included,
+        /// for a tag that sits alone on its line, otherwise `raw`.
+        source: String,
+    },
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.split_inclusive('\n').enumerate() {
+        let line_number = index + 1;
+        let content = line.trim_end_matches(['\n', '\r']);
+        let trimmed = content.trim();
+
+        if is_standalone_block_tag(trimmed) {
+            let offset = content.len() - content.trim_start().len();
+            tokens.push(Token::Tag {
+                inner: trimmed[2..trimmed.len() - 2].trim().to_string(),
+                raw: trimmed.to_string(),
+                position: Position {
+                    line: line_number,
+                    column: content[..offset].chars().count() + 1,
+                },
+                indent: Some(content[..offset].to_string()),
+                source: line.to_string(),
+            });
+            continue;
+        }
+
+        tokenize_line(line, line_number, &mut tokens);
+    }
+
+    tokens
+}
+
+fn is_standalone_block_tag(trimmed: &str) -> bool {
+    if trimmed.len() < 4 || !trimmed.starts_with("{{") || !trimmed.ends_with("}}") {
+        return false;
+    }
+    let inner = &trimmed[2..trimmed.len() - 2];
+    if inner.contains("{{") || inner.contains("}}") {
+        return false;
+    }
+    let inner = inner.trim();
+    inner.starts_with('#') || inner.starts_with('/') || inner.starts_with('>') || inner == "else"
+}
+
+fn tokenize_line(line: &str, line_number: usize, tokens: &mut Vec<Token>) {
+    let mut consumed = 0;
+
+    while let Some(start) = line[consumed..].find("{{").map(|offset| consumed + offset) {
+        let Some(end) = line[start + 2..]
+            .find("}}")
+            .map(|offset| start + 2 + offset)
+        else {
+            break;
+        };
+
+        if start > consumed {
+            push_text(tokens, &line[consumed..start]);
+        }
+        tokens.push(Token::Tag {
+            inner: line[start + 2..end].trim().to_string(),
+            raw: line[start..end + 2].to_string(),
+            position: Position {
+                line: line_number,
+                column: line[..start].chars().count() + 1,
+            },
+            indent: None,
+            source: line[start..end + 2].to_string(),
+        });
+        consumed = end + 2;
+    }
+
+    if consumed < line.len() {
+        push_text(tokens, &line[consumed..]);
+    }
+}
+
+fn push_text(tokens: &mut Vec<Token>, text: &str) {
+    if let Some(Token::Text(previous)) = tokens.last_mut() {
+        previous.push_str(text);
+    } else {
+        tokens.push(Token::Text(text.to_string()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    If,
+    Unless,
+    Each,
+}
+
+impl BlockKind {
+    fn keyword(self) -> &'static str {
+        match self {
+            BlockKind::If => "if",
+            BlockKind::Unless => "unless",
+            BlockKind::Each => "each",
+        }
+    }
+
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "if" => Some(BlockKind::If),
+            "unless" => Some(BlockKind::Unless),
+            "each" => Some(BlockKind::Each),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Path(String),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone)]
+struct Filter {
+    name: String,
+    args: Vec<Operand>,
+}
+
+#[derive(Debug, Clone)]
+struct Expression {
+    head: Operand,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone)]
+enum ExpressionIssue {
+    Syntax(String),
+    UnknownFilter(String),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Output {
+        expression: Result<Expression, ExpressionIssue>,
+        raw: String,
+        position: Position,
+    },
+    Block {
+        kind: BlockKind,
+        target: String,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+        position: Position,
+    },
+    Partial {
+        name: String,
+        params: Vec<(String, Operand)>,
+        raw: String,
+        position: Position,
+        indent: Option<String>,
+    },
+}
+
+enum Terminator {
+    Else,
+    Close,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    cursor: usize,
+    strict: bool,
+}
+
+impl Parser {
+    /// Parses nodes until the closing tag of `open` (or the end of input at the top level).
+    fn parse_nodes(
+        &mut self,
+        open: Option<(BlockKind, Position)>,
+    ) -> Result<Vec<Node>, TemplateError> {
+        let (nodes, _) = self.parse_until(open)?;
+        Ok(nodes)
+    }
+
+    fn parse_until(
+        &mut self,
+        open: Option<(BlockKind, Position)>,
+    ) -> Result<(Vec<Node>, Option<Terminator>), TemplateError> {
+        let mut nodes = Vec::new();
+
+        while self.cursor < self.tokens.len() {
+            let token = self.tokens[self.cursor].clone();
+            self.cursor += 1;
+
+            let (inner, raw, position, indent, source) = match token {
+                Token::Text(text) => {
+                    nodes.push(Node::Text(text));
+                    continue;
+                }
+                Token::Tag {
+                    inner,
+                    raw,
+                    position,
+                    indent,
+                    source,
+                } => (inner, raw, position, indent, source),
+            };
+
+            // In lenient mode a tag that is not template syntax, such as a Go template
+            // `{{ else }}` or `{{/* comment */}}`, is kept as it was written.
+            let lenient = |error: TemplateError, nodes: &mut Vec<Node>| {
+                if self.strict {
+                    return Err(error);
+                }
+                nodes.push(Node::Text(source.clone()));
+                Ok(())
+            };
+
+            if let Some(rest) = inner.strip_prefix('>') {
+                match parse_partial(rest) {
+                    Ok((name, params)) => nodes.push(Node::Partial {
+                        name,
+                        params,
+                        raw,
+                        position,
+                        indent,
+                    }),
+                    Err(e) => lenient(syntax_error(&e, position), &mut nodes)?,
+                }
+                continue;
+            }
+
+            if inner == "else" {
+                match open {
+                    Some(_) => return Ok((nodes, Some(Terminator::Else))),
+                    None => {
+                        lenient(
+                            syntax_error("'{{else}}' outside of a block", position),
+                            &mut nodes,
+                        )?;
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(rest) = inner.strip_prefix('/') {
+                let keyword = rest.trim();
+                if !self.strict && BlockKind::from_keyword(keyword).is_none() {
+                    nodes.push(Node::Text(source));
+                    continue;
+                }
+                return match open {
+                    Some((kind, _)) if kind.keyword() == keyword => {
+                        Ok((nodes, Some(Terminator::Close)))
+                    }
+                    Some((kind, _)) => Err(syntax_error(
+                        &format!("expected '{{{{/{}}}}}' but found '{}'", kind.keyword(), raw),
+                        position,
+                    )),
+                    None => Err(syntax_error(
+                        &format!("'{}' has no matching opening tag", raw),
+                        position,
+                    )),
+                };
+            }
+
+            if let Some(rest) = inner.strip_prefix('#') {
+                let (keyword, target) = match rest.split_once(char::is_whitespace) {
+                    Some((keyword, target)) => (keyword, target.trim()),
+                    None => (rest, ""),
+                };
+                let Some(kind) = BlockKind::from_keyword(keyword) else {
+                    let error =
+                        syntax_error(&format!("unknown block helper '#{}'", keyword), position);
+                    lenient(error, &mut nodes)?;
+                    continue;
+                };
+                if target.is_empty() || !is_valid_path(target) {
+                    return Err(syntax_error(
+                        &format!("'#{}' expects a variable name", kind.keyword()),
+                        position,
+                    ));
+                }
+
+                let (body, terminator) = self.parse_until(Some((kind, position)))?;
+                let otherwise = match terminator {
+                    Some(Terminator::Else) => {
+                        let (otherwise, terminator) = self.parse_until(Some((kind, position)))?;
+                        match terminator {
+                            Some(Terminator::Close) => otherwise,
+                            _ => return Err(unclosed_block(kind, position)),
+                        }
+                    }
+                    Some(Terminator::Close) => Vec::new(),
+                    None => return Err(unclosed_block(kind, position)),
+                };
+
+                nodes.push(Node::Block {
+                    kind,
+                    target: target.to_string(),
+                    body,
+                    otherwise,
+                    position,
+                });
+                continue;
+            }
+
+            nodes.push(Node::Output {
+                expression: parse_expression(&inner),
+                raw,
+                position,
+            });
+        }
+
+        Ok((nodes, None))
+    }
+}
+
+fn syntax_error(message: &str, position: Position) -> TemplateError {
+    TemplateError::Syntax {
+        message: message.to_string(),
+        line: position.line,
+        column: position.column,
+    }
+}
+
+fn unclosed_block(kind: BlockKind, position: Position) -> TemplateError {
+    syntax_error(
+        &format!(
+            "'{{{{#{}}}}}' is never closed with '{{{{/{}}}}}'",
+            kind.keyword(),
+            kind.keyword()
+        ),
+        position,
+    )
+}
+
+/// Parses the `name key=value ...` part of a partial tag.
+fn parse_partial(input: &str) -> Result<(String, Vec<(String, Operand)>), String> {
+    let mut parts = split_outside_quotes(input.trim(), ' ')
+        .into_iter()
+        .filter(|part| !part.is_empty());
+    let name = parts
+        .next()
+        .ok_or_else(|| "'{{>' expects a partial name".to_string())?;
+    if !is_valid_partial_name(name) {
+        return Err(format!("'{}' is not a valid partial name", name));
+    }
+
+    let mut params = Vec::new();
+    for part in parts {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected key=value, found '{}'", part))?;
+        if !is_valid_path(key) || key.contains('.') || key.starts_with('@') {
+            return Err(format!("'{}' is not a valid parameter name", key));
+        }
+        params.push((key.to_string(), parse_operand(value)?));
+    }
+    Ok((name.to_string(), params))
+}
+
+fn is_valid_partial_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || matches!(ch, '_' | '-' | '.'))
+}
+
+fn parse_expression(inner: &str) -> Result<Expression, ExpressionIssue> {
+    let segments = split_outside_quotes(inner, '|');
+    let mut segments = segments.into_iter();
+    let head = segments
+        .next()
+        .ok_or_else(|| ExpressionIssue::Syntax("empty tag".to_string()))?;
+    let head = parse_operand(head.trim()).map_err(ExpressionIssue::Syntax)?;
+
+    let mut filters = Vec::new();
+    for segment in segments {
+        let segment = segment.trim();
+        let (name, args) = match segment.split_once(':') {
+            Some((name, args)) => (name.trim(), Some(args)),
+            None => (segment, None),
+        };
+
+        let Some((_, arity)) = FILTERS.iter().find(|(filter, _)| *filter == name) else {
+            return Err(ExpressionIssue::UnknownFilter(name.to_string()));
+        };
+
+        let args = match args {
+            Some(args) => split_outside_quotes(args, ',')
+                .into_iter()
+                .map(|arg| parse_operand(arg.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(ExpressionIssue::Syntax)?,
+            None => Vec::new(),
+        };
+        if args.len() != *arity {
+            return Err(ExpressionIssue::Syntax(format!(
+                "filter '{}' expects {} argument(s), got {}",
+                name,
+                arity,
+                args.len()
+            )));
+        }
+
+        filters.push(Filter {
+            name: name.to_string(),
+            args,
+        });
+    }
+
+    Ok(Expression { head, filters })
+}
+
+fn split_outside_quotes(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (index, ch) in input.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (quote, ch) {
+            (Some(_), '\\') => escaped = true,
+            (Some(open), ch) if ch == open => quote = None,
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, ch) if ch == separator => {
+                parts.push(&input[start..index]);
+                start = index + ch.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+fn parse_operand(input: &str) -> Result<Operand, String> {
+    if input.is_empty() {
+        return Err("expected a value".to_string());
+    }
+
+    if let Some(quote) = input.chars().next().filter(|ch| *ch == '"' || *ch == '\'') {
+        if input.len() < 2 || !input.ends_with(quote) {
+            return Err(format!("unterminated string {}", input));
+        }
+        let mut value = String::new();
+        let mut chars = input[1..input.len() - 1].chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\\' {
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(other) => value.push(other),
+                    None => value.push('\\'),
+                }
+            } else {
+                value.push(ch);
+            }
+        }
+        return Ok(Operand::Literal(Value::String(value)));
+    }
+
+    match input {
+        "true" => return Ok(Operand::Literal(Value::Bool(true))),
+        "false" => return Ok(Operand::Literal(Value::Bool(false))),
+        _ => {}
+    }
+
+    if input.starts_with(|ch: char| ch.is_ascii_digit() || ch == '-') {
+        if let Ok(number) = input.parse::<i64>() {
+            return Ok(Operand::Literal(Value::from(number)));
+        }
+        if let Ok(number) = input.parse::<f64>() {
+            if let Some(number) = serde_json::Number::from_f64(number) {
+                return Ok(Operand::Literal(Value::Number(number)));
+            }
+        }
+    }
+
+    if is_valid_path(input) {
+        Ok(Operand::Path(input.to_string()))
+    } else {
+        Err(format!("'{}' is not a valid variable name", input))
+    }
+}
+
+fn is_valid_path(input: &str) -> bool {
+    input.split('.').enumerate().all(|(index, segment)| {
+        let segment = if index == 0 {
+            segment.strip_prefix('@').unwrap_or(segment)
+        } else {
+            segment
+        };
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-')
+    })
+}
+
+struct Scope {
+    value: Value,
+    index: usize,
+    len: usize,
+    key: Option<String>,
+}
+
+struct Renderer<'a> {
+    strict: bool,
+    root: Value,
+    scopes: Vec<Scope>,
+    unresolved: Vec<UnresolvedPlaceholder>,
+    partials: &'a BTreeMap<String, String>,
+    /// Names of the partials being rendered, outermost first.
+    stack: Vec<String>,
+}
+
+impl Renderer<'_> {
+    fn render_nodes(&mut self, nodes: &[Node], output: &mut String) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text),
+                Node::Output {
+                    expression,
+                    raw,
+                    position,
+                } => self.render_output(expression, raw, *position, output)?,
+                Node::Block {
+                    kind,
+                    target,
+                    body,
+                    otherwise,
+                    position,
+                } => self.render_block(*kind, target, body, otherwise, *position, output)?,
+                Node::Partial {
+                    name,
+                    params,
+                    raw,
+                    position,
+                    indent,
+                } => {
+                    self.render_partial(name, params, raw, *position, indent.as_deref(), output)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn render_partial(
+        &mut self,
+        name: &str,
+        params: &[(String, Operand)],
+        raw: &str,
+        position: Position,
+        indent: Option<&str>,
+        output: &mut String,
+    ) -> Result<(), TemplateError> {
+        let Some(source) = self.partials.get(name) else {
+            if self.strict {
+                return Err(TemplateError::UnknownPartial {
+                    name: name.to_string(),
+                    line: position.line,
+                    column: position.column,
+                });
+            }
+            self.unresolved.push(UnresolvedPlaceholder {
+                placeholder: raw.to_string(),
+                variable: None,
+                line: position.line,
+                column: position.column,
+                suggestion: None,
+            });
+            match indent {
+                Some(indent) => output.push_str(&format!("{}{}\n", indent, raw)),
+                None => output.push_str(raw),
+            }
+            return Ok(());
+        };
+        if self.stack.iter().any(|partial| partial == name) {
+            let mut chain = self.stack.clone();
+            chain.push(name.to_string());
+            return Err(TemplateError::PartialCycle {
+                chain,
+                line: position.line,
+                column: position.column,
+            });
+        }
+
+        let mut scope = Map::new();
+        for (key, operand) in params {
+            match self.resolve(operand) {
+                Some(value) => {
+                    scope.insert(key.clone(), value);
+                }
+                None if self.strict => {
+                    return Err(TemplateError::UnknownVariable {
+                        name: match operand {
+                            Operand::Path(path) => path.clone(),
+                            Operand::Literal(value) => display_value(value),
+                        },
+                        line: position.line,
+                        column: position.column,
+                    })
+                }
+                // Left out, so the partial reports the parameter as unresolved.
+                None => {}
+            }
+        }
+
+        let in_partial = |source: TemplateError| TemplateError::InPartial {
+            partial: name.to_string(),
+            line: position.line,
+            column: position.column,
+            source: Box::new(source),
+        };
+        let nodes = parse(source, self.strict).map_err(in_partial)?;
+        let unresolved_before = self.unresolved.len();
+        let mut rendered = String::new();
+        self.scopes.push(Scope {
+            value: Value::Object(scope),
+            index: 0,
+            len: 1,
+            key: None,
+        });
+        self.stack.push(name.to_string());
+        let result = self.render_nodes(&nodes, &mut rendered);
+        self.stack.pop();
+        self.scopes.pop();
+        result.map_err(in_partial)?;
+
+        // Placeholders left in a partial are reported where the partial is included.
+        for placeholder in &mut self.unresolved[unresolved_before..] {
+            placeholder.line = position.line;
+            placeholder.column = position.column;
+        }
+
+        match indent {
+            Some(indent) => {
+                for line in rendered.split_inclusive('\n') {
+                    if line.trim().is_empty() {
+                        output.push_str(line.trim_start_matches([' ', '\t']));
+                    } else {
+                        output.push_str(indent);
+                        output.push_str(line);
+                    }
+                }
+                if !rendered.is_empty() && !rendered.ends_with('\n') {
+                    output.push('\n');
+                }
+            }
+            None => output.push_str(rendered.strip_suffix('\n').unwrap_or(&rendered)),
+        }
+        Ok(())
+    }
+
+    fn render_output(
+        &mut self,
+        expression: &Result<Expression, ExpressionIssue>,
+        raw: &str,
+        position: Position,
+        output: &mut String,
+    ) -> Result<(), TemplateError> {
+        let expression = match expression {
+            Ok(expression) => expression,
+            Err(_) if !self.strict => {
+                self.unresolved.push(UnresolvedPlaceholder {
+                    placeholder: raw.to_string(),
+                    variable: None,
+                    line: position.line,
+                    column: position.column,
+                    suggestion: None,
+                });
+                output.push_str(raw);
+                return Ok(());
+            }
+            Err(ExpressionIssue::UnknownFilter(name)) => {
+                return Err(TemplateError::UnknownFilter {
+                    name: name.clone(),
+                    line: position.line,
+                    column: position.column,
+                })
+            }
+            Err(ExpressionIssue::Syntax(message)) => return Err(syntax_error(message, position)),
+        };
+
+        match self.evaluate(expression, position)? {
+            Some(value) => output.push_str(&display_value(&value)),
+            None if self.strict => {
+                let name = match &expression.head {
+                    Operand::Path(path) => path.clone(),
+                    Operand::Literal(value) => display_value(value),
+                };
+                return Err(TemplateError::UnknownVariable {
+                    name,
+                    line: position.line,
+                    column: position.column,
+                });
+            }
+            None => {
+                let (variable, suggestion) = match &expression.head {
+                    Operand::Path(path) => (Some(path.clone()), self.suggest(path)),
+                    Operand::Literal(_) => (None, None),
+                };
+                self.unresolved.push(UnresolvedPlaceholder {
+                    placeholder: raw.to_string(),
+                    variable,
+                    line: position.line,
+                    column: position.column,
+                    suggestion,
+                });
+                output.push_str(raw);
+            }
+        }
+        Ok(())
+    }
+
+    fn render_block(
+        &mut self,
+        kind: BlockKind,
+        target: &str,
+        body: &[Node],
+        otherwise: &[Node],
+        position: Position,
+        output: &mut String,
+    ) -> Result<(), TemplateError> {
+        let value = self.lookup(target);
+
+        match kind {
+            BlockKind::If | BlockKind::Unless => {
+                let truthy = value.as_ref().is_some_and(is_truthy);
+                if truthy == (kind == BlockKind::If) {
+                    self.render_nodes(body, output)
+                } else {
+                    self.render_nodes(otherwise, output)
+                }
+            }
+            BlockKind::Each => {
+                let items: Vec<(Option<String>, Value)> = match value {
+                    Some(Value::Array(items)) => {
+                        items.into_iter().map(|item| (None, item)).collect()
+                    }
+                    Some(Value::Object(entries)) => entries
+                        .into_iter()
+                        .map(|(key, item)| (Some(key), item))
+                        .collect(),
+                    Some(_) => Vec::new(),
+                    None if self.strict => {
+                        return Err(TemplateError::UnknownVariable {
+                            name: target.to_string(),
+                            line: position.line,
+                            column: position.column,
+                        })
+                    }
+                    None => Vec::new(),
+                };
+
+                if items.is_empty() {
+                    return self.render_nodes(otherwise, output);
+                }
+
+                let len = items.len();
+                for (index, (key, value)) in items.into_iter().enumerate() {
+                    self.scopes.push(Scope {
+                        value,
+                        index,
+                        len,
+                        key,
+                    });
+                    let result = self.render_nodes(body, output);
+                    self.scopes.pop();
+                    result?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn evaluate(
+        &self,
+        expression: &Expression,
+        position: Position,
+    ) -> Result<Option<Value>, TemplateError> {
+        let mut current = self.resolve(&expression.head);
+
+        for filter in &expression.filters {
+            current = match filter.name.as_str() {
+                "default" => match current {
+                    Some(value) if !is_empty(&value) => Some(value),
+                    _ => filter.args.first().and_then(|arg| self.resolve(arg)),
+                },
+                "upper" => current.map(|value| Value::String(display_value(&value).to_uppercase())),
+                "lower" => current.map(|value| Value::String(display_value(&value).to_lowercase())),
+                "quote" => current.map(|value| Value::String(quote(&display_value(&value)))),
+                "b64enc" => current.map(|value| {
+                    Value::String(
+                        base64::engine::general_purpose::STANDARD.encode(display_value(&value)),
+                    )
+                }),
+                "indent" => {
+                    let width = filter
+                        .args
+                        .first()
+                        .and_then(|arg| self.resolve(arg))
+                        .and_then(|width| match width {
+                            Value::Number(number) => number.as_u64(),
+                            Value::String(text) => text.parse::<u64>().ok(),
+                            _ => None,
+                        })
+                        .ok_or_else(|| TemplateError::InvalidFilterArgument {
+                            filter: filter.name.clone(),
+                            message: "expected a non-negative number of spaces".to_string(),
+                            line: position.line,
+                            column: position.column,
+                        })?;
+                    current
+                        .map(|value| Value::String(indent(&display_value(&value), width as usize)))
+                }
+                other => {
+                    return Err(TemplateError::UnknownFilter {
+                        name: other.to_string(),
+                        line: position.line,
+                        column: position.column,
+                    })
+                }
+            };
+        }
+
+        Ok(current)
+    }
+
+    /// Finds the known variable closest to `path`, by edit distance over the names
+    /// visible from the current scope.
+    fn suggest(&self, path: &str) -> Option<String> {
+        let max_distance = (path.chars().count() / 3).clamp(1, 3);
+        self.scopes
+            .iter()
+            .rev()
+            .map(|scope| &scope.value)
+            .chain(std::iter::once(&self.root))
+            .filter_map(Value::as_object)
+            .flat_map(|object| object.keys())
+            .map(|candidate| (edit_distance(path, candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by(|(left, left_name), (right, right_name)| {
+                left.cmp(right).then_with(|| left_name.cmp(right_name))
+            })
+            .map(|(_, candidate)| candidate.clone())
+    }
+
+    fn resolve(&self, operand: &Operand) -> Option<Value> {
+        match operand {
+            Operand::Literal(value) => Some(value.clone()),
+            Operand::Path(path) => self.lookup(path),
+        }
+    }
+
+    fn lookup(&self, path: &str) -> Option<Value> {
+        if let Some(meta) = path.strip_prefix('@') {
+            let scope = self.scopes.last()?;
+            return match meta {
+                "index" => Some(Value::from(scope.index)),
+                "key" => scope.key.clone().map(Value::String),
+                "first" => Some(Value::Bool(scope.index == 0)),
+                "last" => Some(Value::Bool(scope.index + 1 == scope.len)),
+                _ => None,
+            };
+        }
+
+        if path == "this" {
+            return self.scopes.last().map(|scope| scope.value.clone());
+        }
+        if let Some(rest) = path.strip_prefix("this.") {
+            return lookup_in(&self.scopes.last()?.value, rest);
+        }
+
+        self.scopes
+            .iter()
+            .rev()
+            .map(|scope| &scope.value)
+            .chain(std::iter::once(&self.root))
+            .find_map(|scope| lookup_in(scope, path))
+    }
+}
+
+fn lookup_in(scope: &Value, path: &str) -> Option<Value> {
+    let object = scope.as_object()?;
+    // Flat keys win over nested lookups so variables named `a.b` keep working.
+    if let Some(value) = object.get(path) {
+        return Some(value.clone());
+    }
+
+    let mut current = scope;
+    for segment in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current.clone())
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and adjacent
+/// transpositions each cost one, so `imgae` is a single edit away from `image`.
+pub(crate) fn edit_distance(left: &str, right: &str) -> usize {
+    let left: Vec<char> = left.chars().collect();
+    let right: Vec<char> = right.chars().collect();
+    let width = right.len() + 1;
+    let mut table = vec![0; (left.len() + 1) * width];
+
+    for i in 0..=left.len() {
+        table[i * width] = i;
+    }
+    for (j, cell) in table.iter_mut().enumerate().take(width) {
+        *cell = j;
+    }
+
+    for i in 1..=left.len() {
+        for j in 1..=right.len() {
+            let cost = usize::from(left[i - 1] != right[j - 1]);
+            let mut best = (table[(i - 1) * width + j] + 1)
+                .min(table[i * width + j - 1] + 1)
+                .min(table[(i - 1) * width + j - 1] + cost);
+            if i > 1 && j > 1 && left[i - 1] == right[j - 2] && left[i - 2] == right[j - 1] {
+                best = best.min(table[(i - 2) * width + j - 2] + 1);
+            }
+            table[i * width + j] = best;
+        }
+    }
+
+    table[left.len() * width + right.len()]
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::Array(_) | Value::Object(_) => value.to_string(),
+    }
+}
+
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(text) => text.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        Value::Object(entries) => entries.is_empty(),
+        Value::Bool(_) | Value::Number(_) => false,
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::String(text) => !text.is_empty() && text != "false",
+        Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(entries) => !entries.is_empty(),
+    }
+}
+
+fn quote(input: &str) -> String {
+    let mut quoted = String::with_capacity(input.len() + 2);
+    quoted.push('"');
+    for ch in input.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            other => quoted.push(other),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn indent(input: &str, width: usize) -> String {
+    let padding = " ".repeat(width);
+    input
+        .split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", padding, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn context(pairs: &[(&str, Value)]) -> TemplateContext {
+        let mut context = TemplateContext::new();
+        for (key, value) in pairs {
+            context.insert(*key, value.clone());
+        }
+        context
+    }
+
+    #[test]
+    fn substitutes_spaced_and_compact_variables() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("service_name", json!("api")), ("replicas", json!(3))]);
+
+        let rendered = engine
+            .render("name: {{service_name}}\nreplicas: {{ replicas }}\n", &ctx)
+            .expect("render");
+
+        assert_eq!(rendered, "name: api\nreplicas: 3\n");
+    }
+
+    #[test]
+    fn lenient_mode_leaves_unknown_and_foreign_tags_untouched() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("name", json!("api"))]);
+        let source = "a: {{ missing }}\nb: {{ .Labels.app }}\nc: {{ name }}\n";
+
+        let rendered = engine.render(source, &ctx).expect("render");
+
+        assert_eq!(rendered, "a: {{ missing }}\nb: {{ .Labels.app }}\nc: api\n");
+    }
+
+    #[test]
+    fn lenient_mode_keeps_go_template_tags_as_text() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("name", json!("api"))]);
+        let source = "\
+data:
+  {{/* rendered by the sidecar */}}
+  greeting: |
+    {{ if .User }}hello {{ .User }}
+    {{ else }}
+    hello {{ name }}
+    {{ end }}
+";
+
+        let rendered = engine.render(source, &ctx).expect("lenient render");
+        assert_eq!(rendered, source.replace("{{ name }}", "api"));
+
+        let strict = TemplateEngine::new().strict(true);
+        assert!(matches!(
+            strict.render("{{ else }}", &ctx),
+            Err(TemplateError::Syntax { .. })
+        ));
+        assert!(matches!(
+            strict.render("{{/* note */}}", &ctx),
+            Err(TemplateError::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn strict_mode_reports_unknown_variable_position() {
+        let engine = TemplateEngine::new().strict(true);
+        let ctx = context(&[("name", json!("api"))]);
+
+        let err = engine
+            .render("name: {{ name }}\nimage: {{ imgae }}\n", &ctx)
+            .expect_err("unknown variable should fail");
+
+        assert_eq!(
+            err,
+            TemplateError::UnknownVariable {
+                name: "imgae".to_string(),
+                line: 2,
+                column: 8,
+            }
+        );
+    }
+
+    #[test]
+    fn default_filter_covers_missing_and_empty_values() {
+        let engine = TemplateEngine::new().strict(true);
+        let ctx = context(&[("empty", json!("")), ("fallback", json!("blue"))]);
+
+        let rendered = engine
+            .render(
+                "{{ replicas | default: 2 }} {{ empty | default: \"x\" }} {{ colour | default: fallback }}",
+                &ctx,
+            )
+            .expect("render");
+
+        assert_eq!(rendered, "2 x blue");
+    }
+
+    #[test]
+    fn applies_chained_filters() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("env", json!("Prod")), ("secret", json!("hunter2"))]);
+
+        let rendered = engine
+            .render(
+                "{{ env | upper }} {{ env | lower | quote }} {{ secret | b64enc }}",
+                &ctx,
+            )
+            .expect("render");
+
+        assert_eq!(rendered, "PROD \"prod\" aHVudGVyMg==");
+    }
+
+    #[test]
+    fn indent_filter_pads_every_line() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("body", json!("a: 1\nb: 2"))]);
+
+        let rendered = engine
+            .render("data:\n{{ body | indent: 2 }}\n", &ctx)
+            .expect("render");
+
+        assert_eq!(rendered, "data:\n  a: 1\n  b: 2\n");
+    }
+
+    #[test]
+    fn if_else_and_unless_blocks_drop_standalone_lines() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("debug", json!("true")), ("tls", json!("false"))]);
+        let source = "spec:\n  {{#if debug}}\n  debug: on\n  {{else}}\n  debug: off\n  {{/if}}\n  {{#unless tls}}\n  insecure: true\n  {{/unless}}\n";
+
+        let rendered = engine.render(source, &ctx).expect("render");
+
+        assert_eq!(rendered, "spec:\n  debug: on\n  insecure: true\n");
+    }
+
+    #[test]
+    fn each_iterates_arrays_and_tables() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[
+            ("ports", json!([80, 443])),
+            ("values", json!({"labels": {"team": "core", "tier": "web"}})),
+        ]);
+        let source = "{{#each ports}}\n- {{ @index }}: {{ this }}\n{{/each}}\n{{#each values.labels}}\n{{ @key }}={{ this }}{{#unless @last}},{{/unless}}\n{{/each}}\n";
+
+        let rendered = engine.render(source, &ctx).expect("render");
+
+        assert_eq!(rendered, "- 0: 80\n- 1: 443\nteam=core,\ntier=web\n");
+    }
+
+    #[test]
+    fn each_body_can_reach_item_fields_and_outer_variables() {
+        let engine = TemplateEngine::new().strict(true);
+        let ctx = context(&[
+            ("namespace", json!("prod")),
+            ("hosts", json!([{"name": "a"}, {"name": "b"}])),
+        ]);
+
+        let rendered = engine
+            .render(
+                "{{#each hosts}}{{ name }}.{{ namespace }} {{/each}}{{#each none}}x{{else}}empty{{/each}}",
+                &context(&[
+                    ("namespace", json!("prod")),
+                    ("hosts", json!([{"name": "a"}, {"name": "b"}])),
+                    ("none", json!([])),
+                ]),
+            )
+            .expect("render");
+        assert_eq!(rendered, "a.prod b.prod empty");
+
+        let err = engine
+            .render("{{#each missing}}x{{/each}}", &ctx)
+            .expect_err("missing collection in strict mode");
+        assert!(matches!(err, TemplateError::UnknownVariable { .. }));
+    }
+
+    #[test]
+    fn reports_unbalanced_blocks() {
+        let engine = TemplateEngine::new();
+        let ctx = TemplateContext::new();
+
+        let unclosed = engine
+            .render("a\n{{#if x}}\nb\n", &ctx)
+            .expect_err("unclosed block");
+        assert_eq!(
+            unclosed,
+            TemplateError::Syntax {
+                message: "'{{#if}}' is never closed with '{{/if}}'".to_string(),
+                line: 2,
+                column: 1,
+            }
+        );
+
+        let mismatched = engine
+            .render("{{#each x}}{{/if}}", &ctx)
+            .expect_err("mismatched block");
+        assert!(matches!(mismatched, TemplateError::Syntax { .. }));
+    }
+
+    #[test]
+    fn reports_unresolved_placeholders_with_suggestions() {
+        let engine = TemplateEngine::new();
+        let ctx = context(&[("service_name", json!("api")), ("image", json!("nginx"))]);
+
+        let output = engine
+            .render_with_report(
+                "name: {{ service_name }}\n  image: {{imgae}}\n  x: {{ .Values.x }} {{ totally_unknown }}\n",
+                &ctx,
+            )
+            .expect("render");
+
+        assert_eq!(
+            output.unresolved,
+            vec![
+                UnresolvedPlaceholder {
+                    placeholder: "{{imgae}}".to_string(),
+                    variable: Some("imgae".to_string()),
+                    line: 2,
+                    column: 10,
+                    suggestion: Some("image".to_string()),
+                },
+                UnresolvedPlaceholder {
+                    placeholder: "{{ .Values.x }}".to_string(),
+                    variable: None,
+                    line: 3,
+                    column: 6,
+                    suggestion: None,
+                },
+                UnresolvedPlaceholder {
+                    placeholder: "{{ totally_unknown }}".to_string(),
+                    variable: Some("totally_unknown".to_string()),
+                    line: 3,
+                    column: 22,
+                    suggestion: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_filters() {
+        let ctx = context(&[("name", json!("api"))]);
+
+        let lenient = TemplateEngine::new()
+            .render("{{ name | toYaml }}", &ctx)
+            .expect("lenient render");
+        assert_eq!(lenient, "{{ name | toYaml }}");
+
+        let err = TemplateEngine::new()
+            .strict(true)
+            .render("{{ name | toYaml }}", &ctx)
+            .expect_err("unknown filter");
+        assert!(matches!(err, TemplateError::UnknownFilter { name, .. } if name == "toYaml"));
+    }
+
+    fn partials(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn partials_take_parameters_and_keep_indentation() {
+        let engine = TemplateEngine::new().partials(partials(&[
+            (
+                "probe",
+                "httpGet:\n  path: {{ path }}\n  port: {{ port | default: 8080 }}\n",
+            ),
+            ("name", "{{ service_name }}-{{ suffix }}"),
+        ]));
+        let ctx = context(&[
+            ("service_name", json!("api")),
+            ("health", json!("/healthz")),
+        ]);
+        let source = "metadata:\n  name: {{> name suffix=\"web\"}}\nspec:\n  livenessProbe:\n    {{> probe path=health}}\n";
+
+        let rendered = engine.render(source, &ctx).expect("render");
+
+        assert_eq!(
+            rendered,
+            "metadata:\n  name: api-web\nspec:\n  livenessProbe:\n    httpGet:\n      path: /healthz\n      port: 8080\n"
+        );
+    }
+
+    #[test]
+    fn partials_can_include_partials() {
+        let engine = TemplateEngine::new().partials(partials(&[
+            ("labels", "labels:\n  {{> app}}\n"),
+            ("app", "app: {{ service_name }}\n"),
+        ]));
+        let ctx = context(&[("service_name", json!("api"))]);
+
+        let rendered = engine
+            .render("metadata:\n  {{> labels}}\n", &ctx)
+            .expect("render");
+
+        assert_eq!(rendered, "metadata:\n  labels:\n    app: api\n");
+    }
+
+    #[test]
+    fn reports_partial_cycles_and_errors_with_the_include_chain() {
+        let engine = TemplateEngine::new().strict(true).partials(partials(&[
+            ("a", "{{> b}}\n"),
+            ("b", "x: 1\n{{> a}}\n"),
+            ("broken", "a: 1\nb: {{ missing }}\n"),
+        ]));
+        let ctx = TemplateContext::new();
+
+        let err = engine.render("{{> a}}\n", &ctx).expect_err("cycle");
+        assert!(matches!(
+            err.innermost(),
+            TemplateError::PartialCycle { chain, .. } if chain == &["a", "b", "a"]
+        ));
+
+        let err = engine
+            .render("first: 1\n{{> broken}}\n", &ctx)
+            .expect_err("unknown variable");
+        assert_eq!(err.line(), 2);
+        assert_eq!(err.partial_chain(), vec![("broken", 2)]);
+        assert!(matches!(
+            err.innermost(),
+            TemplateError::UnknownVariable { name, line: 2, .. } if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn unknown_partials_are_left_untouched_unless_strict() {
+        let ctx = TemplateContext::new();
+
+        let output = TemplateEngine::new()
+            .render_with_report("a: 1\n  {{> missing}}\n", &ctx)
+            .expect("lenient render");
+        assert_eq!(output.content, "a: 1\n  {{> missing}}\n");
+        assert_eq!(output.unresolved[0].line, 2);
+
+        let err = TemplateEngine::new()
+            .strict(true)
+            .render("{{> missing}}", &ctx)
+            .expect_err("unknown partial");
+        assert!(matches!(err, TemplateError::UnknownPartial { name, .. } if name == "missing"));
+    }
+
+    #[test]
+    fn lists_the_variables_a_template_reads() {
+        let engine = TemplateEngine::new().partials(partials(&[(
+            "probes",
+            "port: {{ port }}\npath: {{ values.health_path }}\n{{> probes}}\n",
+        )]));
+
+        let variables = engine
+            .referenced_variables(
+                "image: {{ registry }}/{{ service_name | default: app_name }}\n{{#each values.ports}}- {{ this }} {{ @index }}\n{{/each}}{{> probes port=values.port}}\n",
+            )
+            .expect("valid template");
+
+        assert_eq!(
+            variables.into_iter().collect::<Vec<_>>(),
+            vec![
+                "app_name",
+                "port",
+                "registry",
+                "service_name",
+                "values.health_path",
+                "values.port",
+                "values.ports",
+            ]
+        );
+    }
+}
@@ -0,0 +1,446 @@
+//! Ownership labels and annotations stamped on every generated resource.
+//!
+//! Each document gets the recommended `app.kubernetes.io/*` labels, the
+//! `sailr.dev/environment` and `sailr.dev/service` labels, and the labels listed under
+//! `[labels]` in the environment's config.toml. The source revision the manifests were
+//! generated from is recorded in the `sailr.dev/source-revision` annotation. Pod
+//! templates of workloads get the labels they don't set yet, but not the annotation,
+//! so a new commit does not restart pods whose spec did not change. Labels a pod
+//! template or its selector already set are never changed, so they keep matching.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    environment::{Environment, Service},
+    errors::LabelError,
+    overlays::parse_documents,
+};
+
+pub const NAME_LABEL: &str = "app.kubernetes.io/name";
+pub const VERSION_LABEL: &str = "app.kubernetes.io/version";
+pub const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+pub const ENVIRONMENT_LABEL: &str = "sailr.dev/environment";
+pub const SERVICE_LABEL: &str = "sailr.dev/service";
+pub const SOURCE_REVISION_ANNOTATION: &str = "sailr.dev/source-revision";
+
+/// Value of the `app.kubernetes.io/managed-by` label.
+pub const MANAGED_BY: &str = "sailr";
+
+const RESERVED_LABELS: &[&str] = &[
+    NAME_LABEL,
+    VERSION_LABEL,
+    MANAGED_BY_LABEL,
+    ENVIRONMENT_LABEL,
+    SERVICE_LABEL,
+];
+
+const MAX_NAME_LENGTH: usize = 63;
+const MAX_PREFIX_LENGTH: usize = 253;
+
+/// The labels and annotations added to the documents of one service.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StandardMetadata {
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+}
+
+impl StandardMetadata {
+    pub fn new() -> StandardMetadata {
+        StandardMetadata::default()
+    }
+
+    // The metadata shared by everything in `env`: its common labels from config.toml,
+    // the managed-by and environment labels, and the revision annotation when the
+    // revision is known.
+    pub fn for_environment(
+        env: &Environment,
+        revision: Option<&str>,
+    ) -> Result<StandardMetadata, LabelError> {
+        let mut metadata = StandardMetadata::new();
+        for (key, value) in &env.labels {
+            if RESERVED_LABELS.contains(&key.as_str()) {
+                return Err(LabelError::Reserved { key: key.clone() });
+            }
+            validate_key(key)?;
+            if !is_valid_value(value) {
+                return Err(LabelError::InvalidValue {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+            metadata.labels.insert(key.clone(), value.clone());
+        }
+
+        metadata = metadata
+            .label(MANAGED_BY_LABEL, MANAGED_BY)
+            .label(ENVIRONMENT_LABEL, &env.name);
+        if let Some(revision) = revision {
+            metadata = metadata.annotation(SOURCE_REVISION_ANNOTATION, revision);
+        }
+        Ok(metadata)
+    }
+
+    // The environment's metadata plus the name, version and service labels of `service`.
+    pub fn for_service(
+        env: &Environment,
+        service: &Service,
+        revision: Option<&str>,
+    ) -> Result<StandardMetadata, LabelError> {
+        Ok(StandardMetadata::for_environment(env, revision)?
+            .label(NAME_LABEL, &service.name)
+            .label(VERSION_LABEL, &service.version)
+            .label(SERVICE_LABEL, &service.name))
+    }
+
+    // Adds a label, turning `value` into a valid label value first. Values with nothing
+    // usable left, such as an empty version, are skipped.
+    pub fn label(mut self, key: &str, value: &str) -> StandardMetadata {
+        let value = sanitize_value(value);
+        if !value.is_empty() {
+            self.labels.insert(key.to_string(), value);
+        }
+        self
+    }
+
+    pub fn annotation(mut self, key: &str, value: &str) -> StandardMetadata {
+        self.annotations.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    pub fn annotations(&self) -> &BTreeMap<String, String> {
+        &self.annotations
+    }
+
+    // Stamps every Kubernetes object in `content`. Labels and annotations already on an
+    // object are overwritten, so the ownership labels can always be relied on. Pod
+    // templates only get the labels they are missing.
+    pub fn apply(&self, content: &str) -> Result<String, LabelError> {
+        let mut documents = parse_documents(content).map_err(LabelError::Render)?;
+        let mut changed = false;
+        for document in &mut documents {
+            changed |= self.apply_to(document);
+        }
+        if !changed {
+            return Ok(content.to_string());
+        }
+
+        documents
+            .iter()
+            .map(|document| {
+                serde_yaml::to_string(document).map_err(|e| LabelError::Render(e.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|documents| documents.join("---\n"))
+    }
+
+    fn apply_to(&self, document: &mut Value) -> bool {
+        let Some(object) = document.as_object_mut() else {
+            return false;
+        };
+        if !object.contains_key("apiVersion") || !object.contains_key("kind") {
+            return false;
+        }
+
+        let mut changed = merge(object, "labels", &self.labels, true);
+        changed |= merge(object, "annotations", &self.annotations, true);
+
+        let Some(spec) = object.get_mut("spec").and_then(Value::as_object_mut) else {
+            return changed;
+        };
+        if !spec.contains_key("jobTemplate") {
+            return changed | self.label_pod_template(spec);
+        }
+        if let Some(job_template) = spec.get_mut("jobTemplate").and_then(Value::as_object_mut) {
+            changed |= merge(job_template, "labels", &self.labels, false);
+            if let Some(job_spec) = job_template.get_mut("spec").and_then(Value::as_object_mut) {
+                changed |= self.label_pod_template(job_spec);
+            }
+        }
+        changed
+    }
+
+    // Adds the labels missing from the pod template of `spec`, leaving out the keys
+    // its selector uses: changing or adding those would make the selector stop
+    // matching, which the API server rejects.
+    fn label_pod_template(&self, spec: &mut Map<String, Value>) -> bool {
+        let selector = selector_keys(spec.get("selector"));
+        let labels = self
+            .labels
+            .iter()
+            .filter(|(key, _)| !selector.contains(key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        match spec.get_mut("template").and_then(Value::as_object_mut) {
+            Some(template) => merge(template, "labels", &labels, false),
+            None => false,
+        }
+    }
+}
+
+// The label keys `selector` matches on: a label selector's `matchLabels` and
+// `matchExpressions`, or the plain map ReplicationControllers use.
+fn selector_keys(selector: Option<&Value>) -> BTreeSet<&str> {
+    let Some(selector) = selector.and_then(Value::as_object) else {
+        return BTreeSet::new();
+    };
+    if !selector.contains_key("matchLabels") && !selector.contains_key("matchExpressions") {
+        return selector.keys().map(String::as_str).collect();
+    }
+    let match_labels = selector
+        .get("matchLabels")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|labels| labels.keys().map(String::as_str));
+    let match_expressions = selector
+        .get("matchExpressions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|expression| expression.get("key").and_then(Value::as_str));
+    match_labels.chain(match_expressions).collect()
+}
+
+// Sets `entries` in `object.metadata.<field>`, creating the maps as needed. Without
+// `overwrite`, only keys that are not there yet are set.
+fn merge(
+    object: &mut Map<String, Value>,
+    field: &str,
+    entries: &BTreeMap<String, String>,
+    overwrite: bool,
+) -> bool {
+    if entries.is_empty() {
+        return false;
+    }
+    let Some(metadata) = object_entry(object, "metadata") else {
+        return false;
+    };
+    let Some(map) = object_entry(metadata, field) else {
+        return false;
+    };
+
+    let mut changed = false;
+    for (key, value) in entries {
+        if !overwrite && map.contains_key(key) {
+            continue;
+        }
+        if map.get(key).and_then(Value::as_str) != Some(value.as_str()) {
+            map.insert(key.clone(), Value::String(value.clone()));
+            changed = true;
+        }
+    }
+    changed
+}
+
+// The map under `key`, created when it is missing or empty (`labels:` without entries).
+fn object_entry<'a>(
+    object: &'a mut Map<String, Value>,
+    key: &str,
+) -> Option<&'a mut Map<String, Value>> {
+    let value = object
+        .entry(key)
+        .or_insert_with(|| Value::Object(Map::new()));
+    if value.is_null() {
+        *value = Value::Object(Map::new());
+    }
+    value.as_object_mut()
+}
+
+/// The source revision recorded in previously generated documents, if any.
+pub fn recorded_revision<'a>(contents: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    contents.into_iter().find_map(|content| {
+        parse_documents(content).ok()?.iter().find_map(|document| {
+            document
+                .pointer("/metadata/annotations")?
+                .get(SOURCE_REVISION_ANNOTATION)?
+                .as_str()
+                .map(str::to_string)
+        })
+    })
+}
+
+// Replaces characters a label value cannot contain with `_`, trims it to 63 characters
+// and strips anything but letters and digits from both ends.
+fn sanitize_value(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    value
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn is_valid_value(value: &str) -> bool {
+    value.is_empty() || is_valid_name(value)
+}
+
+fn validate_key(key: &str) -> Result<(), LabelError> {
+    let invalid = |reason: &str| LabelError::InvalidKey {
+        key: key.to_string(),
+        reason: reason.to_string(),
+    };
+    let (prefix, name) = match key.split_once('/') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, key),
+    };
+    if let Some(prefix) = prefix {
+        let valid = !prefix.is_empty()
+            && prefix.len() <= MAX_PREFIX_LENGTH
+            && prefix.split('.').all(|part| {
+                !part.is_empty()
+                    && part.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                    && part.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            });
+        if !valid {
+            return Err(invalid("the prefix must be a lower case DNS subdomain"));
+        }
+    }
+    if !is_valid_name(name) {
+        return Err(invalid(
+            "the name must be at most 63 letters, digits, '-', '_' or '.', starting and ending with a letter or digit",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment(labels: &[(&str, &str)]) -> Environment {
+        let mut env = Environment::new("dev");
+        env.labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        env
+    }
+
+    #[test]
+    fn stamps_objects_and_pod_templates() {
+        let env = environment(&[("team", "payments")]);
+        let service = Service::new("api", None, "1.2.0+build.7");
+        let metadata = StandardMetadata::for_service(&env, &service, Some("abc123")).unwrap();
+        let content = "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\n  labels:\n    app: api\n    app.kubernetes.io/managed-by: helm\nspec:\n  template:\n    metadata:\n      labels:\n        app: api\n---\napiVersion: v1\nkind: Service\nmetadata:\n  name: api\n";
+
+        let stamped = parse_documents(&metadata.apply(content).unwrap()).unwrap();
+
+        let labels = &stamped[0]["metadata"]["labels"];
+        assert_eq!(labels["app"], "api");
+        assert_eq!(labels[NAME_LABEL], "api");
+        assert_eq!(labels[VERSION_LABEL], "1.2.0_build.7");
+        assert_eq!(labels[MANAGED_BY_LABEL], "sailr");
+        assert_eq!(labels[ENVIRONMENT_LABEL], "dev");
+        assert_eq!(labels[SERVICE_LABEL], "api");
+        assert_eq!(labels["team"], "payments");
+        assert_eq!(
+            stamped[0]["metadata"]["annotations"][SOURCE_REVISION_ANNOTATION],
+            "abc123"
+        );
+        let pod = &stamped[0]["spec"]["template"]["metadata"];
+        assert_eq!(pod["labels"][MANAGED_BY_LABEL], "sailr");
+        assert_eq!(pod["labels"]["app"], "api");
+        assert!(pod.get("annotations").is_none());
+        assert_eq!(stamped[1]["metadata"]["labels"][SERVICE_LABEL], "api");
+        assert_eq!(recorded_revision([content]), None);
+        assert_eq!(
+            recorded_revision([metadata.apply(content).unwrap().as_str()]),
+            Some("abc123".to_string())
+        );
+    }
+
+    #[test]
+    fn pod_templates_keep_matching_their_selector() {
+        let env = environment(&[("team", "payments")]);
+        let service = Service::new("cache", None, "7.2.0");
+        let metadata = StandardMetadata::for_service(&env, &service, None).unwrap();
+        let content = "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: cache-redis\n  labels:\n    app.kubernetes.io/name: redis\n    app.kubernetes.io/managed-by: Helm\nspec:\n  selector:\n    matchLabels:\n      app.kubernetes.io/name: redis\n      app.kubernetes.io/instance: cache\n      sailr.dev/service: cache\n  template:\n    metadata:\n      labels:\n        app.kubernetes.io/name: redis\n        app.kubernetes.io/instance: cache\n        app.kubernetes.io/managed-by: Helm\n        sailr.dev/service: cache\n";
+
+        let stamped = parse_documents(&metadata.apply(content).unwrap()).unwrap();
+
+        let selector = &stamped[0]["spec"]["selector"]["matchLabels"];
+        let pod_labels = &stamped[0]["spec"]["template"]["metadata"]["labels"];
+        assert_eq!(
+            selector,
+            &parse_documents(content).unwrap()[0]["spec"]["selector"]["matchLabels"]
+        );
+        for (key, value) in selector.as_object().unwrap() {
+            assert_eq!(&pod_labels[key], value, "{}", key);
+        }
+        assert_eq!(pod_labels[MANAGED_BY_LABEL], "Helm");
+        assert_eq!(pod_labels[ENVIRONMENT_LABEL], "dev");
+        assert_eq!(pod_labels["team"], "payments");
+        assert_eq!(stamped[0]["metadata"]["labels"][NAME_LABEL], "cache");
+        assert_eq!(stamped[0]["metadata"]["labels"][MANAGED_BY_LABEL], "sailr");
+    }
+
+    #[test]
+    fn cron_jobs_label_their_job_and_pod_templates() {
+        let env = environment(&[]);
+        let service = Service::new("report", None, "latest");
+        let metadata = StandardMetadata::for_service(&env, &service, None).unwrap();
+        let content = "apiVersion: batch/v1\nkind: CronJob\nmetadata:\n  name: report\nspec:\n  jobTemplate:\n    spec:\n      template:\n        spec: {}\n";
+
+        let stamped = parse_documents(&metadata.apply(content).unwrap()).unwrap();
+
+        assert!(stamped[0]["metadata"].get("annotations").is_none());
+        assert_eq!(
+            stamped[0]["spec"]["jobTemplate"]["metadata"]["labels"][SERVICE_LABEL],
+            "report"
+        );
+        assert_eq!(
+            stamped[0]["spec"]["jobTemplate"]["spec"]["template"]["metadata"]["labels"]
+                [VERSION_LABEL],
+            "latest"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_and_reserved_common_labels() {
+        let service = Service::new("api", None, "1.0.0");
+        for (key, value) in [
+            ("Example.com/team", "a"),
+            ("team/", "a"),
+            ("-team", "a"),
+            ("team", "not valid"),
+            (MANAGED_BY_LABEL, "me"),
+        ] {
+            let env = environment(&[(key, value)]);
+            assert!(
+                StandardMetadata::for_service(&env, &service, None).is_err(),
+                "{}={} should be rejected",
+                key,
+                value
+            );
+        }
+
+        let env = environment(&[("example.com/team", "payments"), ("tier", "")]);
+        assert!(StandardMetadata::for_service(&env, &service, None).is_ok());
+    }
+}
@@ -1,8 +1,26 @@
-use std::{collections::BTreeSet, error::Error, path::Path};
-
-use crate::{config::Config, environment::Environment, filesystem::FileSystemManager, LOGGER};
-
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config, environment::Environment, errors::TemplateError, filesystem::FileSystemManager,
+    LOGGER,
+};
+
+pub mod config_map;
+pub mod engine;
+pub mod labels;
+pub mod namespaces;
 pub mod scaffolding;
+pub mod search_path;
+pub mod validation;
+
+use config_map::{BuiltConfigMap, ConfigMapBuilder};
+use engine::{RenderOutput, TemplateContext, TemplateEngine};
+use search_path::{TemplateLayer, TemplateSearchPath, EMBEDDED_TEMPLATES};
+use validation::ManifestValidator;
 
 #[derive(Clone, Debug)]
 pub struct Template {
@@ -10,6 +28,8 @@ pub struct Template {
     pub file_name: String,
     pub content: String,
     pub full_path: String,
+    /// The search path layer the template was read from.
+    pub layer: TemplateLayer,
 }
 
 impl Template {
@@ -19,13 +39,31 @@ impl Template {
             file_name: file_name.clone(),
             content,
             full_path: format!("{}/{}", name.clone(), file_name.clone()),
+            layer: TemplateLayer::Shared(PathBuf::from("k8s/templates")),
         }
     }
+
+    pub fn with_layer(mut self, layer: TemplateLayer) -> Template {
+        self.layer = layer;
+        self
+    }
+
+    // The file the template was read from, for messages.
+    pub fn source_path(&self) -> String {
+        self.layer.locate(&self.full_path)
+    }
 }
 
+/// Directory below a templates root holding the partials shared by every service.
+pub const PARTIALS_DIR: &str = "_partials";
+
 pub struct TemplateManager {
     filemanager: FileSystemManager,
     templates: Vec<(String, String)>,
+    engine: TemplateEngine,
+    validator: ManifestValidator,
+    // Where each loaded partial was read from, for error messages.
+    partial_paths: BTreeMap<String, String>,
 }
 
 impl Default for TemplateManager {
@@ -41,44 +79,20 @@ impl TemplateManager {
         TemplateManager {
             filemanager: FileSystemManager::new("./k8s/templates".to_string()),
             templates: Vec::new(),
+            engine: TemplateEngine::new(),
+            validator: ManifestValidator::default(),
+            partial_paths: BTreeMap::new(),
         }
     }
 
     // Copies the base templates embedded in the binary to the `./k8s/templates` directory.
     // This is used to provide boilerplate resource definitions for generating Kubernetes resources.
     pub fn copy_base_templates(&mut self) -> Result<(), Box<dyn Error>> {
-        self.templates.push((
-            "aux/redis/deployment.yaml".to_string(),
-            include_str!("k8s/redis/deployment.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/redis/service.yaml".to_string(),
-            include_str!("k8s/redis/service.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/postgres/deployment.yaml".to_string(),
-            include_str!("k8s/postgres/deployment.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/postgres/service.yaml".to_string(),
-            include_str!("k8s/postgres/service.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/postgres/pvc.yaml".to_string(),
-            include_str!("k8s/postgres/pvc.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/registry/deployment.yaml".to_string(),
-            include_str!("k8s/registry/deployment.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/registry/service.yaml".to_string(),
-            include_str!("k8s/registry/service.yaml").to_string(),
-        ));
-        self.templates.push((
-            "aux/registry/pvc.yaml".to_string(),
-            include_str!("k8s/registry/pvc.yaml").to_string(),
-        ));
+        self.templates.extend(
+            EMBEDDED_TEMPLATES
+                .iter()
+                .map(|(name, template)| (name.to_string(), template.to_string())),
+        );
 
         for (name, template) in &self.templates {
             self.filemanager
@@ -94,20 +108,33 @@ impl TemplateManager {
         Ok(())
     }
 
-    // Each tuple in the returned vector consists of:
-    // - The directory name of the template
-    // - The file name of the template
-    // - The content of the template
+    // The template search path for `env`: its own templates first, then the shared and
+    // embedded ones. Without an environment only the latter two are searched.
+    pub fn search_path(&self, env: Option<&Environment>) -> TemplateSearchPath {
+        match env {
+            Some(env) => TemplateSearchPath::for_environment(&env.name),
+            None => TemplateSearchPath::shared(),
+        }
+    }
+
+    // Reads the templates of every service in `env`, resolving each file through the
+    // search path. Every file records the layer it was read from.
     pub fn read_templates(
         &mut self,
         env: Option<&Environment>,
     ) -> Result<(Vec<Template>, Vec<Config>), Box<dyn Error>> {
+        let search_path = self.search_path(env);
+        self.load_partials(&search_path)?;
         let mut template_dirs: BTreeSet<String> = BTreeSet::new();
         //read the templates from the environment
         //if path is specified in path, read the templates from the path instead and append to the template_dirs
         if let Some(env) = &env {
             for service in &env.services {
                 let service_path = service.get_path();
+                // Services rendered purely from a Helm chart need no template directory.
+                if service.helm.is_some() && !search_path.is_dir(&service_path) {
+                    continue;
+                }
                 if !service_path.contains('/') {
                     template_dirs.insert(service_path);
                     continue;
@@ -120,19 +147,13 @@ impl TemplateManager {
                 };
                 let parent = parent.to_str().unwrap().to_string();
 
-                let service_template_dir = self.filemanager.read_dir(&parent)?;
                 template_dirs.remove(&parent);
-
-                let templates = service_template_dir.into_iter().filter_map(|x| {
-                    let full_path = Path::new(&parent).join(&x).to_str().unwrap().to_string();
-
-                    if self.filemanager.is_dir(&full_path) {
-                        Some(full_path)
-                    } else {
-                        None
-                    }
-                });
-                template_dirs.extend(templates);
+                template_dirs.extend(
+                    search_path
+                        .subdirs(&parent)
+                        .into_iter()
+                        .map(|dir| format!("{}/{}", parent, dir)),
+                );
             }
         }
 
@@ -140,8 +161,6 @@ impl TemplateManager {
         let mut config_maps = Vec::new();
 
         for template_name in template_dirs {
-            let template_dir = self.filemanager.read_dir(&template_name)?;
-
             if let Some(env) = &env {
                 if !env.services.iter().any(|x| {
                     template_name.contains(x.name.as_str()) || template_name == x.get_path()
@@ -150,83 +169,102 @@ impl TemplateManager {
                 }
             }
 
-            for template_file in template_dir {
-                if template_file == "config" {
-                    let (config_name, config_map_content) =
-                        self.read_config_files(&template_name)?;
-                    config_maps.push(Config::new(
-                        &template_name.clone(),
-                        &config_map_content,
-                        &config_name,
-                        &"./k8s/templates".to_string(),
-                    ));
-                    continue;
-                }
+            if !search_path.is_dir(&template_name) {
+                return Err(format!(
+                    "template directory '{}' not found in any of: {}",
+                    template_name,
+                    search_path
+                        .layers()
+                        .iter()
+                        .map(|layer| layer.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .into());
+            }
 
-                let path = Path::new(&template_name)
-                    .join(&template_file)
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-
-                let template = self.filemanager.read_file(&path, None)?;
-                templates.push(Template::new(
-                    template_name.clone(),
-                    template_file,
-                    template,
+            let config_path = format!("{}/config", template_name);
+            if let Some((config_dir, layer)) = search_path.find_dir(&config_path) {
+                let service = env.and_then(|env| {
+                    env.services.iter().find(|service| {
+                        service.get_path() == template_name
+                            || template_name.rsplit('/').next() == Some(service.name.as_str())
+                    })
+                });
+                let default_namespace = env.map(Environment::default_namespace);
+                let namespace = match (default_namespace.as_deref(), service) {
+                    (Some(default_namespace), Some(service)) => {
+                        Some(service.namespace_or(default_namespace))
+                    }
+                    (default_namespace, _) => default_namespace,
+                };
+                let hash_suffix = service
+                    .and_then(|service| service.config_map.as_ref())
+                    .is_some_and(|config_map| config_map.hash_suffix);
+                report_layer(layer, &config_path);
+                let config_map =
+                    self.read_config_files(&template_name, &config_dir, namespace, hash_suffix)?;
+                config_maps.push(Config::from_built(
+                    &template_name,
+                    &config_map,
+                    &self.filemanager.path,
                 ));
             }
+
+            for (template_file, layer) in search_path.files(&template_name)? {
+                let path = format!("{}/{}", template_name, template_file);
+                report_layer(layer, &path);
+                let template = search_path.read(layer, &path)?;
+                templates.push(
+                    Template::new(template_name.clone(), template_file, template)
+                        .with_layer(layer.clone()),
+                );
+            }
         }
 
         Ok((templates, config_maps))
     }
 
+    // Loads the `_partials/*.yaml` files of every layer into the engine, keyed by file
+    // stem. Like templates, a partial in an earlier layer replaces a later one.
+    pub fn load_partials(
+        &mut self,
+        search_path: &TemplateSearchPath,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut partials = BTreeMap::new();
+        self.partial_paths.clear();
+        for (file_name, layer) in search_path.files(PARTIALS_DIR)? {
+            let Some(name) = file_name
+                .strip_suffix(".yaml")
+                .or_else(|| file_name.strip_suffix(".yml"))
+            else {
+                continue;
+            };
+            let path = format!("{}/{}", PARTIALS_DIR, file_name);
+            report_layer(layer, &path);
+            partials.insert(name.to_string(), search_path.read(layer, &path)?);
+            self.partial_paths.insert(name.to_string(), path);
+        }
+        self.engine = std::mem::take(&mut self.engine).partials(partials);
+        Ok(())
+    }
+
+    // Builds the ConfigMap for the `config/` directory of a template directory. It is
+    // named after the last path component, which is the service name.
     pub fn read_config_files(
         &self,
-        path: &String,
-    ) -> Result<(String, Vec<String>), Box<dyn Error>> {
-        let config_files = self.filemanager.read_dir(
-            &Path::new(&path.to_string())
-                .join("config")
-                .to_str()
-                .unwrap()
-                .to_string(),
-        )?;
-
-        let config_map = config_files.into_iter().fold(
-            (
-                format!(
-                    "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: {}\ndata:\n",
-                    path
-                )
-                .to_string(),
-                Vec::new(),
-            ),
-            |mut acc: (String, Vec<String>), config| {
-                let config_file = &Path::new(&path.to_string())
-                    .join("config")
-                    .join(&config)
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-
-                let config_content = self
-                    .filemanager
-                    .read_file(config_file, Some(&"./k8s/templates".to_string()))
-                    .unwrap();
-
-                acc.0.push_str(&format!("\n  {:?}: |", config));
-
-                for line in config_content.lines() {
-                    acc.0.push_str(&format!("\n    {}", line))
-                }
-
-                acc.1.push(config);
-                acc
-            },
-        );
-
-        Ok(config_map)
+        path: &str,
+        config_dir: &Path,
+        namespace: Option<&str>,
+        hash_suffix: bool,
+    ) -> Result<BuiltConfigMap, Box<dyn Error>> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let mut builder = ConfigMapBuilder::new(name).hash_suffix(hash_suffix);
+        if let Some(namespace) = namespace {
+            builder = builder.namespace(namespace);
+        }
+        builder.add_dir(config_dir)?;
+        Ok(builder.build()?)
     }
 
     // Replaces variables in the template. Any missing variable will be left untouched,
@@ -234,25 +272,109 @@ impl TemplateManager {
     pub fn replace_variables(
         &self,
         template: &Template,
-        variables: &Vec<(String, String)>,
+        variables: &[(String, String)],
     ) -> Result<String, Box<dyn Error>> {
-        let mut content = template.content.clone();
+        let output = self.render_template(template, &TemplateContext::from_pairs(variables))?;
+        self.check_rendered(template, &output.content)?;
+        Ok(output.content)
+    }
 
-        for (key, value) in variables {
-            content = content.replace(&format!("{{{{{}}}}}", key), value);
+    // Renders the template without validating the result. Placeholders that didn't
+    // resolve are left in the content and listed in `RenderOutput::unresolved`.
+    pub fn render_template(
+        &self,
+        template: &Template,
+        context: &TemplateContext,
+    ) -> Result<RenderOutput, Box<dyn Error>> {
+        match self.engine.render_with_report(&template.content, context) {
+            Ok(output) => Ok(output),
+            Err(e) => {
+                // Errors inside partials point at the partial, with the include chain
+                // that led there: `api/deployment.yaml:12 > _partials/probes.yaml:3`.
+                let chain = e.partial_chain();
+                let innermost = e.innermost();
+                let mut location = template.full_path.clone();
+                let mut source = template.content.as_str();
+                for (name, line) in &chain {
+                    location = format!(
+                        "{}:{} > {}",
+                        location,
+                        line,
+                        self.partial_paths
+                            .get(*name)
+                            .cloned()
+                            .unwrap_or_else(|| format!("{}/{}.yaml", PARTIALS_DIR, name))
+                    );
+                    source = self.engine.partial(name).unwrap_or_default();
+                }
+                if !chain.is_empty() {
+                    location = format!("{}:{}", location, innermost.line());
+                }
+                LOGGER.status(
+                    "Failed Check",
+                    &format!(
+                        "Template Error: {}\n {}\n{}",
+                        location,
+                        innermost,
+                        read_line_number(source, innermost.line())
+                    ),
+                    "red",
+                );
+                Err(e.into())
+            }
         }
+    }
+
+    // Every variable path the template reads, through the partials it includes.
+    pub fn referenced_variables(
+        &self,
+        template: &Template,
+    ) -> Result<BTreeSet<String>, TemplateError> {
+        self.engine.referenced_variables(&template.content)
+    }
 
-        if let Err(e) = self.validate_yaml(content.clone()) {
+    // Validates rendered template content as YAML, then checks every document against
+    // the Kubernetes schema for its kind, and reports the result.
+    pub fn check_rendered(&self, template: &Template, content: &str) -> Result<(), Box<dyn Error>> {
+        if let Err(e) = self.validate_yaml(content.to_string()) {
             LOGGER.status(
                 "Failed Check",
                 &format!("YAML Validation Error: {}\n {}", template.full_path, e),
                 "red",
             );
             return Err(anyhow::anyhow!("YAML validation failed: {}", e).into());
-        } else {
-            LOGGER.status("Passed Check", &template.full_path.to_string(), "green");
         }
-        Ok(content)
+
+        let report = self.validator.validate(content);
+        for kind in &report.unknown_kinds {
+            LOGGER.warn(&format!(
+                "{}: no schema found for {}, skipping schema validation",
+                template.full_path, kind
+            ));
+        }
+        if !report.violations.is_empty() {
+            let details = report
+                .violations
+                .iter()
+                .map(|violation| format!("  {}", violation))
+                .collect::<Vec<_>>()
+                .join("\n");
+            LOGGER.status(
+                "Failed Check",
+                &format!(
+                    "Schema Validation Error: {}\n{}",
+                    template.full_path, details
+                ),
+                "red",
+            );
+            return Err(anyhow::anyhow!(
+                "schema validation failed with {} violation(s)",
+                report.violations.len()
+            )
+            .into());
+        }
+        LOGGER.status("Passed Check", &template.full_path.to_string(), "green");
+        Ok(())
     }
 
     // Performs basic syntax validation on the provided YAML string using `serde_yaml`.
@@ -262,8 +384,10 @@ impl TemplateManager {
         match serde_yaml::from_str::<serde_yaml::Value>(&yaml) {
             Ok(_) => (),
             Err(e) => {
-                let location = e.location().unwrap();
-                let line = read_line_number(&yaml, location.line());
+                let line = e
+                    .location()
+                    .map(|location| read_line_number(&yaml, location.line()))
+                    .unwrap_or_default();
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
                     format!("{} \n{}", e, line),
@@ -275,6 +399,19 @@ impl TemplateManager {
     }
 }
 
+// Overrides and embedded fallbacks are shown; shared templates only in verbose mode.
+fn report_layer(layer: &TemplateLayer, path: &str) {
+    match layer {
+        TemplateLayer::Shared(_) => LOGGER.debug(&format!("{} from {}", path, layer)),
+        TemplateLayer::Environment(_) => {
+            LOGGER.status("Override", &format!("{} from {}", path, layer), "cyan")
+        }
+        TemplateLayer::Embedded => {
+            LOGGER.status("Embedded", &format!("{} from {}", path, layer), "cyan")
+        }
+    }
+}
+
 fn read_line_number(input: &str, line: usize) -> String {
     let mut line_count = 0;
     let lines = input.lines();
@@ -287,7 +424,7 @@ fn read_line_number(input: &str, line: usize) -> String {
         if line_count > line + window_size {
             break; // Reached window limit
         }
-        if line_count >= line - window_size && line_count <= line + window_size {
+        if line_count >= line.saturating_sub(window_size) && line_count <= line + window_size {
             if line_count == line {
                 result.push(format!(
                     "{}: {} {}",
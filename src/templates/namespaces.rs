@@ -0,0 +1,274 @@
+//! Places generated resources in their service's namespace.
+//!
+//! Namespaced documents without a `metadata.namespace` get the service's namespace,
+//! so templates no longer have to set `namespace: {{service_namespace}}` themselves.
+//! Services without a namespace of their own use the environment's, which is its name
+//! made a valid namespace name. Cluster-scoped kinds are left alone; custom kinds this
+//! list does not know are corrected at deploy time, once the cluster says how they are
+//! scoped. Every namespace the environment's services use
+//! is written to `namespaces.yaml`, which deploy applies before anything else.
+
+use std::collections::BTreeSet;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    environment::{Environment, Service},
+    errors::NamespaceError,
+    overlays::parse_documents,
+    templates::labels::StandardMetadata,
+};
+
+/// File in the generated environment directory holding the Namespace manifests.
+pub const NAMESPACES_FILE: &str = "namespaces.yaml";
+
+/// Namespaces that exist in every cluster and are never generated.
+const SYSTEM_NAMESPACES: &[&str] = &["default", "kube-system", "kube-public", "kube-node-lease"];
+
+/// Built-in kinds that are not namespaced. Custom kinds whose name starts with
+/// `Cluster`, such as cert-manager's `ClusterIssuer`, are treated the same way.
+const CLUSTER_SCOPED_KINDS: &[&str] = &[
+    "APIService",
+    "CSIDriver",
+    "CSINode",
+    "CertificateSigningRequest",
+    "ComponentStatus",
+    "CustomResourceDefinition",
+    "DeviceClass",
+    "FlowSchema",
+    "GatewayClass",
+    "IPAddress",
+    "IngressClass",
+    "MutatingAdmissionPolicy",
+    "MutatingAdmissionPolicyBinding",
+    "MutatingWebhookConfiguration",
+    "Namespace",
+    "Node",
+    "PersistentVolume",
+    "PriorityClass",
+    "PriorityLevelConfiguration",
+    "ResourceSlice",
+    "RuntimeClass",
+    "SelfSubjectAccessReview",
+    "SelfSubjectReview",
+    "SelfSubjectRulesReview",
+    "ServiceCIDR",
+    "StorageClass",
+    "SubjectAccessReview",
+    "TokenReview",
+    "ValidatingAdmissionPolicy",
+    "ValidatingAdmissionPolicyBinding",
+    "ValidatingWebhookConfiguration",
+    "VolumeAttachment",
+    "VolumeAttributesClass",
+];
+
+// Whether objects of `kind` live in a namespace.
+pub fn is_namespaced(kind: &str) -> bool {
+    !kind.starts_with("Cluster") && !CLUSTER_SCOPED_KINDS.contains(&kind)
+}
+
+// Sets `metadata.namespace` on every namespaced document in `content` that has none.
+// Namespaces chosen by the template are kept.
+pub fn set_namespace(content: &str, namespace: &str) -> Result<String, NamespaceError> {
+    let mut documents = parse_documents(content).map_err(NamespaceError::Render)?;
+    let mut changed = false;
+    for document in &mut documents {
+        let Some(object) = document.as_object_mut() else {
+            continue;
+        };
+        if !object.contains_key("apiVersion")
+            || !object
+                .get("kind")
+                .and_then(Value::as_str)
+                .is_some_and(is_namespaced)
+        {
+            continue;
+        }
+        let metadata = object
+            .entry("metadata")
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Some(metadata) = metadata.as_object_mut() else {
+            continue;
+        };
+        if metadata
+            .get("namespace")
+            .and_then(Value::as_str)
+            .is_none_or(str::is_empty)
+        {
+            metadata.insert("namespace".to_string(), json!(namespace));
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(content.to_string());
+    }
+
+    documents
+        .iter()
+        .map(|document| {
+            serde_yaml::to_string(document).map_err(|e| NamespaceError::Render(e.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|documents| documents.join("---\n"))
+}
+
+// The namespace of `env`'s services that don't set one: the environment's name in
+// lower case, with anything a namespace name cannot hold replaced by '-', so that an
+// environment such as `local_dev` deploys to `local-dev`.
+pub fn environment_namespace(env_name: &str) -> String {
+    let namespace: String = env_name
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(63)
+        .collect();
+    match namespace.trim_matches('-') {
+        "" => "default".to_string(),
+        namespace => namespace.to_string(),
+    }
+}
+
+// The namespaces used by the services of `env`, without the system namespaces.
+pub fn environment_namespaces(env: &Environment) -> Result<BTreeSet<String>, NamespaceError> {
+    service_namespaces(env, &env.services)
+}
+
+// The namespaces used by `services` of `env`, without the system namespaces. Each
+// namespace a service sets is checked to be a valid namespace name.
+pub fn service_namespaces<'a>(
+    env: &Environment,
+    services: impl IntoIterator<Item = &'a Service>,
+) -> Result<BTreeSet<String>, NamespaceError> {
+    let mut namespaces = BTreeSet::new();
+    let default_namespace = env.default_namespace();
+    for service in services {
+        let namespace = service.namespace_or(&default_namespace);
+        if !is_valid_namespace(namespace) {
+            return Err(NamespaceError::InvalidName {
+                name: namespace.to_string(),
+                service: service.name.clone(),
+            });
+        }
+        if !SYSTEM_NAMESPACES.contains(&namespace) {
+            namespaces.insert(namespace.to_string());
+        }
+    }
+    Ok(namespaces)
+}
+
+// A Namespace manifest for each of `namespaces`, labelled with `metadata`.
+pub fn namespace_manifests(
+    namespaces: &BTreeSet<String>,
+    metadata: &StandardMetadata,
+) -> Result<String, NamespaceError> {
+    let mut documents = Vec::new();
+    for namespace in namespaces {
+        let manifest = json!({
+            "apiVersion": "v1",
+            "kind": "Namespace",
+            "metadata": { "name": namespace },
+        });
+        let content =
+            serde_yaml::to_string(&manifest).map_err(|e| NamespaceError::Render(e.to_string()))?;
+        documents.push(
+            metadata
+                .apply(&content)
+                .map_err(|e| NamespaceError::Render(e.to_string()))?,
+        );
+    }
+    Ok(documents.join("---\n"))
+}
+
+// Namespace names are DNS labels: at most 63 lower case letters, digits and '-',
+// starting and ending with a letter or digit.
+pub(crate) fn is_valid_namespace(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_namespaces_of_namespaced_kinds_only() {
+        let content = "apiVersion: apps/v1\nkind: Deployment\nmetadata:\n  name: api\n---\napiVersion: v1\nkind: Service\nmetadata:\n  name: api\n  namespace: edge\n---\napiVersion: rbac.authorization.k8s.io/v1\nkind: ClusterRole\nmetadata:\n  name: api\n---\napiVersion: cert-manager.io/v1\nkind: ClusterIssuer\nmetadata:\n  name: letsencrypt\n";
+
+        let documents = parse_documents(&set_namespace(content, "shop").unwrap()).unwrap();
+
+        assert_eq!(documents[0]["metadata"]["namespace"], "shop");
+        assert_eq!(documents[1]["metadata"]["namespace"], "edge");
+        assert!(documents[2]["metadata"].get("namespace").is_none());
+        assert!(documents[3]["metadata"].get("namespace").is_none());
+
+        let unchanged = "apiVersion: v1\nkind: Namespace\nmetadata:\n  name: shop\n";
+        assert_eq!(set_namespace(unchanged, "shop").unwrap(), unchanged);
+    }
+
+    #[test]
+    fn lists_every_namespace_the_environment_uses() {
+        let mut env = Environment::new("staging");
+        env.services = vec![
+            Service::new("api", None, "1.0.0"),
+            Service::new("web", Some("frontend"), "1.0.0"),
+            Service::new("dns", Some("kube-system"), "1.0.0"),
+            Service::new("worker", None, "1.0.0"),
+        ];
+
+        let namespaces = environment_namespaces(&env).unwrap();
+        assert_eq!(
+            namespaces.iter().collect::<Vec<_>>(),
+            vec!["frontend", "staging"]
+        );
+
+        let manifests = namespace_manifests(
+            &namespaces,
+            &StandardMetadata::new().label("app.kubernetes.io/managed-by", "sailr"),
+        )
+        .unwrap();
+        let documents = parse_documents(&manifests).unwrap();
+        assert_eq!(documents.len(), 2);
+        assert_eq!(documents[0]["kind"], "Namespace");
+        assert_eq!(documents[0]["metadata"]["name"], "frontend");
+        assert_eq!(
+            documents[1]["metadata"]["labels"]["app.kubernetes.io/managed-by"],
+            "sailr"
+        );
+
+        env.services
+            .push(Service::new("bad", Some("Not_Valid"), "1.0.0"));
+        assert!(environment_namespaces(&env).is_err());
+    }
+
+    #[test]
+    fn environment_names_become_valid_namespaces() {
+        assert_eq!(environment_namespace("staging"), "staging");
+        assert_eq!(environment_namespace("local_dev"), "local-dev");
+        assert_eq!(environment_namespace("QA.Env_"), "qa-env");
+        assert_eq!(environment_namespace("__"), "default");
+
+        let mut env = Environment::new("local_dev");
+        env.services = vec![Service::new("api", None, "1.0.0")];
+        assert_eq!(
+            environment_namespaces(&env)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec!["local-dev"]
+        );
+        assert!(is_namespaced("Gateway"));
+        assert!(!is_namespaced("GatewayClass"));
+    }
+}
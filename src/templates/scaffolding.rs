@@ -565,19 +565,17 @@ data:
 }
 
 pub fn generate_secret_template(service_name: &str, app_type: &str) -> String {
-    let secret_data = match app_type {
-        "database-client" => {
-            r#"  DATABASE_URL: "cG9zdGdyZXNxbDovL3VzZXI6cGFzc3dvcmRAbG9jYWxob3N0OjU0MzIvbXlhcHA=" # base64 encoded"#
-        }
-        "api" => r#"  JWT_SECRET: "bXlfc2VjcmV0X2p3dF9rZXk=" # base64 encoded"#,
-        "worker" => {
-            r#"  QUEUE_URL: "cmVkaXM6Ly9sb2NhbGhvc3Q6NjM3OS8wIyBiYXNlNjQgZW5jb2RlZA==" # base64 encoded"#
-        }
-        _ => r#"  SECRET_KEY: "bXlfc2VjcmV0X2tleQ==" # base64 encoded"#,
+    let secret_key = match app_type {
+        "database-client" => "DATABASE_URL",
+        "api" => "JWT_SECRET",
+        "worker" => "QUEUE_URL",
+        _ => "SECRET_KEY",
     };
 
     format!(
-        r#"apiVersion: v1
+        r#"# Values are injected at deploy time from k8s/environments/<env>/secrets.enc.toml.
+# Store one with: sailr secrets set --name <env> {service_name}-secrets/{secret_key}
+apiVersion: v1
 kind: Secret
 metadata:
   name: {service_name}-secrets
@@ -585,11 +583,9 @@ metadata:
     app: {service_name}
     type: {app_type}
 type: Opaque
-data:
-{secret_data}
 "#,
         service_name = service_name,
         app_type = app_type,
-        secret_data = secret_data
+        secret_key = secret_key
     )
 }
@@ -0,0 +1,303 @@
+//! The ordered list of places a service's templates are looked up in.
+//!
+//! For an environment the search path is:
+//!
+//! 1. `k8s/environments/<env>/templates/<service>`
+//! 2. `k8s/templates/<service>`
+//! 3. the templates embedded in the sailr binary
+//!
+//! Files are resolved one at a time, so an environment can replace a single
+//! `deployment.yaml` and still pick up the rest of the service from the shared
+//! templates. A `config/` directory is taken as a whole from the first layer that has
+//! one.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Base templates compiled into the binary, keyed by their path below the templates root.
+pub const EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "aux/redis/deployment.yaml",
+        include_str!("k8s/redis/deployment.yaml"),
+    ),
+    (
+        "aux/redis/service.yaml",
+        include_str!("k8s/redis/service.yaml"),
+    ),
+    (
+        "aux/postgres/deployment.yaml",
+        include_str!("k8s/postgres/deployment.yaml"),
+    ),
+    (
+        "aux/postgres/service.yaml",
+        include_str!("k8s/postgres/service.yaml"),
+    ),
+    (
+        "aux/postgres/pvc.yaml",
+        include_str!("k8s/postgres/pvc.yaml"),
+    ),
+    (
+        "aux/registry/deployment.yaml",
+        include_str!("k8s/registry/deployment.yaml"),
+    ),
+    (
+        "aux/registry/service.yaml",
+        include_str!("k8s/registry/service.yaml"),
+    ),
+    (
+        "aux/registry/pvc.yaml",
+        include_str!("k8s/registry/pvc.yaml"),
+    ),
+];
+
+/// One layer of the template search path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateLayer {
+    /// Templates that only apply to one environment.
+    Environment(PathBuf),
+    /// The project's shared templates, `k8s/templates`.
+    Shared(PathBuf),
+    /// Templates compiled into the binary.
+    Embedded,
+}
+
+impl TemplateLayer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemplateLayer::Environment(_) => "environment",
+            TemplateLayer::Shared(_) => "shared",
+            TemplateLayer::Embedded => "embedded",
+        }
+    }
+
+    /// Where `relative` lives in this layer, for messages.
+    pub fn locate(&self, relative: &str) -> String {
+        match self {
+            TemplateLayer::Environment(root) | TemplateLayer::Shared(root) => {
+                root.join(relative).display().to_string()
+            }
+            TemplateLayer::Embedded => format!("<embedded>/{}", relative),
+        }
+    }
+
+    fn root(&self) -> Option<&Path> {
+        match self {
+            TemplateLayer::Environment(root) | TemplateLayer::Shared(root) => Some(root),
+            TemplateLayer::Embedded => None,
+        }
+    }
+}
+
+impl fmt::Display for TemplateLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.root() {
+            Some(root) => write!(f, "{} ({})", self.name(), root.display()),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TemplateSearchPath {
+    layers: Vec<TemplateLayer>,
+}
+
+impl TemplateSearchPath {
+    pub fn new(layers: Vec<TemplateLayer>) -> TemplateSearchPath {
+        TemplateSearchPath { layers }
+    }
+
+    // The shared templates followed by the embedded ones.
+    pub fn shared() -> TemplateSearchPath {
+        TemplateSearchPath::new(vec![
+            TemplateLayer::Shared(PathBuf::from("k8s/templates")),
+            TemplateLayer::Embedded,
+        ])
+    }
+
+    // Environment-local templates, then the shared and embedded ones.
+    pub fn for_environment(env: &str) -> TemplateSearchPath {
+        let mut search_path = TemplateSearchPath::shared();
+        search_path.layers.insert(
+            0,
+            TemplateLayer::Environment(Path::new("k8s/environments").join(env).join("templates")),
+        );
+        search_path
+    }
+
+    pub fn layers(&self) -> &[TemplateLayer] {
+        &self.layers
+    }
+
+    // Whether any layer has the directory `dir`.
+    pub fn is_dir(&self, dir: &str) -> bool {
+        self.layers.iter().any(|layer| match layer.root() {
+            Some(root) => root.join(dir).is_dir(),
+            None => embedded_entries(dir).next().is_some(),
+        })
+    }
+
+    // The subdirectories of `dir` across every layer.
+    pub fn subdirs(&self, dir: &str) -> BTreeSet<String> {
+        let mut subdirs = BTreeSet::new();
+        for layer in &self.layers {
+            match layer.root() {
+                Some(root) => {
+                    let Ok(entries) = fs::read_dir(root.join(dir)) else {
+                        continue;
+                    };
+                    subdirs.extend(
+                        entries
+                            .filter_map(Result::ok)
+                            .filter(|entry| entry.path().is_dir())
+                            .map(|entry| entry.file_name().to_string_lossy().into_owned()),
+                    );
+                }
+                None => subdirs.extend(
+                    embedded_entries(dir)
+                        .filter_map(|rest| rest.split_once('/'))
+                        .map(|(subdir, _)| subdir.to_string()),
+                ),
+            }
+        }
+        subdirs
+    }
+
+    // Every file directly in `dir`, with the first layer that provides it.
+    pub fn files(&self, dir: &str) -> io::Result<BTreeMap<String, &TemplateLayer>> {
+        let mut files = BTreeMap::new();
+        for layer in &self.layers {
+            let names: Vec<String> = match layer.root() {
+                Some(root) => {
+                    let path = root.join(dir);
+                    if !path.is_dir() {
+                        continue;
+                    }
+                    fs::read_dir(path)?
+                        .filter_map(Result::ok)
+                        .filter(|entry| entry.path().is_file())
+                        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                        .collect()
+                }
+                None => embedded_entries(dir)
+                    .filter(|rest| !rest.contains('/'))
+                    .map(str::to_string)
+                    .collect(),
+            };
+            for name in names {
+                files.entry(name).or_insert(layer);
+            }
+        }
+        Ok(files)
+    }
+
+    // The first layer on disk that has the directory `dir`. Embedded templates have no
+    // directories of their own.
+    pub fn find_dir(&self, dir: &str) -> Option<(PathBuf, &TemplateLayer)> {
+        self.layers.iter().find_map(|layer| {
+            let path = layer.root()?.join(dir);
+            path.is_dir().then_some((path, layer))
+        })
+    }
+
+    // Reads `relative` from `layer`.
+    pub fn read(&self, layer: &TemplateLayer, relative: &str) -> io::Result<String> {
+        match layer.root() {
+            Some(root) => fs::read_to_string(root.join(relative)),
+            None => EMBEDDED_TEMPLATES
+                .iter()
+                .find(|(path, _)| *path == relative)
+                .map(|(_, content)| content.to_string())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no embedded template {}", relative),
+                    )
+                }),
+        }
+    }
+}
+
+// Paths of the embedded templates below `dir`, relative to it.
+fn embedded_entries(dir: &str) -> impl Iterator<Item = &'static str> + '_ {
+    let prefix = format!("{}/", dir.trim_end_matches('/'));
+    EMBEDDED_TEMPLATES
+        .iter()
+        .filter_map(move |(path, _)| path.strip_prefix(prefix.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn files_resolve_through_the_layers_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let env = dir.path().join("env");
+        let shared = dir.path().join("shared");
+        write(&env, "api/deployment.yaml", "env deployment");
+        write(&shared, "api/deployment.yaml", "shared deployment");
+        write(&shared, "api/service.yaml", "shared service");
+        write(&shared, "api/config/app.env", "A=1");
+        write(&env, "aux/redis/service.yaml", "env redis service");
+
+        let search_path = TemplateSearchPath::new(vec![
+            TemplateLayer::Environment(env.clone()),
+            TemplateLayer::Shared(shared.clone()),
+            TemplateLayer::Embedded,
+        ]);
+
+        let files = search_path.files("api").unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            vec!["deployment.yaml", "service.yaml"]
+        );
+        assert_eq!(files["deployment.yaml"].name(), "environment");
+        assert_eq!(files["service.yaml"].name(), "shared");
+        assert_eq!(
+            search_path
+                .read(files["deployment.yaml"], "api/deployment.yaml")
+                .unwrap(),
+            "env deployment"
+        );
+        assert_eq!(
+            search_path.find_dir("api/config"),
+            Some((shared.join("api/config"), &search_path.layers()[1]))
+        );
+
+        let redis = search_path.files("aux/redis").unwrap();
+        assert_eq!(redis["service.yaml"].name(), "environment");
+        assert_eq!(redis["deployment.yaml"].name(), "embedded");
+        assert!(search_path
+            .read(redis["deployment.yaml"], "aux/redis/deployment.yaml")
+            .unwrap()
+            .contains("kind: Deployment"));
+    }
+
+    #[test]
+    fn directories_are_merged_across_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "aux/custom/deployment.yaml", "");
+
+        let search_path = TemplateSearchPath::new(vec![
+            TemplateLayer::Shared(dir.path().to_path_buf()),
+            TemplateLayer::Embedded,
+        ]);
+
+        assert!(search_path.is_dir("aux/postgres"));
+        assert!(!search_path.is_dir("aux/missing"));
+        assert_eq!(
+            search_path.subdirs("aux").into_iter().collect::<Vec<_>>(),
+            vec!["custom", "postgres", "redis", "registry"]
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Offline schema validation of rendered manifests.
+//!
+//! Built-in kinds are checked against the OpenAPI schemas shipped with the `k8s-openapi`
+//! v1_33 types. Custom resources are checked against JSON schemas in a directory checked
+//! into the repository (`k8s/crds` by default), named the way kubeconform expects:
+//! `<group>/<kind>_<version>.json` or `<kind>_<version>.json`, all lower case.
+
+use std::path::{Path, PathBuf};
+
+use k8s_openapi::{
+    api::{
+        apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+        autoscaling::v2::HorizontalPodAutoscaler,
+        batch::v1::{CronJob, Job},
+        core::v1::{
+            ConfigMap, Endpoints, LimitRange, Namespace, PersistentVolume, PersistentVolumeClaim,
+            Pod, ResourceQuota, Secret, Service, ServiceAccount,
+        },
+        networking::v1::{Ingress, IngressClass, NetworkPolicy},
+        policy::v1::PodDisruptionBudget,
+        rbac::v1::{ClusterRole, ClusterRoleBinding, Role, RoleBinding},
+        scheduling::v1::PriorityClass,
+        storage::v1::StorageClass,
+    },
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
+    Resource,
+};
+use schemars::{gen::SchemaGenerator, JsonSchema};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::templates::engine::edit_distance;
+
+pub const DEFAULT_CRD_SCHEMA_DIR: &str = "./k8s/crds";
+
+const QUANTITY_DEFINITION: &str = "io.k8s.apimachinery.pkg.api.resource.Quantity";
+
+/// A single problem found in a manifest document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// Zero-based index of the document within the file.
+    pub document: usize,
+    /// `Kind/name` of the offending resource, when known.
+    pub resource: String,
+    /// Dotted path to the offending field, e.g. `spec.template.spec.containers[0].image`.
+    pub path: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}: {}", self.resource, self.message)
+        } else {
+            write!(f, "{} {}: {}", self.resource, self.path, self.message)
+        }
+    }
+}
+
+/// Outcome of validating one file of (possibly multiple) YAML documents.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub violations: Vec<SchemaViolation>,
+    /// `apiVersion/kind` pairs for which no schema was available.
+    pub unknown_kinds: Vec<String>,
+}
+
+pub struct ManifestValidator {
+    crd_schema_dir: PathBuf,
+}
+
+impl Default for ManifestValidator {
+    fn default() -> Self {
+        Self::new(DEFAULT_CRD_SCHEMA_DIR)
+    }
+}
+
+impl ManifestValidator {
+    pub fn new(crd_schema_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            crd_schema_dir: crd_schema_dir.into(),
+        }
+    }
+
+    /// Validates every document in `content` against the schema for its kind.
+    pub fn validate(&self, content: &str) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        for (index, document) in serde_yaml::Deserializer::from_str(content).enumerate() {
+            let value = match serde_yaml::Value::deserialize(document)
+                .map_err(|e| e.to_string())
+                .and_then(|yaml| serde_json::to_value(yaml).map_err(|e| e.to_string()))
+            {
+                Ok(Value::Null) => continue,
+                Ok(value) => value,
+                Err(e) => {
+                    report.violations.push(SchemaViolation {
+                        document: index,
+                        resource: format!("document {}", index),
+                        path: String::new(),
+                        message: e,
+                    });
+                    continue;
+                }
+            };
+            self.validate_document(index, &value, &mut report);
+        }
+
+        report
+    }
+
+    fn validate_document(&self, index: usize, value: &Value, report: &mut ValidationReport) {
+        let api_version = value.get("apiVersion").and_then(Value::as_str);
+        let kind = value.get("kind").and_then(Value::as_str);
+        let name = value
+            .pointer("/metadata/name")
+            .and_then(Value::as_str)
+            .unwrap_or("<unnamed>");
+
+        let (Some(api_version), Some(kind)) = (api_version, kind) else {
+            report.violations.push(SchemaViolation {
+                document: index,
+                resource: format!("document {}", index),
+                path: String::new(),
+                message: "missing apiVersion or kind".to_string(),
+            });
+            return;
+        };
+
+        let Some(schema) = builtin_schema(api_version, kind)
+            .or_else(|| self.custom_resource_schema(api_version, kind))
+        else {
+            report
+                .unknown_kinds
+                .push(format!("{}/{}", api_version, kind));
+            return;
+        };
+
+        let mut errors = Vec::new();
+        let validator = SchemaWalker {
+            definitions: schema
+                .get("definitions")
+                .and_then(Value::as_object)
+                .cloned()
+                .unwrap_or_default(),
+        };
+        validator.check(value, &schema, "", &mut errors);
+
+        report
+            .violations
+            .extend(errors.into_iter().map(|(path, message)| SchemaViolation {
+                document: index,
+                resource: format!("{}/{}", kind, name),
+                path,
+                message,
+            }));
+    }
+
+    fn custom_resource_schema(&self, api_version: &str, kind: &str) -> Option<Value> {
+        let (group, version) = match api_version.split_once('/') {
+            Some((group, version)) => (group, version),
+            None => ("", api_version),
+        };
+        let file_name = format!("{}_{}.json", kind.to_lowercase(), version.to_lowercase());
+
+        let candidates = [
+            self.crd_schema_dir
+                .join(group.to_lowercase())
+                .join(&file_name),
+            self.crd_schema_dir.join(&file_name),
+        ];
+        candidates
+            .iter()
+            .filter(|path| path.is_file())
+            .find_map(|path| read_json(path))
+    }
+}
+
+fn read_json(path: &Path) -> Option<Value> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn schema_for<T: JsonSchema>() -> Value {
+    let root = SchemaGenerator::default().into_root_schema_for::<T>();
+    serde_json::to_value(root).unwrap_or(Value::Null)
+}
+
+macro_rules! builtin_schemas {
+    ($api_version:expr, $kind:expr, [$($ty:ty),* $(,)?]) => {
+        $(
+            if $api_version == <$ty as Resource>::API_VERSION && $kind == <$ty as Resource>::KIND {
+                return Some(schema_for::<$ty>());
+            }
+        )*
+    };
+}
+
+/// Returns the JSON schema for a built-in Kubernetes kind, if it is one we know.
+pub fn builtin_schema(api_version: &str, kind: &str) -> Option<Value> {
+    builtin_schemas!(
+        api_version,
+        kind,
+        [
+            Deployment,
+            StatefulSet,
+            DaemonSet,
+            ReplicaSet,
+            Service,
+            ConfigMap,
+            Secret,
+            Pod,
+            Namespace,
+            ServiceAccount,
+            Endpoints,
+            PersistentVolume,
+            PersistentVolumeClaim,
+            LimitRange,
+            ResourceQuota,
+            Job,
+            CronJob,
+            Ingress,
+            IngressClass,
+            NetworkPolicy,
+            HorizontalPodAutoscaler,
+            PodDisruptionBudget,
+            Role,
+            RoleBinding,
+            ClusterRole,
+            ClusterRoleBinding,
+            PriorityClass,
+            StorageClass,
+            CustomResourceDefinition,
+        ]
+    );
+    None
+}
+
+struct SchemaWalker {
+    definitions: Map<String, Value>,
+}
+
+impl SchemaWalker {
+    fn check(&self, value: &Value, schema: &Value, path: &str, errors: &mut Vec<(String, String)>) {
+        // Kubernetes treats explicit nulls as "unset".
+        if value.is_null() {
+            return;
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let name = reference.trim_start_matches("#/definitions/");
+            if name == QUANTITY_DEFINITION {
+                if !(value.is_string() || value.is_number()) {
+                    errors.push((path.to_string(), type_mismatch("quantity", value)));
+                }
+                return;
+            }
+            if let Some(target) = self.definitions.get(name) {
+                self.check(value, target, path, errors);
+            }
+            return;
+        }
+
+        if let Some(all_of) = schema.get("allOf").and_then(Value::as_array) {
+            for subschema in all_of {
+                self.check(value, subschema, path, errors);
+            }
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(options) = schema.get(keyword).and_then(Value::as_array) {
+                let matches_any = options.iter().any(|option| {
+                    let mut scratch = Vec::new();
+                    self.check(value, option, path, &mut scratch);
+                    scratch.is_empty()
+                });
+                if !matches_any {
+                    errors.push((
+                        path.to_string(),
+                        "does not match any of the allowed schemas".to_string(),
+                    ));
+                }
+            }
+        }
+
+        if schema
+            .get("x-kubernetes-int-or-string")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+        {
+            if !(value.is_string() || value.is_i64() || value.is_u64()) {
+                errors.push((path.to_string(), type_mismatch("integer or string", value)));
+            }
+            return;
+        }
+
+        if let Some(expected) = schema.get("type") {
+            let allowed: Vec<&str> = match expected {
+                Value::String(single) => vec![single.as_str()],
+                Value::Array(many) => many.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|ty| matches_type(value, ty)) {
+                errors.push((
+                    path.to_string(),
+                    type_mismatch(&allowed.join(" or "), value),
+                ));
+                return;
+            }
+        }
+
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if !options.contains(value) {
+                errors.push((
+                    path.to_string(),
+                    format!(
+                        "must be one of {}",
+                        options
+                            .iter()
+                            .map(Value::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                ));
+            }
+        }
+
+        match value {
+            Value::Object(object) => self.check_object(object, schema, path, errors),
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        self.check(item, item_schema, &format!("{}[{}]", path, index), errors);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_object(
+        &self,
+        object: &Map<String, Value>,
+        schema: &Value,
+        path: &str,
+        errors: &mut Vec<(String, String)>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        let preserve_unknown = schema
+            .get("x-kubernetes-preserve-unknown-fields")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for field in required.iter().filter_map(Value::as_str) {
+                if object.get(field).is_none_or(Value::is_null) {
+                    errors.push((join_path(path, field), "missing required field".to_string()));
+                }
+            }
+        }
+
+        for (key, child) in object {
+            let child_path = join_path(path, key);
+            if let Some(child_schema) = properties.and_then(|properties| properties.get(key)) {
+                self.check(child, child_schema, &child_path, errors);
+                continue;
+            }
+
+            match additional {
+                Some(Value::Bool(false)) => {
+                    errors.push((child_path, unknown_field(key, properties)));
+                }
+                Some(additional_schema @ Value::Object(_)) => {
+                    self.check(child, additional_schema, &child_path, errors);
+                }
+                Some(_) => {}
+                None if properties.is_some() && !preserve_unknown => {
+                    errors.push((child_path, unknown_field(key, properties)));
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+fn matches_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn type_mismatch(expected: &str, value: &Value) -> String {
+    format!("expected {}, found {}", expected, type_name(value))
+}
+
+fn unknown_field(key: &str, properties: Option<&Map<String, Value>>) -> String {
+    let suggestion = properties.and_then(|properties| {
+        properties
+            .keys()
+            .map(|candidate| (edit_distance(key, candidate), candidate))
+            .filter(|(distance, _)| *distance <= (key.chars().count() / 3).clamp(1, 3))
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    });
+    match suggestion {
+        Some(candidate) => format!("unknown field '{}' (did you mean '{}'?)", key, candidate),
+        None => format!("unknown field '{}'", key),
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENT: &str = r#"apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: api
+  labels:
+    app: api
+spec:
+  replicas: 2
+  selector:
+    matchLabels:
+      app: api
+  template:
+    metadata:
+      labels:
+        app: api
+    spec:
+      containers:
+        - name: api
+          image: registry/api:1.0.0
+          ports:
+            - containerPort: 8080
+          resources:
+            limits:
+              cpu: 500m
+              memory: 1
+"#;
+
+    #[test]
+    fn accepts_valid_builtin_manifests() {
+        let validator = ManifestValidator::new("/nonexistent");
+        let content = format!(
+            "{}---\napiVersion: v1\nkind: Service\nmetadata:\n  name: api\nspec:\n  ports:\n    - port: 80\n      targetPort: http\n",
+            DEPLOYMENT
+        );
+
+        let report = validator.validate(&content);
+
+        assert_eq!(report, ValidationReport::default());
+    }
+
+    #[test]
+    fn reports_unknown_fields_wrong_types_and_missing_required() {
+        let validator = ManifestValidator::new("/nonexistent");
+        let content = DEPLOYMENT
+            .replace("  replicas: 2", "  replicas: two")
+            .replace(
+                "          image:",
+                "          imagePullPolcy: Always\n          image:",
+            )
+            .replace("        - name: api\n", "        - tty: true\n");
+
+        let report = validator.validate(&content);
+        let messages: Vec<String> = report.violations.iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "Deployment/api spec.replicas: expected integer, found string".to_string(),
+                "Deployment/api spec.template.spec.containers[0].name: missing required field"
+                    .to_string(),
+                "Deployment/api spec.template.spec.containers[0].imagePullPolcy: unknown field 'imagePullPolcy' (did you mean 'imagePullPolicy'?)"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn validates_custom_resources_against_schema_directory() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let group_dir = dir.path().join("example.com");
+        std::fs::create_dir_all(&group_dir).expect("group dir");
+        std::fs::write(
+            group_dir.join("widget_v1.json"),
+            r#"{
+                "type": "object",
+                "properties": {
+                    "apiVersion": {"type": "string"},
+                    "kind": {"type": "string"},
+                    "metadata": {"type": "object"},
+                    "spec": {
+                        "type": "object",
+                        "required": ["size"],
+                        "properties": {"size": {"type": "integer"}},
+                        "additionalProperties": false
+                    }
+                }
+            }"#,
+        )
+        .expect("schema");
+        let validator = ManifestValidator::new(dir.path());
+
+        let report = validator.validate(
+            "apiVersion: example.com/v1\nkind: Widget\nmetadata:\n  name: w\nspec:\n  colour: red\n---\napiVersion: example.com/v2\nkind: Gadget\n",
+        );
+
+        let messages: Vec<String> = report.violations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Widget/w spec.size: missing required field".to_string(),
+                "Widget/w spec.colour: unknown field 'colour'".to_string(),
+            ]
+        );
+        assert_eq!(
+            report.unknown_kinds,
+            vec!["example.com/v2/Gadget".to_string()]
+        );
+    }
+
+    #[test]
+    fn requires_api_version_and_kind() {
+        let report = ManifestValidator::new("/nonexistent").validate("metadata:\n  name: x\n");
+
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].message, "missing apiVersion or kind");
+    }
+}
@@ -306,11 +306,9 @@ fn selected_items(items: &[String], selected_indices: &HashSet<usize>) -> Vec<St
 fn reduce_app_model(model: &mut AppModel, action: AppAction) {
     match action {
         AppAction::MoveNext { menu_len } => match &model.state {
-            AppState::MainMenu => {
-                if menu_len > 0 {
-                    model.main_menu_index = advance_index(Some(model.main_menu_index), menu_len)
-                        .expect("menu index should exist when menu_len > 0");
-                }
+            AppState::MainMenu if menu_len > 0 => {
+                model.main_menu_index = advance_index(Some(model.main_menu_index), menu_len)
+                    .expect("menu index should exist when menu_len > 0");
             }
             AppState::Selection { items, .. } => {
                 model.selection_index = advance_index(model.selection_index, items.len());
@@ -318,11 +316,9 @@ fn reduce_app_model(model: &mut AppModel, action: AppAction) {
             _ => {}
         },
         AppAction::MovePrevious { menu_len } => match &model.state {
-            AppState::MainMenu => {
-                if menu_len > 0 {
-                    model.main_menu_index = rewind_index(Some(model.main_menu_index), menu_len)
-                        .expect("menu index should exist when menu_len > 0");
-                }
+            AppState::MainMenu if menu_len > 0 => {
+                model.main_menu_index = rewind_index(Some(model.main_menu_index), menu_len)
+                    .expect("menu index should exist when menu_len > 0");
             }
             AppState::Selection { items, .. } => {
                 model.selection_index = rewind_index(model.selection_index, items.len());
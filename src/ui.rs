@@ -1,6 +1,6 @@
 use console::style;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -15,8 +15,13 @@ pub struct SailrUI {
 struct SailrUIInner {
     multi: MultiProgress,
     spinners: HashMap<String, ProgressBar>,
+    /// Sensitive values that are masked in every line printed.
+    redactions: BTreeSet<String>,
 }
 
+/// What a redacted value is replaced with.
+pub const REDACTED: &str = "******";
+
 impl SailrUI {
     pub fn new(quiet: bool, verbose: bool) -> Self {
         let is_tty = console::Term::stderr().is_term();
@@ -24,6 +29,7 @@ impl SailrUI {
             inner: Arc::new(Mutex::new(SailrUIInner {
                 multi: MultiProgress::new(),
                 spinners: HashMap::new(),
+                redactions: BTreeSet::new(),
             })),
             is_tty,
             quiet: Arc::new(AtomicBool::new(quiet)),
@@ -47,12 +53,33 @@ impl SailrUI {
         self.quiet.load(Ordering::SeqCst)
     }
 
+    // Masks `value` wherever it appears in anything printed from now on, and in
+    // `redacted`.
+    pub fn redact(&self, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.redactions.insert(value.to_string());
+    }
+
+    // `text` with every redacted value masked, for output that doesn't go through the
+    // logger, such as reports on stdout.
+    pub fn redacted(&self, text: &str) -> String {
+        let inner = self.inner.lock().unwrap();
+        mask(&inner.redactions, text)
+    }
+
+    fn write(&self, line: &str) {
+        let inner = self.inner.lock().unwrap();
+        let _ = inner.multi.println(mask(&inner.redactions, line));
+    }
+
     pub fn println(&self, msg: &str) {
         if self.is_quiet() {
             return;
         }
-        let inner = self.inner.lock().unwrap();
-        let _ = inner.multi.println(msg);
+        self.write(msg);
     }
 
     pub fn status(&self, verb: &str, msg: &str, color: &str) {
@@ -82,6 +109,12 @@ impl SailrUI {
     }
 
     pub fn task_starting(&self, name: &str) {
+        self.task_starting_as("Building", name);
+    }
+
+    // Like `task_starting`, for a task that does something else than building, such as
+    // "Applying".
+    pub fn task_starting_as(&self, verb: &str, name: &str) {
         if self.is_quiet() {
             return;
         }
@@ -93,11 +126,11 @@ impl SailrUI {
                 .template("  {spinner:.cyan} {msg}")
                 .unwrap_or_else(|_| ProgressStyle::default_spinner());
             pb.set_style(style);
-            pb.set_message(format!("building  {}", name));
+            pb.set_message(format!("{}  {}", verb.to_lowercase(), name));
             pb.enable_steady_tick(std::time::Duration::from_millis(80));
             inner.spinners.insert(name.to_string(), pb);
         } else {
-            self.status("Building", name, "cyan");
+            self.status(verb, name, "cyan");
         }
     }
 
@@ -151,8 +184,7 @@ impl SailrUI {
 
     pub fn error(&self, msg: &str) {
         let line = format!("{:>12} {}", style("error").red().bold(), msg);
-        let inner = self.inner.lock().unwrap();
-        let _ = inner.multi.println(line);
+        self.write(&line);
     }
 
     pub fn warn(&self, msg: &str) {
@@ -160,8 +192,7 @@ impl SailrUI {
             return;
         }
         let line = format!("{:>12} {}", style("warning").yellow().bold(), msg);
-        let inner = self.inner.lock().unwrap();
-        let _ = inner.multi.println(line);
+        self.write(&line);
     }
 
     pub fn info(&self, msg: &str) {
@@ -169,8 +200,7 @@ impl SailrUI {
             return;
         }
         let line = format!("{:>12} {}", style("info").cyan().bold(), msg);
-        let inner = self.inner.lock().unwrap();
-        let _ = inner.multi.println(line);
+        self.write(&line);
     }
 
     pub fn debug(&self, msg: &str) {
@@ -178,8 +208,7 @@ impl SailrUI {
             return;
         }
         let line = format!("{:>12} {}", style("debug").magenta().bold(), msg);
-        let inner = self.inner.lock().unwrap();
-        let _ = inner.multi.println(line);
+        self.write(&line);
     }
 
     pub fn trace(&self, msg: &str) {
@@ -187,7 +216,59 @@ impl SailrUI {
             return;
         }
         let line = format!("{:>12} {}", style("trace").dim(), msg);
-        let inner = self.inner.lock().unwrap();
-        let _ = inner.multi.println(line);
+        self.write(&line);
+    }
+}
+
+// Replaces the redacted values in `text`, longest first so a value that contains
+// another is masked whole.
+fn mask(redactions: &BTreeSet<String>, text: &str) -> String {
+    let mut values: Vec<&String> = redactions.iter().collect();
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    values
+        .into_iter()
+        .fold(text.to_string(), |text, value| mask_token(&text, value))
+}
+
+// Replaces the occurrences of `value` in `text` that stand on their own, so a value
+// such as `prod` masks `env=prod` but leaves `production` alone.
+fn mask_token(text: &str, value: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut masked = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, _) in text.match_indices(value) {
+        let end = start + value.len();
+        if text[..start].chars().next_back().is_some_and(is_word)
+            || text[end..].chars().next().is_some_and(is_word)
+        {
+            continue;
+        }
+        masked.push_str(&text[copied..start]);
+        masked.push_str(REDACTED);
+        copied = end;
+    }
+    masked.push_str(&text[copied..]);
+    masked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_redacted_values_only_where_they_stand_alone() {
+        let ui = SailrUI::new(true, false);
+        ui.redact("s3cr3t");
+        ui.redact("s3cr3t-token");
+        ui.redact("prod");
+
+        assert_eq!(
+            ui.redacted("token=s3cr3t-token, password \"s3cr3t\", env prod"),
+            "token=******, password \"******\", env ******"
+        );
+        assert_eq!(
+            ui.redacted("production uses s3cr3ts and prod_db"),
+            "production uses s3cr3ts and prod_db"
+        );
     }
 }
@@ -7,7 +7,8 @@ use chrono::Utc;
 use toml::{from_str, to_string};
 
 use crate::environment::{Environment, EnvironmentVariable, Service};
-use crate::errors::SailrError;
+use crate::errors::{SailrError, TemplateError};
+use crate::templates::engine::{TemplateContext, TemplateEngine};
 
 pub const ENV_DIR: &str = "./k8s/environments/";
 
@@ -112,10 +113,9 @@ pub fn get_current_timestamp() -> String {
     now.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
-pub fn replace_variables(content: String, variables: BTreeMap<String, String>) -> String {
-    let mut new_content = content.clone();
-    for (key, value) in variables {
-        new_content = new_content.replace(&format!("{{{{{}}}}}", key), &value);
-    }
-    new_content
+pub fn replace_variables(
+    content: String,
+    variables: BTreeMap<String, String>,
+) -> Result<String, TemplateError> {
+    TemplateEngine::new().render(&content, &TemplateContext::from(variables))
 }
@@ -18,7 +18,7 @@ const DEFAULT_CONFIG_FILENAME: &str = "sailr.workflow.toml";
 /// environment = "preview"
 /// mode = "check"
 /// ```
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
 pub struct WorkflowConfig {
     #[serde(default)]
     pub workflow: HashMap<String, WorkflowProfile>,
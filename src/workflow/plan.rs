@@ -67,6 +67,8 @@ pub enum WorkflowTaskKind {
     Generate,
     DeploymentPlan,
     Deploy,
+    RollbackPlan,
+    Rollback,
     Verify,
     Approval,
 }
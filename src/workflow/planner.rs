@@ -418,20 +418,35 @@ impl WorkflowPlanner {
             last_tasks = vec![crate::workflow::task_id::GENERATE.to_string()];
         }
 
-        // 3. Deploy Phase
+        // 3. Deploy Phase. A rollback re-applies the revision recorded by the latest
+        // deploy instead of the generated manifests.
+        let rollback = self.profile.mode == crate::workflow::profile::WorkflowMode::Rollback;
         if self.profile.deploy.is_active() {
-            tasks.push(WorkflowTaskPlan {
-                id: crate::workflow::task_id::DEPLOYMENT_PLAN.to_string(),
-                label: "Deployment Plan".to_string(),
-                kind: WorkflowTaskKind::DeploymentPlan,
-                dependencies: last_tasks.clone(),
-                effects: WorkflowEffects::default(),
-                description:
-                    "Create and validate the Kubernetes deployment plan without applying changes."
-                        .to_string(),
-            });
+            if rollback {
+                tasks.push(WorkflowTaskPlan {
+                    id: crate::workflow::task_id::ROLLBACK_PLAN.to_string(),
+                    label: "Rollback Plan".to_string(),
+                    kind: WorkflowTaskKind::RollbackPlan,
+                    dependencies: last_tasks.clone(),
+                    effects: WorkflowEffects::default(),
+                    description: "Show the recorded revision a rollback would restore.".to_string(),
+                });
 
-            last_tasks = vec![crate::workflow::task_id::DEPLOYMENT_PLAN.to_string()];
+                last_tasks = vec![crate::workflow::task_id::ROLLBACK_PLAN.to_string()];
+            } else {
+                tasks.push(WorkflowTaskPlan {
+                    id: crate::workflow::task_id::DEPLOYMENT_PLAN.to_string(),
+                    label: "Deployment Plan".to_string(),
+                    kind: WorkflowTaskKind::DeploymentPlan,
+                    dependencies: last_tasks.clone(),
+                    effects: WorkflowEffects::default(),
+                    description:
+                        "Create and validate the Kubernetes deployment plan without applying changes."
+                            .to_string(),
+                });
+
+                last_tasks = vec![crate::workflow::task_id::DEPLOYMENT_PLAN.to_string()];
+            }
 
             if self.profile.deploy == crate::workflow::profile::WorkflowStepMode::Run {
                 if self.profile.approval == crate::workflow::profile::ApprovalMode::Prompt {
@@ -452,7 +467,20 @@ impl WorkflowPlanner {
                     last_tasks = vec![crate::workflow::task_id::APPROVAL.to_string()];
                 }
 
-                if self.profile.apply {
+                if self.profile.apply && rollback {
+                    tasks.push(WorkflowTaskPlan {
+                        id: crate::workflow::task_id::ROLLBACK.to_string(),
+                        label: "Rollback".to_string(),
+                        kind: WorkflowTaskKind::Rollback,
+                        dependencies: last_tasks.clone(),
+                        effects: WorkflowEffects {
+                            mutates_cluster: true,
+                            ..Default::default()
+                        },
+                        description: "Restore the workloads recorded before the latest deploy."
+                            .to_string(),
+                    });
+                } else if self.profile.apply {
                     tasks.push(WorkflowTaskPlan {
                         id: crate::workflow::task_id::DEPLOY.to_string(),
                         label: "Deploy".to_string(),
@@ -814,12 +842,21 @@ impl WorkflowPlanner {
             let only = self.options.only.clone();
             let ignore = self.options.ignore.clone();
             let env_clone = self.env.clone();
+            let source_revision_resolver = self.source_revision_resolver.clone();
+            let runner = self.runner.clone();
 
             task = task.exec_fn(move |_ctx| {
                 let name = name.clone();
                 let only = only.clone();
                 let ignore = ignore.clone();
                 let env_clone = env_clone.clone();
+                let source_revision = match source_revision_resolver.resolve(&runner) {
+                    Ok(revision) => revision,
+                    Err(e) => {
+                        crate::LOGGER.debug(&format!("No source revision: {}", e));
+                        None
+                    }
+                };
                 async move {
                     crate::LOGGER.info("Generating Kubernetes manifests...");
 
@@ -829,8 +866,16 @@ impl WorkflowPlanner {
                         &ignore,
                     );
 
-                    crate::generate(&name, &env_clone, services)
-                        .map_err(|e| anyhow::anyhow!("Generate failed: {}", e))?;
+                    crate::generate(
+                        &name,
+                        &env_clone,
+                        services,
+                        &crate::generate::GenerateOptions {
+                            source_revision,
+                            ..Default::default()
+                        },
+                    )
+                    .map_err(|e| anyhow::anyhow!("Generate failed: {}", e))?;
 
                     Ok(())
                 }
@@ -839,7 +884,51 @@ impl WorkflowPlanner {
             pipeline.add(task);
         }
 
-        if self.profile.deploy.is_active() {
+        let rollback = self.profile.mode == crate::workflow::profile::WorkflowMode::Rollback;
+        if self.profile.deploy.is_active() && rollback {
+            let mut task = runtime_task(plan, crate::workflow::task_id::ROLLBACK_PLAN)?;
+
+            let env_name = self.profile.environment.clone();
+            let context = self.profile.deploy_context.clone().unwrap_or_default();
+            let is_static_plan =
+                self.profile.deploy == crate::workflow::profile::WorkflowStepMode::Plan;
+
+            task = task.exec_fn(move |_ctx| {
+                let env_name = env_name.clone();
+                let context = context.clone();
+                async move {
+                    println!("Sailr rollback plan:");
+                    println!("  environment: {}", env_name);
+                    println!("  context: {}", context);
+                    if is_static_plan {
+                        println!("  restores: the revision recorded by the latest deploy");
+                        return Ok(());
+                    }
+
+                    let revisions = crate::deployment::revisions(context, &env_name)
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Rollback plan failed: {}", e))?;
+                    let revision = revisions.last().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Rollback plan failed: {}",
+                            crate::errors::DeployError::NoRevisions(env_name.clone())
+                        )
+                    })?;
+                    println!(
+                        "  restores: revision {} ({})\n",
+                        revision.number, revision.recorded_at
+                    );
+                    println!("Workloads:");
+                    for line in revision.describe() {
+                        println!("  - {}", line);
+                    }
+
+                    Ok(())
+                }
+            });
+
+            pipeline.add(task);
+        } else if self.profile.deploy.is_active() {
             let mut task = runtime_task(plan, crate::workflow::task_id::DEPLOYMENT_PLAN)?;
 
             let env_name = self.profile.environment.clone();
@@ -878,10 +967,11 @@ impl WorkflowPlanner {
                             println!("  - {} {} \twould apply", res.kind, res.name);
                         }
                     } else {
-                        let plan =
-                            crate::plan::generate_deployment_plan(&env_name, &context, &namespace)
-                                .await
-                                .map_err(|e| anyhow::anyhow!("Deployment plan failed: {}", e))?;
+                        let plan = crate::plan::generate_deployment_plan(
+                            &env_name, &context, &namespace, false,
+                        )
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Deployment plan failed: {}", e))?;
 
                         crate::plan::validate_plan_safety(&plan).map_err(|e| {
                             anyhow::anyhow!("Deployment plan validation failed: {}", e)
@@ -893,21 +983,27 @@ impl WorkflowPlanner {
             });
 
             pipeline.add(task);
+        }
+
+        if self.profile.deploy.is_active() {
             if self.profile.approval == crate::workflow::profile::ApprovalMode::Prompt {
                 let mut task = runtime_task(plan, crate::workflow::task_id::APPROVAL)?;
 
+                let (question, cancelled) = if rollback {
+                    ("Proceed with rollback?", "Rollback cancelled by user")
+                } else {
+                    ("Proceed with deployment?", "Deployment cancelled by user")
+                };
                 task = task.exec_fn(move |_ctx| async move {
-                    let approved = tokio::task::spawn_blocking(|| {
-                        inquire::Confirm::new("Proceed with deployment?")
-                            .with_default(false)
-                            .prompt()
+                    let approved = tokio::task::spawn_blocking(move || {
+                        inquire::Confirm::new(question).with_default(false).prompt()
                     })
                     .await
                     .map_err(|e| anyhow::anyhow!("Approval prompt failed: {}", e))?
                     .map_err(|e| anyhow::anyhow!("Approval prompt failed: {}", e))?;
 
                     if !approved {
-                        return Err(anyhow::anyhow!("Deployment cancelled by user"));
+                        return Err(anyhow::anyhow!(cancelled));
                     }
 
                     Ok(())
@@ -916,9 +1012,34 @@ impl WorkflowPlanner {
                 pipeline.add(task);
             }
 
-            if self.profile.deploy == crate::workflow::profile::WorkflowStepMode::Run
-                && self.profile.apply
-            {
+            let apply = self.profile.deploy == crate::workflow::profile::WorkflowStepMode::Run
+                && self.profile.apply;
+            if apply && rollback {
+                let mut task = runtime_task(plan, crate::workflow::task_id::ROLLBACK)?;
+
+                let context = self.profile.deploy_context.clone().unwrap_or_default();
+                let env_name = self.profile.environment.clone();
+
+                task = task.exec_fn(move |_ctx| {
+                    let context = context.clone();
+                    let env_name = env_name.clone();
+
+                    async move {
+                        crate::deployment::rollback(
+                            context,
+                            &env_name,
+                            None,
+                            Some(crate::deployment::rollout::DEFAULT_ROLLOUT_TIMEOUT),
+                        )
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Rollback failed: {}", e))?;
+
+                        Ok(())
+                    }
+                });
+
+                pipeline.add(task);
+            } else if apply {
                 let mut task = runtime_task(plan, crate::workflow::task_id::DEPLOY)?;
 
                 let context = self.profile.deploy_context.clone().unwrap_or_default();
@@ -936,7 +1057,7 @@ impl WorkflowPlanner {
                         crate::deployment::deploy(
                             context,
                             &env_name,
-                            crate::cli::DeploymentStrategy::Rolling,
+                            &crate::deployment::DeployOptions::default(),
                         )
                         .await
                         .map_err(|e| anyhow::anyhow!("Deploy failed: {}", e))?;
@@ -1157,6 +1278,47 @@ mod tests {
         expected.sort();
         assert_eq!(task_names, expected);
     }
+
+    #[test]
+    fn rollback_mode_restores_instead_of_deploying() {
+        let mut profile = dummy_profile(WorkflowStepMode::Run, WorkflowStepMode::Disabled);
+        profile.mode = WorkflowMode::Rollback;
+        profile.generate = WorkflowStepMode::Disabled;
+        profile.approval = ApprovalMode::Prompt;
+        profile.apply = true;
+
+        let planner = WorkflowPlanner::new(
+            profile,
+            Arc::new(Environment::new("local")),
+            dummy_options(false),
+            dummy_runner(),
+        );
+        let plan = planner.plan().unwrap();
+        let rollback = plan
+            .tasks
+            .iter()
+            .find(|t| t.kind == WorkflowTaskKind::Rollback)
+            .unwrap();
+        assert_eq!(
+            rollback.dependencies,
+            vec![crate::workflow::task_id::APPROVAL.to_string()]
+        );
+        assert!(rollback.effects.mutates_cluster);
+
+        let (pipeline, _) = planner
+            .build_pipeline_from_plan(&plan, Default::default())
+            .unwrap();
+        let mut task_names: Vec<String> = pipeline.tasks().map(|t| t.name.clone()).collect();
+        task_names.sort();
+        let mut expected = vec![
+            crate::workflow::task_id::VALIDATE_CONFIG.to_string(),
+            crate::workflow::task_id::ROLLBACK_PLAN.to_string(),
+            crate::workflow::task_id::APPROVAL.to_string(),
+            crate::workflow::task_id::ROLLBACK.to_string(),
+        ];
+        expected.sort();
+        assert_eq!(task_names, expected);
+    }
 }
 
 #[cfg(test)]
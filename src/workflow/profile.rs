@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 /// A workflow profile defines how a particular workflow (local dev, PR check,
 /// staging deploy, production deploy) should behave. Profiles are loaded from
 /// `sailr.workflow.toml` and converted into runkernel pipelines by the planner.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct WorkflowProfile {
     /// Profile name, injected at load time from the TOML key.
     #[serde(skip)]
@@ -152,7 +152,21 @@ impl WorkflowProfile {
                 WorkflowStepMode::Disabled,
                 WorkflowStepMode::Disabled,
             ),
-            WorkflowMode::Promote | WorkflowMode::Rollback => (
+            // Restores the revision recorded by the latest deploy; nothing is built or
+            // generated.
+            WorkflowMode::Rollback => (
+                WorkflowStepMode::Disabled,
+                WorkflowStepMode::Disabled,
+                WorkflowStepMode::Disabled,
+                if apply {
+                    WorkflowStepMode::Run
+                } else {
+                    WorkflowStepMode::Plan
+                },
+                WorkflowStepMode::Disabled,
+                WorkflowStepMode::Disabled,
+            ),
+            WorkflowMode::Promote => (
                 WorkflowStepMode::Disabled,
                 WorkflowStepMode::Disabled,
                 WorkflowStepMode::Disabled,
@@ -182,7 +196,7 @@ impl WorkflowProfile {
                     apply = false;
                 }
             }
-            WorkflowMode::Go | WorkflowMode::Deploy => {
+            WorkflowMode::Go | WorkflowMode::Deploy | WorkflowMode::Rollback => {
                 if approval == ApprovalMode::None && deploy == WorkflowStepMode::Run {
                     approval = if runner_is_ci {
                         ApprovalMode::External
@@ -191,7 +205,7 @@ impl WorkflowProfile {
                     };
                 }
             }
-            WorkflowMode::Promote | WorkflowMode::Rollback => {}
+            WorkflowMode::Promote => {}
         }
 
         NormalizedWorkflowProfile {
@@ -245,7 +259,7 @@ fn default_engine() -> WorkflowEngine {
 // ---------------------------------------------------------------------------
 
 /// The overall intent of a workflow profile.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, schemars::JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum WorkflowMode {
     Build,
@@ -280,7 +294,7 @@ impl std::fmt::Display for WorkflowMode {
 // ---------------------------------------------------------------------------
 
 /// Execution engine for the workflow.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, schemars::JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum WorkflowEngine {
     Runkernel,
@@ -305,7 +319,9 @@ impl std::fmt::Display for WorkflowEngine {
 ///
 /// Defaults to `Disabled`. The workflow planner applies context-aware defaults
 /// based on `WorkflowMode` when a step is not explicitly configured.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, schemars::JsonSchema,
+)]
 #[serde(rename_all = "kebab-case")]
 pub enum WorkflowStepMode {
     #[default]
@@ -345,7 +361,9 @@ impl std::fmt::Display for WorkflowStepMode {
 // ---------------------------------------------------------------------------
 
 /// How deployment approval is handled.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, schemars::JsonSchema,
+)]
 #[serde(rename_all = "kebab-case")]
 pub enum ApprovalMode {
     /// No approval needed.
@@ -381,7 +399,9 @@ impl std::fmt::Display for ApprovalMode {
 // ---------------------------------------------------------------------------
 
 /// Output format for workflow reports.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, schemars::JsonSchema,
+)]
 #[serde(rename_all = "kebab-case")]
 pub enum ReportMode {
     #[default]
@@ -405,7 +425,7 @@ impl std::fmt::Display for ReportMode {
 // ---------------------------------------------------------------------------
 
 /// Controls artifact upload and storage behaviour.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, schemars::JsonSchema)]
 pub struct ArtifactPolicy {
     /// Whether to upload artifacts (e.g. to CI artifact storage).
     #[serde(default)]
@@ -422,7 +442,7 @@ pub struct ArtifactPolicy {
 
 /// Policy for promoting artifacts from one environment to another
 /// instead of rebuilding.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct PromotionPolicy {
     /// Source environment to promote from.
     pub from: String,
@@ -432,7 +452,7 @@ pub struct PromotionPolicy {
 }
 
 /// Strategy for artifact promotion.
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, schemars::JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 pub enum PromotionStrategy {
     ImageTag,
@@ -806,4 +826,20 @@ mod tests {
         assert_eq!(normalized.deploy_context.as_deref(), Some("none"));
         assert!(!normalized.apply);
     }
+
+    #[test]
+    fn normalize_rollback_profile() {
+        let toml_str = r#"
+            environment = "prod"
+            mode = "rollback"
+            deploy_context = "prod-cluster"
+            apply = true
+        "#;
+        let profile: WorkflowProfile = toml::from_str(toml_str).unwrap();
+        let normalized = profile.normalize(true);
+        assert_eq!(normalized.build, WorkflowStepMode::Disabled);
+        assert_eq!(normalized.generate, WorkflowStepMode::Disabled);
+        assert_eq!(normalized.deploy, WorkflowStepMode::Run);
+        assert_eq!(normalized.approval, ApprovalMode::External);
+    }
 }
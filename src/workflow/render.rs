@@ -188,7 +188,11 @@ pub fn render_workflow_explain_text(plan: &WorkflowPlan, task_id: &str) -> Resul
         out.push_str(&format!("Dependencies: {}\n", task.dependencies.join(", ")));
     }
 
-    if task.kind == crate::workflow::plan::WorkflowTaskKind::Deploy {
+    if matches!(
+        task.kind,
+        crate::workflow::plan::WorkflowTaskKind::Deploy
+            | crate::workflow::plan::WorkflowTaskKind::Rollback
+    ) {
         if plan.profile.approval == crate::workflow::profile::ApprovalMode::External {
             out.push_str("\nApproval:\n");
             out.push_str("  mode: external\n");
@@ -98,7 +98,7 @@ fn print_failed_tasks(result: &runkernel::PipelineResult) {
         if matches!(task.status, runkernel::TaskStatus::Failed) {
             println!("Failed task: {}", task.name);
             if let Some(error) = &task.error {
-                println!("  error: {}", error);
+                println!("  error: {}", crate::LOGGER.redacted(error));
             }
         }
     }
@@ -8,6 +8,8 @@ pub const GENERATE: &str = "workflow:generate";
 pub const DEPLOYMENT_PLAN: &str = "workflow:deployment-plan";
 pub const APPROVAL: &str = "workflow:approval";
 pub const DEPLOY: &str = "workflow:deploy";
+pub const ROLLBACK_PLAN: &str = "workflow:rollback-plan";
+pub const ROLLBACK: &str = "workflow:rollback";
 pub const REPORT_ARTIFACTS: &str = "workflow:image-report";
 pub const WRITE_REPORT_FINALIZER: &str = "finalizer:write-workflow-report";
 pub const WRITE_BUILD_CACHE_FINALIZER: &str = "finalizer:write-build-cache";